@@ -0,0 +1,319 @@
+// src/allergies.rs
+// Charcot EMR: Allergies and intolerances, and checking prescriptions against them
+//
+// Medication allergies are coded to RxNorm ingredients or to a drug class (ATC),
+// e.g. "penicillins". A prescription whose drug is an allergen, or belongs to
+// an allergen's class, needs an override reason and is refused outright for a
+// confirmed high-criticality allergy. Cross-reactive classes (penicillins and
+// cephalosporins) and intolerances only give a warning.
+
+use std::fmt;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use anyhow::{Result, anyhow};
+
+use crate::terminology::{ATC_SYSTEM, RXNORM_SYSTEM};
+use crate::{
+    AllergyIntolerance, AllergyReaction, BundleEntry, Coding, EMR, Reference, ResolvedDrug, Resource,
+    validate_clinical_time,
+};
+
+pub const ALLERGY_TYPES: &[&str] = &["allergy", "intolerance"];
+pub const CATEGORIES: &[&str] = &["medication", "food", "environment", "biologic"];
+pub const CRITICALITIES: &[&str] = &["low", "high", "unable-to-assess"];
+pub const VERIFICATION_STATUSES: &[&str] = &["unconfirmed", "confirmed", "refuted", "entered-in-error"];
+pub const REACTION_SEVERITIES: &[&str] = &["mild", "moderate", "severe"];
+
+pub struct DrugClass {
+    pub code: &'static str,             // ATC code
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub members: &'static [&'static str], // RxNorm ingredients
+}
+
+const DRUG_CLASSES: &[DrugClass] = &[
+    DrugClass { code: "J01C", name: "penicillins", aliases: &["penicillin"],
+                members: &["723", "733", "7980", "7984"] },
+    DrugClass { code: "J01D", name: "cephalosporins", aliases: &["cephalosporin"],
+                members: &["2231", "2180", "2193"] },
+    DrugClass { code: "J01E", name: "sulfonamides", aliases: &["sulfonamide", "sulfa", "sulfa drugs"],
+                members: &["10180"] },
+    DrugClass { code: "J01FA", name: "macrolides", aliases: &["macrolide"],
+                members: &["18631", "21212", "4053"] },
+    DrugClass { code: "J01MA", name: "fluoroquinolones", aliases: &["fluoroquinolone", "quinolones"],
+                members: &["2551", "82122", "139462"] },
+    DrugClass { code: "J01AA", name: "tetracyclines", aliases: &["tetracycline antibiotics"],
+                members: &["3640", "6980", "10395"] },
+    // Aspirin cross-reacts with the other NSAIDs in sensitive patients
+    DrugClass { code: "M01A", name: "NSAIDs", aliases: &["nsaid", "anti-inflammatories"],
+                members: &["5640", "7258", "3355", "140587", "35827", "41493", "1191"] },
+    DrugClass { code: "N02A", name: "opioids", aliases: &["opioid", "opiates"],
+                members: &["7052", "7804", "10689", "2670", "5489", "4337", "3423"] },
+    DrugClass { code: "C09A", name: "ACE inhibitors", aliases: &["ace inhibitor"],
+                members: &["29046", "3827", "35296", "1998"] },
+    DrugClass { code: "C10AA", name: "statins", aliases: &["statin"],
+                members: &["83367", "36567", "301542", "42463"] },
+    DrugClass { code: "N05BA", name: "benzodiazepines", aliases: &["benzodiazepine"],
+                members: &["6470", "596", "3322", "2598"] },
+    DrugClass { code: "A10BB", name: "sulfonylureas", aliases: &["sulfonylurea"],
+                members: &["4821", "4815", "25789"] },
+];
+
+// Classes with clinically relevant cross-reactivity
+const CROSS_REACTIVE: &[(&str, &str)] = &[("J01C", "J01D")];
+
+// A drug class by ATC code, name or alias
+pub fn find_drug_class(name: &str) -> Option<&'static DrugClass> {
+    let name = name.trim();
+    DRUG_CLASSES.iter().find(|class| {
+        class.code.eq_ignore_ascii_case(name)
+            || class.name.eq_ignore_ascii_case(name)
+            || class.aliases.iter().any(|alias| alias.eq_ignore_ascii_case(name))
+    })
+}
+
+fn classes_of<'a>(codes: &'a [&str]) -> impl Iterator<Item = &'static DrugClass> + 'a {
+    DRUG_CLASSES.iter().filter(|class| class.members.iter().any(|member| codes.contains(member)))
+}
+
+fn cross_reactive(a: &str, b: &str) -> bool {
+    CROSS_REACTIVE.iter().any(|&(x, y)| (x == a && y == b) || (x == b && y == a))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AllergyAction {
+    Warn,                               // Prescribe, noting it in the audit log
+    RequireOverride,                    // Prescribe only with an override reason
+    Refuse,                             // Never prescribe
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllergyAlert {
+    pub action: AllergyAction,
+    pub medication: String,
+    pub allergy_type: String,           // allergy | intolerance
+    pub allergen: String,
+    pub detail: Option<String>,         // How the medication relates to the allergen, if not directly
+    pub reaction: Option<String>,
+}
+
+impl fmt::Display for AllergyAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} to {}", self.medication, self.allergy_type, self.allergen)?;
+        if let Some(detail) = &self.detail {
+            write!(f, ", {}", detail)?;
+        }
+        match &self.reaction {
+            Some(reaction) => write!(f, " (reaction: {})", reaction),
+            None => Ok(()),
+        }
+    }
+}
+
+// An allergy to record
+#[derive(Debug, Clone)]
+pub struct NewAllergy {
+    pub substance: String,              // Medication, drug class or other substance
+    pub allergy_type: String,
+    pub category: String,
+    pub criticality: String,
+    pub verification_status: String,
+    pub reaction: Option<String>,
+    pub reaction_severity: Option<String>,
+    pub recorded: DateTime<Utc>,
+}
+
+impl Default for NewAllergy {
+    fn default() -> Self {
+        NewAllergy {
+            substance: String::new(),
+            allergy_type: "allergy".to_string(),
+            category: "medication".to_string(),
+            criticality: "unable-to-assess".to_string(),
+            verification_status: "unconfirmed".to_string(),
+            reaction: None,
+            reaction_severity: None,
+            recorded: Utc::now(),
+        }
+    }
+}
+
+fn check_value(what: &str, value: &str, allowed: &[&str]) -> Result<()> {
+    if allowed.contains(&value) {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid {}: {}. Expected one of {}", what, value, allowed.join(", ")))
+    }
+}
+
+// Whether an allergy is in force. Refuted and mistaken entries, and allergies
+// that have resolved, are kept in the record but not checked.
+pub fn is_active(allergy: &AllergyIntolerance) -> bool {
+    allergy.clinical_status == "active"
+        && !matches!(allergy.verification_status.as_str(), "refuted" | "entered-in-error")
+}
+
+fn reaction_text(allergy: &AllergyIntolerance) -> Option<String> {
+    let reactions: Vec<String> = allergy.reaction.iter()
+        .map(|reaction| match &reaction.severity {
+            Some(severity) => format!("{}, {}", reaction.manifestation, severity),
+            None => reaction.manifestation.clone(),
+        })
+        .collect();
+    (!reactions.is_empty()).then(|| reactions.join("; "))
+}
+
+// How prescribing `drug` (or the unlisted medication `name`) relates to an
+// allergy, if at all
+fn check(allergy: &AllergyIntolerance, drug: Option<&ResolvedDrug>, name: &str) -> Option<AllergyAlert> {
+    if allergy.category != "medication" || !is_active(allergy) {
+        return None;
+    }
+    let direct = if allergy.criticality == "high" && allergy.verification_status == "confirmed" {
+        AllergyAction::Refuse
+    } else {
+        AllergyAction::RequireOverride
+    };
+    let codes = drug.map(|drug| drug.codes()).unwrap_or_default();
+    let allergen = &allergy.code;
+
+    let (action, detail) = if allergen.system == ATC_SYSTEM {
+        let class = find_drug_class(&allergen.code)?;
+        if classes_of(&codes).any(|drug_class| drug_class.code == class.code) {
+            (direct, None)
+        } else {
+            let related = classes_of(&codes).find(|drug_class| cross_reactive(drug_class.code, class.code))?;
+            (AllergyAction::Warn, Some(format!("which may cross-react with {}", related.name)))
+        }
+    } else if allergen.system == RXNORM_SYSTEM {
+        if codes.contains(&allergen.code.as_str()) {
+            (direct, None)
+        } else {
+            let allergen_codes = [allergen.code.as_str()];
+            let allergen_classes: Vec<&DrugClass> = classes_of(&allergen_codes).collect();
+            let drug_classes: Vec<&DrugClass> = classes_of(&codes).collect();
+            if let Some(class) = drug_classes.iter().find(|class| allergen_classes.iter().any(|a| a.code == class.code)) {
+                (AllergyAction::RequireOverride, Some(format!("both {}", class.name)))
+            } else {
+                let related = drug_classes.iter()
+                    .find(|class| allergen_classes.iter().any(|a| cross_reactive(a.code, class.code)))?;
+                (AllergyAction::Warn, Some(format!("which may cross-react with {}", related.name)))
+            }
+        }
+    } else if allergen.display.eq_ignore_ascii_case(name.trim()) {
+        (direct, None)
+    } else {
+        return None;
+    };
+
+    // An intolerance is an expected side effect rather than an immune reaction
+    let action = if allergy.allergy_type == "intolerance" { AllergyAction::Warn } else { action };
+
+    Some(AllergyAlert {
+        action,
+        medication: drug.map(|drug| drug.drug.name.clone()).unwrap_or_else(|| name.trim().to_string()),
+        allergy_type: allergy.allergy_type.clone(),
+        allergen: allergen.display.clone(),
+        detail,
+        reaction: reaction_text(allergy),
+    })
+}
+
+impl EMR {
+    // Record an allergy. Medication allergies must name a drug class or a
+    // medication in the RxNorm dictionary, and are recorded against the
+    // medication's ingredients so that other products containing them match;
+    // other substances are recorded by name. Returns the ids of the new
+    // AllergyIntolerances, one for each ingredient of a combination.
+    pub fn add_allergy(&mut self, patient_id: &str, allergy: &NewAllergy) -> Result<Vec<String>> {
+        let substance = allergy.substance.trim();
+        if substance.is_empty() {
+            return Err(anyhow!("Allergy substance is required"));
+        }
+        check_value("allergy type", &allergy.allergy_type, ALLERGY_TYPES)?;
+        check_value("allergy category", &allergy.category, CATEGORIES)?;
+        check_value("criticality", &allergy.criticality, CRITICALITIES)?;
+        check_value("verification status", &allergy.verification_status, VERIFICATION_STATUSES)?;
+        if let Some(severity) = &allergy.reaction_severity {
+            check_value("reaction severity", severity, REACTION_SEVERITIES)?;
+        }
+        validate_clinical_time(allergy.recorded)?;
+
+        let codes = if allergy.category != "medication" {
+            vec![Coding { system: String::new(), code: String::new(), display: substance.to_string() }]
+        } else if let Some(class) = find_drug_class(substance) {
+            vec![Coding { system: ATC_SYSTEM.to_string(), code: class.code.to_string(), display: class.name.to_string() }]
+        } else {
+            let drug = self.drugs.resolve(substance)
+                .map_err(|e| anyhow!("{}\nMedication allergies need a drug class or a medication in the RxNorm dictionary", e))?;
+            let ingredients = if drug.ingredients.is_empty() { vec![drug.drug] } else { drug.ingredients };
+            ingredients.into_iter()
+                .map(|ingredient| Coding { system: RXNORM_SYSTEM.to_string(), code: ingredient.rxcui, display: ingredient.name })
+                .collect()
+        };
+
+        let reaction: Vec<AllergyReaction> = allergy.reaction.iter()
+            .map(|manifestation| AllergyReaction {
+                manifestation: manifestation.trim().to_string(),
+                severity: allergy.reaction_severity.clone(),
+            })
+            .collect();
+
+        let mut ids = Vec::new();
+        for code in codes {
+            let record = AllergyIntolerance {
+                id: Uuid::new_v4().to_string(),
+                clinical_status: "active".to_string(),
+                verification_status: allergy.verification_status.clone(),
+                allergy_type: allergy.allergy_type.clone(),
+                category: allergy.category.clone(),
+                criticality: allergy.criticality.clone(),
+                code: code.clone(),
+                patient: Reference {
+                    reference: format!("Patient/{}", patient_id),
+                },
+                recorded_date: allergy.recorded.to_rfc3339(),
+                reaction: reaction.clone(),
+            };
+            ids.push(record.id.clone());
+
+            let bundle = self.bundles.get_mut(patient_id)
+                .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+
+            bundle.entry.push(BundleEntry {
+                resource_type: "AllergyIntolerance".to_string(),
+                resource: Resource::AllergyIntolerance(record),
+            });
+
+            self.log_audit(&format!("Added {}: {} ({}, criticality {}, {})",
+                                   allergy.allergy_type, code.display, allergy.category,
+                                   allergy.criticality, allergy.verification_status), patient_id)?;
+        }
+
+        Ok(ids)
+    }
+
+    // The patient's allergies that are in force
+    pub fn allergies(&self, patient_id: &str) -> Result<Vec<&AllergyIntolerance>> {
+        let bundle = self.bundles.get(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        Ok(bundle.entry.iter()
+            .filter_map(|entry| match &entry.resource {
+                Resource::AllergyIntolerance(allergy) if is_active(allergy) => Some(allergy),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // Allergy alerts for prescribing `medication` to the patient, most
+    // serious first. Medications not in the RxNorm dictionary are checked by
+    // name and as their closest match.
+    pub fn check_allergies(&self, patient_id: &str, medication: &str) -> Result<Vec<AllergyAlert>> {
+        let drug = self.drugs.resolve_or_closest(medication);
+        let mut alerts: Vec<AllergyAlert> = self.allergies(patient_id)?.into_iter()
+            .filter_map(|allergy| check(allergy, drug.as_ref(), medication))
+            .collect();
+        alerts.sort_by_key(|alert| std::cmp::Reverse(alert.action));
+        Ok(alerts)
+    }
+}
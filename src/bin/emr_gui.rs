@@ -1,12 +1,19 @@
 // src/bin/emr_gui.rs
 // A simple GUI for the Charcot EMR using egui
 
-use charcot_emr::{EMR, Resource, BundleEntry};
+use charcot_emr::allergies::{ALLERGY_TYPES, CATEGORIES, CRITICALITIES, REACTION_SEVERITIES, VERIFICATION_STATUSES};
+use charcot_emr::conditions::{self, CLINICAL_STATUSES};
+use charcot_emr::dose_calc::DOSE_UNITS;
+use charcot_emr::history;
+use charcot_emr::medications;
+use charcot_emr::mar::ADMINISTRATION_WINDOW_MINUTES;
+use charcot_emr::reconcile::{self, CONTEXTS};
+use charcot_emr::{EMR, Signer, MedicationRequest, Resource, BundleEntry, AllergyAction, DoseBasis, DoseOrder, DoseSeverity, Drug, InteractionAlert, MED_FORMAT_VERSION, MedFile, AdministrationOutcome, MedicationChange, NewAdministration, NewAllergy, NewCondition, PrescribeOptions, ReconcileAction, Reconciliation, ReconciliationItem, Vital, VitalKind, parse_clinical_time};
+use chrono::{DateTime, Duration, Local, NaiveDate, TimeZone, Utc};
 use eframe::egui;
 use egui::{TextEdit, Ui, Vec2};
-use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 
 // Make sure to add egui dependency to Cargo.toml:
 // eframe = "0.19"
@@ -18,13 +25,33 @@ fn main() -> Result<(), eframe::Error> {
         ..Default::default()
     };
     
+    // Without its dose limits, interactions, lab ranges or clinician keys the
+    // EMR would prescribe and verify unchecked, so show why and go no further
+    let app: Box<dyn eframe::App> = match EMR::new() {
+        Ok(emr) => Box::new(EMRApp::new(emr)),
+        Err(e) => Box::new(StartupError(format!("{:#}", e))),
+    };
+
     eframe::run_native(
         "Charcot EMR",
         native_options,
-        Box::new(|_cc| Box::new(EMRApp::default()))
+        Box::new(move |_cc| app)
     )
 }
 
+// Shown instead of the EMR when it cannot be opened
+struct StartupError(String);
+
+impl eframe::App for StartupError {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Charcot EMR could not start");
+            ui.colored_label(egui::Color32::RED, &self.0);
+            ui.label("Fix the file named above and start Charcot EMR again.");
+        });
+    }
+}
+
 struct EMRApp {
     emr: Arc<Mutex<EMR>>,
     current_patient_id: String,
@@ -35,10 +62,19 @@ struct EMRApp {
     new_patient: PatientForm,
     vital_signs: VitalSignsForm,
     medication: MedicationForm,
+    allergy: AllergyForm,
+    problem: ProblemForm,
+    medication_change: Option<MedicationChangeForm>,  // Open change dialog
+    reconciliation: ReconciliationForm,
+    history: HistoryForm,
+    sign_in: SignInForm,
     
     // View state
     current_view: View,
     load_path: String,
+    show_medication_history: bool,
+    mar_day: NaiveDate,
+    mar_performer: String,  // Who is giving doses on the MAR
 }
 
 impl eframe::App for EMRApp {
@@ -50,6 +86,15 @@ impl eframe::App for EMRApp {
         egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.label(&self.status_message);
+                if let Ok(emr) = self.emr.lock() {
+                    let signer = match &emr.signer {
+                        Some(signer) => format!("Signing as {}", signer.clinician),
+                        None => "Not signing commits".to_string(),
+                    };
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        ui.label(signer);
+                    });
+                }
             });
         });
 
@@ -59,8 +104,14 @@ impl eframe::App for EMRApp {
                 View::CreatePatient => self.render_create_patient_view(ui),
                 View::AddVitals => self.render_add_vitals_view(ui),
                 View::Prescribe => self.render_prescribe_view(ui),
+                View::AddAllergy => self.render_add_allergy_view(ui),
+                View::AddProblem => self.render_add_problem_view(ui),
+                View::Mar => self.render_mar_view(ui),
+                View::Reconcile => self.render_reconcile_view(ui),
+                View::History => self.render_history_view(ui),
                 View::ViewPatient => self.render_view_patient(ui),
                 View::LoadPatient => self.render_load_patient_view(ui),
+                View::SignIn => self.render_sign_in_view(ui),
             }
         });
     }
@@ -78,6 +129,10 @@ impl EMRApp {
                     self.current_view = View::LoadPatient;
                     ui.close_menu();
                 }
+                if ui.button("Clinician Sign-In").clicked() {
+                    self.current_view = View::SignIn;
+                    ui.close_menu();
+                }
                 if ui.button("Exit").clicked() {
                     std::process::exit(0);
                 }
@@ -97,6 +152,26 @@ impl EMRApp {
                         self.current_view = View::Prescribe;
                         ui.close_menu();
                     }
+                    if ui.button("Add Allergy").clicked() {
+                        self.current_view = View::AddAllergy;
+                        ui.close_menu();
+                    }
+                    if ui.button("Add Problem").clicked() {
+                        self.current_view = View::AddProblem;
+                        ui.close_menu();
+                    }
+                    if ui.button("Medication Administration").clicked() {
+                        self.current_view = View::Mar;
+                        ui.close_menu();
+                    }
+                    if ui.button("Reconcile Medications").clicked() {
+                        self.current_view = View::Reconcile;
+                        ui.close_menu();
+                    }
+                    if ui.button("Version History").clicked() {
+                        self.current_view = View::History;
+                        ui.close_menu();
+                    }
                 });
             }
             
@@ -251,26 +326,62 @@ impl EMRApp {
         ui.add_space(10.0);
         
         ui.horizontal(|ui| {
-            ui.label("Blood Pressure - Systolic: ");
-            ui.text_edit_singleline(&mut self.vital_signs.systolic);
+            ui.label("Vital Sign: ");
+            let selected = self.vital_signs.kind.map(|k| k.display()).unwrap_or("Blood pressure");
+            egui::ComboBox::from_id_source("vital_combo")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.vital_signs.kind, None, "Blood pressure");
+                    for kind in VitalKind::ALL {
+                        ui.selectable_value(&mut self.vital_signs.kind, Some(kind), kind.display());
+                    }
+                });
         });
         
+        if let Some(kind) = self.vital_signs.kind {
+            ui.horizontal(|ui| {
+                ui.label(format!("{}: ", kind.display()));
+                ui.text_edit_singleline(&mut self.vital_signs.value);
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Unit: ");
+                ui.add(TextEdit::singleline(&mut self.vital_signs.unit).hint_text(kind.unit().1));
+            });
+        } else {
+            ui.horizontal(|ui| {
+                ui.label("Blood Pressure - Systolic: ");
+                ui.text_edit_singleline(&mut self.vital_signs.systolic);
+            });
+            
+            ui.horizontal(|ui| {
+                ui.label("Blood Pressure - Diastolic: ");
+                ui.text_edit_singleline(&mut self.vital_signs.diastolic);
+            });
+        }
+        
         ui.horizontal(|ui| {
-            ui.label("Blood Pressure - Diastolic: ");
-            ui.text_edit_singleline(&mut self.vital_signs.diastolic);
+            ui.label("Taken at (blank for now): ");
+            ui.add(TextEdit::singleline(&mut self.vital_signs.taken_at).hint_text("2025-04-08T08:30:00"));
         });
         
         ui.add_space(10.0);
         
         if ui.button("Add Vital Signs").clicked() {
-            if self.vital_signs.systolic.is_empty() || self.vital_signs.diastolic.is_empty() {
+            if let Some(kind) = self.vital_signs.kind {
+                self.add_single_vital(kind);
+            } else if self.vital_signs.systolic.is_empty() || self.vital_signs.diastolic.is_empty() {
                 self.status_message = "Error: Both systolic and diastolic values are required".to_string();
             } else {
-                match (self.vital_signs.systolic.parse::<i32>(), self.vital_signs.diastolic.parse::<i32>()) {
-                    (Ok(systolic), Ok(diastolic)) => {
+                match (self.vital_signs.systolic.parse::<i32>(), self.vital_signs.diastolic.parse::<i32>(),
+                       form_time(&self.vital_signs.taken_at)) {
+                    (_, _, Err(e)) => {
+                        self.status_message = format!("Error: {}", e);
+                    },
+                    (Ok(systolic), Ok(diastolic), Ok(effective)) => {
                         match self.emr.lock() {
                             Ok(mut emr) => {
-                                match emr.add_blood_pressure(&self.current_patient_id, systolic, diastolic) {
+                                match emr.add_blood_pressure_at(&self.current_patient_id, systolic, diastolic, effective) {
                                     Ok(_) => {
                                         match emr.commit_changes(&self.current_patient_id, &format!("Added BP: {}/{}", systolic, diastolic)) {
                                             Ok(_) => {
@@ -313,6 +424,58 @@ impl EMRApp {
         }
     }
     
+    fn add_single_vital(&mut self, kind: VitalKind) {
+        // Values in another unit (e.g. [lb_av] or [degF]) are converted
+        let value = self.vital_signs.value.trim().parse::<f64>()
+            .map_err(|_| anyhow!("{} must be a number", kind))
+            .and_then(|value| match self.vital_signs.unit.trim() {
+                "" => Ok(value),
+                unit => Vital::from_quantity(kind, value, unit).map(|vital| vital.value),
+            });
+        match (value, form_time(&self.vital_signs.taken_at)) {
+            (_, Err(e)) => {
+                self.status_message = format!("Error: {}", e);
+            },
+            (Ok(value), Ok(effective)) => {
+                match self.emr.lock() {
+                    Ok(mut emr) => {
+                        let unit = kind.unit().0;
+                        match emr.add_vital_at(&self.current_patient_id, kind, value, effective) {
+                            Ok(_) => {
+                                match emr.commit_changes(&self.current_patient_id, &format!("Added {}: {} {}", kind, value, unit)) {
+                                    Ok(_) => {
+                                        match emr.save_patient(&self.current_patient_id, &self.patient_key) {
+                                            Ok(_) => {
+                                                self.status_message = format!("{} {} {} added successfully", kind, value, unit);
+                                                self.vital_signs = VitalSignsForm::default();
+                                                self.current_view = View::ViewPatient;
+                                            },
+                                            Err(e) => {
+                                                self.status_message = format!("Error saving patient: {}", e);
+                                            }
+                                        }
+                                    },
+                                    Err(e) => {
+                                        self.status_message = format!("Error committing changes: {}", e);
+                                    }
+                                }
+                            },
+                            Err(e) => {
+                                self.status_message = format!("Error adding {}: {}", kind.display().to_lowercase(), e);
+                            }
+                        }
+                    },
+                    Err(_) => {
+                        self.status_message = "Error accessing EMR".to_string();
+                    }
+                }
+            },
+            (Err(e), _) => {
+                self.status_message = format!("Error: {:#}", e);
+            }
+        }
+    }
+    
     fn render_prescribe_view(&mut self, ui: &mut Ui) {
         ui.heading("Prescribe Medication");
         ui.add_space(10.0);
@@ -325,122 +488,640 @@ impl EMRApp {
             ui.text_edit_singleline(&mut self.medication.name);
         });
         
+        // Suggest dictionary names while the typed one is unknown
+        if self.medication.suggested_for != self.medication.name {
+            self.medication.suggestions = match self.emr.lock() {
+                Ok(emr) if emr.drugs.lookup(&self.medication.name).is_none() => {
+                    emr.drugs.suggest(&self.medication.name, 5).into_iter().cloned().collect()
+                }
+                _ => Vec::new(),
+            };
+            self.medication.suggested_for = self.medication.name.clone();
+        }
+        if !self.medication.suggestions.is_empty() {
+            let mut picked = None;
+            ui.horizontal(|ui| {
+                ui.label("Did you mean: ");
+                for drug in &self.medication.suggestions {
+                    if ui.button(&drug.name).clicked() {
+                        picked = Some(drug.name.clone());
+                    }
+                }
+            });
+            if let Some(name) = picked {
+                self.medication.name = name;
+            }
+        }
+        ui.checkbox(&mut self.medication.unlisted, "Not in RxNorm - prescribe anyway, with an override reason");
+        
+        ui.horizontal(|ui| {
+            ui.label("Dose: ");
+            ui.radio_value(&mut self.medication.dose_basis, None, "Fixed");
+            ui.radio_value(&mut self.medication.dose_basis, Some(DoseBasis::Weight), "Per kg");
+            ui.radio_value(&mut self.medication.dose_basis, Some(DoseBasis::Bsa), "Per m2 BSA");
+        });
+        
         ui.horizontal(|ui| {
-            ui.label("Dose (mg): ");
+            match self.medication.dose_basis {
+                None => {
+                    ui.label("Dose (mg): ");
+                }
+                Some(basis) => {
+                    ui.label(format!("Dose ({}/{}): ", self.medication.dose_unit, basis.unit()));
+                }
+            }
             ui.text_edit_singleline(&mut self.medication.dose_mg);
+            if self.medication.dose_basis.is_some() {
+                egui::ComboBox::from_id_source("dose_unit_combo")
+                    .selected_text(&self.medication.dose_unit)
+                    .show_ui(ui, |ui| {
+                        for unit in DOSE_UNITS {
+                            ui.selectable_value(&mut self.medication.dose_unit, unit.to_string(), *unit);
+                        }
+                    });
+            }
+        });
+        
+        // Work the dose out from the latest measurements as it is typed
+        let mut calculated_dose = None;
+        if let (Some(basis), Ok(amount)) = (self.medication.dose_basis, self.medication.dose_mg.parse::<f64>()) {
+            let order = DoseOrder { amount, unit: self.medication.dose_unit.clone(), basis };
+            let at = form_time(&self.medication.written_at).unwrap_or_else(|_| Utc::now());
+            let calculation = match self.emr.lock() {
+                Ok(emr) if !self.medication.name.is_empty() => {
+                    Some(emr.calculate_dose(&self.current_patient_id, &self.medication.name, &order, at))
+                }
+                _ => None,
+            };
+            match calculation {
+                Some(Ok(calculation)) => {
+                    ui.label(format!("Calculated dose: {} {}", calculation.dose, calculation.unit));
+                    for input in &calculation.inputs {
+                        ui.small(input.to_string());
+                    }
+                    for step in &calculation.steps {
+                        ui.small(step.as_str());
+                    }
+                    if calculation.unit == "mg" {
+                        calculated_dose = Some(calculation.dose);
+                    }
+                }
+                Some(Err(e)) => {
+                    ui.colored_label(egui::Color32::RED, format!("Cannot calculate the dose: {}", e));
+                }
+                None => {}
+            }
+        }
+        
+        ui.horizontal(|ui| {
+            ui.label("Written at (blank for now): ");
+            ui.add(TextEdit::singleline(&mut self.medication.written_at).hint_text("2025-04-08T08:30:00"));
         });
         
         ui.horizontal(|ui| {
             ui.label("Frequency: ");
+            ui.add(TextEdit::singleline(&mut self.medication.frequency).hint_text("e.g. q8h, twice daily with meals"));
             egui::ComboBox::from_id_source("frequency_combo")
-                .selected_text(&self.medication.frequency)
+                .selected_text("Common")
                 .show_ui(ui, |ui| {
                     ui.selectable_value(&mut self.medication.frequency, "daily".to_string(), "Daily");
                     ui.selectable_value(&mut self.medication.frequency, "twice daily".to_string(), "Twice Daily");
                     ui.selectable_value(&mut self.medication.frequency, "three times daily".to_string(), "Three Times Daily");
+                    ui.selectable_value(&mut self.medication.frequency, "every 8 hours".to_string(), "Every 8 Hours");
+                    ui.selectable_value(&mut self.medication.frequency, "at bedtime".to_string(), "At Bedtime");
                     ui.selectable_value(&mut self.medication.frequency, "as needed".to_string(), "As Needed");
                 });
         });
         
+        // Show dose alerts as the prescription is typed
+        let dose = match self.medication.dose_basis {
+            None => self.medication.dose_mg.parse::<f64>().ok(),
+            Some(_) => calculated_dose,
+        };
+        let alerts = match (self.emr.lock(), dose) {
+            (Ok(emr), Some(dose)) => emr.check_dose(&self.medication.name, dose, &self.medication.frequency).unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        for alert in &alerts {
+            let color = match alert.severity {
+                DoseSeverity::HardStop => egui::Color32::RED,
+                DoseSeverity::Warning => egui::Color32::from_rgb(230, 160, 0),
+            };
+            ui.colored_label(color, alert.to_string());
+        }
+        let allergy_alerts = match self.emr.lock() {
+            Ok(emr) if !self.medication.name.is_empty() => {
+                emr.check_allergies(&self.current_patient_id, &self.medication.name).unwrap_or_default()
+            }
+            _ => Vec::new(),
+        };
+        for alert in &allergy_alerts {
+            let color = match alert.action {
+                AllergyAction::Warn => egui::Color32::from_rgb(230, 160, 0),
+                AllergyAction::RequireOverride | AllergyAction::Refuse => egui::Color32::RED,
+            };
+            let prefix = if alert.action == AllergyAction::Refuse { "Cannot prescribe - " } else { "" };
+            ui.colored_label(color, format!("{}{}", prefix, alert));
+        }
+        if alerts.iter().any(|alert| alert.severity == DoseSeverity::Warning)
+            || allergy_alerts.iter().any(|alert| alert.action == AllergyAction::RequireOverride) {
+            ui.horizontal(|ui| {
+                ui.label("Override reason: ");
+                ui.add(TextEdit::singleline(&mut self.medication.override_reason).hint_text("required to prescribe despite the warnings above"));
+            });
+        }
+        
         ui.add_space(10.0);
         
+        let mut submit = false;
         if ui.button("Prescribe Medication").clicked() {
-            if self.medication.name.is_empty() || self.medication.dose_mg.is_empty() {
-                self.status_message = "Error: Medication name and dose are required".to_string();
+            // Warn about interactions with the patient's other prescriptions first
+            let interactions = match self.emr.lock() {
+                Ok(emr) => emr.verify_interactions(&self.current_patient_id, &self.medication.name).unwrap_or_default(),
+                Err(_) => Vec::new(),
+            };
+            if interactions.is_empty() {
+                submit = true;
             } else {
-                match self.medication.dose_mg.parse::<f64>() {
-                    Ok(dose) => {
-                        match self.emr.lock() {
-                            Ok(mut emr) => {
-                                match emr.prescribe_medication(
+                self.medication.interactions = Some(interactions);
+            }
+        }
+        
+        if let Some(interactions) = self.medication.interactions.clone() {
+            let mut close = false;
+            egui::Window::new("Drug Interactions")
+                .collapsible(false)
+                .resizable(false)
+                .show(ui.ctx(), |ui| {
+                    for alert in &interactions {
+                        let color = if alert.is_severe() { egui::Color32::RED } else { egui::Color32::from_rgb(230, 160, 0) };
+                        ui.colored_label(color, alert.to_string());
+                    }
+                    if interactions.iter().any(InteractionAlert::is_severe) {
+                        ui.add_space(5.0);
+                        ui.horizontal(|ui| {
+                            ui.label("Override reason: ");
+                            ui.add(TextEdit::singleline(&mut self.medication.override_reason).hint_text("required for severe interactions"));
+                        });
+                    }
+                    ui.add_space(5.0);
+                    ui.horizontal(|ui| {
+                        if ui.button("Prescribe Anyway").clicked() {
+                            submit = true;
+                            close = true;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            close = true;
+                        }
+                    });
+                });
+            if close {
+                self.medication.interactions = None;
+            }
+        }
+        
+        if submit {
+            self.submit_prescription();
+        }
+        
+        if ui.button("Cancel").clicked() {
+            self.current_view = View::ViewPatient;
+            self.medication = MedicationForm::default();
+        }
+    }
+    
+    fn submit_prescription(&mut self) {
+        if self.medication.name.is_empty() || self.medication.dose_mg.is_empty() {
+            self.status_message = "Error: Medication name and dose are required".to_string();
+        } else {
+            match (self.medication.dose_mg.parse::<f64>(), form_time(&self.medication.written_at)) {
+                (_, Err(e)) => {
+                    self.status_message = format!("Error: {}", e);
+                },
+                (Ok(dose), Ok(authored)) => {
+                    match self.emr.lock() {
+                        Ok(mut emr) => {
+                            let options = PrescribeOptions {
+                                authored,
+                                unlisted: self.medication.unlisted,
+                                override_reason: Some(self.medication.override_reason.clone()),
+                            };
+                            // The amount prescribed, for the commit and status messages
+                            let prescribed = match self.medication.dose_basis {
+                                None => emr.prescribe_medication_with(
                                     &self.current_patient_id,
                                     &self.medication.name,
                                     dose,
-                                    &self.medication.frequency
-                                ) {
-                                    Ok(_) => {
-                                        match emr.commit_changes(&self.current_patient_id, &format!(
-                                            "Prescribed {} {}mg {}", 
-                                            self.medication.name, 
-                                            dose, 
-                                            self.medication.frequency
-                                        )) {
-                                            Ok(_) => {
-                                                match emr.save_patient(&self.current_patient_id, &self.patient_key) {
-                                                    Ok(_) => {
-                                                        self.status_message = format!(
-                                                            "Prescribed {} {}mg {} successfully", 
-                                                            self.medication.name, 
-                                                            dose, 
-                                                            self.medication.frequency
-                                                        );
-                                                        self.medication = MedicationForm::default();
-                                                        self.current_view = View::ViewPatient;
-                                                    },
-                                                    Err(e) => {
-                                                        self.status_message = format!("Error saving patient: {}", e);
-                                                    }
+                                    &self.medication.frequency,
+                                    &options
+                                ).map(|_| format!("{}mg", dose)),
+                                Some(basis) => {
+                                    let order = DoseOrder { amount: dose, unit: self.medication.dose_unit.clone(), basis };
+                                    emr.prescribe_calculated_dose(
+                                        &self.current_patient_id,
+                                        &self.medication.name,
+                                        &order,
+                                        &self.medication.frequency,
+                                        &options
+                                    ).map(|(_, calculation)| format!("{} {} ({})", calculation.dose, calculation.unit, order))
+                                }
+                            };
+                            match prescribed {
+                                Ok(amount) => {
+                                    match emr.commit_changes(&self.current_patient_id, &format!(
+                                        "Prescribed {} {} {}", 
+                                        self.medication.name, 
+                                        amount, 
+                                        self.medication.frequency
+                                    )) {
+                                        Ok(_) => {
+                                            match emr.save_patient(&self.current_patient_id, &self.patient_key) {
+                                                Ok(_) => {
+                                                    self.status_message = format!(
+                                                        "Prescribed {} {} {} successfully", 
+                                                        self.medication.name, 
+                                                        amount, 
+                                                        self.medication.frequency
+                                                    );
+                                                    self.medication = MedicationForm::default();
+                                                    self.current_view = View::ViewPatient;
+                                                },
+                                                Err(e) => {
+                                                    self.status_message = format!("Error saving patient: {}", e);
                                                 }
-                                            },
-                                            Err(e) => {
-                                                self.status_message = format!("Error committing changes: {}", e);
                                             }
+                                        },
+                                        Err(e) => {
+                                            self.status_message = format!("Error committing changes: {}", e);
                                         }
+                                    }
+                                },
+                                Err(e) => {
+                                    self.status_message = format!("Error prescribing medication: {}", e);
+                                }
+                            }
+                        },
+                        Err(_) => {
+                            self.status_message = "Error accessing EMR".to_string();
+                        }
+                    }
+                },
+                (Err(_), _) => {
+                    self.status_message = "Error: Dose must be a number".to_string();
+                }
+            }
+        }
+    }
+    
+    fn render_add_allergy_view(&mut self, ui: &mut Ui) {
+        ui.heading("Add Allergy");
+        ui.add_space(10.0);
+        
+        ui.label(format!("Patient ID: {}", self.current_patient_id));
+        ui.add_space(10.0);
+        
+        ui.horizontal(|ui| {
+            ui.label("Substance: ");
+            ui.add(TextEdit::singleline(&mut self.allergy.substance).hint_text("e.g. penicillins, sulfa, amoxicillin, peanuts"));
+        });
+        choice(ui, "Type", &mut self.allergy.allergy_type, ALLERGY_TYPES);
+        choice(ui, "Category", &mut self.allergy.category, CATEGORIES);
+        choice(ui, "Criticality", &mut self.allergy.criticality, CRITICALITIES);
+        choice(ui, "Verification", &mut self.allergy.status, VERIFICATION_STATUSES);
+        
+        ui.horizontal(|ui| {
+            ui.label("Reaction: ");
+            ui.add(TextEdit::singleline(&mut self.allergy.reaction).hint_text("e.g. hives"));
+            egui::ComboBox::from_id_source("reaction_severity")
+                .selected_text(if self.allergy.severity.is_empty() { "Severity" } else { &self.allergy.severity })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.allergy.severity, String::new(), "Not recorded");
+                    for severity in REACTION_SEVERITIES {
+                        ui.selectable_value(&mut self.allergy.severity, severity.to_string(), *severity);
+                    }
+                });
+        });
+        
+        ui.horizontal(|ui| {
+            ui.label("Recorded at (blank for now): ");
+            ui.add(TextEdit::singleline(&mut self.allergy.recorded_at).hint_text("2025-04-08T08:30:00"));
+        });
+        
+        ui.add_space(10.0);
+        
+        if ui.button("Add Allergy").clicked() {
+            self.add_allergy();
+        }
+        
+        if ui.button("Cancel").clicked() {
+            self.current_view = View::ViewPatient;
+            self.allergy = AllergyForm::default();
+        }
+    }
+    
+    fn add_allergy(&mut self) {
+        let recorded = match form_time(&self.allergy.recorded_at) {
+            Ok(recorded) => recorded,
+            Err(e) => {
+                self.status_message = format!("Error: {}", e);
+                return;
+            }
+        };
+        let optional = |text: &str| Some(text.trim().to_string()).filter(|text| !text.is_empty());
+        let allergy = NewAllergy {
+            substance: self.allergy.substance.clone(),
+            allergy_type: self.allergy.allergy_type.clone(),
+            category: self.allergy.category.clone(),
+            criticality: self.allergy.criticality.clone(),
+            verification_status: self.allergy.status.clone(),
+            reaction: optional(&self.allergy.reaction),
+            reaction_severity: optional(&self.allergy.severity),
+            recorded,
+        };
+        match self.emr.lock() {
+            Ok(mut emr) => {
+                match emr.add_allergy(&self.current_patient_id, &allergy) {
+                    Ok(_) => {
+                        let message = format!("Added {}: {}", allergy.allergy_type, allergy.substance);
+                        match emr.commit_changes(&self.current_patient_id, &message) {
+                            Ok(_) => {
+                                match emr.save_patient(&self.current_patient_id, &self.patient_key) {
+                                    Ok(_) => {
+                                        self.status_message = format!("{} successfully", message);
+                                        self.allergy = AllergyForm::default();
+                                        self.current_view = View::ViewPatient;
                                     },
                                     Err(e) => {
-                                        self.status_message = format!("Error prescribing medication: {}", e);
+                                        self.status_message = format!("Error saving patient: {}", e);
                                     }
                                 }
                             },
-                            Err(_) => {
-                                self.status_message = "Error accessing EMR".to_string();
+                            Err(e) => {
+                                self.status_message = format!("Error committing changes: {}", e);
                             }
                         }
                     },
-                    Err(_) => {
-                        self.status_message = "Error: Dose must be a number".to_string();
+                    Err(e) => {
+                        self.status_message = format!("Error adding allergy: {}", e);
                     }
                 }
+            },
+            Err(_) => {
+                self.status_message = "Error accessing EMR".to_string();
+            }
+        }
+    }
+    
+    fn render_add_problem_view(&mut self, ui: &mut Ui) {
+        ui.heading("Add Problem");
+        ui.add_space(10.0);
+        
+        ui.label(format!("Patient ID: {}", self.current_patient_id));
+        ui.add_space(10.0);
+        
+        ui.horizontal(|ui| {
+            ui.label("ICD-10-CM: ");
+            ui.add(TextEdit::singleline(&mut self.problem.code).hint_text("code or description, e.g. E11.9 or diabetes"));
+        });
+        
+        // Look the code up as it is typed, offering matches from the code table
+        let mut picked = None;
+        if let Ok(emr) = self.emr.lock() {
+            let text = self.problem.code.trim();
+            match emr.condition_codes.icd10_coding(text) {
+                Ok(coding) => {
+                    ui.label(format!("{} {}", coding.code, coding.display));
+                },
+                Err(_) if text.len() >= 2 => {
+                    for code in emr.condition_codes.icd10.search(text, 8) {
+                        if ui.small_button(format!("{} {}", code.code, code.display)).clicked() {
+                            picked = Some(code.code);
+                        }
+                    }
+                },
+                Err(_) => {}
             }
         }
+        if let Some(code) = picked {
+            self.problem.code = code;
+        }
+        
+        ui.horizontal(|ui| {
+            ui.label("SNOMED CT (optional): ");
+            ui.add(TextEdit::singleline(&mut self.problem.snomed).hint_text("e.g. 44054006"));
+        });
+        choice(ui, "Clinical status", &mut self.problem.status, CLINICAL_STATUSES);
+        choice(ui, "Verification status", &mut self.problem.verification, conditions::VERIFICATION_STATUSES);
+        
+        ui.horizontal(|ui| {
+            ui.label("Onset (optional): ");
+            ui.add(TextEdit::singleline(&mut self.problem.onset).hint_text("2019-05-01"));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Note: ");
+            ui.text_edit_singleline(&mut self.problem.note);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Recorded at (blank for now): ");
+            ui.add(TextEdit::singleline(&mut self.problem.recorded_at).hint_text("2025-04-08T08:30:00"));
+        });
+        
+        ui.add_space(10.0);
+        
+        if ui.button("Add Problem").clicked() {
+            self.add_problem();
+        }
         
         if ui.button("Cancel").clicked() {
             self.current_view = View::ViewPatient;
-            self.medication = MedicationForm::default();
+            self.problem = ProblemForm::default();
         }
     }
     
-    fn render_view_patient(&mut self, ui: &mut Ui) {
-        ui.heading("Patient Record");
-        ui.add_space(10.0);
-        
+    fn add_problem(&mut self) {
+        let optional = |text: &str| Some(text.trim().to_string()).filter(|text| !text.is_empty());
+        let onset = match optional(&self.problem.onset).map(|text| parse_clinical_time(&text)).transpose() {
+            Ok(onset) => onset,
+            Err(e) => {
+                self.status_message = format!("Error: {}", e);
+                return;
+            }
+        };
+        let recorded = match form_time(&self.problem.recorded_at) {
+            Ok(recorded) => recorded,
+            Err(e) => {
+                self.status_message = format!("Error: {}", e);
+                return;
+            }
+        };
+        let condition = NewCondition {
+            icd10: self.problem.code.clone(),
+            snomed: optional(&self.problem.snomed),
+            clinical_status: self.problem.status.clone(),
+            verification_status: self.problem.verification.clone(),
+            onset,
+            recorded,
+            note: optional(&self.problem.note),
+        };
         match self.emr.lock() {
-            Ok(emr) => {
-                if let Some(bundle) = emr.bundles.get(&self.current_patient_id) {
-                    // Display patient information
-                    ui.heading("Patient Information");
-                    
-                    if let Some(entry) = bundle.entry.iter().find(|e| {
-                        matches!(e.resource, Resource::Patient(_))
-                    }) {
-                        if let Resource::Patient(patient) = &entry.resource {
-                            if let Some(name) = patient.name.first() {
-                                let given = name.given.join(" ");
-                                let family = name.family.clone().unwrap_or_default();
-                                ui.label(format!("Name: {} {}", given, family));
+            Ok(mut emr) => {
+                match emr.add_condition(&self.current_patient_id, &condition) {
+                    Ok(_) => {
+                        let message = format!("Added condition: {}", condition.icd10.trim());
+                        match emr.commit_changes(&self.current_patient_id, &message) {
+                            Ok(_) => {
+                                match emr.save_patient(&self.current_patient_id, &self.patient_key) {
+                                    Ok(_) => {
+                                        self.status_message = format!("{} successfully", message);
+                                        self.problem = ProblemForm::default();
+                                        self.current_view = View::ViewPatient;
+                                    },
+                                    Err(e) => {
+                                        self.status_message = format!("Error saving patient: {}", e);
+                                    }
+                                }
+                            },
+                            Err(e) => {
+                                self.status_message = format!("Error committing changes: {}", e);
                             }
-                            ui.label(format!("Gender: {}", patient.gender));
-                            ui.label(format!("Birth Date: {}", patient.birth_date));
-                            ui.add_space(10.0);
                         }
+                    },
+                    Err(e) => {
+                        self.status_message = format!("Error adding problem: {}", e);
                     }
-                    
-                    // Display vital signs
-                    ui.collapsing("Vital Signs", |ui| {
-                        let observations = bundle.entry.iter()
-                            .filter_map(|e| {
+                }
+            },
+            Err(_) => {
+                self.status_message = "Error accessing EMR".to_string();
+            }
+        }
+    }
+    
+    // Mark the Condition `id` as resolved now
+    fn resolve_problem(&mut self, id: &str) {
+        match self.emr.lock() {
+            Ok(mut emr) => {
+                match emr.resolve_condition(&self.current_patient_id, id, Utc::now()) {
+                    Ok(_) => {
+                        match emr.commit_changes(&self.current_patient_id, &format!("Resolved condition: {}", id)) {
+                            Ok(_) => {
+                                match emr.save_patient(&self.current_patient_id, &self.patient_key) {
+                                    Ok(_) => {
+                                        self.status_message = "Problem resolved successfully".to_string();
+                                    },
+                                    Err(e) => {
+                                        self.status_message = format!("Error saving patient: {}", e);
+                                    }
+                                }
+                            },
+                            Err(e) => {
+                                self.status_message = format!("Error committing changes: {}", e);
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        self.status_message = format!("Error resolving problem: {}", e);
+                    }
+                }
+            },
+            Err(_) => {
+                self.status_message = "Error accessing EMR".to_string();
+            }
+        }
+    }
+    
+    fn render_view_patient(&mut self, ui: &mut Ui) {
+        ui.heading("Patient Record");
+        ui.add_space(10.0);
+        
+        let mut resolve = None;
+        let mut change = None;
+        match self.emr.lock() {
+            Ok(emr) => {
+                if let Some(bundle) = emr.bundles.get(&self.current_patient_id) {
+                    // Display patient information
+                    ui.heading("Patient Information");
+                    
+                    if let Some(entry) = bundle.entry.iter().find(|e| {
+                        matches!(e.resource, Resource::Patient(_))
+                    }) {
+                        if let Resource::Patient(patient) = &entry.resource {
+                            if let Some(name) = patient.name.first() {
+                                let given = name.given.join(" ");
+                                let family = name.family.clone().unwrap_or_default();
+                                ui.label(format!("Name: {} {}", given, family));
+                            }
+                            ui.label(format!("Gender: {}", patient.gender));
+                            ui.label(format!("Birth Date: {}", patient.birth_date));
+                            ui.add_space(10.0);
+                        }
+                    }
+                    
+                    // Allergy banner
+                    let allergies = emr.allergies(&self.current_patient_id).unwrap_or_default();
+                    let fill = if allergies.is_empty() {
+                        egui::Color32::from_rgb(225, 240, 225)
+                    } else {
+                        egui::Color32::from_rgb(255, 215, 215)
+                    };
+                    egui::Frame::none().fill(fill).inner_margin(egui::Margin::same(6.0)).show(ui, |ui| {
+                        if allergies.is_empty() {
+                            ui.label("No known allergies");
+                        } else {
+                            ui.strong("ALLERGIES");
+                            for allergy in &allergies {
+                                let reactions: Vec<&str> = allergy.reaction.iter().map(|r| r.manifestation.as_str()).collect();
+                                let text = format!("{} ({}, criticality {}, {}){}", allergy.code.display, allergy.allergy_type,
+                                                   allergy.criticality, allergy.verification_status,
+                                                   if reactions.is_empty() { String::new() } else { format!(": {}", reactions.join(", ")) });
+                                if allergy.criticality == "high" {
+                                    ui.colored_label(egui::Color32::RED, text);
+                                } else {
+                                    ui.label(text);
+                                }
+                            }
+                        }
+                    });
+                    ui.add_space(10.0);
+                    
+                    // Display the problem list, current problems first
+                    ui.collapsing("Problem List", |ui| {
+                        let mut problems = emr.conditions(&self.current_patient_id).unwrap_or_default();
+                        problems.sort_by_key(|c| !conditions::is_current(c));
+                        
+                        if problems.is_empty() {
+                            ui.label("No problems recorded");
+                        } else {
+                            for condition in problems {
+                                ui.horizontal(|ui| {
+                                    let mut text = format!("{} {} ({}, {})", condition.code.code, condition.code.display,
+                                                           condition.clinical_status, condition.verification_status);
+                                    if let Some(onset) = &condition.onset_date_time {
+                                        text.push_str(&format!(", onset {}", onset));
+                                    }
+                                    if let Some(abatement) = &condition.abatement_date_time {
+                                        text.push_str(&format!(", resolved {}", abatement));
+                                    }
+                                    if conditions::is_current(condition) {
+                                        ui.label(text);
+                                        if ui.small_button("Resolve").clicked() {
+                                            resolve = Some(condition.id.clone());
+                                        }
+                                    } else {
+                                        ui.weak(text);
+                                    }
+                                });
+                            }
+                        }
+                    });
+                    
+                    // Display vital signs
+                    ui.collapsing("Vital Signs", |ui| {
+                        let observations = bundle.entry.iter()
+                            .filter_map(|e| {
                                 if let Resource::Observation(obs) = &e.resource {
-                                    if obs.code.display.contains("Blood pressure") {
+                                    if obs.code.display.contains("Blood pressure")
+                                        || VitalKind::from_loinc(&obs.code.code).is_some() {
                                         return Some(obs);
                                     }
                                 }
@@ -465,13 +1146,50 @@ impl EMRApp {
                                     
                                     ui.label(format!("{} - BP: {}/{} mmHg", 
                                         obs.effective_date_time, systolic, diastolic));
+                                } else if let Some(quantity) = &obs.value_quantity {
+                                    ui.label(format!("{} - {}: {} {}",
+                                        obs.effective_date_time, obs.code.display, quantity.value, quantity.unit));
                                 }
                             }
                         }
                     });
                     
-                    // Display medications
+                    // Display lab results with their flags
+                    ui.collapsing("Lab Results", |ui| {
+                        let labs = bundle.entry.iter()
+                            .filter_map(|e| match &e.resource {
+                                Resource::Observation(obs) if obs.reference_range.is_some() => Some(obs),
+                                _ => None,
+                            })
+                            .collect::<Vec<_>>();
+                        
+                        if labs.is_empty() {
+                            ui.label("No lab results recorded");
+                        } else {
+                            for obs in labs {
+                                let value = obs.value_quantity.as_ref()
+                                    .map(|q| format!("{} {}", q.value, q.unit))
+                                    .unwrap_or_else(|| "N/A".to_string());
+                                let flag = obs.interpretation.iter().flatten()
+                                    .map(|i| i.display.clone())
+                                    .next()
+                                    .unwrap_or_default();
+                                let reference = obs.reference_range.iter().flatten()
+                                    .filter_map(|r| r.text.clone())
+                                    .next()
+                                    .unwrap_or_default();
+                                let note = obs.note.iter().flatten()
+                                    .map(|n| format!(" ({})", n.text))
+                                    .collect::<String>();
+                                ui.label(format!("{} - {}: {} [{}] ref {}{}",
+                                    obs.effective_date_time, obs.code.display, value, flag, reference, note));
+                            }
+                        }
+                    });
+                    
+                    // Display medications, current ones only unless history is asked for
                     ui.collapsing("Medications", |ui| {
+                        ui.checkbox(&mut self.show_medication_history, "Show stopped and replaced prescriptions");
                         let medications = bundle.entry.iter()
                             .filter_map(|e| {
                                 if let Resource::MedicationRequest(med) = &e.resource {
@@ -479,18 +1197,42 @@ impl EMRApp {
                                 }
                                 None
                             })
+                            .filter(|med| self.show_medication_history || medications::is_current(med))
                             .collect::<Vec<_>>();
                         
                         if medications.is_empty() {
-                            ui.label("No medications prescribed");
+                            ui.label("No current medications");
                         } else {
                             for med in medications {
                                 let dosage_text = med.dosage_instruction.first()
                                     .map(|d| d.text.clone())
                                     .unwrap_or_else(|| "No dosage information".to_string());
+                                let text = format!("{} - {}: {}", 
+                                    med.authored_on, med.medication_codeable_concept.display, dosage_text);
                                 
-                                ui.label(format!("{} - {}: {}", 
-                                    med.authored_on, med.medication_codeable_concept.display, dosage_text));
+                                ui.horizontal(|ui| {
+                                    if !medications::is_current(med) {
+                                        ui.weak(format!("{} ({})", text, med.status));
+                                        return;
+                                    }
+                                    if med.status == "active" {
+                                        ui.label(text);
+                                    } else {
+                                        ui.colored_label(egui::Color32::from_rgb(230, 160, 0), format!("{} (on hold)", text));
+                                    }
+                                    let mut actions = vec![ChangeAction::Stop, ChangeAction::Change, ChangeAction::Renew];
+                                    actions.push(if med.status == "active" { ChangeAction::Hold } else { ChangeAction::Resume });
+                                    for action in actions {
+                                        if ui.small_button(action.label()).clicked() {
+                                            change = Some(MedicationChangeForm::new(action, med, &emr.user));
+                                        }
+                                    }
+                                });
+                                if self.show_medication_history {
+                                    for status in &med.status_history {
+                                        ui.weak(format!("    {} {} by {}: {}", status.date, status.status, status.author, status.reason));
+                                    }
+                                }
                             }
                         }
                     });
@@ -498,8 +1240,7 @@ impl EMRApp {
                     // Display version history
                     ui.collapsing("Version History", |ui| {
                         for (i, version) in bundle.version_history.iter().enumerate() {
-                            ui.label(format!("Version {}: {} - {}", 
-                                i+1, version.timestamp, version.message));
+                            ui.label(format!("Version {}: {}", i+1, version));
                         }
                     });
                     
@@ -513,6 +1254,26 @@ impl EMRApp {
                         if ui.button("Prescribe Medication").clicked() {
                             self.current_view = View::Prescribe;
                         }
+                        
+                        if ui.button("Add Allergy").clicked() {
+                            self.current_view = View::AddAllergy;
+                        }
+                        
+                        if ui.button("Add Problem").clicked() {
+                            self.current_view = View::AddProblem;
+                        }
+                        
+                        if ui.button("Medication Administration").clicked() {
+                            self.current_view = View::Mar;
+                        }
+                        
+                        if ui.button("Reconcile Medications").clicked() {
+                            self.current_view = View::Reconcile;
+                        }
+                        
+                        if ui.button("Version History").clicked() {
+                            self.current_view = View::History;
+                        }
                     });
                 } else {
                     ui.label(format!("No data found for patient ID: {}", self.current_patient_id));
@@ -523,11 +1284,709 @@ impl EMRApp {
             }
         }
         
+        if let Some(id) = resolve {
+            self.resolve_problem(&id);
+        }
+        if change.is_some() {
+            self.medication_change = change;
+        }
+        self.render_medication_change(ui);
+        
+        if ui.button("Back to Home").clicked() {
+            self.current_view = View::Home;
+        }
+    }
+    
+    // Medication administration record: one row per prescription, one
+    // column per scheduled time of the chosen day
+    fn render_mar_view(&mut self, ui: &mut Ui) {
+        ui.heading("Medication Administration Record");
+        ui.add_space(10.0);
+        
+        ui.label(format!("Patient ID: {}", self.current_patient_id));
+        ui.horizontal(|ui| {
+            if ui.button("< Previous Day").clicked() {
+                self.mar_day -= Duration::days(1);
+            }
+            ui.strong(self.mar_day.format("%A %Y-%m-%d").to_string());
+            if ui.button("Next Day >").clicked() {
+                self.mar_day += Duration::days(1);
+            }
+            if ui.button("Today").clicked() {
+                self.mar_day = Local::now().date_naive();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Given by: ");
+            ui.add(TextEdit::singleline(&mut self.mar_performer).hint_text("your name"));
+        });
+        ui.add_space(10.0);
+        
+        let start = Local.from_local_datetime(&self.mar_day.and_hms_opt(0, 0, 0).unwrap_or_default())
+            .earliest()
+            .map(|time| time.with_timezone(&Utc))
+            .unwrap_or_else(Utc::now);
+        let end = start + Duration::days(1) - Duration::seconds(1);
+        let now = Utc::now();
+        let mut record = None;
+        
+        match self.emr.lock() {
+            Ok(emr) => {
+                let doses = emr.scheduled_doses(&self.current_patient_id, start, end).unwrap_or_default();
+                let mut times: Vec<DateTime<Utc>> = doses.iter().map(|dose| dose.scheduled).collect();
+                times.sort();
+                times.dedup();
+                let mut rows: Vec<(String, String)> = Vec::new();
+                for dose in &doses {
+                    if !rows.iter().any(|(id, _)| *id == dose.request_id) {
+                        let amount = dose.dose.as_ref().map(|q| format!(" {} {}", q.value, q.unit)).unwrap_or_default();
+                        rows.push((dose.request_id.clone(), format!("{}{}", dose.medication, amount)));
+                    }
+                }
+                
+                if doses.is_empty() {
+                    ui.label("No doses scheduled on this day");
+                } else {
+                    egui::Grid::new("mar_grid").striped(true).spacing([12.0, 6.0]).show(ui, |ui| {
+                        ui.strong("Medication");
+                        for time in &times {
+                            ui.strong(time.with_timezone(&Local).format("%H:%M").to_string());
+                        }
+                        ui.end_row();
+                        
+                        for (request_id, label) in &rows {
+                            ui.label(label);
+                            for time in &times {
+                                let Some(dose) = doses.iter().find(|d| d.request_id == *request_id && d.scheduled == *time) else {
+                                    ui.label("");
+                                    continue;
+                                };
+                                match (&dose.administration, dose.outcome()) {
+                                    (Some(administration), Some(outcome)) => {
+                                        let color = match outcome {
+                                            AdministrationOutcome::Given => egui::Color32::from_rgb(0, 150, 0),
+                                            _ => egui::Color32::RED,
+                                        };
+                                        let at = DateTime::parse_from_rfc3339(&administration.effective_date_time)
+                                            .map(|t| t.with_timezone(&Local).format("%H:%M").to_string())
+                                            .unwrap_or_default();
+                                        ui.colored_label(color, format!("{} {}", outcome, at))
+                                            .on_hover_text(administration.performer.clone().unwrap_or_default());
+                                    },
+                                    _ => {
+                                        let label = if dose.scheduled + Duration::minutes(ADMINISTRATION_WINDOW_MINUTES) < now {
+                                            "overdue"
+                                        } else {
+                                            "due"
+                                        };
+                                        ui.menu_button(label, |ui| {
+                                            for outcome in [AdministrationOutcome::Given, AdministrationOutcome::Missed, AdministrationOutcome::Refused] {
+                                                if ui.button(format!("Record {}", outcome)).clicked() {
+                                                    record = Some((request_id.clone(), Some(dose.scheduled), outcome));
+                                                    ui.close_menu();
+                                                }
+                                            }
+                                        });
+                                    }
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+                }
+                
+                // As-needed prescriptions have no schedule and are given on request
+                let as_needed: Vec<(String, String)> = emr.medication_requests(&self.current_patient_id).unwrap_or_default()
+                    .into_iter()
+                    .filter(|request| request.status == "active")
+                    .filter(|request| request.dosage_instruction.first().and_then(|d| d.as_needed_boolean) == Some(true))
+                    .map(|request| (request.id.clone(), format!("{} {}", request.medication_codeable_concept.display,
+                        request.dosage_instruction.first().map(|d| d.text.as_str()).unwrap_or_default())))
+                    .collect();
+                if !as_needed.is_empty() {
+                    ui.add_space(10.0);
+                    ui.strong("As needed");
+                    for (request_id, label) in as_needed {
+                        ui.horizontal(|ui| {
+                            ui.label(label);
+                            if ui.small_button("Give Now").clicked() {
+                                record = Some((request_id.clone(), None, AdministrationOutcome::Given));
+                            }
+                        });
+                    }
+                }
+                
+                ui.add_space(10.0);
+                ui.collapsing("Adherence (last 30 days)", |ui| {
+                    let adherence = emr.adherence(&self.current_patient_id, now - Duration::days(30), now).unwrap_or_default();
+                    if adherence.is_empty() {
+                        ui.label("No doses scheduled");
+                    }
+                    for entry in adherence {
+                        ui.label(entry.to_string());
+                    }
+                });
+            },
+            Err(_) => {
+                ui.label("Error accessing EMR");
+            }
+        }
+        
+        if let Some((request_id, scheduled, outcome)) = record {
+            self.record_dose(&request_id, scheduled, outcome);
+        }
+        
+        ui.add_space(10.0);
+        if ui.button("Back to Patient").clicked() {
+            self.current_view = View::ViewPatient;
+        }
+    }
+    
+    fn record_dose(&mut self, request_id: &str, scheduled: Option<DateTime<Utc>>, outcome: AdministrationOutcome) {
+        let administration = NewAdministration {
+            outcome,
+            scheduled,
+            at: Utc::now(),
+            dose: None,
+            dose_unit: None,
+            performer: self.mar_performer.clone(),
+            note: None,
+        };
+        match self.emr.lock() {
+            Ok(mut emr) => {
+                match emr.record_administration(&self.current_patient_id, request_id, &administration) {
+                    Ok(_) => {
+                        let message = format!("Recorded {} dose", outcome);
+                        match emr.commit_changes(&self.current_patient_id, &message) {
+                            Ok(_) => {
+                                match emr.save_patient(&self.current_patient_id, &self.patient_key) {
+                                    Ok(_) => {
+                                        self.status_message = format!("{} successfully", message);
+                                    },
+                                    Err(e) => {
+                                        self.status_message = format!("Error saving patient: {}", e);
+                                    }
+                                }
+                            },
+                            Err(e) => {
+                                self.status_message = format!("Error committing changes: {}", e);
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        self.status_message = format!("Error recording dose: {}", e);
+                    }
+                }
+            },
+            Err(_) => {
+                self.status_message = "Error accessing EMR".to_string();
+            }
+        }
+    }
+    
+    // Dialog asking why a prescription is being changed
+    fn render_medication_change(&mut self, ui: &mut Ui) {
+        let Some(form) = &mut self.medication_change else {
+            return;
+        };
+        let mut submit = false;
+        let mut close = false;
+        egui::Window::new(format!("{} Medication", form.action.label()))
+            .collapsible(false)
+            .resizable(false)
+            .show(ui.ctx(), |ui| {
+                ui.label(&form.description);
+                ui.add_space(5.0);
+                if form.action == ChangeAction::Change {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("New dose ({}): ", form.dose_unit));
+                        ui.text_edit_singleline(&mut form.dose_mg);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("New frequency: ");
+                        ui.text_edit_singleline(&mut form.frequency);
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Reason: ");
+                    ui.text_edit_singleline(&mut form.reason);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Changed by: ");
+                    ui.text_edit_singleline(&mut form.author);
+                });
+                if matches!(form.action, ChangeAction::Change | ChangeAction::Resume) {
+                    ui.horizontal(|ui| {
+                        ui.label("Override reason: ");
+                        ui.add(TextEdit::singleline(&mut form.override_reason)
+                            .hint_text("only for dose warnings, allergies or severe interactions"));
+                    });
+                }
+                ui.add_space(5.0);
+                ui.horizontal(|ui| {
+                    if ui.button(form.action.label()).clicked() {
+                        submit = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        close = true;
+                    }
+                });
+            });
+        if submit {
+            self.change_medication();
+        } else if close {
+            self.medication_change = None;
+        }
+    }
+    
+    fn change_medication(&mut self) {
+        let Some(form) = self.medication_change.clone() else {
+            return;
+        };
+        let change = MedicationChange::new(&form.reason, &form.author);
+        let override_reason = Some(form.override_reason.as_str()).filter(|reason| !reason.trim().is_empty());
+        match self.emr.lock() {
+            Ok(mut emr) => {
+                let patient_id = &self.current_patient_id;
+                let changed = match form.action {
+                    ChangeAction::Stop => emr.discontinue_medication(patient_id, &form.request_id, &change),
+                    ChangeAction::Hold => emr.hold_medication(patient_id, &form.request_id, &change),
+                    ChangeAction::Resume => emr.resume_medication(patient_id, &form.request_id, &change, override_reason),
+                    ChangeAction::Renew => emr.renew_medication(patient_id, &form.request_id, &change).map(|_| ()),
+                    ChangeAction::Change => match form.dose_mg.trim().parse::<f64>() {
+                        Ok(dose) => {
+                            emr.modify_medication(patient_id, &form.request_id, dose, &form.frequency, &change, override_reason)
+                                .map(|_| ())
+                        },
+                        Err(_) => Err(anyhow!("Dose must be a number")),
+                    },
+                };
+                match changed {
+                    Ok(_) => {
+                        match emr.save_patient(&self.current_patient_id, &self.patient_key) {
+                            Ok(_) => {
+                                self.status_message = format!("{} {} successfully", form.action.past_tense(), form.description);
+                                self.medication_change = None;
+                            },
+                            Err(e) => {
+                                self.status_message = format!("Error saving patient: {}", e);
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        self.status_message = format!("Error changing medication: {}", e);
+                    }
+                }
+            },
+            Err(_) => {
+                self.status_message = "Error accessing EMR".to_string();
+            }
+        }
+    }
+    
+    // Outside medication list and current prescriptions side by side, with a
+    // decision for each
+    fn render_reconcile_view(&mut self, ui: &mut Ui) {
+        ui.heading("Medication Reconciliation");
+        ui.add_space(10.0);
+        
+        ui.label(format!("Patient ID: {}", self.current_patient_id));
+        ui.horizontal(|ui| {
+            ui.label("Medication List: ");
+            ui.add(TextEdit::singleline(&mut self.reconciliation.path).hint_text("MedicationStatement bundle (.json) or .csv"));
+            if ui.button("Compare").clicked() {
+                self.compare_medication_list();
+            }
+        });
+        choice(ui, "Context", &mut self.reconciliation.context, CONTEXTS);
+        ui.add_space(10.0);
+        
+        let form = &mut self.reconciliation;
+        if form.rows.is_empty() {
+            ui.label("Load a medication list to compare it with the current prescriptions");
+        } else {
+            egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                egui::Grid::new("reconcile_grid").striped(true).spacing([12.0, 6.0]).show(ui, |ui| {
+                    ui.strong("Outside List");
+                    ui.strong("Current Prescription");
+                    ui.strong("Decision");
+                    ui.strong("Dose");
+                    ui.strong("Frequency");
+                    ui.strong("Override Reason");
+                    ui.end_row();
+                    
+                    for (i, row) in form.rows.iter_mut().enumerate() {
+                        let outside = row.item.external.as_ref().map(|m| m.to_string()).unwrap_or_else(|| "-".to_string());
+                        ui.label(outside).on_hover_text(&row.item.note);
+                        ui.label(row.item.current.as_deref().unwrap_or("-")).on_hover_text(&row.item.note);
+                        ui.horizontal(|ui| {
+                            egui::ComboBox::from_id_source(("reconcile_decision", i))
+                                .selected_text(row.item.decision.to_string())
+                                .show_ui(ui, |ui| {
+                                    for action in ReconcileAction::ALL {
+                                        ui.selectable_value(&mut row.item.decision, action, action.to_string());
+                                    }
+                                });
+                            if row.item.decision != row.item.proposed {
+                                ui.small(format!("(proposed {})", row.item.proposed));
+                            }
+                        });
+                        let editable = matches!(row.item.decision, ReconcileAction::Modify | ReconcileAction::New);
+                        ui.horizontal(|ui| {
+                            ui.add_enabled(editable, TextEdit::singleline(&mut row.dose).desired_width(60.0));
+                            ui.label(&row.item.dose_unit);
+                        });
+                        ui.add_enabled(editable, TextEdit::singleline(&mut row.frequency).desired_width(120.0));
+                        ui.add_enabled(editable, TextEdit::singleline(&mut row.override_reason).desired_width(160.0));
+                        ui.end_row();
+                    }
+                });
+            });
+            
+            ui.add_space(10.0);
+            ui.horizontal(|ui| {
+                ui.label("Reconciled by: ");
+                ui.text_edit_singleline(&mut form.author);
+            });
+            if ui.button("Apply Decisions").clicked() {
+                self.apply_reconciliation();
+            }
+        }
+        
+        ui.add_space(10.0);
+        if ui.button("Back to Patient").clicked() {
+            self.current_view = View::ViewPatient;
+        }
+    }
+    
+    fn compare_medication_list(&mut self) {
+        let list = match std::fs::read_to_string(&self.reconciliation.path) {
+            Ok(text) => reconcile::parse_medication_list(&text),
+            Err(e) => Err(anyhow!("Failed to read {}: {}", self.reconciliation.path, e)),
+        };
+        match list {
+            Ok(list) => {
+                match self.emr.lock() {
+                    Ok(emr) => {
+                        match emr.reconcile_medications(&self.current_patient_id, &list) {
+                            Ok(items) => {
+                                self.status_message = format!("{} medications to reconcile", items.len());
+                                self.reconciliation.rows = items.into_iter().map(ReconciliationRow::new).collect();
+                            },
+                            Err(e) => {
+                                self.status_message = format!("Error comparing medications: {}", e);
+                            }
+                        }
+                    },
+                    Err(_) => {
+                        self.status_message = "Error accessing EMR".to_string();
+                    }
+                }
+            },
+            Err(e) => {
+                self.status_message = format!("Error reading medication list: {:#}", e);
+            }
+        }
+    }
+    
+    fn apply_reconciliation(&mut self) {
+        let items: Result<Vec<ReconciliationItem>> = self.reconciliation.rows.iter()
+            .map(ReconciliationRow::item)
+            .collect();
+        let items = match items {
+            Ok(items) => items,
+            Err(e) => {
+                self.status_message = format!("Error: {}", e);
+                return;
+            }
+        };
+        let reconciliation = Reconciliation::new(&self.reconciliation.context, &self.reconciliation.author);
+        match self.emr.lock() {
+            Ok(mut emr) => {
+                match emr.apply_reconciliation(&self.current_patient_id, &items, &reconciliation) {
+                    Ok(message) => {
+                        match emr.save_patient(&self.current_patient_id, &self.patient_key) {
+                            Ok(_) => {
+                                self.status_message = message;
+                                self.reconciliation.rows.clear();
+                                self.current_view = View::ViewPatient;
+                            },
+                            Err(e) => {
+                                self.status_message = format!("Error saving patient: {}", e);
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        self.status_message = format!("Error reconciling medications: {:#}", e);
+                    }
+                }
+            },
+            Err(_) => {
+                self.status_message = "Error accessing EMR".to_string();
+            }
+        }
+    }
+    
+    // The record's versions, what each changed, and reverting to one
+    fn render_history_view(&mut self, ui: &mut Ui) {
+        ui.heading("Version History");
+        ui.add_space(10.0);
+        
+        ui.label(format!("Patient ID: {}", self.current_patient_id));
+        ui.add_space(10.0);
+        
+        let mut show = None;
+        let mut revert = None;
+        match self.emr.lock() {
+            Ok(emr) => {
+                match emr.history(&self.current_patient_id) {
+                    Ok(versions) => {
+                        // Tampering with earlier versions breaks the hash chain
+                        match emr.verify_history(&self.current_patient_id) {
+                            Ok(report) if report.is_intact() => {
+                                ui.label(report.to_string());
+                            }
+                            Ok(report) => {
+                                ui.colored_label(egui::Color32::RED, report.to_string());
+                            }
+                            Err(_) => {}
+                        }
+                        ui.add_space(5.0);
+                        egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                            egui::Grid::new("history_grid").striped(true).spacing([12.0, 6.0]).show(ui, |ui| {
+                                ui.strong("Version");
+                                ui.strong("Hash");
+                                ui.strong("Date");
+                                ui.strong("Author");
+                                ui.strong("Signature");
+                                ui.strong("Message");
+                                ui.end_row();
+                                
+                                for (i, version) in versions.iter().enumerate().rev() {
+                                    ui.label((i + 1).to_string());
+                                    ui.monospace(history::short_hash(&version.hash)).on_hover_text(&version.hash);
+                                    ui.label(version.timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string());
+                                    ui.label(&version.author);
+                                    let signature = emr.clinicians.verify(version);
+                                    if signature.is_warning() {
+                                        ui.colored_label(egui::Color32::from_rgb(230, 160, 0), signature.to_string());
+                                    } else {
+                                        ui.label(signature.to_string());
+                                    }
+                                    ui.label(&version.message);
+                                    ui.horizontal(|ui| {
+                                        if ui.button("Changes").clicked() {
+                                            show = Some(i);
+                                        }
+                                        if i + 1 < versions.len() && ui.button("Revert to This").clicked() {
+                                            revert = Some(i);
+                                        }
+                                    });
+                                    ui.end_row();
+                                }
+                            });
+                        });
+                    },
+                    Err(e) => {
+                        ui.label(format!("Error: {}", e));
+                    }
+                }
+            },
+            Err(_) => {
+                ui.label("Error accessing EMR");
+            }
+        }
+        
+        if let Some(index) = show {
+            self.show_version_changes(index);
+        }
+        if revert.is_some() {
+            self.history.revert = revert;
+            self.history.reason.clear();
+        }
+        
+        ui.add_space(10.0);
+        ui.horizontal(|ui| {
+            ui.label("Compare version ");
+            ui.add(TextEdit::singleline(&mut self.history.from).desired_width(80.0).hint_text("number or hash"));
+            ui.label(" with ");
+            ui.add(TextEdit::singleline(&mut self.history.to).desired_width(80.0).hint_text("number or hash"));
+            if ui.button("Compare").clicked() {
+                self.compare_versions();
+            }
+        });
+        
+        if !self.history.title.is_empty() {
+            ui.add_space(10.0);
+            ui.strong(&self.history.title);
+            if self.history.changes.is_empty() {
+                ui.label("No changes");
+            }
+            for change in &self.history.changes {
+                ui.label(change);
+            }
+        }
+        
+        if let Some(index) = self.history.revert {
+            let mut confirm = false;
+            ui.add_space(10.0);
+            ui.group(|ui| {
+                ui.label(format!("Revert to version {}? Its contents are restored as a new version; no history is lost.", index + 1));
+                ui.horizontal(|ui| {
+                    ui.label("Reason: ");
+                    ui.text_edit_singleline(&mut self.history.reason);
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Revert").clicked() {
+                        confirm = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.history.revert = None;
+                    }
+                });
+            });
+            if confirm {
+                self.revert_version(index);
+            }
+        }
+        
+        ui.add_space(10.0);
+        if ui.button("Back to Patient").clicked() {
+            self.current_view = View::ViewPatient;
+        }
+    }
+    
+    fn show_version_changes(&mut self, index: usize) {
+        match self.emr.lock() {
+            Ok(emr) => {
+                match emr.version_changes(&self.current_patient_id, index) {
+                    Ok(changes) => {
+                        self.history.title = format!("Changes in version {}", index + 1);
+                        self.history.changes = changes.iter().map(|change| change.to_string()).collect();
+                    },
+                    Err(e) => {
+                        self.status_message = format!("Error: {}", e);
+                    }
+                }
+            },
+            Err(_) => {
+                self.status_message = "Error accessing EMR".to_string();
+            }
+        }
+    }
+    
+    fn compare_versions(&mut self) {
+        match self.emr.lock() {
+            Ok(emr) => {
+                let changes = emr.find_version(&self.current_patient_id, &self.history.from)
+                    .and_then(|from| Ok((from, emr.find_version(&self.current_patient_id, &self.history.to)?)))
+                    .and_then(|(from, to)| Ok((from, to, emr.diff_versions(&self.current_patient_id, from, to)?)));
+                match changes {
+                    Ok((from, to, changes)) => {
+                        self.history.title = format!("Changes from version {} to version {}", from + 1, to + 1);
+                        self.history.changes = changes.iter().map(|change| change.to_string()).collect();
+                    },
+                    Err(e) => {
+                        self.status_message = format!("Error: {}", e);
+                    }
+                }
+            },
+            Err(_) => {
+                self.status_message = "Error accessing EMR".to_string();
+            }
+        }
+    }
+    
+    fn revert_version(&mut self, index: usize) {
+        match self.emr.lock() {
+            Ok(mut emr) => {
+                match emr.revert_to_version(&self.current_patient_id, index, &self.history.reason) {
+                    Ok(message) => {
+                        match emr.save_patient(&self.current_patient_id, &self.patient_key) {
+                            Ok(_) => {
+                                self.status_message = message;
+                                self.history = HistoryForm::default();
+                            },
+                            Err(e) => {
+                                self.status_message = format!("Error saving patient: {}", e);
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        self.status_message = format!("Error reverting record: {}", e);
+                    }
+                }
+            },
+            Err(_) => {
+                self.status_message = "Error accessing EMR".to_string();
+            }
+        }
+    }
+    
+    // Unlock a clinician's signing key, or generate one, so that commits are
+    // signed and attributed to them
+    fn render_sign_in_view(&mut self, ui: &mut Ui) {
+        ui.heading("Clinician Sign-In");
+        ui.add_space(10.0);
+        
+        ui.horizontal(|ui| {
+            ui.label("Clinician: ");
+            ui.add(TextEdit::singleline(&mut self.sign_in.clinician).hint_text("e.g. dr.smith"));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Passphrase: ");
+            ui.add(TextEdit::singleline(&mut self.sign_in.passphrase).password(true));
+        });
+        
+        ui.add_space(10.0);
+        ui.horizontal(|ui| {
+            if ui.button("Sign In").clicked() {
+                self.sign_in(false);
+            }
+            if ui.button("Generate Key and Sign In").clicked() {
+                self.sign_in(true);
+            }
+            if ui.button("Sign Out").clicked() {
+                if let Ok(mut emr) = self.emr.lock() {
+                    emr.signer = None;
+                    emr.user = charcot_emr::login_name();
+                    self.status_message = "Signed out; commits are no longer signed".to_string();
+                }
+            }
+        });
+        
+        ui.add_space(10.0);
         if ui.button("Back to Home").clicked() {
             self.current_view = View::Home;
         }
     }
     
+    fn sign_in(&mut self, generate: bool) {
+        match self.emr.lock() {
+            Ok(mut emr) => {
+                let signer = if generate {
+                    Signer::generate(&self.sign_in.clinician, &self.sign_in.passphrase, &mut emr.clinicians)
+                } else {
+                    Signer::unlock(&self.sign_in.clinician, &self.sign_in.passphrase)
+                };
+                match signer {
+                    Ok(signer) => {
+                        self.status_message = format!("Signed in as {}; commits are signed with their key", signer.clinician);
+                        emr.sign_in(signer);
+                        self.sign_in = SignInForm::default();
+                        self.current_view = View::Home;
+                    },
+                    Err(e) => {
+                        self.status_message = format!("Error: {}", e);
+                    }
+                }
+            },
+            Err(_) => {
+                self.status_message = "Error accessing EMR".to_string();
+            }
+        }
+    }
+    
     fn render_load_patient_view(&mut self, ui: &mut Ui) {
         ui.heading("Load Patient Record");
         ui.add_space(10.0);
@@ -557,8 +2016,20 @@ impl EMRApp {
                     Ok(mut emr) => {
                         match emr.load_patient(&self.load_path, &self.patient_key) {
                             Ok(patient_id) => {
+                                self.status_message = match emr.signature_warnings(&patient_id) {
+                                    Ok(warnings) if !warnings.is_empty() => {
+                                        format!("Patient loaded from {}. Warning: {}", self.load_path, warnings)
+                                    }
+                                    _ => format!("Patient loaded successfully from {}", self.load_path),
+                                };
+                                if let Ok(med_file) = MedFile::read(&self.load_path) {
+                                    if !med_file.is_current() {
+                                        self.status_message.push_str(&format!(
+                                            ". It is in .med format {} and will be saved in format {}",
+                                            med_file.format, MED_FORMAT_VERSION));
+                                    }
+                                }
                                 self.current_patient_id = patient_id;
-                                self.status_message = format!("Patient loaded successfully from {}", self.load_path);
                                 self.current_view = View::ViewPatient;
                                 self.load_path = String::new();
                             },
@@ -591,14 +2062,174 @@ struct PatientForm {
 }
 
 struct VitalSignsForm {
+    kind: Option<VitalKind>, // None for blood pressure
     systolic: String,
     diastolic: String,
+    value: String,
+    unit: String, // Blank for the vital's own unit
+    taken_at: String,
 }
 
 struct MedicationForm {
     name: String,
-    dose_mg: String,
+    dose_mg: String,            // Per kg or m2 when the dose is calculated
+    dose_basis: Option<DoseBasis>,  // None for a fixed dose
+    dose_unit: String,          // mg or units, for a calculated dose
+    frequency: String,
+    written_at: String,
+    unlisted: bool,
+    override_reason: String,    // Why dose warnings and severe interactions are accepted
+    interactions: Option<Vec<InteractionAlert>>,  // Shown for confirmation before prescribing
+    suggestions: Vec<Drug>,
+    suggested_for: String,  // Name the suggestions were made for
+}
+
+struct AllergyForm {
+    substance: String,
+    allergy_type: String,
+    category: String,
+    criticality: String,
+    status: String,
+    reaction: String,
+    severity: String,       // Empty if not recorded
+    recorded_at: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ChangeAction {
+    Stop,
+    Hold,
+    Resume,
+    Change,
+    Renew,
+}
+
+impl ChangeAction {
+    fn label(self) -> &'static str {
+        match self {
+            ChangeAction::Stop => "Stop",
+            ChangeAction::Hold => "Hold",
+            ChangeAction::Resume => "Resume",
+            ChangeAction::Change => "Change Dose",
+            ChangeAction::Renew => "Renew",
+        }
+    }
+    
+    fn past_tense(self) -> &'static str {
+        match self {
+            ChangeAction::Stop => "Stopped",
+            ChangeAction::Hold => "Held",
+            ChangeAction::Resume => "Resumed",
+            ChangeAction::Change => "Changed",
+            ChangeAction::Renew => "Renewed",
+        }
+    }
+}
+
+#[derive(Clone)]
+struct MedicationChangeForm {
+    action: ChangeAction,
+    request_id: String,
+    description: String,    // e.g. "metformin 500 mg twice daily"
+    reason: String,
+    author: String,
+    dose_mg: String,        // New dose and frequency, for ChangeAction::Change
+    dose_unit: String,      // Unit of the current dose, which the new one keeps
+    frequency: String,
+    override_reason: String,
+}
+
+impl MedicationChangeForm {
+    fn new(action: ChangeAction, request: &MedicationRequest, author: &str) -> Self {
+        let dosage = request.dosage_instruction.first();
+        Self {
+            action,
+            request_id: request.id.clone(),
+            description: format!("{} {}", request.medication_codeable_concept.display,
+                                 dosage.map(|d| d.text.as_str()).unwrap_or_default()),
+            reason: String::new(),
+            author: author.to_string(),
+            dose_mg: medications::dose(request).map(|q| q.value.to_string()).unwrap_or_default(),
+            dose_unit: medications::dose(request).map_or("mg".to_string(), |q| q.unit.clone()),
+            frequency: medications::frequency(request).unwrap_or_default().to_string(),
+            override_reason: String::new(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SignInForm {
+    clinician: String,
+    passphrase: String,
+}
+
+#[derive(Default)]
+struct HistoryForm {
+    from: String,           // Versions to compare, by number or hash
+    to: String,
+    title: String,          // What `changes` are, e.g. "Changes in version 3"
+    changes: Vec<String>,
+    revert: Option<usize>,  // Version being reverted to, awaiting a reason
+    reason: String,
+}
+
+struct ReconciliationForm {
+    path: String,           // Outside medication list
+    context: String,
+    author: String,
+    rows: Vec<ReconciliationRow>,
+}
+
+// A reconciliation decision with its dose and frequency as typed
+struct ReconciliationRow {
+    item: ReconciliationItem,
+    dose: String,               // In item.dose_unit
     frequency: String,
+    override_reason: String,
+}
+
+impl ReconciliationRow {
+    fn new(item: ReconciliationItem) -> Self {
+        Self {
+            dose: item.dose.map(|dose| dose.to_string()).unwrap_or_default(),
+            frequency: item.frequency.clone().unwrap_or_default(),
+            override_reason: String::new(),
+            item,
+        }
+    }
+    
+    fn item(&self) -> Result<ReconciliationItem> {
+        let dose = match self.dose.trim() {
+            "" => None,
+            dose => Some(dose.parse::<f64>()
+                .map_err(|_| anyhow!("Dose of {} must be a number", self.item.medication()))?),
+        };
+        Ok(ReconciliationItem {
+            dose,
+            frequency: Some(self.frequency.trim().to_string()).filter(|frequency| !frequency.is_empty()),
+            override_reason: Some(self.override_reason.trim().to_string()).filter(|reason| !reason.is_empty()),
+            ..self.item.clone()
+        })
+    }
+}
+
+struct ProblemForm {
+    code: String,           // ICD-10-CM code, or text to search for one
+    snomed: String,
+    status: String,
+    verification: String,
+    onset: String,
+    note: String,
+    recorded_at: String,
+}
+
+// Time typed into a form, or now if the field was left blank
+fn form_time(text: &str) -> Result<DateTime<Utc>> {
+    if text.trim().is_empty() {
+        Ok(Utc::now())
+    } else {
+        parse_clinical_time(text)
+    }
 }
 
 enum View {
@@ -606,8 +2237,14 @@ enum View {
     CreatePatient,
     AddVitals,
     Prescribe,
+    AddAllergy,
+    AddProblem,
+    Mar,
+    Reconcile,
+    History,
     ViewPatient,
     LoadPatient,
+    SignIn,
 }
 
 impl Default for PatientForm {
@@ -626,8 +2263,12 @@ impl Default for PatientForm {
 impl Default for VitalSignsForm {
     fn default() -> Self {
         Self {
+            kind: None,
             systolic: String::new(),
             diastolic: String::new(),
+            value: String::new(),
+            unit: String::new(),
+            taken_at: String::new(),
         }
     }
 }
@@ -637,33 +2278,96 @@ impl Default for MedicationForm {
         Self {
             name: String::new(),
             dose_mg: String::new(),
+            dose_basis: None,
+            dose_unit: String::from("mg"),
             frequency: String::from("daily"),
+            written_at: String::new(),
+            unlisted: false,
+            override_reason: String::new(),
+            interactions: None,
+            suggestions: Vec::new(),
+            suggested_for: String::new(),
+        }
+    }
+}
+
+impl Default for AllergyForm {
+    fn default() -> Self {
+        let allergy = NewAllergy::default();
+        Self {
+            substance: String::new(),
+            allergy_type: allergy.allergy_type,
+            category: allergy.category,
+            criticality: allergy.criticality,
+            status: allergy.verification_status,
+            reaction: String::new(),
+            severity: String::new(),
+            recorded_at: String::new(),
         }
     }
 }
 
-impl Default for EMRApp {
+impl Default for ReconciliationForm {
     fn default() -> Self {
         Self {
-            emr: Arc::new(Mutex::new(EMR::new().unwrap_or_else(|_| {
-                eprintln!("Failed to create EMR. Using empty instance.");
-                EMR {
-                    bundles: HashMap::new(),
-                    audit_log: std::fs::OpenOptions::new()
-                        .append(true)
-                        .create(true)
-                        .open("audit.log")
-                        .expect("Failed to create audit log file"),
-                }
-            }))),
+            path: String::new(),
+            context: CONTEXTS[0].to_string(),
+            author: String::new(),
+            rows: Vec::new(),
+        }
+    }
+}
+
+impl Default for ProblemForm {
+    fn default() -> Self {
+        let condition = NewCondition::default();
+        Self {
+            code: String::new(),
+            snomed: String::new(),
+            status: condition.clinical_status,
+            verification: condition.verification_status,
+            onset: String::new(),
+            note: String::new(),
+            recorded_at: String::new(),
+        }
+    }
+}
+
+// A labelled drop-down for one of `options`
+fn choice(ui: &mut Ui, label: &str, value: &mut String, options: &[&str]) {
+    ui.horizontal(|ui| {
+        ui.label(format!("{}: ", label));
+        egui::ComboBox::from_id_source(label)
+            .selected_text(value.as_str())
+            .show_ui(ui, |ui| {
+                for option in options {
+                    ui.selectable_value(value, option.to_string(), *option);
+                }
+            });
+    });
+}
+
+impl EMRApp {
+    fn new(emr: EMR) -> Self {
+        Self {
+            emr: Arc::new(Mutex::new(emr)),
             current_patient_id: String::new(),
             patient_key: String::new(),
             status_message: String::from("Welcome to Charcot EMR"),
             new_patient: PatientForm::default(),
             vital_signs: VitalSignsForm::default(),
             medication: MedicationForm::default(),
+            allergy: AllergyForm::default(),
+            problem: ProblemForm::default(),
+            medication_change: None,
+            reconciliation: ReconciliationForm::default(),
+            history: HistoryForm::default(),
+            sign_in: SignInForm::default(),
             current_view: View::Home,
             load_path: String::new(),
+            show_medication_history: false,
+            mar_day: Local::now().date_naive(),
+            mar_performer: String::new(),
         }
     }
 }
\ No newline at end of file
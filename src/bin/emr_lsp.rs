@@ -0,0 +1,231 @@
+// src/bin/emr_lsp.rs
+// Charcot EMR: Language server for `.charcot` scripts, speaking LSP over stdio
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+use anyhow::Result;
+use charcot_emr::lang::diagnostic::{Severity, Span};
+use charcot_emr::lang::ide::{self, Analysis, CompletionKind};
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, DiagnosticSeverity,
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    GotoDefinitionParams, Hover, HoverContents, HoverParams, HoverProviderCapability, Location,
+    MarkupContent, MarkupKind, OneOf, Position, PublishDiagnosticsParams, Range,
+    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        completion_provider: Some(CompletionOptions::default()),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    log::info!("emr_lsp initialized");
+
+    let mut server = Server { documents: HashMap::new() };
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                let response = server.handle_request(request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                if let Some(uri) = server.handle_notification(notification)? {
+                    connection.sender.send(Message::Notification(server.diagnostics(&uri)))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+
+    // The writer thread finishes once the connection is gone
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+struct Server {
+    documents: HashMap<Url, String>,
+}
+
+impl Server {
+    // Returns the document whose diagnostics need republishing, if any
+    fn handle_notification(&mut self, notification: Notification) -> Result<Option<Url>> {
+        match notification.method.as_str() {
+            "textDocument/didOpen" => {
+                let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+                let uri = params.text_document.uri;
+                self.documents.insert(uri.clone(), params.text_document.text);
+                Ok(Some(uri))
+            }
+            "textDocument/didChange" => {
+                let params: DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+                let uri = params.text_document.uri;
+                // Full sync: the last change holds the whole document
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(uri.clone(), change.text);
+                }
+                Ok(Some(uri))
+            }
+            "textDocument/didClose" => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn handle_request(&self, request: Request) -> Response {
+        let id = request.id.clone();
+        let result = match request.method.as_str() {
+            "textDocument/completion" => serde_json::from_value(request.params)
+                .map_err(anyhow::Error::from)
+                .and_then(|params| self.completion(params)),
+            "textDocument/hover" => serde_json::from_value(request.params)
+                .map_err(anyhow::Error::from)
+                .and_then(|params| self.hover(params)),
+            "textDocument/definition" => serde_json::from_value(request.params)
+                .map_err(anyhow::Error::from)
+                .and_then(|params| self.definition(params)),
+            method => {
+                return Response::new_err(
+                    id,
+                    lsp_server::ErrorCode::MethodNotFound as i32,
+                    format!("unsupported request {}", method),
+                )
+            }
+        };
+        match result {
+            Ok(value) => Response::new_ok(id, value),
+            Err(e) => Response::new_err(id, lsp_server::ErrorCode::InvalidParams as i32, e.to_string()),
+        }
+    }
+
+    fn analysis(&self, uri: &Url) -> Option<(&str, Analysis)> {
+        let source = self.documents.get(uri)?;
+        Some((source.as_str(), ide::analyze(source, &known_patients())))
+    }
+
+    fn diagnostics(&self, uri: &Url) -> Notification {
+        let diagnostics = match self.analysis(uri) {
+            Some((source, analysis)) => analysis.diagnostics.iter()
+                .map(|d| lsp_types::Diagnostic {
+                    range: to_range(source, d.span),
+                    severity: Some(match d.severity {
+                        Severity::Error => DiagnosticSeverity::ERROR,
+                        Severity::Warning => DiagnosticSeverity::WARNING,
+                    }),
+                    source: Some("charcot".to_string()),
+                    message: d.message.clone(),
+                    ..Default::default()
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        let params = PublishDiagnosticsParams { uri: uri.clone(), diagnostics, version: None };
+        Notification::new("textDocument/publishDiagnostics".to_string(), params)
+    }
+
+    fn completion(&self, params: CompletionParams) -> Result<serde_json::Value> {
+        let position = params.text_document_position;
+        let Some((source, analysis)) = self.analysis(&position.text_document.uri) else {
+            return Ok(serde_json::Value::Null);
+        };
+        let offset = to_offset(source, position.position);
+        let items: Vec<CompletionItem> = ide::completions(&analysis, offset).into_iter()
+            .map(|c| CompletionItem {
+                label: c.label,
+                kind: Some(match c.kind {
+                    CompletionKind::Keyword => CompletionItemKind::KEYWORD,
+                    CompletionKind::Constructor => CompletionItemKind::CONSTRUCTOR,
+                    CompletionKind::Variable => CompletionItemKind::VARIABLE,
+                }),
+                detail: Some(c.detail),
+                insert_text: Some(c.insert_text),
+                ..Default::default()
+            })
+            .collect();
+        Ok(serde_json::to_value(items)?)
+    }
+
+    fn hover(&self, params: HoverParams) -> Result<serde_json::Value> {
+        let position = params.text_document_position_params;
+        let Some((source, analysis)) = self.analysis(&position.text_document.uri) else {
+            return Ok(serde_json::Value::Null);
+        };
+        let offset = to_offset(source, position.position);
+        let hover = ide::hover(&analysis, offset).map(|(text, span)| Hover {
+            contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value: text }),
+            range: Some(to_range(source, span)),
+        });
+        Ok(serde_json::to_value(hover)?)
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Result<serde_json::Value> {
+        let position = params.text_document_position_params;
+        let uri = position.text_document.uri;
+        let Some((source, analysis)) = self.analysis(&uri) else {
+            return Ok(serde_json::Value::Null);
+        };
+        let offset = to_offset(source, position.position);
+        let location = ide::definition(&analysis, offset)
+            .map(|span| Location { uri: uri.clone(), range: to_range(source, span) });
+        Ok(serde_json::to_value(location)?)
+    }
+}
+
+// Patients with a `.med` file in the working directory
+fn known_patients() -> HashSet<String> {
+    let Ok(entries) = fs::read_dir(".") else {
+        return HashSet::new();
+    };
+    entries.filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            Some(name.strip_prefix("patient_")?.strip_suffix(".med")?.to_string())
+        })
+        .collect()
+}
+
+// LSP positions count lines from 0 and columns in UTF-16 code units
+fn to_position(source: &str, offset: usize) -> Position {
+    let offset = offset.min(source.len());
+    let line_start = source[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = source[..line_start].matches('\n').count();
+    let character: usize = source[line_start..offset].chars().map(char::len_utf16).sum();
+    Position::new(line as u32, character as u32)
+}
+
+fn to_offset(source: &str, position: Position) -> usize {
+    let mut offset = 0;
+    for (i, line) in source.split_inclusive('\n').enumerate() {
+        if i == position.line as usize {
+            let mut units = 0;
+            for (byte, c) in line.char_indices() {
+                if units >= position.character as usize || c == '\n' {
+                    return offset + byte;
+                }
+                units += c.len_utf16();
+            }
+            return offset + line.len();
+        }
+        offset += line.len();
+    }
+    source.len()
+}
+
+fn to_range(source: &str, span: Span) -> Range {
+    Range::new(to_position(source, span.start), to_position(source, span.end))
+}
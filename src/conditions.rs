@@ -0,0 +1,425 @@
+// src/conditions.rs
+// Charcot EMR: Problem list of Condition resources coded with ICD-10-CM
+//
+// Condition codes are checked against local code tables. The EMR ships with
+// common ICD-10-CM and SNOMED CT codes; `icd10cm.json` and `snomed.json` in
+// the working directory replace them. The full ICD-10-CM table can be built
+// from the CMS release (`icd10cm_codes_<year>.txt`) with `emr_cli condition
+// import`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use anyhow::{Result, anyhow, Context};
+
+use crate::terminology::{ICD10_SYSTEM, SNOMED_SYSTEM};
+use crate::{Annotation, BundleEntry, Coding, Condition, EMR, Reference, Resource, validate_clinical_time};
+
+pub const ICD10_FILE: &str = "icd10cm.json";
+pub const SNOMED_FILE: &str = "snomed.json";
+
+pub const CLINICAL_STATUSES: &[&str] = &["active", "recurrence", "relapse", "inactive", "remission", "resolved"];
+pub const VERIFICATION_STATUSES: &[&str] =
+    &["unconfirmed", "provisional", "differential", "confirmed", "refuted", "entered-in-error"];
+
+// Common ICD-10-CM diagnosis codes
+const ICD10_BUILTIN: &[(&str, &str)] = &[
+    ("A41.9", "Sepsis, unspecified organism"),
+    ("B18.2", "Chronic viral hepatitis C"),
+    ("B20", "Human immunodeficiency virus [HIV] disease"),
+    ("C18.9", "Malignant neoplasm of colon, unspecified"),
+    ("C34.90", "Malignant neoplasm of unspecified part of unspecified bronchus or lung"),
+    ("C50.919", "Malignant neoplasm of unspecified site of unspecified female breast"),
+    ("C61", "Malignant neoplasm of prostate"),
+    ("D50.9", "Iron deficiency anemia, unspecified"),
+    ("D64.9", "Anemia, unspecified"),
+    ("E03.9", "Hypothyroidism, unspecified"),
+    ("E05.90", "Thyrotoxicosis, unspecified without thyrotoxic crisis or storm"),
+    ("E10.9", "Type 1 diabetes mellitus without complications"),
+    ("E11.22", "Type 2 diabetes mellitus with diabetic chronic kidney disease"),
+    ("E11.40", "Type 2 diabetes mellitus with diabetic neuropathy, unspecified"),
+    ("E11.65", "Type 2 diabetes mellitus with hyperglycemia"),
+    ("E11.9", "Type 2 diabetes mellitus without complications"),
+    ("E66.9", "Obesity, unspecified"),
+    ("E78.00", "Pure hypercholesterolemia, unspecified"),
+    ("E78.5", "Hyperlipidemia, unspecified"),
+    ("E87.5", "Hyperkalemia"),
+    ("E87.6", "Hypokalemia"),
+    ("F10.20", "Alcohol dependence, uncomplicated"),
+    ("F17.210", "Nicotine dependence, cigarettes, uncomplicated"),
+    ("F20.9", "Schizophrenia, unspecified"),
+    ("F31.9", "Bipolar disorder, unspecified"),
+    ("F32.9", "Major depressive disorder, single episode, unspecified"),
+    ("F32.A", "Depression, unspecified"),
+    ("F41.1", "Generalized anxiety disorder"),
+    ("F41.9", "Anxiety disorder, unspecified"),
+    ("G30.9", "Alzheimer's disease, unspecified"),
+    ("G40.909", "Epilepsy, unspecified, not intractable, without status epilepticus"),
+    ("G43.909", "Migraine, unspecified, not intractable, without status migrainosus"),
+    ("G47.33", "Obstructive sleep apnea (adult) (pediatric)"),
+    ("G89.29", "Other chronic pain"),
+    ("H25.9", "Unspecified age-related cataract"),
+    ("H40.9", "Unspecified glaucoma"),
+    ("I10", "Essential (primary) hypertension"),
+    ("I25.10", "Atherosclerotic heart disease of native coronary artery without angina pectoris"),
+    ("I26.99", "Other pulmonary embolism without acute cor pulmonale"),
+    ("I48.91", "Unspecified atrial fibrillation"),
+    ("I50.9", "Heart failure, unspecified"),
+    ("I63.9", "Cerebral infarction, unspecified"),
+    ("I73.9", "Peripheral vascular disease, unspecified"),
+    ("J06.9", "Acute upper respiratory infection, unspecified"),
+    ("J18.9", "Pneumonia, unspecified organism"),
+    ("J44.9", "Chronic obstructive pulmonary disease, unspecified"),
+    ("J45.909", "Unspecified asthma, uncomplicated"),
+    ("K21.9", "Gastro-esophageal reflux disease without esophagitis"),
+    ("K29.70", "Gastritis, unspecified, without bleeding"),
+    ("K76.0", "Fatty (change of) liver, not elsewhere classified"),
+    ("L40.0", "Psoriasis vulgaris"),
+    ("M06.9", "Rheumatoid arthritis, unspecified"),
+    ("M10.9", "Gout, unspecified"),
+    ("M17.9", "Osteoarthritis of knee, unspecified"),
+    ("M19.90", "Unspecified osteoarthritis, unspecified site"),
+    ("M54.50", "Low back pain, unspecified"),
+    ("M81.0", "Age-related osteoporosis without current pathological fracture"),
+    ("N17.9", "Acute kidney failure, unspecified"),
+    ("N18.4", "Chronic kidney disease, stage 4 (severe)"),
+    ("N18.9", "Chronic kidney disease, unspecified"),
+    ("N39.0", "Urinary tract infection, site not specified"),
+    ("R05.9", "Cough, unspecified"),
+    ("R10.9", "Unspecified abdominal pain"),
+    ("R50.9", "Fever, unspecified"),
+    ("R51.9", "Headache, unspecified"),
+    ("R73.03", "Prediabetes"),
+    ("U07.1", "COVID-19"),
+    ("Z79.01", "Long term (current) use of anticoagulants"),
+    ("Z79.4", "Long term (current) use of insulin"),
+    ("Z87.891", "Personal history of nicotine dependence"),
+    ("Z99.2", "Dependence on renal dialysis"),
+];
+
+// Common SNOMED CT clinical findings
+const SNOMED_BUILTIN: &[(&str, &str)] = &[
+    ("13645005", "Chronic obstructive lung disease"),
+    ("195967001", "Asthma"),
+    ("197480006", "Anxiety disorder"),
+    ("233604007", "Pneumonia"),
+    ("235595009", "Gastroesophageal reflux disease"),
+    ("26929004", "Alzheimer's disease"),
+    ("271737000", "Anemia"),
+    ("35489007", "Depressive disorder"),
+    ("37796009", "Migraine"),
+    ("38341003", "Hypertensive disorder, systemic arterial"),
+    ("396275006", "Osteoarthritis"),
+    ("40930008", "Hypothyroidism"),
+    ("414916001", "Obesity"),
+    ("44054006", "Diabetes mellitus type 2"),
+    ("46635009", "Diabetes mellitus type 1"),
+    ("49049000", "Parkinson's disease"),
+    ("49436004", "Atrial fibrillation"),
+    ("53741008", "Coronary arteriosclerosis"),
+    ("55822004", "Hyperlipidemia"),
+    ("59621000", "Essential hypertension"),
+    ("64859006", "Osteoporosis"),
+    ("68566005", "Urinary tract infectious disease"),
+    ("69896004", "Rheumatoid arthritis"),
+    ("709044004", "Chronic kidney disease"),
+    ("714628002", "Prediabetes"),
+    ("78275009", "Obstructive sleep apnea syndrome"),
+    ("840539006", "COVID-19"),
+    ("84114007", "Heart failure"),
+    ("84757009", "Epilepsy"),
+    ("90560007", "Gout"),
+];
+
+// Display of a built-in ICD-10-CM code
+pub fn builtin_icd10(code: &str) -> Option<&'static str> {
+    let code = normalize_icd10(code).ok()?;
+    ICD10_BUILTIN.iter().find(|(c, _)| *c == code).map(|(_, display)| *display)
+}
+
+// Display of a built-in SNOMED CT concept
+pub fn builtin_snomed(code: &str) -> Option<&'static str> {
+    SNOMED_BUILTIN.iter().find(|(c, _)| *c == code.trim()).map(|(_, display)| *display)
+}
+
+// "e119" or "E11.9" -> "E11.9"
+pub fn normalize_icd10(code: &str) -> Result<String> {
+    let compact: String = code.trim().to_uppercase().chars().filter(|c| *c != '.').collect();
+    let valid = (3..=7).contains(&compact.len())
+        && compact.starts_with(|c: char| c.is_ascii_uppercase())
+        && compact.chars().all(|c| c.is_ascii_alphanumeric());
+    if !valid {
+        return Err(anyhow!("Invalid ICD-10-CM code: {}", code));
+    }
+    Ok(if compact.len() > 3 {
+        format!("{}.{}", &compact[..3], &compact[3..])
+    } else {
+        compact
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Code {
+    pub code: String,
+    pub display: String,
+}
+
+// Codes of one code system
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeTable {
+    pub system: String,
+    codes: BTreeMap<String, String>,
+}
+
+impl CodeTable {
+    pub fn new(system: &str, codes: Vec<Code>) -> Self {
+        CodeTable {
+            system: system.to_string(),
+            codes: codes.into_iter().map(|code| (code.code, code.display)).collect(),
+        }
+    }
+
+    fn builtin(system: &str, codes: &[(&str, &str)]) -> Self {
+        CodeTable::new(system, codes.iter()
+            .map(|(code, display)| Code { code: code.to_string(), display: display.to_string() })
+            .collect())
+    }
+
+    pub fn load(system: &str, path: &str) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read code table from {}", path))?;
+        let codes: Vec<Code> = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid code table in {}", path))?;
+        Ok(CodeTable::new(system, codes))
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        let codes: Vec<Code> = self.codes.iter()
+            .map(|(code, display)| Code { code: code.clone(), display: display.clone() })
+            .collect();
+        fs::write(path, serde_json::to_string(&codes)?)
+            .with_context(|| format!("Failed to write code table to {}", path))
+    }
+
+    // Build the ICD-10-CM table from the CMS code file, where each line is a
+    // code without its dot followed by the description:
+    // "E119    Type 2 diabetes mellitus without complications"
+    pub fn import_icd10(path: &str) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path))?;
+        let mut codes = Vec::new();
+        for (number, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let (code, display) = line.split_once(char::is_whitespace)
+                .ok_or_else(|| anyhow!("{}:{}: expected a code and a description", path, number + 1))?;
+            let code = normalize_icd10(code).with_context(|| format!("{}:{}", path, number + 1))?;
+            codes.push(Code { code, display: display.trim().to_string() });
+        }
+        if codes.is_empty() {
+            return Err(anyhow!("No ICD-10-CM codes found in {}", path));
+        }
+        Ok(CodeTable::new(ICD10_SYSTEM, codes))
+    }
+
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    pub fn lookup(&self, code: &str) -> Option<&str> {
+        self.codes.get(code).map(String::as_str)
+    }
+
+    // Codes whose code or description contains `text`
+    pub fn search(&self, text: &str, limit: usize) -> Vec<Code> {
+        let text = text.trim().to_lowercase();
+        self.codes.iter()
+            .filter(|(code, display)| code.to_lowercase().starts_with(&text) || display.to_lowercase().contains(&text))
+            .take(limit)
+            .map(|(code, display)| Code { code: code.clone(), display: display.clone() })
+            .collect()
+    }
+}
+
+// The code tables conditions are checked against
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConditionCodes {
+    pub icd10: CodeTable,
+    pub snomed: CodeTable,
+}
+
+impl Default for ConditionCodes {
+    fn default() -> Self {
+        ConditionCodes {
+            icd10: CodeTable::builtin(ICD10_SYSTEM, ICD10_BUILTIN),
+            snomed: CodeTable::builtin(SNOMED_SYSTEM, SNOMED_BUILTIN),
+        }
+    }
+}
+
+impl ConditionCodes {
+    // `icd10cm.json` and `snomed.json` where present, otherwise the built-in codes
+    pub fn load_or_default() -> Result<Self> {
+        let mut codes = ConditionCodes::default();
+        if Path::new(ICD10_FILE).exists() {
+            codes.icd10 = CodeTable::load(ICD10_SYSTEM, ICD10_FILE)?;
+        }
+        if Path::new(SNOMED_FILE).exists() {
+            codes.snomed = CodeTable::load(SNOMED_SYSTEM, SNOMED_FILE)?;
+        }
+        Ok(codes)
+    }
+
+    // An ICD-10-CM coding, if the code is in the table
+    pub fn icd10_coding(&self, code: &str) -> Result<Coding> {
+        let code = normalize_icd10(code)?;
+        let display = self.icd10.lookup(&code)
+            .ok_or_else(|| anyhow!("Unknown ICD-10-CM code: {}", code))?;
+        Ok(Coding { system: ICD10_SYSTEM.to_string(), code: code.clone(), display: display.to_string() })
+    }
+
+    // A SNOMED CT coding, if the concept is in the table
+    pub fn snomed_coding(&self, code: &str) -> Result<Coding> {
+        let code = code.trim();
+        let display = self.snomed.lookup(code)
+            .ok_or_else(|| anyhow!("Unknown SNOMED CT code: {}", code))?;
+        Ok(Coding { system: SNOMED_SYSTEM.to_string(), code: code.to_string(), display: display.to_string() })
+    }
+}
+
+// A problem to add to the list
+#[derive(Debug, Clone)]
+pub struct NewCondition {
+    pub icd10: String,
+    pub snomed: Option<String>,
+    pub clinical_status: String,
+    pub verification_status: String,
+    pub onset: Option<DateTime<Utc>>,
+    pub recorded: DateTime<Utc>,
+    pub note: Option<String>,
+}
+
+impl Default for NewCondition {
+    fn default() -> Self {
+        NewCondition {
+            icd10: String::new(),
+            snomed: None,
+            clinical_status: "active".to_string(),
+            verification_status: "confirmed".to_string(),
+            onset: None,
+            recorded: Utc::now(),
+            note: None,
+        }
+    }
+}
+
+fn check_value(what: &str, value: &str, allowed: &[&str]) -> Result<()> {
+    if allowed.contains(&value) {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid {}: {}. Expected one of {}", what, value, allowed.join(", ")))
+    }
+}
+
+// Whether a condition is still a current problem
+pub fn is_current(condition: &Condition) -> bool {
+    matches!(condition.clinical_status.as_str(), "active" | "recurrence" | "relapse")
+        && !matches!(condition.verification_status.as_str(), "refuted" | "entered-in-error")
+}
+
+impl EMR {
+    // Add a condition to the patient's problem list. Returns the new
+    // Condition's id.
+    pub fn add_condition(&mut self, patient_id: &str, condition: &NewCondition) -> Result<String> {
+        check_value("clinical status", &condition.clinical_status, CLINICAL_STATUSES)?;
+        check_value("verification status", &condition.verification_status, VERIFICATION_STATUSES)?;
+        let code = self.condition_codes.icd10_coding(&condition.icd10)?;
+        let snomed = condition.snomed.as_deref()
+            .map(|code| self.condition_codes.snomed_coding(code))
+            .transpose()?;
+        validate_clinical_time(condition.recorded)?;
+        if let Some(onset) = condition.onset {
+            validate_clinical_time(onset)?;
+            if onset > condition.recorded {
+                return Err(anyhow!("Onset {} is after the condition was recorded", onset.to_rfc3339()));
+            }
+        }
+        if self.conditions(patient_id)?.iter().any(|c| is_current(c) && c.code.code == code.code) {
+            return Err(anyhow!("{} ({}) is already on the problem list", code.display, code.code));
+        }
+
+        let record = Condition {
+            id: Uuid::new_v4().to_string(),
+            clinical_status: condition.clinical_status.clone(),
+            verification_status: condition.verification_status.clone(),
+            code: code.clone(),
+            snomed,
+            subject: Reference {
+                reference: format!("Patient/{}", patient_id),
+            },
+            onset_date_time: condition.onset.map(|onset| onset.to_rfc3339()),
+            abatement_date_time: None,
+            recorded_date: condition.recorded.to_rfc3339(),
+            note: condition.note.as_ref().map(|text| vec![Annotation { text: text.clone() }]),
+        };
+        let id = record.id.clone();
+
+        let bundle = self.bundles.get_mut(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+
+        bundle.entry.push(BundleEntry {
+            resource_type: "Condition".to_string(),
+            resource: Resource::Condition(record),
+        });
+
+        self.log_audit(&format!("Added condition: {} (ICD-10-CM {})", code.display, code.code), patient_id)?;
+
+        Ok(id)
+    }
+
+    // Mark a current problem, given by ICD-10-CM code or Condition id, as
+    // resolved at `abated`
+    pub fn resolve_condition(&mut self, patient_id: &str, code_or_id: &str, abated: DateTime<Utc>) -> Result<()> {
+        validate_clinical_time(abated)?;
+        let code = normalize_icd10(code_or_id).ok();
+        let bundle = self.bundles.get_mut(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        let condition = bundle.entry.iter_mut()
+            .filter_map(|entry| match &mut entry.resource {
+                Resource::Condition(condition) if is_current(condition) => Some(condition),
+                _ => None,
+            })
+            .find(|condition| condition.id == code_or_id || Some(&condition.code.code) == code.as_ref())
+            .ok_or_else(|| anyhow!("No current problem {} for patient {}", code_or_id, patient_id))?;
+
+        if let Some(onset) = &condition.onset_date_time {
+            if DateTime::parse_from_rfc3339(onset).is_ok_and(|onset| abated < onset) {
+                return Err(anyhow!("Resolution {} is before the onset {}", abated.to_rfc3339(), onset));
+            }
+        }
+        condition.clinical_status = "resolved".to_string();
+        condition.abatement_date_time = Some(abated.to_rfc3339());
+        let description = format!("{} ({})", condition.code.display, condition.code.code);
+
+        self.log_audit(&format!("Resolved condition: {} at {}", description, abated.to_rfc3339()), patient_id)?;
+
+        Ok(())
+    }
+
+    // The patient's problem list, current and past
+    pub fn conditions(&self, patient_id: &str) -> Result<Vec<&Condition>> {
+        let bundle = self.bundles.get(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        Ok(bundle.entry.iter()
+            .filter_map(|entry| match &entry.resource {
+                Resource::Condition(condition) => Some(condition),
+                _ => None,
+            })
+            .collect())
+    }
+}
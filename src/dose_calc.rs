@@ -0,0 +1,443 @@
+// src/dose_calc.rs
+// Charcot EMR: Weight-, body surface area- and renal function-based doses
+//
+// Doses are calculated from the latest weight, height and serum creatinine in
+// the patient's record. A missing input, or one older than its maximum age,
+// refuses the calculation rather than falling back on a guess. Renal function
+// is estimated both as eGFR (CKD-EPI 2021) and as creatinine clearance
+// (Cockcroft-Gault, actual body weight); medications with a renal adjustment
+// have their dose reduced, or are refused, below its threshold. The inputs and
+// each step of the calculation are kept on the MedicationRequest. The
+// built-in adult adjustments can be replaced by a `renal_adjustments.json`
+// file in the working directory, holding a list of `RenalAdjustment`s.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use anyhow::{Result, anyhow, Context};
+
+use crate::{EMR, Observation, PrescribeOptions, ResolvedDrug, Resource, VitalKind, units};
+
+pub const RENAL_ADJUSTMENTS_FILE: &str = "renal_adjustments.json";
+
+// Inputs older than this are too stale to dose from
+pub const WEIGHT_MAX_AGE_DAYS: i64 = 30;
+pub const HEIGHT_MAX_AGE_DAYS: i64 = 365;
+pub const CREATININE_MAX_AGE_DAYS: i64 = 7;
+
+const CREATININE_LOINC: &str = "2160-0";
+
+// Units a calculated dose can be prescribed in
+pub const DOSE_UNITS: &[&str] = &["mg", "units"];
+
+// What a dose is given per
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DoseBasis {
+    Weight,                             // per kg
+    Bsa,                                // per m2 of body surface area
+}
+
+impl DoseBasis {
+    // The `patient.<property>` a script multiplies by
+    pub fn from_property(name: &str) -> Option<DoseBasis> {
+        match name {
+            "weight" => Some(DoseBasis::Weight),
+            "bsa" => Some(DoseBasis::Bsa),
+            _ => None,
+        }
+    }
+
+    pub fn unit(self) -> &'static str {
+        match self {
+            DoseBasis::Weight => "kg",
+            DoseBasis::Bsa => "m2",
+        }
+    }
+}
+
+// e.g. 0.5 units per kg
+#[derive(Debug, Clone, PartialEq)]
+pub struct DoseOrder {
+    pub amount: f64,
+    pub unit: String,                   // mg or units
+    pub basis: DoseBasis,
+}
+
+impl fmt::Display for DoseOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}/{}", self.amount, self.unit, self.basis.unit())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenalMeasure {
+    Egfr,                               // mL/min/1.73m2
+    CrCl,                               // mL/min
+}
+
+impl fmt::Display for RenalMeasure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenalMeasure::Egfr => write!(f, "eGFR"),
+            RenalMeasure::CrCl => write!(f, "CrCl"),
+        }
+    }
+}
+
+// Below `below`, give `factor` of the dose, or avoid the drug if there is no
+// factor. A drug may have several tiers; the lowest one that applies is used.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RenalAdjustment {
+    pub rxcui: String,                  // RxNorm ingredient or drug
+    pub name: String,
+    pub measure: RenalMeasure,
+    pub below: f64,
+    pub factor: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenalAdjustments {
+    pub adjustments: Vec<RenalAdjustment>,
+}
+
+impl Default for RenalAdjustments {
+    fn default() -> Self {
+        let adjust = |rxcui: &str, name: &str, measure: RenalMeasure, below: f64, factor: Option<f64>| RenalAdjustment {
+            rxcui: rxcui.to_string(),
+            name: name.to_string(),
+            measure,
+            below,
+            factor,
+        };
+        use RenalMeasure::{CrCl, Egfr};
+        RenalAdjustments {
+            adjustments: vec![
+                // Adult adjustments
+                adjust("6809", "metformin", Egfr, 45.0, Some(0.5)),
+                adjust("6809", "metformin", Egfr, 30.0, None),
+                adjust("25480", "gabapentin", CrCl, 60.0, Some(0.5)),
+                adjust("25480", "gabapentin", CrCl, 30.0, Some(0.25)),
+                adjust("25480", "gabapentin", CrCl, 15.0, Some(0.125)),
+                adjust("67108", "enoxaparin", CrCl, 30.0, Some(0.5)),
+                adjust("1114195", "rivaroxaban", CrCl, 15.0, None),
+                adjust("3407", "digoxin", CrCl, 50.0, Some(0.5)),
+                adjust("6448", "lithium", CrCl, 30.0, None),
+                adjust("2551", "ciprofloxacin", CrCl, 30.0, Some(0.5)),
+                adjust("10689", "tramadol", CrCl, 30.0, Some(0.5)),
+                adjust("2683", "colchicine", CrCl, 30.0, Some(0.5)),
+                adjust("7052", "morphine", Egfr, 30.0, Some(0.5)),
+                adjust("519", "allopurinol", CrCl, 20.0, Some(0.5)),
+            ],
+        }
+    }
+}
+
+impl RenalAdjustments {
+    // Read a list of adjustments from a JSON file
+    pub fn load(path: &str) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read renal adjustments from {}", path))?;
+        let adjustments: Vec<RenalAdjustment> = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid renal adjustments in {}", path))?;
+        if let Some(adjustment) = adjustments.iter().find(|a| a.factor.is_some_and(|factor| !(0.0..=1.0).contains(&factor))) {
+            return Err(anyhow!("Invalid renal adjustment for {} in {}: factor must be between 0 and 1", adjustment.name, path));
+        }
+        Ok(RenalAdjustments { adjustments })
+    }
+
+    // `renal_adjustments.json` if present, otherwise the built-in adjustments
+    pub fn load_or_default() -> Result<Self> {
+        if Path::new(RENAL_ADJUSTMENTS_FILE).exists() {
+            RenalAdjustments::load(RENAL_ADJUSTMENTS_FILE)
+        } else {
+            Ok(RenalAdjustments::default())
+        }
+    }
+
+    // Adjustments for a drug, or for its ingredient if it has none of its own
+    pub fn find(&self, drug: &ResolvedDrug) -> Vec<&RenalAdjustment> {
+        let of = |rxcui: &str| -> Vec<&RenalAdjustment> {
+            self.adjustments.iter().filter(|a| a.rxcui == rxcui).collect()
+        };
+        let own = of(&drug.drug.rxcui);
+        match drug.ingredients.as_slice() {
+            [ingredient] if own.is_empty() => of(&ingredient.rxcui),
+            _ => own,
+        }
+    }
+}
+
+// A value a dose was calculated from, e.g. weight "85.5 kg" from an Observation
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CalculationInput {
+    pub name: String,
+    pub value: String,
+    pub source: Option<String>,         // Observation/<id>, or Patient/<id> for age and sex
+    pub effective: Option<String>,      // When it was measured
+}
+
+impl fmt::Display for CalculationInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.value)?;
+        if let Some(effective) = &self.effective {
+            write!(f, " ({})", effective)?;
+        }
+        Ok(())
+    }
+}
+
+// How a dose was worked out, kept with the MedicationRequest
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DoseCalculation {
+    pub order: String,                  // e.g. "0.5 units/kg"
+    pub inputs: Vec<CalculationInput>,
+    pub steps: Vec<String>,             // e.g. "0.5 units/kg x 85.5 kg = 42.75 units"
+    pub dose: f64,
+    pub unit: String,
+    pub calculated_at: String,
+}
+
+impl fmt::Display for DoseCalculation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} ({})", self.dose, self.unit, self.steps.join("; "))
+    }
+}
+
+// Estimated renal function with what it was estimated from
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenalFunction {
+    pub egfr: f64,                      // mL/min/1.73m2
+    pub crcl: f64,                      // mL/min
+    pub inputs: Vec<CalculationInput>,
+    pub steps: Vec<String>,
+}
+
+impl RenalFunction {
+    pub fn value(&self, measure: RenalMeasure) -> f64 {
+        match measure {
+            RenalMeasure::Egfr => self.egfr,
+            RenalMeasure::CrCl => self.crcl,
+        }
+    }
+}
+
+impl fmt::Display for RenalFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "eGFR {} mL/min/1.73m2, CrCl {} mL/min", round(self.egfr, 1), round(self.crcl, 1))
+    }
+}
+
+// Body surface area in m2 (Mosteller)
+pub fn bsa_mosteller(weight_kg: f64, height_cm: f64) -> f64 {
+    (weight_kg * height_cm / 3600.0).sqrt()
+}
+
+// eGFR in mL/min/1.73m2 (CKD-EPI 2021, without race)
+pub fn egfr_ckd_epi(creatinine_mg_dl: f64, age: f64, female: bool) -> f64 {
+    let (kappa, alpha) = if female { (0.7, -0.241) } else { (0.9, -0.302) };
+    let ratio = creatinine_mg_dl / kappa;
+    142.0 * ratio.min(1.0).powf(alpha) * ratio.max(1.0).powf(-1.2) * 0.9938f64.powf(age)
+        * if female { 1.012 } else { 1.0 }
+}
+
+// Creatinine clearance in mL/min (Cockcroft-Gault)
+pub fn crcl_cockcroft_gault(creatinine_mg_dl: f64, age: f64, weight_kg: f64, female: bool) -> f64 {
+    (140.0 - age) * weight_kg / (72.0 * creatinine_mg_dl) * if female { 0.85 } else { 1.0 }
+}
+
+fn round(value: f64, places: i32) -> f64 {
+    let scale = 10f64.powi(places);
+    (value * scale).round() / scale
+}
+
+// Whole units; mg to one decimal place
+fn round_dose(dose: f64, unit: &str) -> f64 {
+    if unit == "units" { dose.round() } else { round(dose, 1) }
+}
+
+fn input(name: &str, value: String, observation: &Observation) -> CalculationInput {
+    CalculationInput {
+        name: name.to_string(),
+        value,
+        source: Some(format!("Observation/{}", observation.id)),
+        effective: Some(observation.effective_date_time.clone()),
+    }
+}
+
+impl EMR {
+    // The latest `loinc` observation taken by `at`, converted to `unit`.
+    // Fails if there is none or it is more than `max_age_days` old.
+    fn latest_measurement(&self, patient_id: &str, loinc: &str, what: &str, unit: &str,
+                          max_age_days: i64, at: DateTime<Utc>) -> Result<(&Observation, f64)> {
+        let bundle = self.bundles.get(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        let latest = bundle.entry.iter()
+            .filter_map(|entry| match &entry.resource {
+                Resource::Observation(observation) if observation.code.code == loinc
+                    && observation.status != "entered-in-error" => {
+                    let taken = DateTime::parse_from_rfc3339(&observation.effective_date_time).ok()?;
+                    let quantity = observation.value_quantity.as_ref()?;
+                    Some((taken.with_timezone(&Utc), observation, quantity))
+                }
+                _ => None,
+            })
+            .filter(|(taken, _, _)| *taken <= at)
+            .max_by_key(|(taken, _, _)| *taken);
+        let Some((taken, observation, quantity)) = latest else {
+            return Err(anyhow!("No {} is recorded for patient {}; record one to calculate the dose", what, patient_id));
+        };
+        let age = (at - taken).num_days();
+        if age > max_age_days {
+            return Err(anyhow!("The latest {} for patient {} ({} {}, taken {}) is {} days old; \
+                                dose calculations need one from the last {} days",
+                               what, patient_id, quantity.value, quantity.unit, observation.effective_date_time,
+                               age, max_age_days));
+        }
+        let value = units::convert(quantity.value, &quantity.code, unit)
+            .with_context(|| format!("The latest {} for patient {} is in {}", what, patient_id, quantity.unit))?;
+        Ok((observation, value))
+    }
+
+    // Age in whole years at `at` and whether the patient is female, as the
+    // renal function equations need them
+    fn age_and_sex(&self, patient_id: &str, at: DateTime<Utc>) -> Result<(f64, bool, Vec<CalculationInput>)> {
+        let bundle = self.bundles.get(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        let patient = bundle.entry.iter()
+            .find_map(|entry| match &entry.resource {
+                Resource::Patient(patient) => Some(patient),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("Patient {} has no Patient resource", patient_id))?;
+        let born = NaiveDate::parse_from_str(&patient.birth_date, "%Y-%m-%d")
+            .map_err(|_| anyhow!("Patient {} has no valid birth date ({})", patient_id, patient.birth_date))?;
+        let today = at.date_naive();
+        let mut age = today.year() - born.year();
+        if (today.month(), today.day()) < (born.month(), born.day()) {
+            age -= 1;
+        }
+        let female = match patient.gender.as_str() {
+            "female" => true,
+            "male" => false,
+            other => return Err(anyhow!("Renal function equations need a sex of male or female; patient {} is {}",
+                                        patient_id, other)),
+        };
+        let source = Some(format!("Patient/{}", patient_id));
+        let inputs = vec![
+            CalculationInput { name: "age".to_string(), value: format!("{} years", age), source: source.clone(), effective: None },
+            CalculationInput { name: "sex".to_string(), value: patient.gender.clone(), source, effective: None },
+        ];
+        Ok((age as f64, female, inputs))
+    }
+
+    // eGFR and creatinine clearance from the latest creatinine and weight
+    pub fn renal_function(&self, patient_id: &str, at: DateTime<Utc>) -> Result<RenalFunction> {
+        let (creatinine_observation, creatinine) = self.latest_measurement(
+            patient_id, CREATININE_LOINC, "serum creatinine", "mg/dL", CREATININE_MAX_AGE_DAYS, at)?;
+        let (weight_observation, weight) = self.latest_measurement(
+            patient_id, VitalKind::Weight.loinc_code(), "weight", "kg", WEIGHT_MAX_AGE_DAYS, at)?;
+        let (age, female, mut inputs) = self.age_and_sex(patient_id, at)?;
+        inputs.push(input("serum creatinine", format!("{} mg/dL", creatinine), creatinine_observation));
+        inputs.push(input("weight", format!("{} kg", weight), weight_observation));
+
+        let egfr = egfr_ckd_epi(creatinine, age, female);
+        let crcl = crcl_cockcroft_gault(creatinine, age, weight, female);
+        let steps = vec![
+            format!("eGFR (CKD-EPI 2021) = {} mL/min/1.73m2", round(egfr, 1)),
+            format!("CrCl (Cockcroft-Gault) = (140 - {}) x {} kg / (72 x {} mg/dL){} = {} mL/min",
+                    age, weight, creatinine, if female { " x 0.85" } else { "" }, round(crcl, 1)),
+        ];
+        Ok(RenalFunction { egfr, crcl, inputs, steps })
+    }
+
+    // Work out a weight- or BSA-based dose of `medication` as of `at`,
+    // adjusted for renal function if the medication needs it
+    pub fn calculate_dose(&self, patient_id: &str, medication: &str, order: &DoseOrder,
+                          at: DateTime<Utc>) -> Result<DoseCalculation> {
+        if order.amount <= 0.0 || !order.amount.is_finite() {
+            return Err(anyhow!("Invalid dose: {}", order));
+        }
+        if !DOSE_UNITS.contains(&order.unit.as_str()) {
+            return Err(anyhow!("Calculated doses must be in {}, not {}", DOSE_UNITS.join(" or "), order.unit));
+        }
+        let mut inputs = Vec::new();
+        let mut steps = Vec::new();
+
+        let (weight_observation, weight) = self.latest_measurement(
+            patient_id, VitalKind::Weight.loinc_code(), "weight", "kg", WEIGHT_MAX_AGE_DAYS, at)?;
+        inputs.push(input("weight", format!("{} kg", weight), weight_observation));
+        let base = match order.basis {
+            DoseBasis::Weight => weight,
+            DoseBasis::Bsa => {
+                let (height_observation, height) = self.latest_measurement(
+                    patient_id, VitalKind::Height.loinc_code(), "height", "cm", HEIGHT_MAX_AGE_DAYS, at)?;
+                inputs.push(input("height", format!("{} cm", height), height_observation));
+                let bsa = round(bsa_mosteller(weight, height), 2);
+                steps.push(format!("BSA (Mosteller) = sqrt({} cm x {} kg / 3600) = {} m2", height, weight, bsa));
+                bsa
+            }
+        };
+        let mut dose = order.amount * base;
+        steps.push(format!("{} x {} {} = {} {}", order, base, order.basis.unit(), round(dose, 2), order.unit));
+
+        let adjustments = match self.drugs.resolve(medication) {
+            Ok(drug) => self.renal_adjustments.find(&drug),
+            Err(_) => Vec::new(),
+        };
+        if !adjustments.is_empty() {
+            let renal = self.renal_function(patient_id, at)?;
+            for input in renal.inputs.iter() {
+                if !inputs.iter().any(|existing: &CalculationInput| existing.name == input.name) {
+                    inputs.push(input.clone());
+                }
+            }
+            steps.extend(renal.steps.iter().cloned());
+            let applies = adjustments.iter()
+                .filter(|a| renal.value(a.measure) < a.below)
+                .min_by(|a, b| a.below.total_cmp(&b.below));
+            if let Some(adjustment) = applies {
+                let value = round(renal.value(adjustment.measure), 1);
+                let Some(factor) = adjustment.factor else {
+                    return Err(anyhow!("{} should be avoided with {} {} (below {})",
+                                       adjustment.name, adjustment.measure, value, adjustment.below));
+                };
+                dose *= factor;
+                steps.push(format!("{} {} is below {}: {}% of the dose = {} {}", adjustment.measure, value,
+                                   adjustment.below, factor * 100.0, round(dose, 2), order.unit));
+            }
+        }
+
+        let rounded = round_dose(dose, &order.unit);
+        if rounded <= 0.0 {
+            return Err(anyhow!("The calculated dose of {} {} rounds to nothing", round(dose, 2), order.unit));
+        }
+        if rounded != round(dose, 2) {
+            steps.push(format!("Rounded to {} {}", rounded, order.unit));
+        }
+        Ok(DoseCalculation {
+            order: order.to_string(),
+            inputs,
+            steps,
+            dose: rounded,
+            unit: order.unit.clone(),
+            calculated_at: at.to_rfc3339(),
+        })
+    }
+
+    // Calculate a dose as of when the prescription is written, prescribe it
+    // and keep the calculation on the MedicationRequest. Returns the new
+    // MedicationRequest's id and the calculation.
+    pub fn prescribe_calculated_dose(&mut self, patient_id: &str, medication: &str, order: &DoseOrder,
+                                     frequency: &str, options: &PrescribeOptions) -> Result<(String, DoseCalculation)> {
+        let calculation = self.calculate_dose(patient_id, medication, order, options.authored)?;
+        let id = self.prescribe_dose(patient_id, medication, calculation.dose, &calculation.unit, frequency, options)?;
+        self.request_mut(patient_id, &id)?.dose_calculation = Some(calculation.clone());
+
+        let inputs: Vec<String> = calculation.inputs.iter().map(|input| input.to_string()).collect();
+        self.log_audit(&format!("Dose calculation for {}: {}. Inputs: {}", medication, calculation,
+                               inputs.join(", ")), patient_id)?;
+        Ok((id, calculation))
+    }
+}
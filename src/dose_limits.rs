@@ -0,0 +1,251 @@
+// src/dose_limits.rs
+// Charcot EMR: Single-dose and daily-dose limits for prescriptions
+//
+// Each limit has two tiers. Going over the usual dose is a warning that can
+// be overridden with a reason, which is kept in the audit trail; going over
+// the maximum is a hard stop. Daily totals come from the parsed dosing
+// frequency. The built-in adult limits can be replaced by a
+// `dose_limits.json` file in the working directory, holding a list of
+// `DoseLimit`s; limits with an empty `rxcui` apply by name to medications
+// that are not in the RxNorm dictionary. Amounts are in mg, or in units for
+// medications dosed in units such as insulin, which can only be prescribed
+// in units and only with a limit.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, anyhow, Context};
+
+use crate::{ResolvedDrug, Sig};
+
+pub const DOSE_LIMITS_FILE: &str = "dose_limits.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DoseLimit {
+    pub rxcui: String,                  // RxNorm ingredient or drug
+    pub name: String,
+    #[serde(default = "default_unit")]
+    pub unit: String,                   // Unit of the amounts below, "mg" or "units"
+    pub usual_single_mg: Option<f64>,
+    pub max_single_mg: Option<f64>,
+    pub usual_daily_mg: Option<f64>,
+    pub max_daily_mg: Option<f64>,
+}
+
+fn default_unit() -> String {
+    "mg".to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DoseSeverity {
+    Warning,                            // Allowed with an override reason
+    HardStop,                           // Never allowed
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DoseAlert {
+    pub severity: DoseSeverity,
+    pub message: String,
+}
+
+impl fmt::Display for DoseAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl DoseLimit {
+    // Alerts for `dose_mg`, in the limit's unit, given on the schedule of `sig`
+    pub fn check(&self, dose_mg: f64, sig: &Sig) -> Vec<DoseAlert> {
+        let mut alerts = Vec::new();
+        let mut tier = |amount: f64, what: &str, usual: Option<f64>, max: Option<f64>| {
+            if max.is_some_and(|max| amount > max) {
+                alerts.push(DoseAlert {
+                    severity: DoseSeverity::HardStop,
+                    message: format!("{} {} of {} {} exceeds the maximum of {} {}", self.name, what,
+                                     format_mg(amount), self.unit, format_mg(max.unwrap_or_default()), self.unit),
+                });
+            } else if usual.is_some_and(|usual| amount > usual) {
+                alerts.push(DoseAlert {
+                    severity: DoseSeverity::Warning,
+                    message: format!("{} {} of {} {} is above the usual {} {}", self.name, what,
+                                     format_mg(amount), self.unit, format_mg(usual.unwrap_or_default()), self.unit),
+                });
+            }
+        };
+        tier(dose_mg, "single dose", self.usual_single_mg, self.max_single_mg);
+        // As-needed doses count at their most frequent
+        if let Some(per_day) = sig.doses_per_day() {
+            tier(dose_mg * per_day, "daily dose", self.usual_daily_mg, self.max_daily_mg);
+        }
+        alerts
+    }
+}
+
+fn format_mg(amount: f64) -> String {
+    let rounded = (amount * 100.0).round() / 100.0;
+    rounded.to_string()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DoseLimits {
+    pub limits: Vec<DoseLimit>,
+}
+
+impl Default for DoseLimits {
+    fn default() -> Self {
+        let in_units = |rxcui: &str, name: &str, unit: &str, (usual_single, max_single): (Option<f64>, Option<f64>),
+                        (usual_daily, max_daily): (Option<f64>, Option<f64>)| DoseLimit {
+            rxcui: rxcui.to_string(),
+            name: name.to_string(),
+            unit: unit.to_string(),
+            usual_single_mg: usual_single,
+            max_single_mg: max_single,
+            usual_daily_mg: usual_daily,
+            max_daily_mg: max_daily,
+        };
+        let limit = |rxcui: &str, name: &str, single, daily| in_units(rxcui, name, "mg", single, daily);
+        DoseLimits {
+            limits: vec![
+                // Adult oral doses
+                limit("161", "acetaminophen", (Some(1000.0), Some(1000.0)), (Some(3000.0), Some(4000.0))),
+                limit("1191", "aspirin", (Some(1000.0), Some(1000.0)), (Some(3000.0), Some(4000.0))),
+                limit("5640", "ibuprofen", (Some(400.0), Some(800.0)), (Some(2400.0), Some(3200.0))),
+                limit("6809", "metformin", (Some(1000.0), Some(2550.0)), (Some(2000.0), Some(2550.0))),
+                limit("4821", "glipizide", (Some(20.0), Some(20.0)), (Some(20.0), Some(40.0))),
+                limit("29046", "lisinopril", (Some(40.0), Some(80.0)), (Some(40.0), Some(80.0))),
+                limit("17767", "amlodipine", (Some(10.0), Some(10.0)), (Some(10.0), Some(10.0))),
+                limit("6918", "metoprolol", (Some(200.0), Some(400.0)), (Some(200.0), Some(400.0))),
+                limit("4603", "furosemide", (Some(80.0), Some(200.0)), (Some(160.0), Some(600.0))),
+                limit("83367", "atorvastatin", (Some(80.0), Some(80.0)), (Some(80.0), Some(80.0))),
+                limit("36567", "simvastatin", (Some(40.0), Some(80.0)), (Some(40.0), Some(80.0))),
+                limit("11289", "warfarin", (Some(10.0), Some(20.0)), (Some(10.0), Some(20.0))),
+                limit("704", "amitriptyline", (Some(100.0), Some(150.0)), (Some(150.0), Some(300.0))),
+                limit("36437", "sertraline", (Some(200.0), Some(200.0)), (Some(200.0), Some(200.0))),
+                limit("4493", "fluoxetine", (Some(60.0), Some(80.0)), (Some(60.0), Some(80.0))),
+                limit("2556", "citalopram", (Some(40.0), Some(40.0)), (Some(20.0), Some(40.0))),
+                limit("25480", "gabapentin", (Some(800.0), Some(1200.0)), (Some(2400.0), Some(3600.0))),
+                limit("10689", "tramadol", (Some(100.0), Some(100.0)), (Some(300.0), Some(400.0))),
+                limit("10582", "levothyroxine", (Some(0.2), Some(0.3)), (Some(0.2), Some(0.3))),
+                limit("519", "allopurinol", (Some(300.0), Some(800.0)), (Some(300.0), Some(800.0))),
+                limit("2683", "colchicine", (Some(0.6), Some(1.2)), (Some(1.2), Some(1.8))),
+                // Given weekly: 25 mg a week is about 3.6 mg a day
+                limit("6851", "methotrexate", (Some(25.0), Some(25.0)), (Some(3.6), Some(3.6))),
+                // Adult insulin doses, in units: basal once daily, mealtime up to three times
+                in_units("274783", "insulin glargine", "units", (Some(60.0), Some(100.0)), (Some(60.0), Some(100.0))),
+                in_units("86009", "insulin lispro", "units", (Some(20.0), Some(50.0)), (Some(60.0), Some(150.0))),
+                in_units("51428", "insulin aspart", "units", (Some(20.0), Some(50.0)), (Some(60.0), Some(150.0))),
+            ],
+        }
+    }
+}
+
+impl DoseLimits {
+    // Read a list of limits from a JSON file
+    pub fn load(path: &str) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read dose limits from {}", path))?;
+        let limits: Vec<DoseLimit> = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid dose limits in {}", path))?;
+        for limit in &limits {
+            if !matches!(limit.unit.as_str(), "mg" | "units") {
+                return Err(anyhow!("Invalid dose limit for {} in {}: unit must be mg or units", limit.name, path));
+            }
+            let tiers = [(limit.usual_single_mg, limit.max_single_mg), (limit.usual_daily_mg, limit.max_daily_mg)];
+            if tiers.iter().any(|(usual, max)| matches!((usual, max), (Some(usual), Some(max)) if usual > max)) {
+                return Err(anyhow!("Invalid dose limit for {} in {}: usual dose above maximum", limit.name, path));
+            }
+        }
+        Ok(DoseLimits { limits })
+    }
+
+    // `dose_limits.json` if present, otherwise the built-in limits
+    pub fn load_or_default() -> Result<Self> {
+        if Path::new(DOSE_LIMITS_FILE).exists() {
+            DoseLimits::load(DOSE_LIMITS_FILE)
+        } else {
+            Ok(DoseLimits::default())
+        }
+    }
+
+    pub fn find(&self, rxcui: &str) -> Option<&DoseLimit> {
+        self.limits.iter().find(|limit| limit.rxcui == rxcui)
+    }
+
+    // A limit for a medication not in the RxNorm dictionary, by name
+    pub fn find_unlisted(&self, name: &str) -> Option<&DoseLimit> {
+        self.limits.iter().find(|limit| limit.rxcui.is_empty() && limit.name.eq_ignore_ascii_case(name.trim()))
+    }
+
+    // The limit for `drug`. Clinical drugs without a limit of their own fall
+    // back to their ingredient; combinations have none since the dose covers
+    // several ingredients.
+    pub fn limit_for(&self, drug: &ResolvedDrug) -> Option<&DoseLimit> {
+        self.find(&drug.drug.rxcui).or_else(|| match drug.ingredients.as_slice() {
+            [ingredient] => self.find(&ingredient.rxcui),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_sig;
+
+    fn check(rxcui: &str, dose_mg: f64, frequency: &str) -> Vec<(DoseSeverity, String)> {
+        let limits = DoseLimits::default();
+        limits.find(rxcui).unwrap().check(dose_mg, &parse_sig(frequency).unwrap()).into_iter()
+            .map(|alert| (alert.severity, alert.message))
+            .collect()
+    }
+
+    #[test]
+    fn within_usual_dose() {
+        assert!(check("6809", 1000.0, "twice daily").is_empty());
+    }
+
+    #[test]
+    fn warning_tier() {
+        assert_eq!(check("6809", 1500.0, "daily"), vec![
+            (DoseSeverity::Warning, "metformin single dose of 1500 mg is above the usual 1000 mg".to_string()),
+        ]);
+        assert_eq!(check("6809", 750.0, "three times daily"), vec![
+            (DoseSeverity::Warning, "metformin daily dose of 2250 mg is above the usual 2000 mg".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn hard_stop_tier() {
+        assert_eq!(check("6809", 3000.0, "daily"), vec![
+            (DoseSeverity::HardStop, "metformin single dose of 3000 mg exceeds the maximum of 2550 mg".to_string()),
+            (DoseSeverity::HardStop, "metformin daily dose of 3000 mg exceeds the maximum of 2550 mg".to_string()),
+        ]);
+        // As-needed doses count at their most frequent
+        assert_eq!(check("161", 1000.0, "q4h prn")[0].0, DoseSeverity::HardStop);
+    }
+
+    #[test]
+    fn unlisted_limits_by_name() {
+        let mut limits = DoseLimits::default();
+        limits.limits.push(DoseLimit {
+            rxcui: String::new(),
+            name: "zorblatide".to_string(),
+            unit: "mg".to_string(),
+            usual_single_mg: Some(5.0),
+            max_single_mg: Some(10.0),
+            usual_daily_mg: None,
+            max_daily_mg: None,
+        });
+        assert!(limits.find_unlisted(" Zorblatide ").is_some());
+        assert!(limits.find_unlisted("metformin").is_none());
+    }
+
+    #[test]
+    fn insulin_limits_are_in_units() {
+        assert_eq!(check("274783", 120.0, "daily")[0],
+                   (DoseSeverity::HardStop, "insulin glargine single dose of 120 units exceeds the maximum of 100 units".to_string()));
+        assert!(check("274783", 43.0, "daily").is_empty());
+    }
+}
@@ -0,0 +1,399 @@
+// src/history.rs
+// Charcot EMR: Version history - snapshots, log, show, diff and revert
+//
+// Every commit keeps a snapshot of the record. Entries are stored once in the
+// bundle's object store under the SHA-256 of their JSON, and a version lists
+// the hashes of the entries it held, so a commit that changes one observation
+// stores only that observation again. History is never rewritten: reverting
+// commits the old state as a new version.
+//
+// Versions form a hash chain: each hash covers the hash of the version before
+// it, so changing any earlier version, its snapshot or the order of versions
+// breaks every link after it.
+
+use std::collections::HashMap;
+use std::fmt;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use sha2::{Sha256, Digest};
+use anyhow::{Result, anyhow};
+
+use crate::{Bundle, BundleEntry, EMR, Resource, Signer, VersionEntry, entries_hash};
+
+// Characters of a hash shown in logs
+pub const SHORT_HASH_LEN: usize = 12;
+
+// Shortest hash prefix accepted in place of a version number
+pub const MIN_HASH_PREFIX: usize = 4;
+
+// Message of the first version of a record loaded from a file saved before
+// the history was kept
+pub const EARLIER_HISTORY: &str = "Earlier history not recorded in this file";
+
+pub fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(SHORT_HASH_LEN)]
+}
+
+// SHA-256 of one entry, its address in the object store
+pub fn entry_hash(entry: &BundleEntry) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_string(entry)?.as_bytes());
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Store the bundle's current entries and return their hashes, in order
+pub(crate) fn store_snapshot(bundle: &mut Bundle) -> Result<Vec<String>> {
+    let mut tree = Vec::with_capacity(bundle.entry.len());
+    for entry in &bundle.entry {
+        let hash = entry_hash(entry)?;
+        bundle.objects.entry(hash.clone()).or_insert_with(|| entry.clone());
+        tree.push(hash);
+    }
+    Ok(tree)
+}
+
+// What a version's hash covers
+#[derive(Serialize)]
+struct ChainLink<'a> {
+    parent: &'a str,
+    author: &'a str,
+    timestamp: String,
+    message: &'a str,
+    content: &'a str,
+}
+
+// The chained hash of a version with the given parent and content hashes
+pub fn version_hash(parent: &str, author: &str, timestamp: DateTime<Utc>, message: &str, content: &str) -> Result<String> {
+    let link = ChainLink {
+        parent,
+        author,
+        timestamp: timestamp.to_rfc3339_opts(SecondsFormat::Nanos, true),
+        message,
+        content,
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_string(&link)?.as_bytes());
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Commit the bundle's current entries as a new version, chained to the last
+// and signed by `signer` if a clinician is signed in
+pub(crate) fn add_version(bundle: &mut Bundle, message: &str, author: &str, timestamp: DateTime<Utc>,
+                          signer: Option<&Signer>) -> Result<()> {
+    let tree = store_snapshot(bundle)?;
+    let content = entries_hash(&bundle.entry)?;
+    let parent = bundle.version_history.last().map(|version| version.hash.clone()).unwrap_or_default();
+    let hash = version_hash(&parent, author, timestamp, message, &content)?;
+    bundle.version_history.push(VersionEntry {
+        timestamp,
+        message: message.to_string(),
+        signature: signer.map(|signer| signer.sign(&hash)),
+        hash,
+        author: author.to_string(),
+        tree,
+        parent,
+        content,
+    });
+    Ok(())
+}
+
+// The result of walking a record's hash chain
+#[derive(Debug, Clone)]
+pub struct ChainReport {
+    pub versions: usize,
+    pub broken: Option<(usize, String)>,    // Index of the first broken version, and what is wrong
+}
+
+impl ChainReport {
+    pub fn is_intact(&self) -> bool {
+        self.broken.is_none()
+    }
+}
+
+impl fmt::Display for ChainReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.broken {
+            Some((index, problem)) => write!(f, "History broken at version {} of {}: {}", index + 1, self.versions, problem),
+            None => write!(f, "History intact: {} versions", self.versions),
+        }
+    }
+}
+
+// Check every version's hash, its link to the version before and its
+// snapshot, and that the record still holds what the last version committed.
+// Stops at the first broken link.
+pub fn verify_chain(bundle: &Bundle) -> ChainReport {
+    let mut report = ChainReport { versions: bundle.version_history.len(), broken: None };
+    let mut previous: Option<&VersionEntry> = None;
+    for (i, version) in bundle.version_history.iter().enumerate() {
+        if let Err(problem) = verify_version(bundle, version, previous) {
+            report.broken = Some((i, problem));
+            return report;
+        }
+        previous = Some(version);
+    }
+    if let Some(head) = previous {
+        if entries_hash(&bundle.entry).ok().as_ref() != Some(&head.content) {
+            report.broken = Some((report.versions - 1,
+                                  "the record was changed after this version without a new commit".to_string()));
+        }
+    }
+    report
+}
+
+fn verify_version(bundle: &Bundle, version: &VersionEntry, previous: Option<&VersionEntry>) -> Result<(), String> {
+    let expected = previous.map(|previous| previous.hash.as_str()).unwrap_or_default();
+    if version.parent != expected {
+        return Err(format!("its parent {} is not the version before ({})",
+                           short_hash(&version.parent), short_hash(expected)));
+    }
+    let hash = version_hash(&version.parent, &version.author, version.timestamp, &version.message, &version.content)
+        .map_err(|e| e.to_string())?;
+    if hash != version.hash {
+        return Err("its hash does not match its author, time, message, parent and content".to_string());
+    }
+    // The tree is not hashed; the content hash its entries must match is
+    if version.tree.is_empty() {
+        return Err("it has no snapshot".to_string());
+    }
+    let entries: Vec<BundleEntry> = version.tree.iter()
+        .map(|hash| bundle.objects.get(hash).cloned()
+            .ok_or_else(|| format!("its snapshot refers to entry {} which is missing", short_hash(hash))))
+        .collect::<Result<_, _>>()?;
+    for (hash, entry) in version.tree.iter().zip(&entries) {
+        if entry_hash(entry).ok().as_ref() != Some(hash) {
+            return Err(format!("stored entry {} has been altered", short_hash(hash)));
+        }
+    }
+    if entries_hash(&entries).ok().as_ref() != Some(&version.content) {
+        return Err("its snapshot does not match its content hash".to_string());
+    }
+    Ok(())
+}
+
+// "Observation/<id>", identifying a resource across versions
+pub fn resource_key(resource: &Resource) -> String {
+    let (kind, id) = match resource {
+        Resource::Patient(patient) => ("Patient", &patient.id),
+        Resource::Observation(observation) => ("Observation", &observation.id),
+        Resource::MedicationRequest(request) => ("MedicationRequest", &request.id),
+        Resource::MedicationAdministration(administration) => ("MedicationAdministration", &administration.id),
+        Resource::DiagnosticReport(report) => ("DiagnosticReport", &report.id),
+        Resource::AllergyIntolerance(allergy) => ("AllergyIntolerance", &allergy.id),
+        Resource::Condition(condition) => ("Condition", &condition.id),
+    };
+    format!("{}/{}", kind, id)
+}
+
+// A one-line summary, e.g. "Heart rate 72 beats/min" or "metformin 500 mg twice daily (active)"
+pub fn describe_resource(resource: &Resource) -> String {
+    match resource {
+        Resource::Patient(patient) => match patient.name.first() {
+            Some(name) => format!("{} {}", name.given.join(" "), name.family.clone().unwrap_or_default()),
+            None => "Patient".to_string(),
+        },
+        Resource::Observation(observation) => match (&observation.value_quantity, &observation.component) {
+            (Some(quantity), _) => format!("{} {} {}", observation.code.display, quantity.value, quantity.unit),
+            (None, Some(components)) => {
+                let values: Vec<String> = components.iter().map(|c| c.value_quantity.value.to_string()).collect();
+                let unit = components.first().map(|c| c.value_quantity.unit.as_str()).unwrap_or_default();
+                format!("{} {} {}", observation.code.display, values.join("/"), unit)
+            }
+            (None, None) => observation.code.display.clone(),
+        },
+        Resource::MedicationRequest(request) => {
+            let dosage = request.dosage_instruction.first().map(|d| d.text.as_str()).unwrap_or_default();
+            format!("{} {} ({})", request.medication_codeable_concept.display, dosage, request.status)
+        }
+        Resource::MedicationAdministration(administration) => format!("{} {} at {}",
+            administration.medication_codeable_concept.display, administration.status, administration.effective_date_time),
+        Resource::DiagnosticReport(report) => report.code.display.clone(),
+        Resource::AllergyIntolerance(allergy) => format!("{} ({})", allergy.code.display, allergy.clinical_status),
+        Resource::Condition(condition) => format!("{} ({})", condition.code.display, condition.clinical_status),
+    }
+}
+
+// A resource added, removed or modified between two versions
+#[derive(Debug, Clone)]
+pub enum ResourceChange {
+    Added(BundleEntry),
+    Removed(BundleEntry),
+    Modified { before: Box<BundleEntry>, after: Box<BundleEntry> },
+}
+
+impl ResourceChange {
+    // Top-level fields that differ, with old and new values where they are short
+    pub fn fields(&self) -> Vec<String> {
+        let ResourceChange::Modified { before, after } = self else {
+            return Vec::new();
+        };
+        let (Ok(serde_json::Value::Object(before)), Ok(serde_json::Value::Object(after))) =
+            (serde_json::to_value(&before.resource), serde_json::to_value(&after.resource)) else {
+            return Vec::new();
+        };
+        let mut names: Vec<&String> = before.keys().chain(after.keys().filter(|name| !before.contains_key(*name))).collect();
+        names.retain(|name| before.get(*name) != after.get(*name));
+        names.into_iter()
+            .map(|name| match (before.get(name), after.get(name)) {
+                (Some(old), Some(new)) if is_scalar(old) && is_scalar(new) => format!("{}: {} -> {}", name, old, new),
+                _ => name.clone(),
+            })
+            .collect()
+    }
+}
+
+fn is_scalar(value: &serde_json::Value) -> bool {
+    !value.is_array() && !value.is_object()
+}
+
+impl fmt::Display for ResourceChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceChange::Added(entry) => write!(f, "+ {}: {}",
+                resource_key(&entry.resource), describe_resource(&entry.resource)),
+            ResourceChange::Removed(entry) => write!(f, "- {}: {}",
+                resource_key(&entry.resource), describe_resource(&entry.resource)),
+            ResourceChange::Modified { after, .. } => write!(f, "~ {}: {} [{}]",
+                resource_key(&after.resource), describe_resource(&after.resource), self.fields().join("; ")),
+        }
+    }
+}
+
+// Resource-level changes from `before` to `after`
+pub fn diff_entries(before: &[BundleEntry], after: &[BundleEntry]) -> Result<Vec<ResourceChange>> {
+    let mut old: HashMap<String, (&BundleEntry, String)> = HashMap::new();
+    for entry in before {
+        old.insert(resource_key(&entry.resource), (entry, entry_hash(entry)?));
+    }
+    let mut changes = Vec::new();
+    for entry in after {
+        let key = resource_key(&entry.resource);
+        match old.remove(&key) {
+            None => changes.push(ResourceChange::Added(entry.clone())),
+            Some((previous, hash)) if hash != entry_hash(entry)? => changes.push(ResourceChange::Modified {
+                before: Box::new(previous.clone()),
+                after: Box::new(entry.clone()),
+            }),
+            Some(_) => {}
+        }
+    }
+    // Removed resources, in the order they were in
+    for entry in before {
+        if old.contains_key(&resource_key(&entry.resource)) {
+            changes.push(ResourceChange::Removed(entry.clone()));
+        }
+    }
+    Ok(changes)
+}
+
+impl EMR {
+    pub fn history(&self, patient_id: &str) -> Result<&[VersionEntry]> {
+        let bundle = self.bundles.get(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        Ok(&bundle.version_history)
+    }
+
+    // The index of a version given by its number in the log (from 1), as
+    // "3" or "#3", or a prefix of its hash. A number that is also the start of
+    // a hash is refused as ambiguous. Versions committed before hashes were
+    // chained can share a hash, and then hold the same entries; the latest is
+    // used.
+    pub fn find_version(&self, patient_id: &str, rev: &str) -> Result<usize> {
+        let history = self.history(patient_id)?;
+        let rev = rev.trim();
+        let in_range = |number: &str| number.parse::<usize>().ok()
+            .filter(|number| (1..=history.len()).contains(number));
+        if let Some(number) = rev.strip_prefix('#') {
+            return in_range(number).map(|number| number - 1)
+                .ok_or_else(|| anyhow!("No version {} for patient {}; versions are numbered from 1 to {}",
+                                       rev, patient_id, history.len()));
+        }
+        let number = in_range(rev);
+        if rev.len() < MIN_HASH_PREFIX || !rev.chars().all(|c| c.is_ascii_hexdigit()) {
+            return number.map(|number| number - 1)
+                .ok_or_else(|| anyhow!("No version {} for patient {}; give a version number from 1 to {} or at least {} characters of its hash",
+                                       rev, patient_id, history.len(), MIN_HASH_PREFIX));
+        }
+        let rev = rev.to_ascii_lowercase();
+        let mut hashes: Vec<&str> = history.iter()
+            .filter(|version| version.hash.starts_with(&rev))
+            .map(|version| version.hash.as_str())
+            .collect();
+        hashes.sort_unstable();
+        hashes.dedup();
+        match (hashes.as_slice(), number) {
+            ([], Some(number)) => Ok(number - 1),
+            ([], None) => Err(anyhow!("No version with hash {} for patient {}", rev, patient_id)),
+            (_, Some(number)) => Err(anyhow!("{} is both version {} and the start of a hash; write #{} for the version \
+                                              or give more of the hash", rev, number, number)),
+            ([hash], None) => Ok(history.iter().rposition(|version| version.hash == *hash).unwrap_or_default()),
+            (_, None) => Err(anyhow!("Hash {} matches {} versions; give more of it", rev, hashes.len())),
+        }
+    }
+
+    // The entries as they were committed in version `index`
+    pub fn snapshot(&self, patient_id: &str, index: usize) -> Result<Vec<BundleEntry>> {
+        let bundle = self.bundles.get(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        let version = bundle.version_history.get(index)
+            .ok_or_else(|| anyhow!("Patient {} has no version {}", patient_id, index + 1))?;
+        if version.tree.is_empty() {
+            return Err(anyhow!("Version {} ({}) has no snapshot", index + 1, version.message));
+        }
+        version.tree.iter()
+            .map(|hash| bundle.objects.get(hash).cloned()
+                .ok_or_else(|| anyhow!("Version {} refers to entry {} which is missing from the file", index + 1, short_hash(hash))))
+            .collect()
+    }
+
+    pub fn verify_history(&self, patient_id: &str) -> Result<ChainReport> {
+        let bundle = self.bundles.get(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        Ok(verify_chain(bundle))
+    }
+
+    // Changes between two versions
+    pub fn diff_versions(&self, patient_id: &str, from: usize, to: usize) -> Result<Vec<ResourceChange>> {
+        diff_entries(&self.snapshot(patient_id, from)?, &self.snapshot(patient_id, to)?)
+    }
+
+    // What version `index` changed from the one before it; everything, for the first
+    pub fn version_changes(&self, patient_id: &str, index: usize) -> Result<Vec<ResourceChange>> {
+        let after = self.snapshot(patient_id, index)?;
+        let before = match index {
+            0 => Vec::new(),
+            _ => self.snapshot(patient_id, index - 1)?,
+        };
+        diff_entries(&before, &after)
+    }
+
+    // Restore the entries of version `index` as a new commit. Refused while
+    // the record has uncommitted changes, which would be lost. Returns the
+    // commit message.
+    pub fn revert_to_version(&mut self, patient_id: &str, index: usize, reason: &str) -> Result<String> {
+        if reason.trim().is_empty() {
+            return Err(anyhow!("A reason is required to revert a record"));
+        }
+        let entries = self.snapshot(patient_id, index)?;
+        let bundle = self.bundles.get(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        let current: Vec<String> = bundle.entry.iter().map(entry_hash).collect::<Result<_>>()?;
+        let head = bundle.version_history.last()
+            .ok_or_else(|| anyhow!("Patient {} has no version history", patient_id))?;
+        if current != head.tree {
+            return Err(anyhow!("Patient {} has uncommitted changes; commit them before reverting", patient_id));
+        }
+        if current == bundle.version_history[index].tree {
+            return Err(anyhow!("The record already matches version {}", index + 1));
+        }
+        let target = &bundle.version_history[index];
+        let message = format!("Reverted to version {} ({}) \"{}\". Reason: {}",
+                              index + 1, short_hash(&target.hash), target.message, reason.trim());
+
+        let bundle = self.bundles.get_mut(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        bundle.entry = entries;
+        self.commit_changes(patient_id, &message)?;
+        Ok(message)
+    }
+}
@@ -0,0 +1,270 @@
+// src/interactions.rs
+// Charcot EMR: Drug-drug interaction checking
+//
+// Interactions are looked up between ingredients by RxNorm code, so a
+// clinical drug such as "warfarin sodium 5 MG Oral Tablet" interacts like its
+// ingredient. The built-in table covers well-known pairs among the built-in
+// RxNorm ingredients; an `interactions.json` file in the working directory,
+// holding a list of `Interaction`s, replaces it.
+
+use std::cmp::Reverse;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, anyhow, Context};
+
+use crate::{EMR, MedicationRequest, ResolvedDrug, Resource};
+
+pub const INTERACTIONS_FILE: &str = "interactions.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InteractionSeverity {
+    Minor,
+    Moderate,
+    Major,
+    Contraindicated,
+}
+
+impl InteractionSeverity {
+    // Severe interactions block prescribing unless overridden
+    pub fn is_severe(self) -> bool {
+        self >= InteractionSeverity::Major
+    }
+}
+
+impl fmt::Display for InteractionSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InteractionSeverity::Minor => write!(f, "minor"),
+            InteractionSeverity::Moderate => write!(f, "moderate"),
+            InteractionSeverity::Major => write!(f, "major"),
+            InteractionSeverity::Contraindicated => write!(f, "contraindicated"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Interaction {
+    pub rxcuis: [String; 2],            // RxNorm ingredients
+    pub names: [String; 2],
+    pub severity: InteractionSeverity,
+    pub mechanism: String,
+    pub effect: String,
+}
+
+// An interaction between a medication and another one the patient takes
+#[derive(Debug, Clone, PartialEq)]
+pub struct InteractionAlert {
+    pub medication: String,
+    pub other: String,
+    pub other_request_id: String,       // MedicationRequest of `other`
+    pub interaction: Interaction,
+}
+
+impl InteractionAlert {
+    pub fn is_severe(&self) -> bool {
+        self.interaction.severity.is_severe()
+    }
+}
+
+impl fmt::Display for InteractionAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} interaction between {} and {}: {} ({})",
+               self.interaction.severity, self.medication, self.other,
+               self.interaction.effect, self.interaction.mechanism)
+    }
+}
+
+// (ingredient, ingredient, severity, mechanism, effect)
+const BUILTIN: &[(&str, &str, InteractionSeverity, &str, &str)] = {
+    use InteractionSeverity::*;
+    &[
+        // Anticoagulants and antiplatelets
+        ("11289", "1191", Major, "additive anticoagulant and antiplatelet effects", "increased bleeding risk"),
+        ("11289", "5640", Major, "NSAID platelet inhibition and gastric mucosal injury", "increased bleeding risk"),
+        ("11289", "7258", Major, "NSAID platelet inhibition and gastric mucosal injury", "increased bleeding risk"),
+        ("11289", "32968", Major, "additive anticoagulant and antiplatelet effects", "increased bleeding risk"),
+        ("11289", "703", Major, "CYP2C9 inhibition reduces warfarin clearance", "raised INR and bleeding risk"),
+        ("11289", "4450", Major, "CYP2C9 inhibition reduces warfarin clearance", "raised INR and bleeding risk"),
+        ("11289", "2551", Moderate, "reduced warfarin clearance and gut flora changes", "raised INR"),
+        ("11289", "21212", Moderate, "CYP3A4 inhibition reduces warfarin clearance", "raised INR"),
+        ("11289", "161", Minor, "regular acetaminophen use potentiates warfarin", "raised INR"),
+        ("1364430", "1191", Major, "additive anticoagulant and antiplatelet effects", "increased bleeding risk"),
+        ("1114195", "1191", Major, "additive anticoagulant and antiplatelet effects", "increased bleeding risk"),
+        ("1114195", "21212", Major, "CYP3A4 and P-glycoprotein inhibition raises rivaroxaban levels", "increased bleeding risk"),
+        ("32968", "7646", Moderate, "CYP2C19 inhibition reduces clopidogrel activation", "reduced antiplatelet effect"),
+        // Statins
+        ("36567", "21212", Contraindicated, "CYP3A4 inhibition raises simvastatin levels", "myopathy and rhabdomyolysis"),
+        ("36567", "703", Major, "CYP3A4 inhibition raises simvastatin levels", "myopathy and rhabdomyolysis"),
+        ("36567", "3443", Major, "CYP3A4 inhibition raises simvastatin levels", "myopathy and rhabdomyolysis"),
+        ("36567", "11170", Major, "CYP3A4 inhibition raises simvastatin levels", "myopathy and rhabdomyolysis"),
+        ("83367", "21212", Major, "CYP3A4 inhibition raises atorvastatin levels", "myopathy and rhabdomyolysis"),
+        // Potassium and the renin-angiotensin system
+        ("29046", "9997", Major, "reduced potassium excretion", "hyperkalemia"),
+        ("3827", "9997", Major, "reduced potassium excretion", "hyperkalemia"),
+        ("35296", "9997", Major, "reduced potassium excretion", "hyperkalemia"),
+        ("52175", "9997", Major, "reduced potassium excretion", "hyperkalemia"),
+        ("29046", "8591", Major, "reduced potassium excretion", "hyperkalemia"),
+        ("9997", "8591", Major, "reduced potassium excretion", "hyperkalemia"),
+        ("29046", "52175", Major, "dual renin-angiotensin blockade", "hyperkalemia, hypotension and renal impairment"),
+        // Lithium
+        ("6448", "29046", Major, "reduced renal lithium clearance", "lithium toxicity"),
+        ("6448", "5487", Major, "reduced renal lithium clearance", "lithium toxicity"),
+        ("6448", "5640", Major, "reduced renal lithium clearance", "lithium toxicity"),
+        // Serotonergic drugs
+        ("36437", "10689", Major, "additive serotonergic effects", "serotonin syndrome and seizures"),
+        ("4493", "10689", Major, "additive serotonergic effects", "serotonin syndrome and seizures"),
+        ("2556", "10689", Major, "additive serotonergic effects", "serotonin syndrome and seizures"),
+        ("321988", "10689", Major, "additive serotonergic effects", "serotonin syndrome and seizures"),
+        ("72625", "10689", Major, "additive serotonergic effects", "serotonin syndrome and seizures"),
+        ("704", "10689", Major, "additive serotonergic effects and lowered seizure threshold", "serotonin syndrome and seizures"),
+        ("4493", "704", Moderate, "CYP2D6 inhibition raises tricyclic levels", "tricyclic toxicity"),
+        // QT prolongation
+        ("2556", "703", Major, "additive QT prolongation", "torsades de pointes"),
+        ("5093", "703", Major, "additive QT prolongation", "torsades de pointes"),
+        ("2556", "26225", Moderate, "additive QT prolongation", "torsades de pointes"),
+        ("51272", "21212", Major, "CYP3A4 inhibition and additive QT prolongation", "quetiapine toxicity and torsades de pointes"),
+        // CNS depressants
+        ("7804", "6470", Major, "additive CNS and respiratory depression", "sedation and respiratory depression"),
+        ("7804", "596", Major, "additive CNS and respiratory depression", "sedation and respiratory depression"),
+        ("7804", "3322", Major, "additive CNS and respiratory depression", "sedation and respiratory depression"),
+        ("7804", "39993", Major, "additive CNS and respiratory depression", "sedation and respiratory depression"),
+        ("7052", "6470", Major, "additive CNS and respiratory depression", "sedation and respiratory depression"),
+        ("7052", "596", Major, "additive CNS and respiratory depression", "sedation and respiratory depression"),
+        ("7052", "3322", Major, "additive CNS and respiratory depression", "sedation and respiratory depression"),
+        // Cardiac drugs
+        ("3407", "703", Major, "P-glycoprotein inhibition raises digoxin levels", "digoxin toxicity"),
+        ("3407", "11170", Major, "P-glycoprotein inhibition raises digoxin levels", "digoxin toxicity and bradycardia"),
+        ("3407", "21212", Major, "P-glycoprotein inhibition raises digoxin levels", "digoxin toxicity"),
+        ("6918", "11170", Major, "additive negative chronotropic and inotropic effects", "bradycardia and heart block"),
+        ("6918", "3443", Moderate, "additive negative chronotropic effects", "bradycardia"),
+        // Others
+        ("6851", "5640", Major, "NSAIDs reduce renal methotrexate clearance", "methotrexate toxicity"),
+        ("6851", "7258", Major, "NSAIDs reduce renal methotrexate clearance", "methotrexate toxicity"),
+        ("6851", "1191", Major, "salicylates reduce renal methotrexate clearance", "methotrexate toxicity"),
+        ("2683", "21212", Contraindicated, "CYP3A4 and P-glycoprotein inhibition raises colchicine levels", "colchicine toxicity"),
+        ("4821", "4450", Moderate, "CYP2C9 inhibition reduces glipizide clearance", "hypoglycemia"),
+        ("8640", "5640", Moderate, "additive gastric mucosal injury", "gastrointestinal bleeding"),
+        ("10582", "7646", Minor, "reduced gastric acid lowers levothyroxine absorption", "reduced thyroid replacement"),
+    ]
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InteractionTable {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Default for InteractionTable {
+    fn default() -> Self {
+        let name = |rxcui: &str| crate::rxnorm::builtin_name(rxcui).unwrap_or(rxcui).to_string();
+        InteractionTable {
+            interactions: BUILTIN.iter()
+                .map(|&(a, b, severity, mechanism, effect)| Interaction {
+                    rxcuis: [a.to_string(), b.to_string()],
+                    names: [name(a), name(b)],
+                    severity,
+                    mechanism: mechanism.to_string(),
+                    effect: effect.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl InteractionTable {
+    pub fn load(path: &str) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read interactions from {}", path))?;
+        let interactions: Vec<Interaction> = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid interactions in {}", path))?;
+        Ok(InteractionTable { interactions })
+    }
+
+    // `interactions.json` if present, otherwise the built-in table
+    pub fn load_or_default() -> Result<Self> {
+        if Path::new(INTERACTIONS_FILE).exists() {
+            InteractionTable::load(INTERACTIONS_FILE)
+        } else {
+            Ok(InteractionTable::default())
+        }
+    }
+
+    // Interactions between two drugs, most severe first
+    pub fn between(&self, a: &ResolvedDrug, b: &ResolvedDrug) -> Vec<&Interaction> {
+        let (a, b) = (a.codes(), b.codes());
+        let mut found: Vec<&Interaction> = self.interactions.iter()
+            .filter(|interaction| {
+                let [x, y] = &interaction.rxcuis;
+                (a.contains(&x.as_str()) && b.contains(&y.as_str()))
+                    || (a.contains(&y.as_str()) && b.contains(&x.as_str()))
+            })
+            .collect();
+        found.sort_by_key(|interaction| Reverse(interaction.severity));
+        found
+    }
+}
+
+impl EMR {
+    // The patient's active medication requests that can be found in the
+    // RxNorm dictionary, unlisted ones as their closest match
+    fn active_medications(&self, patient_id: &str) -> Result<Vec<(&MedicationRequest, ResolvedDrug)>> {
+        let bundle = self.bundles.get(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        Ok(bundle.entry.iter()
+            .filter_map(|entry| match &entry.resource {
+                Resource::MedicationRequest(request) if request.status == "active" => Some(request),
+                _ => None,
+            })
+            .filter_map(|request| {
+                let coding = &request.medication_codeable_concept;
+                let drug = if coding.code.is_empty() {
+                    self.drugs.resolve_or_closest(&coding.display)
+                } else {
+                    self.drugs.resolve(&coding.code).ok()
+                };
+                drug.map(|drug| (request, drug))
+            })
+            .collect())
+    }
+
+    // Interactions between `medication` and the patient's active
+    // prescriptions, most severe first. Medications that are not in the RxNorm
+    // dictionary are checked as their closest match, if there is one.
+    pub fn verify_interactions(&self, patient_id: &str, medication: &str) -> Result<Vec<InteractionAlert>> {
+        let active = self.active_medications(patient_id)?;
+        let Some(drug) = self.drugs.resolve_or_closest(medication) else {
+            return Ok(Vec::new());
+        };
+        let mut alerts: Vec<InteractionAlert> = active.iter()
+            .flat_map(|(request, other)| self.interactions.between(&drug, other).into_iter()
+                .map(|interaction| InteractionAlert {
+                    medication: drug.drug.name.clone(),
+                    other: request.medication_codeable_concept.display.clone(),
+                    other_request_id: request.id.clone(),
+                    interaction: interaction.clone(),
+                }))
+            .collect();
+        alerts.sort_by_key(|alert| Reverse(alert.interaction.severity));
+        Ok(alerts)
+    }
+
+    // Interactions among all of the patient's active prescriptions
+    pub fn check_interactions(&self, patient_id: &str) -> Result<Vec<InteractionAlert>> {
+        let active = self.active_medications(patient_id)?;
+        let mut alerts = Vec::new();
+        for (i, (request, drug)) in active.iter().enumerate() {
+            for (other_request, other) in &active[i + 1..] {
+                alerts.extend(self.interactions.between(drug, other).into_iter()
+                    .map(|interaction| InteractionAlert {
+                        medication: request.medication_codeable_concept.display.clone(),
+                        other: other_request.medication_codeable_concept.display.clone(),
+                        other_request_id: other_request.id.clone(),
+                        interaction: interaction.clone(),
+                    }));
+            }
+        }
+        alerts.sort_by_key(|alert| Reverse(alert.interaction.severity));
+        Ok(alerts)
+    }
+}
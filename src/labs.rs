@@ -0,0 +1,315 @@
+// src/labs.rs
+// Charcot EMR: Laboratory results with reference ranges and abnormal flags
+//
+// Each lab test has a reference range and optional critical limits in a
+// fixed unit. The built-in ranges can be replaced by a `lab_ranges.json` file
+// in the working directory, holding a list of `LabTest`s.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use anyhow::{Result, anyhow, Context};
+
+use crate::units;
+use crate::terminology::{INTERPRETATION_SYSTEM, LOINC_SYSTEM, UCUM_SYSTEM};
+use crate::{
+    Annotation, BundleEntry, Coding, DiagnosticReport, EMR, Observation, Quantity, Reference,
+    ReferenceRange, Resource, validate_clinical_time,
+};
+
+pub const LAB_RANGES_FILE: &str = "lab_ranges.json";
+
+// LOINC "Laboratory report"
+const LAB_REPORT_CODE: &str = "11502-2";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LabTest {
+    pub name: String,               // Short name, e.g. "glucose"
+    #[serde(default)]
+    pub aliases: Vec<String>,       // Other names accepted for the test
+    pub loinc: String,
+    pub display: String,
+    pub unit: String,               // UCUM unit results must be reported in
+    pub low: f64,
+    pub high: f64,
+    pub critical_low: Option<f64>,
+    pub critical_high: Option<f64>,
+}
+
+impl LabTest {
+    pub fn interpret(&self, value: f64) -> Interpretation {
+        if self.critical_low.is_some_and(|limit| value < limit) {
+            Interpretation::CriticalLow
+        } else if self.critical_high.is_some_and(|limit| value > limit) {
+            Interpretation::CriticalHigh
+        } else if value < self.low {
+            Interpretation::Low
+        } else if value > self.high {
+            Interpretation::High
+        } else {
+            Interpretation::Normal
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.trim();
+        self.loinc == name
+            || self.name.eq_ignore_ascii_case(name)
+            || self.aliases.iter().any(|alias| alias.eq_ignore_ascii_case(name))
+    }
+
+    fn quantity(&self, value: f64) -> Quantity {
+        Quantity {
+            value,
+            unit: self.unit.clone(),
+            system: UCUM_SYSTEM.to_string(),
+            code: self.unit.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpretation {
+    CriticalLow,
+    Low,
+    Normal,
+    High,
+    CriticalHigh,
+}
+
+impl Interpretation {
+    // HL7 v3 ObservationInterpretation code
+    pub fn code(&self) -> &'static str {
+        match self {
+            Interpretation::CriticalLow => "LL",
+            Interpretation::Low => "L",
+            Interpretation::Normal => "N",
+            Interpretation::High => "H",
+            Interpretation::CriticalHigh => "HH",
+        }
+    }
+
+    pub fn display(&self) -> &'static str {
+        match self {
+            Interpretation::CriticalLow => "Critical low",
+            Interpretation::Low => "Low",
+            Interpretation::Normal => "Normal",
+            Interpretation::High => "High",
+            Interpretation::CriticalHigh => "Critical high",
+        }
+    }
+
+    pub fn is_abnormal(&self) -> bool {
+        *self != Interpretation::Normal
+    }
+
+    pub fn from_code(code: &str) -> Option<Interpretation> {
+        [
+            Interpretation::CriticalLow,
+            Interpretation::Low,
+            Interpretation::Normal,
+            Interpretation::High,
+            Interpretation::CriticalHigh,
+        ]
+        .into_iter()
+        .find(|i| i.code() == code)
+    }
+}
+
+impl fmt::Display for Interpretation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.display())
+    }
+}
+
+// The lab tests the EMR knows, with their reference ranges
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabRanges {
+    pub tests: Vec<LabTest>,
+}
+
+impl Default for LabRanges {
+    fn default() -> Self {
+        let test = |name: &str, aliases: &[&str], loinc: &str, display: &str, unit: &str,
+                    (low, high): (f64, f64), critical_low: Option<f64>, critical_high: Option<f64>| LabTest {
+            name: name.to_string(),
+            aliases: aliases.iter().map(|a| a.to_string()).collect(),
+            loinc: loinc.to_string(),
+            display: display.to_string(),
+            unit: unit.to_string(),
+            low,
+            high,
+            critical_low,
+            critical_high,
+        };
+        LabRanges {
+            tests: vec![
+                // Fasting plasma glucose
+                test("glucose", &["blood glucose", "blood sugar"], "2345-7",
+                     "Glucose [Mass/volume] in Serum or Plasma", "mg/dL",
+                     (70.0, 99.0), Some(40.0), Some(400.0)),
+                test("hba1c", &["a1c", "hemoglobin a1c"], "4548-4",
+                     "Hemoglobin A1c/Hemoglobin.total in Blood", "%",
+                     (4.0, 5.6), None, Some(14.0)),
+                test("creatinine", &[], "2160-0",
+                     "Creatinine [Mass/volume] in Serum or Plasma", "mg/dL",
+                     (0.6, 1.3), None, Some(10.0)),
+                test("potassium", &["k"], "2823-3",
+                     "Potassium [Moles/volume] in Serum or Plasma", "mmol/L",
+                     (3.5, 5.1), Some(2.5), Some(6.5)),
+            ],
+        }
+    }
+}
+
+impl LabRanges {
+    // Read a list of tests from a JSON file
+    pub fn load(path: &str) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read lab ranges from {}", path))?;
+        let tests: Vec<LabTest> = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid lab ranges in {}", path))?;
+        for test in &tests {
+            if test.low > test.high {
+                return Err(anyhow!("Invalid lab range for {} in {}: {} > {}", test.name, path, test.low, test.high));
+            }
+        }
+        Ok(LabRanges { tests })
+    }
+
+    // `lab_ranges.json` if present, otherwise the built-in ranges
+    pub fn load_or_default() -> Result<Self> {
+        if Path::new(LAB_RANGES_FILE).exists() {
+            LabRanges::load(LAB_RANGES_FILE)
+        } else {
+            Ok(LabRanges::default())
+        }
+    }
+
+    // Find a test by name, alias or LOINC code
+    pub fn find(&self, name: &str) -> Option<&LabTest> {
+        self.tests.iter().find(|test| test.matches(name))
+    }
+}
+
+// Build the observation for one lab result
+pub fn lab_observation(test: &LabTest, patient_id: &str, value: f64, effective: DateTime<Utc>,
+                       note: Option<&str>) -> Observation {
+    let interpretation = test.interpret(value);
+    Observation {
+        id: Uuid::new_v4().to_string(),
+        status: "final".to_string(),
+        code: Coding {
+            system: LOINC_SYSTEM.to_string(),
+            code: test.loinc.clone(),
+            display: test.display.clone(),
+        },
+        subject: Reference {
+            reference: format!("Patient/{}", patient_id),
+        },
+        effective_date_time: effective.to_rfc3339(),
+        issued: Some(Utc::now().to_rfc3339()),
+        value_quantity: Some(test.quantity(value)),
+        component: None,
+        reference_range: Some(vec![ReferenceRange {
+            low: Some(test.quantity(test.low)),
+            high: Some(test.quantity(test.high)),
+            text: Some(format!("{}-{} {}", test.low, test.high, test.unit)),
+        }]),
+        interpretation: Some(vec![Coding {
+            system: INTERPRETATION_SYSTEM.to_string(),
+            code: interpretation.code().to_string(),
+            display: interpretation.display().to_string(),
+        }]),
+        note: note.map(|text| vec![Annotation { text: text.to_string() }]),
+    }
+}
+
+impl EMR {
+    // Add a lab result taken at `effective` and flag it against the test's
+    // reference range. `test` is a test name, alias or LOINC code; the value is
+    // converted from `unit` to the test's unit before it is stored. Returns the
+    // new observation's id.
+    pub fn add_lab_result_at(&mut self, patient_id: &str, test: &str, value: f64, unit: &str,
+                             effective: DateTime<Utc>, note: Option<&str>) -> Result<(String, Interpretation)> {
+        let test = self.lab_ranges.find(test)
+            .ok_or_else(|| anyhow!("Unknown lab test: {}", test))?
+            .clone();
+        let value = units::convert_analyte(value, unit, &test.unit, &test.loinc)
+            .with_context(|| format!("Invalid unit for {}", test.name))?;
+        if !value.is_finite() || value < 0.0 {
+            return Err(anyhow!("Invalid {} value: {}", test.name, value));
+        }
+        validate_clinical_time(effective)?;
+
+        let observation = lab_observation(&test, patient_id, value, effective, note);
+        let id = observation.id.clone();
+        let interpretation = test.interpret(value);
+
+        let bundle = self.bundles.get_mut(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+
+        bundle.entry.push(BundleEntry {
+            resource_type: "Observation".to_string(),
+            resource: Resource::Observation(observation),
+        });
+
+        self.log_audit(&format!("Added lab {}: {} {} ({})", test.name, value, test.unit,
+                               interpretation.code()), patient_id)?;
+
+        Ok((id, interpretation))
+    }
+
+    // Group existing observations of a patient into a DiagnosticReport
+    pub fn add_diagnostic_report(&mut self, patient_id: &str, title: &str,
+                                 observation_ids: &[String]) -> Result<String> {
+        let bundle = self.bundles.get_mut(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+
+        let mut effective: Option<DateTime<Utc>> = None;
+        for id in observation_ids {
+            let observation = bundle.entry.iter()
+                .find_map(|entry| match &entry.resource {
+                    Resource::Observation(obs) if &obs.id == id => Some(obs),
+                    _ => None,
+                })
+                .ok_or_else(|| anyhow!("Observation not found: {}", id))?;
+            // The report is effective when its latest result was taken
+            let taken = DateTime::parse_from_rfc3339(&observation.effective_date_time)?.with_timezone(&Utc);
+            effective = effective.max(Some(taken));
+        }
+        let effective = effective.ok_or_else(|| anyhow!("A report needs at least one result"))?;
+
+        let report = DiagnosticReport {
+            id: Uuid::new_v4().to_string(),
+            status: "final".to_string(),
+            code: Coding {
+                system: LOINC_SYSTEM.to_string(),
+                code: LAB_REPORT_CODE.to_string(),
+                display: title.to_string(),
+            },
+            subject: Reference {
+                reference: format!("Patient/{}", patient_id),
+            },
+            effective_date_time: effective.to_rfc3339(),
+            issued: Some(Utc::now().to_rfc3339()),
+            result: observation_ids.iter()
+                .map(|id| Reference { reference: format!("Observation/{}", id) })
+                .collect(),
+        };
+        let id = report.id.clone();
+
+        bundle.entry.push(BundleEntry {
+            resource_type: "DiagnosticReport".to_string(),
+            resource: Resource::DiagnosticReport(report),
+        });
+
+        self.log_audit(&format!("Added report {} with {} result(s)", title, observation_ids.len()),
+                       patient_id)?;
+
+        Ok(id)
+    }
+}
@@ -0,0 +1,212 @@
+// src/lang/ast.rs
+// Charcot EMR: Typed syntax tree for Charcot scripts
+
+use super::diagnostic::Span;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub kind: ItemKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ItemKind {
+    // import standards.icd10;
+    Import(Vec<Ident>),
+    // record patient #123 { ... }, track for patient #123 { ... }, ...
+    Block(Block),
+    // commit changes to patient #123 with message "...";
+    Commit(Commit),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrLit {
+    pub value: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatientRef {
+    pub id: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub patient: PatientRef,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockHeader {
+    Record,                                     // record patient #id
+    Track,                                      // track for patient #id
+    Prescribe,                                  // prescribe for patient #id
+    ConnectDevice { device: StrLit },           // connect device "x" to patient #id
+    Schedule,                                   // schedule for patient #id
+    Generate { artifact: Ident },               // generate handout for patient #id
+    Analyze,                                    // analyze for patient #id
+    Share { anonymized: bool, team: StrLit },   // share [anonymized] patient #id with team "x"
+    Bill,                                       // bill for patient #id
+}
+
+impl BlockHeader {
+    // Leading keyword, used in diagnostics and by tooling
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            BlockHeader::Record => "record",
+            BlockHeader::Track => "track",
+            BlockHeader::Prescribe => "prescribe",
+            BlockHeader::ConnectDevice { .. } => "connect",
+            BlockHeader::Schedule => "schedule",
+            BlockHeader::Generate { .. } => "generate",
+            BlockHeader::Analyze => "analyze",
+            BlockHeader::Share { .. } => "share",
+            BlockHeader::Bill => "bill",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Commit {
+    pub patient: PatientRef,
+    pub message: StrLit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    // name: "John Doe";  condition: "diabetes" with icd10 "E11.9";  include illustrations: true;
+    Field { key: Vec<Ident>, value: Expr, modifiers: Vec<Modifier> },
+    // bp = BloodPressure(120, 80) on "2025-04-08T08:30:00";
+    Assign { target: Ident, value: Expr, modifiers: Vec<Modifier> },
+    // encrypt with patient_key;
+    Encrypt { key: Ident },
+    // calculate dose for insulin as 0.5 * patient.weight unit "units";
+    Calculate { property: Ident, subject: Ident, value: Expr, unit: Option<StrLit> },
+    // administer metformin 1000 mg twice daily with meals;
+    Administer { medication: Ident, dose: Dose, sig: Vec<SigWord> },
+    // verify_interactions();  recommend("...") if avg(glucose) > 180 mg/dL;
+    Call { call: Expr, condition: Option<Expr> },
+    // sample every 6 hours;  alert when glucose > 250 mg/dL;
+    Directive { verb: Ident, args: Vec<Expr> },
+}
+
+// Trailing clauses on fields and assignments
+#[derive(Debug, Clone, PartialEq)]
+pub enum Modifier {
+    Unit(StrLit),                           // unit "mg/dL"
+    On(StrLit),                             // on "2025-04-08T08:30:00"
+    Note(StrLit),                           // with note "fasting"
+    Code { system: Ident, code: StrLit },   // with rxnorm "6809"
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Dose {
+    // `administer insulin dose ...` uses the value from a `calculate dose` statement
+    Calculated(Span),
+    Amount(Expr),
+}
+
+// Free-text administration instructions, e.g. `twice daily with meals`
+#[derive(Debug, Clone, PartialEq)]
+pub enum SigWord {
+    Word(Ident),
+    Number(f64, Span),
+    Str(StrLit),
+}
+
+impl SigWord {
+    pub fn span(&self) -> Span {
+        match self {
+            SigWord::Word(ident) => ident.span,
+            SigWord::Number(_, span) => *span,
+            SigWord::Str(lit) => lit.span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Number(f64),
+    Str(String),
+    Bool(bool),
+    // 85.5 kg, 250 mg/dL
+    Quantity { value: f64, unit: String },
+    // 70-130 mg/dL
+    Range { low: f64, high: f64, unit: Option<String> },
+    Ident(String),
+    // patient.weight
+    Member { object: Box<Expr>, property: Ident },
+    // BloodPressure(120, 80)
+    Call { callee: Ident, args: Vec<Expr> },
+    List(Vec<Expr>),
+    Neg(Box<Expr>),
+    Binary { op: BinOp, lhs: Box<Expr>, rhs: Box<Expr> },
+    // in 3 months
+    Offset(Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    NotEq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl BinOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Eq => "==",
+            BinOp::NotEq => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+        }
+    }
+
+    // Binding strength; higher binds tighter
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinOp::Eq | BinOp::NotEq => 1,
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 2,
+            BinOp::Add | BinOp::Sub => 3,
+            BinOp::Mul | BinOp::Div => 4,
+        }
+    }
+}
@@ -0,0 +1,343 @@
+// src/lang/check.rs
+// Charcot EMR: Static semantic checks for Charcot scripts
+//
+// The checker runs over a parsed program before anything is executed and
+// reports every problem it finds: references to undeclared patients,
+// variables used before they are defined, quantities whose units cannot be
+// compared, and values the library would reject at runtime.
+
+use std::collections::{HashMap, HashSet};
+
+use super::ast::*;
+use super::diagnostic::{Diagnostic, Span};
+use super::interp::{sig_span, sig_text};
+use crate::{BloodPressure, parse_clinical_time, parse_sig, units, validate_clinical_time};
+
+// Physical dimension of a unit, used to catch comparisons like `glucose > 250 kg`
+pub use crate::units::Dimension;
+
+pub fn dimension_of(unit: &str) -> Option<Dimension> {
+    units::parse(unit).ok().map(|unit| unit.dimension())
+}
+
+// Mass and molar concentrations of the same analyte convert through its molar
+// mass, so `glucose > 10 mmol/L` is fine for a glucose recorded in mg/dL
+fn commensurable(a: Dimension, b: Dimension) -> bool {
+    use Dimension::{MassConcentration, MolarConcentration};
+    a == b || matches!((a, b), (MassConcentration, MolarConcentration) | (MolarConcentration, MassConcentration))
+}
+
+// Check `program`. Patients in `known_patients` already exist outside the
+// script (e.g. as `.med` files) and may be referenced without a `record` block.
+pub fn check(program: &Program, known_patients: &HashSet<String>) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        declared: known_patients.clone(),
+        variables: HashMap::new(),
+        diagnostics: Vec::new(),
+    };
+
+    for item in &program.items {
+        if let ItemKind::Block(Block { header: BlockHeader::Record, patient, .. }) = &item.kind {
+            checker.declared.insert(patient.id.clone());
+        }
+    }
+    for item in &program.items {
+        checker.item(item);
+    }
+
+    checker.diagnostics.sort_by_key(|d| d.span.start);
+    checker.diagnostics
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VarKind {
+    Medication,
+    Observation,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Variable {
+    kind: VarKind,
+    dimension: Option<Dimension>,
+}
+
+// What we know statically about the value of an expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ty {
+    Unknown,
+    Number,
+    Quantity(Dimension),
+}
+
+struct Checker {
+    declared: HashSet<String>,
+    // Variables are scoped to the patient whose blocks define them
+    variables: HashMap<(String, String), Variable>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn error(&mut self, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic::error(message, span));
+    }
+
+    fn warning(&mut self, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic::warning(message, span));
+    }
+
+    fn patient(&mut self, patient: &PatientRef) {
+        if !self.declared.contains(&patient.id) {
+            self.error(
+                format!("patient #{} is never declared; add a `record patient #{}` block", patient.id, patient.id),
+                patient.span,
+            );
+        }
+    }
+
+    fn item(&mut self, item: &Item) {
+        match &item.kind {
+            ItemKind::Import(_) => {}
+            ItemKind::Commit(commit) => self.patient(&commit.patient),
+            ItemKind::Block(block) => {
+                self.patient(&block.patient);
+                for stmt in &block.body {
+                    self.stmt(&block.patient.id, stmt);
+                }
+            }
+        }
+    }
+
+    fn stmt(&mut self, patient: &str, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Field { value, .. } => {
+                self.expr(patient, value);
+            }
+            StmtKind::Assign { target, value, modifiers } => {
+                self.expr(patient, value);
+                self.times(modifiers);
+                let variable = self.assigned_variable(value, modifiers);
+                self.variables.insert((patient.to_string(), target.name.clone()), variable);
+            }
+            StmtKind::Encrypt { .. } => {}
+            StmtKind::Calculate { subject, value, .. } => {
+                self.medication(patient, subject);
+                self.expr(patient, value);
+            }
+            StmtKind::Administer { medication, dose, sig } => {
+                self.medication(patient, medication);
+                // Same sig parsing the library applies when the prescription is stored
+                if !sig.is_empty() {
+                    if let Err(e) = parse_sig(&sig_text(sig)) {
+                        self.error(e.to_string(), sig_span(sig));
+                    }
+                }
+                if let Dose::Amount(amount) = dose {
+                    if let Ty::Quantity(dimension) = self.expr(patient, amount) {
+                        if !matches!(dimension, Dimension::Mass | Dimension::Volume | Dimension::Units) {
+                            self.error(format!("a dose must be a mass, volume or units, not {}", dimension), amount.span);
+                        }
+                    }
+                }
+            }
+            StmtKind::Call { call, condition } => {
+                self.expr(patient, call);
+                if let Some(condition) = condition {
+                    self.expr(patient, condition);
+                }
+            }
+            StmtKind::Directive { args, .. } => {
+                // Bare words in directives (`sample every 6 hours`) are not
+                // variables, so only compound expressions are checked
+                for arg in args {
+                    if !matches!(arg.kind, ExprKind::Ident(_)) {
+                        self.expr(patient, arg);
+                    }
+                }
+            }
+        }
+    }
+
+    // `on "..."` must be a valid time that is not in the future
+    fn times(&mut self, modifiers: &[Modifier]) {
+        for modifier in modifiers {
+            if let Modifier::On(time) = modifier {
+                if let Err(e) = parse_clinical_time(&time.value).and_then(validate_clinical_time) {
+                    self.error(e.to_string(), time.span);
+                }
+            }
+        }
+    }
+
+    fn assigned_variable(&mut self, value: &Expr, modifiers: &[Modifier]) -> Variable {
+        let unit = modifiers.iter().find_map(|m| match m {
+            Modifier::Unit(unit) => Some(unit),
+            _ => None,
+        });
+        let unit_dimension = unit.and_then(|unit| {
+            let dimension = dimension_of(&unit.value);
+            if dimension.is_none() {
+                self.warning(format!("unknown unit `{}`", unit.value), unit.span);
+            }
+            dimension
+        });
+
+        match &value.kind {
+            ExprKind::Call { callee, .. } if callee.name == "Medication" => {
+                Variable { kind: VarKind::Medication, dimension: None }
+            }
+            ExprKind::Call { callee, .. } if callee.name == "BloodPressure" => {
+                Variable { kind: VarKind::Observation, dimension: Some(Dimension::Pressure) }
+            }
+            ExprKind::Quantity { unit, .. } => {
+                Variable { kind: VarKind::Observation, dimension: dimension_of(unit) }
+            }
+            _ => Variable { kind: VarKind::Observation, dimension: unit_dimension },
+        }
+    }
+
+    fn lookup(&self, patient: &str, name: &str) -> Option<Variable> {
+        self.variables.get(&(patient.to_string(), name.to_string())).copied()
+    }
+
+    fn medication(&mut self, patient: &str, name: &Ident) {
+        match self.lookup(patient, &name.name) {
+            Some(Variable { kind: VarKind::Medication, .. }) => {}
+            Some(_) => self.error(format!("`{}` is not a medication", name.name), name.span),
+            None => self.error(
+                format!("`{}` is used before it is defined for patient #{}", name.name, patient),
+                name.span,
+            ),
+        }
+    }
+
+    fn expr(&mut self, patient: &str, expr: &Expr) -> Ty {
+        match &expr.kind {
+            ExprKind::Number(_) => Ty::Number,
+            ExprKind::Str(_) | ExprKind::Bool(_) => Ty::Unknown,
+            ExprKind::Quantity { unit, .. } => self.unit(unit, expr.span),
+            ExprKind::Range { low, high, unit } => {
+                if low > high {
+                    self.error(format!("range {}-{} is empty", low, high), expr.span);
+                }
+                match unit {
+                    Some(unit) => self.unit(unit, expr.span),
+                    None => Ty::Number,
+                }
+            }
+            ExprKind::Ident(name) => match self.lookup(patient, name) {
+                Some(Variable { dimension: Some(dimension), .. }) => Ty::Quantity(dimension),
+                Some(_) => Ty::Unknown,
+                None => {
+                    self.error(
+                        format!("`{}` is used before it is defined for patient #{}", name, patient),
+                        expr.span,
+                    );
+                    Ty::Unknown
+                }
+            },
+            ExprKind::Member { object, property } => {
+                if matches!(&object.kind, ExprKind::Ident(name) if name == "patient") {
+                    // `patient.weight` reads the latest recorded value
+                    match self.lookup(patient, &property.name) {
+                        Some(Variable { dimension: Some(dimension), .. }) => {
+                            return Ty::Quantity(dimension)
+                        }
+                        Some(_) => {}
+                        None if property.name == "record" => {}
+                        // Worked out from the latest weight and height
+                        None if property.name == "bsa" => {}
+                        None => self.warning(
+                            format!("no `{}` is recorded for patient #{} in this script", property.name, patient),
+                            property.span,
+                        ),
+                    }
+                } else {
+                    self.expr(patient, object);
+                }
+                Ty::Unknown
+            }
+            ExprKind::Call { callee, args } => self.call(patient, callee, args, expr.span),
+            ExprKind::List(elements) => {
+                for element in elements {
+                    self.expr(patient, element);
+                }
+                Ty::Unknown
+            }
+            ExprKind::Neg(operand) | ExprKind::Offset(operand) => self.expr(patient, operand),
+            ExprKind::Binary { op, lhs, rhs } => {
+                let left = self.expr(patient, lhs);
+                let right = self.expr(patient, rhs);
+                self.binary(*op, left, right, expr.span)
+            }
+        }
+    }
+
+    fn unit(&mut self, unit: &str, span: Span) -> Ty {
+        match dimension_of(unit) {
+            Some(dimension) => Ty::Quantity(dimension),
+            None => {
+                self.warning(format!("unknown unit `{}`", unit), span);
+                Ty::Unknown
+            }
+        }
+    }
+
+    fn call(&mut self, patient: &str, callee: &Ident, args: &[Expr], span: Span) -> Ty {
+        let types: Vec<Ty> = args.iter().map(|arg| self.expr(patient, arg)).collect();
+
+        match callee.name.as_str() {
+            "BloodPressure" => {
+                let values: Vec<Option<f64>> = args.iter().map(number).collect();
+                match values.as_slice() {
+                    [Some(systolic), Some(diastolic)] => {
+                        // Same validation the library applies when the reading is stored
+                        if let Err(e) = BloodPressure::new(*systolic as i32, *diastolic as i32) {
+                            self.error(e.to_string(), span);
+                        }
+                    }
+                    _ => self.error(
+                        "BloodPressure expects two numbers: BloodPressure(systolic, diastolic)".to_string(),
+                        span,
+                    ),
+                }
+                Ty::Quantity(Dimension::Pressure)
+            }
+            // Aggregates keep the unit of their argument
+            "avg" | "min" | "max" | "latest" => types.first().copied().unwrap_or(Ty::Unknown),
+            _ => Ty::Unknown,
+        }
+    }
+
+    fn binary(&mut self, op: BinOp, left: Ty, right: Ty, span: Span) -> Ty {
+        match op {
+            BinOp::Mul | BinOp::Div => match (left, right) {
+                (Ty::Number, quantity @ Ty::Quantity(_)) if op == BinOp::Mul => quantity,
+                (quantity @ Ty::Quantity(_), Ty::Number) => quantity,
+                (Ty::Number, Ty::Number) => Ty::Number,
+                _ => Ty::Unknown,
+            },
+            _ => {
+                if let (Ty::Quantity(a), Ty::Quantity(b)) = (left, right) {
+                    if !commensurable(a, b) {
+                        self.error(
+                            format!("unit mismatch: cannot use `{}` between {} and {}", op.symbol(), a, b),
+                            span,
+                        );
+                    }
+                }
+                match op {
+                    BinOp::Add | BinOp::Sub => left,
+                    _ => Ty::Unknown,
+                }
+            }
+        }
+    }
+}
+
+fn number(expr: &Expr) -> Option<f64> {
+    match &expr.kind {
+        ExprKind::Number(n) => Some(*n),
+        ExprKind::Neg(inner) => number(inner).map(|n| -n),
+        _ => None,
+    }
+}
@@ -0,0 +1,123 @@
+// src/lang/diagnostic.rs
+// Charcot EMR: Source spans and diagnostics for the Charcot language
+
+use std::fmt;
+
+// Byte range into the source text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    // Smallest span covering both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset <= self.end
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic { severity: Severity::Error, message: message.into(), span }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic { severity: Severity::Warning, message: message.into(), span }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // Render as a compiler-style message with the offending line and a caret underline:
+    //
+    //   error: expected `;` after statement
+    //     --> script.charcot:12:5
+    //      |
+    //   12 |     name: "John"
+    //      |     ^^^^
+    pub fn render(&self, source: &str, filename: &str) -> String {
+        let start = self.span.start.min(source.len());
+        let (line, column) = line_col(source, start);
+        let line_start = source[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = source[line_start..].find('\n').map(|i| line_start + i).unwrap_or(source.len());
+        let line_text = source[line_start..line_end].trim_end_matches('\r');
+
+        // Underline at least one character, and never past the end of the line
+        let padding: String = source[line_start..start].chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = source.get(start..self.span.end.min(line_end))
+            .map(|s| s.chars().count())
+            .unwrap_or(0);
+        let carets = "^".repeat(width.max(1));
+
+        let gutter = " ".repeat(line.to_string().len());
+        format!(
+            "{}: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+            self.severity, self.message,
+            gutter, filename, line, column,
+            gutter,
+            line, line_text,
+            gutter, padding, carets,
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+// 1-based line and column (in characters) of a byte offset
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+// Render a list of diagnostics one after another
+pub fn render_all(diagnostics: &[Diagnostic], source: &str, filename: &str) -> String {
+    diagnostics.iter()
+        .map(|d| d.render(source, filename))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
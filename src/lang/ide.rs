@@ -0,0 +1,243 @@
+// src/lang/ide.rs
+// Charcot EMR: Editor features (diagnostics, completion, hover, go-to-definition)
+//
+// Everything here works on byte offsets into the source; converting to and
+// from editor positions is left to the caller (see `src/bin/emr_lsp.rs`).
+
+use std::collections::HashSet;
+
+use super::ast::*;
+use super::diagnostic::{Diagnostic, Span};
+use super::lexer::{self, Token, TokenKind};
+use super::{check, parser, printer};
+use crate::terminology;
+
+// A parsed document together with everything wrong with it
+pub struct Analysis {
+    pub program: Program,
+    pub tokens: Vec<Token>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+// Parse and check `source`. Semantic checks only run on syntactically valid
+// documents, so a half-typed statement doesn't produce a cascade of errors.
+pub fn analyze(source: &str, known_patients: &HashSet<String>) -> Analysis {
+    let (tokens, mut diagnostics) = lexer::tokenize(source);
+    let (program, parse_diagnostics) = parser::Parser::new(tokens.clone()).parse_program();
+    diagnostics.extend(parse_diagnostics);
+    if !diagnostics.iter().any(Diagnostic::is_error) {
+        diagnostics.extend(check::check(&program, known_patients));
+    }
+    Analysis { program, tokens, diagnostics }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Keyword,
+    Constructor,
+    Variable,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: String,
+    pub insert_text: String,
+}
+
+// Top-level statements: (label, text inserted, description)
+const BLOCK_KEYWORDS: &[(&str, &str, &str)] = &[
+    ("import", "import ", "Import a standard library"),
+    ("record", "record patient #", "Declare a patient record"),
+    ("track", "track for patient #", "Record observations"),
+    ("prescribe", "prescribe for patient #", "Prescribe medications"),
+    ("connect", "connect device \"\" to patient #", "Connect a monitoring device"),
+    ("schedule", "schedule for patient #", "Schedule appointments"),
+    ("generate", "generate handout for patient #", "Generate patient materials"),
+    ("analyze", "analyze for patient #", "Clinical analytics"),
+    ("commit", "commit changes to patient #", "Commit and save the patient record"),
+    ("share", "share anonymized patient #", "Share a record with a care team"),
+    ("bill", "bill for patient #", "Billing and coding"),
+];
+
+// Statements that only make sense inside a block
+const STATEMENT_KEYWORDS: &[(&str, &str, &str)] = &[
+    ("encrypt", "encrypt with ", "Encrypt the record with a key"),
+    ("calculate", "calculate dose for ", "Calculate a dose from patient data"),
+    ("administer", "administer ", "Administer a declared medication"),
+    ("verify_interactions", "verify_interactions();", "Check the patient's medications for drug-drug interactions"),
+    ("assess", "assess compliance with medications;", "Record medication adherence in the audit log"),
+];
+
+// Code systems that can follow `with` and have hover documentation
+const CODE_SYSTEMS: &[&str] = &["loinc", "rxnorm", "icd10", "snomed"];
+
+// Resource constructors: (name, signature, documentation)
+const CONSTRUCTORS: &[(&str, &str, &str)] = &[
+    (
+        "BloodPressure",
+        "BloodPressure(systolic, diastolic) on \"time\"",
+        "Blood pressure reading in mmHg (LOINC 85354-9), taken now or at the `on` time. \
+         Systolic must be 40-300 and diastolic 20-200.",
+    ),
+    (
+        "LabResult",
+        "LabResult(value) unit \"...\"",
+        "Laboratory result with a unit, optional time (`on`) and note (`with note`). \
+         The test is named by the variable (`glucose = ...`) or `with loinc \"code\"`, \
+         and the result is converted to the test's unit (e.g. mmol/L to mg/dL for glucose) \
+         and flagged against its reference range.",
+    ),
+    (
+        "Medication",
+        "Medication(\"name\") with rxnorm \"code\"",
+        "Declare a medication by name, optionally coded with its RxNorm RXCUI. \
+         The medication must be in the RxNorm dictionary.",
+    ),
+];
+
+pub fn completions(analysis: &Analysis, offset: usize) -> Vec<Completion> {
+    let scope = enclosing_patient(&analysis.tokens, offset);
+    let keyword = |(label, insert, detail): &(&str, &str, &str)| Completion {
+        label: label.to_string(),
+        kind: CompletionKind::Keyword,
+        detail: detail.to_string(),
+        insert_text: insert.to_string(),
+    };
+
+    let Some(patient) = scope else {
+        return BLOCK_KEYWORDS.iter().map(keyword).collect();
+    };
+
+    let mut items: Vec<Completion> = STATEMENT_KEYWORDS.iter().map(keyword).collect();
+    items.extend(CONSTRUCTORS.iter().map(|(name, signature, doc)| Completion {
+        label: name.to_string(),
+        kind: CompletionKind::Constructor,
+        detail: format!("{} - {}", signature, doc),
+        insert_text: format!("{}(", name),
+    }));
+
+    let mut seen = HashSet::new();
+    for (target, _) in assignments(&analysis.program, &patient) {
+        if seen.insert(target.name.clone()) {
+            items.push(Completion {
+                label: target.name.clone(),
+                kind: CompletionKind::Variable,
+                detail: format!("variable of patient #{}", patient),
+                insert_text: target.name.clone(),
+            });
+        }
+    }
+    items
+}
+
+// Markdown documentation for the token under `offset`
+pub fn hover(analysis: &Analysis, offset: usize) -> Option<(String, Span)> {
+    let index = token_at(&analysis.tokens, offset)?;
+    let token = &analysis.tokens[index];
+
+    match &token.kind {
+        // `with rxnorm "6809"`, `with loinc "2345-7"`
+        TokenKind::Str(code) => {
+            let system = match index.checked_sub(1).map(|i| &analysis.tokens[i].kind) {
+                Some(TokenKind::Ident(system)) if CODE_SYSTEMS.contains(&system.as_str()) => system,
+                _ => return None,
+            };
+            let text = terminology::describe(system, code)
+                .unwrap_or_else(|| format!("Unknown {} code `{}`", system, code));
+            Some((text, token.span))
+        }
+        TokenKind::Ident(name) => {
+            if let Some((_, signature, doc)) = CONSTRUCTORS.iter().find(|(c, _, _)| c == name) {
+                return Some((format!("```charcot\n{}\n```\n{}", signature, doc), token.span));
+            }
+            let stmt = definition_stmt(analysis, offset, name)?;
+            Some((format!("```charcot\n{}\n```", printer::print_stmt(stmt)), token.span))
+        }
+        _ => None,
+    }
+}
+
+// Span of the assignment that defines the variable under `offset`
+pub fn definition(analysis: &Analysis, offset: usize) -> Option<Span> {
+    let index = token_at(&analysis.tokens, offset)?;
+    match &analysis.tokens[index].kind {
+        TokenKind::Ident(name) => {
+            let stmt = definition_stmt(analysis, offset, name)?;
+            match &stmt.kind {
+                StmtKind::Assign { target, .. } => Some(target.span),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// The latest assignment to `name` before `offset` in the same patient's
+// blocks, or the first one if it is only assigned later
+fn definition_stmt<'a>(analysis: &'a Analysis, offset: usize, name: &str) -> Option<&'a Stmt> {
+    let patient = enclosing_patient(&analysis.tokens, offset)?;
+    let candidates: Vec<_> = assignments(&analysis.program, &patient)
+        .into_iter()
+        .filter(|(target, _)| target.name == name)
+        .collect();
+    candidates.iter()
+        .rev()
+        .find(|(target, _)| target.span.start <= offset)
+        .or(candidates.first())
+        .map(|(_, stmt)| *stmt)
+}
+
+// All `x = ...` statements in blocks for `patient`, in source order
+fn assignments<'a>(program: &'a Program, patient: &str) -> Vec<(&'a Ident, &'a Stmt)> {
+    program.items.iter()
+        .filter_map(|item| match &item.kind {
+            ItemKind::Block(block) if block.patient.id == patient => Some(&block.body),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|stmt| match &stmt.kind {
+            StmtKind::Assign { target, .. } => Some((target, stmt)),
+            _ => None,
+        })
+        .collect()
+}
+
+// Index of the identifier or string touching `offset`; the cursor just after
+// a word still counts as on it
+fn token_at(tokens: &[Token], offset: usize) -> Option<usize> {
+    tokens.iter()
+        .position(|t| t.span.contains(offset) && matches!(t.kind, TokenKind::Ident(_) | TokenKind::Str(_)))
+}
+
+// Patient of the block containing `offset`, found from the tokens alone so it
+// also works while the block is still being typed
+fn enclosing_patient(tokens: &[Token], offset: usize) -> Option<String> {
+    let mut depth = 0usize;
+    let mut last_patient: Option<String> = None;
+    let mut current: Option<String> = None;
+    for token in tokens {
+        if token.span.start >= offset {
+            break;
+        }
+        match &token.kind {
+            TokenKind::PatientId(id) if depth == 0 => last_patient = Some(id.clone()),
+            TokenKind::Semi if depth == 0 => last_patient = None,
+            TokenKind::LBrace => {
+                if depth == 0 {
+                    current = last_patient.take();
+                }
+                depth += 1;
+            }
+            TokenKind::RBrace => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    current = None;
+                }
+            }
+            _ => {}
+        }
+    }
+    if depth > 0 { current } else { None }
+}
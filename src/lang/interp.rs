@@ -0,0 +1,974 @@
+// src/lang/interp.rs
+// Charcot EMR: Interpreter driving the EMR library from Charcot scripts
+//
+// Execution happens in two steps. `plan` walks the syntax tree and produces the
+// list of EMR changes the script asks for, without touching any patient data;
+// `execute` then applies that plan. A dry run is simply a plan that is printed
+// instead of executed.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Duration, Utc};
+
+use super::ast::*;
+use super::diagnostic::{Diagnostic, Span};
+use crate::{
+    ConditionCodes, DoseBasis, DoseLimits, DoseOrder, DoseSeverity, DrugDictionary, EMR, PrescribeOptions, Interpretation, LabRanges, NewCondition, Vital, VitalKind,
+    med_filename, parse_clinical_time, parse_sig, units, validate_clinical_time,
+};
+
+// Days of doses `assess compliance with medications` looks back over
+pub const ADHERENCE_DAYS: i64 = 30;
+
+// A single change to the EMR requested by a script
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    CreatePatient {
+        id: String,
+        given_name: String,
+        family_name: String,
+        gender: String,
+        birth_date: String,
+    },
+    AddBloodPressure {
+        patient_id: String,
+        systolic: i32,
+        diastolic: i32,
+        // When the reading was taken; now if the script doesn't say
+        effective: Option<DateTime<Utc>>,
+    },
+    AddVital {
+        patient_id: String,
+        kind: VitalKind,
+        // In the kind's unit
+        value: f64,
+        effective: Option<DateTime<Utc>>,
+    },
+    AddLabResult {
+        patient_id: String,
+        test: String,
+        value: f64,
+        unit: String,
+        effective: Option<DateTime<Utc>>,
+        note: Option<String>,
+        // Flag against the reference range at planning time
+        interpretation: Interpretation,
+    },
+    AddCondition {
+        patient_id: String,
+        icd10: String,
+        // Description from the code table
+        display: String,
+        snomed: Option<String>,
+        onset: Option<DateTime<Utc>>,
+        note: Option<String>,
+    },
+    Prescribe {
+        patient_id: String,
+        medication: String,
+        dose_mg: f64,
+        frequency: String,
+    },
+    // A dose calculated from the patient's latest measurements when the
+    // script is executed, e.g. 0.5 units/kg
+    PrescribeCalculated {
+        patient_id: String,
+        medication: String,
+        order: DoseOrder,
+        frequency: String,
+    },
+    // Record interactions among the patient's active prescriptions in the
+    // audit log. Severe ones were already refused or overridden when prescribed.
+    VerifyInteractions {
+        patient_id: String,
+    },
+    // Record the share of scheduled doses given over the last
+    // ADHERENCE_DAYS in the audit log
+    AssessAdherence {
+        patient_id: String,
+    },
+    Commit {
+        patient_id: String,
+        message: String,
+    },
+}
+
+impl Action {
+    pub fn patient_id(&self) -> &str {
+        match self {
+            Action::CreatePatient { id, .. } => id,
+            Action::AddBloodPressure { patient_id, .. }
+            | Action::AddVital { patient_id, .. }
+            | Action::AddLabResult { patient_id, .. }
+            | Action::AddCondition { patient_id, .. }
+            | Action::Prescribe { patient_id, .. }
+            | Action::PrescribeCalculated { patient_id, .. }
+            | Action::VerifyInteractions { patient_id }
+            | Action::AssessAdherence { patient_id }
+            | Action::Commit { patient_id, .. } => patient_id,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::CreatePatient { id, given_name, family_name, gender, birth_date } => write!(
+                f, "+ Patient/{}: {} {}, {}, born {}",
+                id, given_name, family_name, gender, birth_date
+            ),
+            Action::AddBloodPressure { patient_id, systolic, diastolic, effective } => {
+                write!(f, "+ Observation for Patient/{}: Blood pressure {}/{} mmHg",
+                       patient_id, systolic, diastolic)?;
+                match effective {
+                    Some(time) => write!(f, " taken {}", time.to_rfc3339()),
+                    None => Ok(()),
+                }
+            }
+            Action::AddVital { patient_id, kind, value, effective } => {
+                write!(f, "+ Observation for Patient/{}: {} {} {}",
+                       patient_id, kind, value, kind.unit().0)?;
+                match effective {
+                    Some(time) => write!(f, " taken {}", time.to_rfc3339()),
+                    None => Ok(()),
+                }
+            }
+            Action::AddLabResult { patient_id, test, value, unit, effective, note, interpretation } => {
+                write!(f, "+ Observation for Patient/{}: {} {} {}", patient_id, test, value, unit)?;
+                if interpretation.is_abnormal() {
+                    write!(f, " [{}]", interpretation.code())?;
+                }
+                if let Some(time) = effective {
+                    write!(f, " taken {}", time.to_rfc3339())?;
+                }
+                match note {
+                    Some(note) => write!(f, " ({})", note),
+                    None => Ok(()),
+                }
+            }
+            Action::AddCondition { patient_id, icd10, display, snomed, onset, note } => {
+                write!(f, "+ Condition for Patient/{}: {} (ICD-10-CM {})", patient_id, display, icd10)?;
+                if let Some(snomed) = snomed {
+                    write!(f, " [SNOMED CT {}]", snomed)?;
+                }
+                if let Some(time) = onset {
+                    write!(f, " since {}", time.to_rfc3339())?;
+                }
+                match note {
+                    Some(note) => write!(f, " ({})", note),
+                    None => Ok(()),
+                }
+            }
+            Action::Prescribe { patient_id, medication, dose_mg, frequency } => write!(
+                f, "+ MedicationRequest for Patient/{}: {} {} mg {}",
+                patient_id, medication, dose_mg, frequency
+            ),
+            Action::PrescribeCalculated { patient_id, medication, order, frequency } => {
+                let basis = match order.basis {
+                    DoseBasis::Weight => "latest weight",
+                    DoseBasis::Bsa => "BSA from latest weight and height",
+                };
+                write!(f, "+ MedicationRequest for Patient/{}: {} {} x {} {}",
+                       patient_id, medication, order, basis, frequency)
+            }
+            Action::VerifyInteractions { patient_id } => write!(
+                f, "? Check Patient/{} medications for interactions and log them", patient_id
+            ),
+            Action::AssessAdherence { patient_id } => write!(
+                f, "? Assess Patient/{} medication adherence over the last {} days and log it", patient_id, ADHERENCE_DAYS
+            ),
+            Action::Commit { patient_id, message } => write!(
+                f, "* Commit Patient/{} \"{}\" and save to {}",
+                patient_id, message, med_filename(patient_id)
+            ),
+        }
+    }
+}
+
+// The changes a script would make, plus warnings about parts of the script
+// that are valid Charcot but have no effect on the EMR yet
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    pub actions: Vec<Action>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+// Work out what `program` would do, flagging lab results against the EMR's
+// lab ranges and looking medications up in its RxNorm dictionary. Patient
+// records are not touched. Fails with every error found if the script cannot
+// be executed as a whole.
+pub fn plan(program: &Program, emr: &EMR) -> Result<Plan, Vec<Diagnostic>> {
+    let mut planner = Planner::new(emr);
+    for item in &program.items {
+        planner.item(item);
+    }
+    planner.finish()
+}
+
+// Apply a plan to `emr`. Patients that are not already open are loaded from
+// their `.med` file with `key`, and each `Commit` also saves the file.
+pub fn execute(emr: &mut EMR, plan: &Plan, key: &str) -> Result<()> {
+    for action in &plan.actions {
+        apply(emr, action, key)?;
+    }
+    Ok(())
+}
+
+// Apply a single action
+pub fn apply(emr: &mut EMR, action: &Action, key: &str) -> Result<()> {
+    if !matches!(action, Action::CreatePatient { .. }) {
+        ensure_loaded(emr, action.patient_id(), key)?;
+    }
+
+    match action {
+        Action::CreatePatient { id, given_name, family_name, gender, birth_date } => {
+            // Never silently replace an existing record
+            let filename = med_filename(id);
+            if emr.bundles.contains_key(id) || Path::new(&filename).exists() {
+                return Err(anyhow!("Patient {} already exists ({})", id, filename));
+            }
+            emr.create_patient(id, given_name, family_name, gender, birth_date)?;
+        }
+        Action::AddBloodPressure { patient_id, systolic, diastolic, effective } => {
+            let effective = effective.unwrap_or_else(Utc::now);
+            emr.add_blood_pressure_at(patient_id, *systolic, *diastolic, effective)?;
+        }
+        Action::AddVital { patient_id, kind, value, effective } => {
+            let effective = effective.unwrap_or_else(Utc::now);
+            emr.add_vital_at(patient_id, *kind, *value, effective)?;
+        }
+        Action::AddLabResult { patient_id, test, value, unit, effective, note, .. } => {
+            let effective = effective.unwrap_or_else(Utc::now);
+            emr.add_lab_result_at(patient_id, test, *value, unit, effective, note.as_deref())?;
+        }
+        Action::AddCondition { patient_id, icd10, snomed, onset, note, .. } => {
+            emr.add_condition(patient_id, &NewCondition {
+                icd10: icd10.clone(),
+                snomed: snomed.clone(),
+                onset: *onset,
+                note: note.clone(),
+                ..NewCondition::default()
+            })?;
+        }
+        Action::Prescribe { patient_id, medication, dose_mg, frequency } => {
+            emr.prescribe_medication(patient_id, medication, *dose_mg, frequency)?;
+        }
+        Action::PrescribeCalculated { patient_id, medication, order, frequency } => {
+            emr.prescribe_calculated_dose(patient_id, medication, order, frequency, &PrescribeOptions::default())?;
+        }
+        Action::VerifyInteractions { patient_id } => {
+            let alerts = emr.check_interactions(patient_id)?;
+            if alerts.is_empty() {
+                emr.log_audit("Interaction check: none found", patient_id)?;
+            }
+            for alert in &alerts {
+                emr.log_audit(&format!("Interaction check: {}", alert), patient_id)?;
+            }
+        }
+        Action::AssessAdherence { patient_id } => {
+            let now = Utc::now();
+            let adherence = emr.adherence(patient_id, now - Duration::days(ADHERENCE_DAYS), now)?;
+            if adherence.is_empty() {
+                emr.log_audit("Adherence check: no doses scheduled", patient_id)?;
+            }
+            for entry in &adherence {
+                emr.log_audit(&format!("Adherence check: {}", entry), patient_id)?;
+            }
+        }
+        Action::Commit { patient_id, message } => {
+            emr.commit_changes(patient_id, message)?;
+            emr.save_patient(patient_id, key)?;
+        }
+    }
+    Ok(())
+}
+
+fn ensure_loaded(emr: &mut EMR, patient_id: &str, key: &str) -> Result<()> {
+    if emr.bundles.contains_key(patient_id) {
+        return Ok(());
+    }
+    let filename = med_filename(patient_id);
+    if !Path::new(&filename).exists() {
+        return Err(anyhow!("Patient file not found: {}", filename));
+    }
+    emr.load_patient(&filename, key)?;
+    Ok(())
+}
+
+#[derive(Default)]
+pub(super) struct Planner {
+    pub(super) actions: Vec<Action>,
+    pub(super) errors: Vec<Diagnostic>,
+    pub(super) warnings: Vec<Diagnostic>,
+    // Medication variables declared in prescribe blocks, by patient
+    medications: HashMap<(String, String), String>,
+    // `calculate dose for` orders, by patient and medication variable
+    calculated: HashMap<(String, String), DoseOrder>,
+    // Patients with changes that have not been committed yet
+    uncommitted: HashMap<String, Span>,
+    created: HashSet<String>,
+    lab_ranges: LabRanges,
+    drugs: Arc<DrugDictionary>,
+    dose_limits: DoseLimits,
+    condition_codes: ConditionCodes,
+}
+
+impl Planner {
+    pub(super) fn new(emr: &EMR) -> Self {
+        Planner {
+            lab_ranges: emr.lab_ranges.clone(),
+            drugs: emr.drugs.clone(),
+            dose_limits: emr.dose_limits.clone(),
+            condition_codes: emr.condition_codes.clone(),
+            ..Planner::default()
+        }
+    }
+
+    fn finish(mut self) -> Result<Plan, Vec<Diagnostic>> {
+        let mut uncommitted: Vec<_> = self.uncommitted.into_iter().collect();
+        uncommitted.sort_by_key(|(_, span)| span.start);
+        for (patient_id, span) in uncommitted {
+            self.warnings.push(Diagnostic::warning(
+                format!("changes to patient #{} are never committed and will not be saved", patient_id),
+                span,
+            ));
+        }
+
+        if self.errors.is_empty() {
+            Ok(Plan { actions: self.actions, warnings: self.warnings })
+        } else {
+            self.errors.extend(self.warnings);
+            Err(self.errors)
+        }
+    }
+
+    fn push(&mut self, action: Action, span: Span) {
+        match &action {
+            Action::Commit { patient_id, .. } => {
+                self.uncommitted.remove(patient_id);
+            }
+            // Checks change nothing that needs committing
+            Action::VerifyInteractions { .. } | Action::AssessAdherence { .. } => {}
+            other => {
+                self.uncommitted.entry(other.patient_id().to_string()).or_insert(span);
+            }
+        }
+        self.actions.push(action);
+    }
+
+    fn unsupported(&mut self, what: &str, span: Span) {
+        self.warnings.push(Diagnostic::warning(
+            format!("{} is not supported by the interpreter yet and will be skipped", what),
+            span,
+        ));
+    }
+
+    fn item(&mut self, item: &Item) {
+        match &item.kind {
+            ItemKind::Import(_) => {}
+            ItemKind::Commit(commit) => {
+                self.push(Action::Commit {
+                    patient_id: commit.patient.id.clone(),
+                    message: commit.message.value.clone(),
+                }, item.span);
+            }
+            ItemKind::Block(block) => match block.header {
+                BlockHeader::Record => self.record(block, item.span),
+                BlockHeader::Track => self.track(block),
+                BlockHeader::Prescribe => self.prescribe(block),
+                BlockHeader::Analyze => self.analyze(block),
+                _ => self.unsupported(
+                    &format!("`{}` block", block.header.keyword()),
+                    item.span,
+                ),
+            },
+        }
+    }
+
+    fn record(&mut self, block: &Block, span: Span) {
+        let patient_id = block.patient.id.clone();
+        if !self.created.insert(patient_id.clone()) {
+            self.errors.push(Diagnostic::error(
+                format!("patient #{} is already recorded earlier in this script", patient_id),
+                block.patient.span,
+            ));
+            return;
+        }
+
+        let mut name = None;
+        let mut gender = None;
+        let mut birth_date = None;
+        let mut conditions = Vec::new();
+
+        for stmt in &block.body {
+            match &stmt.kind {
+                StmtKind::Field { key, value, modifiers } if key.len() == 1 && key[0].name == "condition" => {
+                    if let Some(condition) = self.condition(&patient_id, value, modifiers, stmt.span) {
+                        conditions.push((condition, stmt.span));
+                    }
+                }
+                StmtKind::Field { key, value, .. } if key.len() == 1 => {
+                    let slot = match key[0].name.as_str() {
+                        "name" => &mut name,
+                        "gender" => &mut gender,
+                        "birth_date" => &mut birth_date,
+                        other => {
+                            self.unsupported(&format!("record field `{}`", other), stmt.span);
+                            continue;
+                        }
+                    };
+                    match &value.kind {
+                        ExprKind::Str(text) => *slot = Some(text.clone()),
+                        _ => self.errors.push(Diagnostic::error(
+                            format!("`{}` must be a string", key[0].name),
+                            value.span,
+                        )),
+                    }
+                }
+                // The key itself is supplied when the script is run
+                StmtKind::Encrypt { .. } => {}
+                _ => self.unsupported("this statement in a `record` block", stmt.span),
+            }
+        }
+
+        let mut missing = Vec::new();
+        if name.is_none() { missing.push("name"); }
+        if gender.is_none() { missing.push("gender"); }
+        if birth_date.is_none() { missing.push("birth_date"); }
+        if !missing.is_empty() {
+            self.errors.push(Diagnostic::error(
+                format!("record for patient #{} is missing: {}", patient_id, missing.join(", ")),
+                block.patient.span,
+            ));
+            return;
+        }
+
+        // "Mary Ann Smith" -> given "Mary Ann", family "Smith"
+        let name = name.unwrap_or_default();
+        let (given_name, family_name) = match name.trim().rsplit_once(' ') {
+            Some((given, family)) => (given.trim().to_string(), family.to_string()),
+            None => (name.trim().to_string(), String::new()),
+        };
+
+        self.push(Action::CreatePatient {
+            id: patient_id,
+            given_name,
+            family_name,
+            gender: gender.unwrap_or_default(),
+            birth_date: birth_date.unwrap_or_default(),
+        }, span);
+        for (condition, span) in conditions {
+            self.push(condition, span);
+        }
+    }
+
+    // `condition: "diabetes" with icd10 "E11.9" on "2019-05-01";`. The name
+    // is for the reader; the ICD-10-CM code is what gets recorded.
+    fn condition(&mut self, patient_id: &str, value: &Expr, modifiers: &[Modifier], span: Span) -> Option<Action> {
+        if !matches!(value.kind, ExprKind::Str(_)) {
+            self.errors.push(Diagnostic::error("`condition` must be a string", value.span));
+            return None;
+        }
+        let code = |name: &str| modifiers.iter().find_map(|m| match m {
+            Modifier::Code { system, code } if system.name == name => Some(code),
+            _ => None,
+        });
+        let Some(icd10) = code("icd10") else {
+            self.errors.push(Diagnostic::error(
+                "a condition needs an ICD-10-CM code: condition: \"diabetes\" with icd10 \"E11.9\"",
+                span,
+            ));
+            return None;
+        };
+        let coding = match self.condition_codes.icd10_coding(&icd10.value) {
+            Ok(coding) => coding,
+            Err(e) => {
+                self.errors.push(Diagnostic::error(e.to_string(), icd10.span));
+                return None;
+            }
+        };
+        let snomed = code("snomed");
+        if let Some(snomed) = snomed {
+            if let Err(e) = self.condition_codes.snomed_coding(&snomed.value) {
+                self.errors.push(Diagnostic::error(e.to_string(), snomed.span));
+                return None;
+            }
+        }
+        let note = modifiers.iter().find_map(|m| match m {
+            Modifier::Note(note) => Some(note.value.clone()),
+            _ => None,
+        });
+        let onset = self.effective_time(modifiers).ok()?;
+        Some(Action::AddCondition {
+            patient_id: patient_id.to_string(),
+            icd10: coding.code,
+            display: coding.display,
+            snomed: snomed.map(|code| code.value.trim().to_string()),
+            onset,
+            note,
+        })
+    }
+
+    fn track(&mut self, block: &Block) {
+        for stmt in &block.body {
+            self.track_stmt(&block.patient.id, stmt);
+        }
+    }
+
+    fn track_stmt(&mut self, patient_id: &str, stmt: &Stmt) {
+        let (target, value, modifiers) = match &stmt.kind {
+            StmtKind::Assign { target, value, modifiers } => (target, value, modifiers),
+            _ => {
+                self.unsupported("this statement in a `track` block", stmt.span);
+                return;
+            }
+        };
+
+        match &value.kind {
+            ExprKind::Call { callee, args } if callee.name == "BloodPressure" => {
+                let values: Vec<Option<i32>> = args.iter().map(integer).collect();
+                match values.as_slice() {
+                    [Some(systolic), Some(diastolic)] => {
+                        let Ok(effective) = self.effective_time(modifiers) else {
+                            return;
+                        };
+                        self.push(Action::AddBloodPressure {
+                            patient_id: patient_id.to_string(),
+                            systolic: *systolic,
+                            diastolic: *diastolic,
+                            effective,
+                        }, stmt.span);
+                    }
+                    _ => self.errors.push(Diagnostic::error(
+                        "BloodPressure expects two whole numbers: BloodPressure(systolic, diastolic)",
+                        value.span,
+                    )),
+                }
+            }
+            ExprKind::Call { callee, args } if callee.name == "LabResult" => {
+                self.lab_result(patient_id, target, args, modifiers, stmt.span);
+            }
+            ExprKind::Call { callee, .. } => {
+                self.unsupported(&format!("`{}`", callee.name), value.span);
+            }
+            ExprKind::Quantity { .. } => match VitalKind::from_name(&target.name) {
+                Some(kind) => self.vital(patient_id, kind, value, modifiers, stmt.span),
+                None => self.unsupported("this kind of observation", value.span),
+            },
+            _ => self.unsupported("this kind of observation", value.span),
+        }
+    }
+
+    // `weight = 189 lb;` records the vital named by the variable,
+    // converted to its unit
+    fn vital(&mut self, patient_id: &str, kind: VitalKind, value: &Expr, modifiers: &[Modifier], span: Span) {
+        let ExprKind::Quantity { value: amount, unit } = &value.kind else {
+            return;
+        };
+        let vital = match Vital::from_quantity(kind, *amount, unit) {
+            Ok(vital) => vital,
+            Err(e) => {
+                self.errors.push(Diagnostic::error(format!("{:#}", e), value.span));
+                return;
+            }
+        };
+        let Ok(effective) = self.effective_time(modifiers) else {
+            return;
+        };
+        self.push(Action::AddVital {
+            patient_id: patient_id.to_string(),
+            kind,
+            value: vital.value,
+            effective,
+        }, span);
+    }
+
+    // `glucose = LabResult(180) unit "mg/dL" with note "fasting";`. The test is
+    // taken from `with loinc "..."` if given, otherwise from the variable name.
+    fn lab_result(&mut self, patient_id: &str, target: &Ident, args: &[Expr], modifiers: &[Modifier], span: Span) {
+        let value = match args {
+            [Expr { kind: ExprKind::Number(value), .. }] => *value,
+            _ => {
+                let span = args.first().map(|a| a.span).unwrap_or(target.span);
+                self.errors.push(Diagnostic::error("LabResult expects one number: LabResult(value)", span));
+                return;
+            }
+        };
+
+        let loinc = modifiers.iter().find_map(|m| match m {
+            Modifier::Code { system, code } if system.name == "loinc" => Some(code),
+            _ => None,
+        });
+        let (name, name_span) = match loinc {
+            Some(code) => (code.value.as_str(), code.span),
+            None => (target.name.as_str(), target.span),
+        };
+        let Some(test) = self.lab_ranges.find(name).cloned() else {
+            self.errors.push(Diagnostic::error(
+                format!("unknown lab test `{}`; name the variable after the test or add `with loinc \"<code>\"`", name),
+                name_span,
+            ));
+            return;
+        };
+
+        let Some(unit) = modifiers.iter().find_map(|m| match m {
+            Modifier::Unit(unit) => Some(unit),
+            _ => None,
+        }) else {
+            self.errors.push(Diagnostic::error(
+                format!("lab result needs a unit: unit \"{}\"", test.unit),
+                span,
+            ));
+            return;
+        };
+        // Results in other units are stored converted, e.g. glucose in mmol/L
+        let value = match units::convert_analyte(value, &unit.value, &test.unit, &test.loinc) {
+            Ok(value) => value,
+            Err(e) => {
+                self.errors.push(Diagnostic::error(
+                    format!("{} is reported in {}: {}", test.name, test.unit, e),
+                    unit.span,
+                ));
+                return;
+            }
+        };
+
+        let note = modifiers.iter().find_map(|m| match m {
+            Modifier::Note(note) => Some(note.value.clone()),
+            _ => None,
+        });
+        let Ok(effective) = self.effective_time(modifiers) else {
+            return;
+        };
+        self.push(Action::AddLabResult {
+            patient_id: patient_id.to_string(),
+            test: test.name.clone(),
+            value,
+            unit: test.unit.clone(),
+            effective,
+            note,
+            interpretation: test.interpret(value),
+        }, span);
+    }
+
+    // The `on "..."` time of an observation, if any. Invalid and future times
+    // are reported as errors.
+    fn effective_time(&mut self, modifiers: &[Modifier]) -> Result<Option<DateTime<Utc>>, ()> {
+        let Some(time) = modifiers.iter().find_map(|m| match m {
+            Modifier::On(time) => Some(time),
+            _ => None,
+        }) else {
+            return Ok(None);
+        };
+        match parse_clinical_time(&time.value).and_then(|t| validate_clinical_time(t).map(|_| t)) {
+            Ok(t) => Ok(Some(t)),
+            Err(e) => {
+                self.errors.push(Diagnostic::error(e.to_string(), time.span));
+                Err(())
+            }
+        }
+    }
+
+    // Look up a medication in the RxNorm dictionary, by its `with rxnorm`
+    // code if given. A code the dictionary does not have, such as a brand or
+    // clinical drug when only ingredients are loaded, falls back to the name
+    // with a warning. Returns the dictionary name.
+    fn drug(&mut self, name: &str, span: Span, modifiers: &[Modifier]) -> Option<String> {
+        let code = modifiers.iter().find_map(|m| match m {
+            Modifier::Code { system, code } if system.name == "rxnorm" => Some(code),
+            _ => None,
+        });
+        let found = match code {
+            Some(code) => match (self.drugs.lookup(&code.value), self.drugs.lookup(name)) {
+                (Some(drug), _) if drug.name.eq_ignore_ascii_case(name) => Ok(drug.name.clone()),
+                (Some(drug), _) => Err((format!("RxNorm {} is {}, not {}", code.value, drug.name, name), code.span)),
+                (None, Some(drug)) => {
+                    self.warnings.push(Diagnostic::warning(
+                        format!("RxNorm {} is not in the medication dictionary; using {} (RxNorm {}) by name",
+                                code.value, drug.name, drug.rxcui),
+                        code.span));
+                    Ok(drug.name.clone())
+                }
+                (None, None) => Err((format!("Unknown RxNorm code: {}", code.value), code.span)),
+            },
+            None => self.drugs.resolve(name)
+                .map(|resolved| resolved.drug.name)
+                .map_err(|e| (e.to_string(), span)),
+        };
+        match found {
+            Ok(name) => Some(name),
+            Err((message, span)) => {
+                self.errors.push(Diagnostic::error(message, span));
+                None
+            }
+        }
+    }
+
+    fn analyze(&mut self, block: &Block) {
+        for stmt in &block.body {
+            match &stmt.kind {
+                // assess compliance with medications;
+                StmtKind::Directive { verb, args } if verb.name == "assess" => {
+                    let words: Vec<&str> = args.iter()
+                        .map(|arg| match &arg.kind {
+                            ExprKind::Ident(word) => word.as_str(),
+                            _ => "",
+                        })
+                        .collect();
+                    match words.as_slice() {
+                        ["compliance" | "adherence", "with" | "to", "medications"] => self.push(Action::AssessAdherence {
+                            patient_id: block.patient.id.clone(),
+                        }, stmt.span),
+                        _ => self.unsupported("this assessment", stmt.span),
+                    }
+                }
+                _ => self.unsupported("this statement in an `analyze` block", stmt.span),
+            }
+        }
+    }
+
+    fn prescribe(&mut self, block: &Block) {
+        for stmt in &block.body {
+            self.prescribe_stmt(&block.patient.id, stmt);
+        }
+    }
+
+    fn prescribe_stmt(&mut self, patient_id: &str, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Assign { target, value, modifiers } => match &value.kind {
+                ExprKind::Call { callee, args } if callee.name == "Medication" => {
+                    match args.as_slice() {
+                        [Expr { kind: ExprKind::Str(name), span }] => {
+                            let Some(drug) = self.drug(name, *span, modifiers) else {
+                                return;
+                            };
+                            self.medications.insert(
+                                (patient_id.to_string(), target.name.clone()),
+                                drug,
+                            );
+                        }
+                        _ => self.errors.push(Diagnostic::error(
+                            "Medication expects a single name: Medication(\"metformin\")",
+                            value.span,
+                        )),
+                    }
+                }
+                _ => self.unsupported("this assignment in a `prescribe` block", stmt.span),
+            },
+            StmtKind::Administer { medication, dose, sig } => {
+                let key = (patient_id.to_string(), medication.name.clone());
+                let name = match self.medications.get(&key) {
+                    Some(name) => name.clone(),
+                    None => {
+                        self.errors.push(Diagnostic::error(
+                            format!("`{}` is not a medication declared for patient #{}",
+                                    medication.name, patient_id),
+                            medication.span,
+                        ));
+                        return;
+                    }
+                };
+                let calculated = match dose {
+                    Dose::Calculated(span) => match self.calculated.get(&key) {
+                        Some(order) => Some(order.clone()),
+                        None => {
+                            self.errors.push(Diagnostic::error(
+                                format!("no dose is calculated for `{}`; add `calculate dose for {} as 0.5 * patient.weight` first",
+                                        medication.name, medication.name),
+                                *span,
+                            ));
+                            return;
+                        }
+                    },
+                    Dose::Amount(_) => None,
+                };
+                if sig.is_empty() {
+                    self.errors.push(Diagnostic::error(
+                        "missing frequency, e.g. `twice daily`",
+                        stmt.span,
+                    ));
+                    return;
+                }
+                let parsed = match parse_sig(&sig_text(sig)) {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        self.errors.push(Diagnostic::error(e.to_string(), sig_span(sig)));
+                        return;
+                    }
+                };
+                // The dose is only known once the measurements are read, so
+                // its limits are checked when the script is executed
+                if let Some(order) = calculated {
+                    self.push(Action::PrescribeCalculated {
+                        patient_id: patient_id.to_string(),
+                        medication: name,
+                        order,
+                        frequency: sig_text(sig),
+                    }, stmt.span);
+                    return;
+                }
+                let (dose_mg, dose_span) = match dose {
+                    Dose::Amount(Expr { kind: ExprKind::Quantity { value, unit }, span }) if unit == "mg" => (*value, *span),
+                    Dose::Amount(expr) => {
+                        self.errors.push(Diagnostic::error(
+                            "dose must be given in mg, e.g. `1000 mg`",
+                            expr.span,
+                        ));
+                        return;
+                    }
+                    Dose::Calculated(_) => return,
+                };
+                // Scripts cannot give an override reason, so warnings stop them too
+                if let Some(limit) = self.drugs.resolve(&name).ok().and_then(|drug| self.dose_limits.limit_for(&drug)) {
+                    if limit.unit != "mg" {
+                        self.errors.push(Diagnostic::error(
+                            format!("{} is dosed in {}; calculate its dose with `calculate dose for`", limit.name, limit.unit),
+                            dose_span,
+                        ));
+                        return;
+                    }
+                    let alerts = limit.check(dose_mg, &parsed);
+                    if !alerts.is_empty() {
+                        for alert in alerts {
+                            let message = match alert.severity {
+                                DoseSeverity::HardStop => alert.to_string(),
+                                DoseSeverity::Warning => format!("{}; prescribe it with an override reason outside the script", alert),
+                            };
+                            self.errors.push(Diagnostic::error(message, dose_span));
+                        }
+                        return;
+                    }
+                }
+                self.push(Action::Prescribe {
+                    patient_id: patient_id.to_string(),
+                    medication: name,
+                    dose_mg,
+                    frequency: sig_text(sig),
+                }, stmt.span);
+            }
+            StmtKind::Calculate { property, subject, value, unit } => {
+                self.calculate(patient_id, property, subject, value, unit.as_ref());
+            }
+            StmtKind::Call { call: Expr { kind: ExprKind::Call { callee, args }, .. }, condition: None }
+                if callee.name == "verify_interactions" && args.is_empty() =>
+            {
+                self.push(Action::VerifyInteractions { patient_id: patient_id.to_string() }, stmt.span);
+            }
+            StmtKind::Call { call, .. } => self.unsupported(
+                &format!("`{}`", super::printer::print_expr(call)),
+                stmt.span,
+            ),
+            _ => self.unsupported("this statement in a `prescribe` block", stmt.span),
+        }
+    }
+
+    // `calculate dose for insulin as 0.5 * patient.weight unit "units";`
+    fn calculate(&mut self, patient_id: &str, property: &Ident, subject: &Ident, value: &Expr, unit: Option<&StrLit>) {
+        if property.name != "dose" {
+            self.errors.push(Diagnostic::error(
+                format!("only a `dose` can be calculated, not `{}`", property.name),
+                property.span,
+            ));
+            return;
+        }
+        let key = (patient_id.to_string(), subject.name.clone());
+        if !self.medications.contains_key(&key) {
+            self.errors.push(Diagnostic::error(
+                format!("`{}` is not a medication declared for patient #{}", subject.name, patient_id),
+                subject.span,
+            ));
+            return;
+        }
+        let Some((amount, basis)) = dose_per(value) else {
+            self.errors.push(Diagnostic::error(
+                "a dose is calculated as an amount times `patient.weight` or `patient.bsa`, e.g. `0.5 * patient.weight`",
+                value.span,
+            ));
+            return;
+        };
+        if amount <= 0.0 {
+            self.errors.push(Diagnostic::error("the dose per kg or m2 must be positive", value.span));
+            return;
+        }
+        let unit = match unit {
+            Some(lit) if !crate::dose_calc::DOSE_UNITS.contains(&lit.value.as_str()) => {
+                self.errors.push(Diagnostic::error(
+                    format!("a calculated dose must be in {}, not `{}`", crate::dose_calc::DOSE_UNITS.join(" or "), lit.value),
+                    lit.span,
+                ));
+                return;
+            }
+            Some(lit) => lit.value.clone(),
+            None => "mg".to_string(),
+        };
+        self.calculated.insert(key, DoseOrder { amount, unit, basis });
+    }
+
+    // Plan a statement outside of any block, as typed at the REPL. Medication
+    // statements are handled as in a `prescribe` block, everything else as in
+    // a `track` block.
+    pub(super) fn statement(&mut self, patient_id: &str, stmt: &Stmt) {
+        let is_prescription = match &stmt.kind {
+            StmtKind::Assign { value, .. } => {
+                matches!(&value.kind, ExprKind::Call { callee, .. } if callee.name == "Medication")
+            }
+            StmtKind::Administer { .. } | StmtKind::Calculate { .. } | StmtKind::Call { .. } => true,
+            _ => false,
+        };
+        if is_prescription {
+            self.prescribe_stmt(patient_id, stmt);
+        } else {
+            self.track_stmt(patient_id, stmt);
+        }
+    }
+}
+
+// `0.5 * patient.weight` or `patient.bsa * 75`
+fn dose_per(expr: &Expr) -> Option<(f64, DoseBasis)> {
+    let ExprKind::Binary { op: BinOp::Mul, lhs, rhs } = &expr.kind else {
+        return None;
+    };
+    let basis = |expr: &Expr| match &expr.kind {
+        ExprKind::Member { object, property } if matches!(&object.kind, ExprKind::Ident(name) if name == "patient") => {
+            DoseBasis::from_property(&property.name)
+        }
+        _ => None,
+    };
+    let amount = |expr: &Expr| match &expr.kind {
+        ExprKind::Number(n) => Some(*n),
+        _ => None,
+    };
+    match (amount(lhs), basis(rhs)) {
+        (Some(amount), Some(basis)) => Some((amount, basis)),
+        _ => Some((amount(rhs)?, basis(lhs)?)),
+    }
+}
+
+fn integer(expr: &Expr) -> Option<i32> {
+    match &expr.kind {
+        ExprKind::Number(n) if n.fract() == 0.0 => Some(*n as i32),
+        ExprKind::Neg(inner) => integer(inner).map(|n| -n),
+        _ => None,
+    }
+}
+
+// Span covering all the words of a non-empty sig
+pub fn sig_span(sig: &[SigWord]) -> Span {
+    let first = sig.first().map(SigWord::span).unwrap_or_default();
+    sig.last().map(|last| first.to(last.span())).unwrap_or(first)
+}
+
+// Administration instructions as free text, e.g. "every 24 hours starting 08:00"
+pub fn sig_text(sig: &[SigWord]) -> String {
+    sig.iter()
+        .map(|word| match word {
+            SigWord::Word(ident) => ident.name.clone(),
+            SigWord::Number(n, _) => super::printer::format_number(*n),
+            SigWord::Str(lit) => lit.value.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,304 @@
+// src/lang/lexer.rs
+// Charcot EMR: Tokenizer for Charcot scripts
+
+use super::diagnostic::{Diagnostic, Span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    PatientId(String), // `#123`
+    Comment(String),   // `// ...` or `/* ... */`, including the delimiters
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Semi,
+    Colon,
+    Comma,
+    Dot,
+    Eq,
+    EqEq,
+    NotEq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Eof,
+}
+
+impl TokenKind {
+    // Short human-readable description used in "expected X, found Y" messages
+    pub fn describe(&self) -> String {
+        match self {
+            TokenKind::Ident(name) => format!("`{}`", name),
+            TokenKind::Number(n) => format!("number `{}`", n),
+            TokenKind::Str(_) => "string literal".to_string(),
+            TokenKind::PatientId(id) => format!("patient reference `#{}`", id),
+            TokenKind::Comment(_) => "comment".to_string(),
+            TokenKind::Eof => "end of file".to_string(),
+            other => format!("`{}`", other.punct()),
+        }
+    }
+
+    pub fn punct(&self) -> &'static str {
+        match self {
+            TokenKind::LBrace => "{",
+            TokenKind::RBrace => "}",
+            TokenKind::LParen => "(",
+            TokenKind::RParen => ")",
+            TokenKind::LBracket => "[",
+            TokenKind::RBracket => "]",
+            TokenKind::Semi => ";",
+            TokenKind::Colon => ":",
+            TokenKind::Comma => ",",
+            TokenKind::Dot => ".",
+            TokenKind::Eq => "=",
+            TokenKind::EqEq => "==",
+            TokenKind::NotEq => "!=",
+            TokenKind::Lt => "<",
+            TokenKind::Le => "<=",
+            TokenKind::Gt => ">",
+            TokenKind::Ge => ">=",
+            TokenKind::Plus => "+",
+            TokenKind::Minus => "-",
+            TokenKind::Star => "*",
+            TokenKind::Slash => "/",
+            TokenKind::Percent => "%",
+            _ => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+    tokens: Vec<Token>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+// Split `source` into tokens. Lexing never stops early: invalid characters and
+// unterminated literals are reported and skipped so the parser still sees the rest.
+// The token list always ends with `Eof`.
+pub fn tokenize(source: &str) -> (Vec<Token>, Vec<Diagnostic>) {
+    let mut lexer = Lexer {
+        source,
+        chars: source.char_indices().collect(),
+        pos: 0,
+        tokens: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    lexer.run();
+    (lexer.tokens, lexer.diagnostics)
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).map(|&(_, c)| c)
+    }
+
+    fn peek_next(&self) -> Option<char> {
+        self.chars.get(self.pos + 1).map(|&(_, c)| c)
+    }
+
+    fn offset(&self) -> usize {
+        self.chars.get(self.pos).map(|&(i, _)| i).unwrap_or(self.source.len())
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn push(&mut self, kind: TokenKind, start: usize) {
+        let span = Span::new(start, self.offset());
+        self.tokens.push(Token { kind, span });
+    }
+
+    fn run(&mut self) {
+        while let Some(c) = self.peek() {
+            let start = self.offset();
+            match c {
+                c if c.is_whitespace() => {
+                    self.bump();
+                }
+                '/' if self.peek_next() == Some('/') => self.line_comment(start),
+                '/' if self.peek_next() == Some('*') => self.block_comment(start),
+                '"' => self.string(start),
+                '#' => self.patient_id(start),
+                c if c.is_ascii_digit() => self.number(start),
+                '.' if self.peek_next().is_some_and(|n| n.is_ascii_digit()) => self.number(start),
+                c if c.is_alphabetic() || c == '_' => self.ident(start),
+                _ => self.punct(start, c),
+            }
+        }
+        let end = self.source.len();
+        self.tokens.push(Token { kind: TokenKind::Eof, span: Span::new(end, end) });
+    }
+
+    fn line_comment(&mut self, start: usize) {
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                break;
+            }
+            self.bump();
+        }
+        let text = self.source[start..self.offset()].trim_end().to_string();
+        self.push(TokenKind::Comment(text), start);
+    }
+
+    fn block_comment(&mut self, start: usize) {
+        self.bump();
+        self.bump();
+        loop {
+            match self.bump() {
+                Some('*') if self.peek() == Some('/') => {
+                    self.bump();
+                    break;
+                }
+                Some(_) => {}
+                None => {
+                    self.diagnostics.push(Diagnostic::error(
+                        "unterminated block comment",
+                        Span::new(start, start + 2),
+                    ));
+                    break;
+                }
+            }
+        }
+        let text = self.source[start..self.offset()].to_string();
+        self.push(TokenKind::Comment(text), start);
+    }
+
+    fn string(&mut self, start: usize) {
+        self.bump();
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => break,
+                Some('\\') => {
+                    let escape_start = self.offset() - 1;
+                    match self.bump() {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some('"') => value.push('"'),
+                        Some('\\') => value.push('\\'),
+                        Some(other) => {
+                            self.diagnostics.push(Diagnostic::error(
+                                format!("unknown escape sequence `\\{}`", other),
+                                Span::new(escape_start, self.offset()),
+                            ));
+                        }
+                        None => {}
+                    }
+                }
+                Some('\n') | None => {
+                    self.diagnostics.push(Diagnostic::error(
+                        "unterminated string literal",
+                        Span::new(start, start + 1),
+                    ));
+                    break;
+                }
+                Some(c) => value.push(c),
+            }
+        }
+        self.push(TokenKind::Str(value), start);
+    }
+
+    fn patient_id(&mut self, start: usize) {
+        self.bump();
+        let id_start = self.offset();
+        while let Some(c) = self.peek() {
+            if c.is_alphanumeric() || c == '_' || c == '-' {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        let id = self.source[id_start..self.offset()].to_string();
+        if id.is_empty() {
+            self.diagnostics.push(Diagnostic::error(
+                "expected a patient identifier after `#`",
+                Span::new(start, start + 1),
+            ));
+        }
+        self.push(TokenKind::PatientId(id), start);
+    }
+
+    fn number(&mut self, start: usize) {
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+        }
+        if self.peek() == Some('.') && self.peek_next().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                self.bump();
+            }
+        }
+        let text = &self.source[start..self.offset()];
+        // Only digits and at most one dot were consumed, so this cannot fail
+        let value = text.parse::<f64>().unwrap_or_default();
+        self.push(TokenKind::Number(value), start);
+    }
+
+    fn ident(&mut self, start: usize) {
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            self.bump();
+        }
+        let name = self.source[start..self.offset()].to_string();
+        self.push(TokenKind::Ident(name), start);
+    }
+
+    fn punct(&mut self, start: usize, c: char) {
+        self.bump();
+        let next = self.peek();
+        let kind = match (c, next) {
+            ('=', Some('=')) => { self.bump(); TokenKind::EqEq }
+            ('!', Some('=')) => { self.bump(); TokenKind::NotEq }
+            ('<', Some('=')) => { self.bump(); TokenKind::Le }
+            ('>', Some('=')) => { self.bump(); TokenKind::Ge }
+            ('{', _) => TokenKind::LBrace,
+            ('}', _) => TokenKind::RBrace,
+            ('(', _) => TokenKind::LParen,
+            (')', _) => TokenKind::RParen,
+            ('[', _) => TokenKind::LBracket,
+            (']', _) => TokenKind::RBracket,
+            (';', _) => TokenKind::Semi,
+            (':', _) => TokenKind::Colon,
+            (',', _) => TokenKind::Comma,
+            ('.', _) => TokenKind::Dot,
+            ('=', _) => TokenKind::Eq,
+            ('<', _) => TokenKind::Lt,
+            ('>', _) => TokenKind::Gt,
+            ('+', _) => TokenKind::Plus,
+            ('-', _) => TokenKind::Minus,
+            ('*', _) => TokenKind::Star,
+            ('/', _) => TokenKind::Slash,
+            ('%', _) => TokenKind::Percent,
+            _ => {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("unexpected character `{}`", c),
+                    Span::new(start, self.offset()),
+                ));
+                return;
+            }
+        };
+        self.push(kind, start);
+    }
+}
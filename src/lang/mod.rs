@@ -0,0 +1,66 @@
+// src/lang/mod.rs
+// Charcot EMR: Front-end for the Charcot scripting language
+//
+// Source text goes through `lexer::tokenize` and `parser::Parser` to become an
+// `ast::Program`; `check` validates a program before it runs, `printer` turns
+// a program back into canonical source and `interp` executes a program
+// against an `EMR`, either as a whole script or one statement at a time in a
+// `repl` session. `ide` provides the editor features used by `emr_lsp`.
+
+pub mod ast;
+pub mod check;
+pub mod diagnostic;
+pub mod ide;
+pub mod interp;
+pub mod lexer;
+pub mod parser;
+pub mod printer;
+pub mod repl;
+
+use std::fs;
+use anyhow::{Result, anyhow, Context};
+
+pub use ast::Program;
+pub use diagnostic::{Diagnostic, Severity, Span};
+
+// Parse Charcot source. On failure every lexical and syntax error found is
+// returned, not just the first one.
+pub fn parse(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    let (tokens, mut diagnostics) = lexer::tokenize(source);
+    let (program, parse_diagnostics) = parser::Parser::new(tokens).parse_program();
+    diagnostics.extend(parse_diagnostics);
+
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        Err(diagnostics)
+    } else {
+        Ok(program)
+    }
+}
+
+// Parse a single statement such as `bp = BloodPressure(120, 80);`
+pub fn parse_statement(source: &str) -> Result<ast::Stmt, Vec<Diagnostic>> {
+    let (tokens, mut diagnostics) = lexer::tokenize(source);
+    let (stmt, parse_diagnostics) = parser::Parser::new(tokens).parse_single_stmt();
+    diagnostics.extend(parse_diagnostics);
+
+    match stmt {
+        Some(stmt) if !diagnostics.iter().any(Diagnostic::is_error) => Ok(stmt),
+        _ => Err(diagnostics),
+    }
+}
+
+// Read and parse a `.charcot` file, rendering any syntax errors with
+// line/column information and source snippets
+pub fn parse_file(path: &str) -> Result<(String, Program)> {
+    let source = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read script {}", path))?;
+    match parse(&source) {
+        Ok(program) => Ok((source, program)),
+        Err(diagnostics) => Err(anyhow!(
+            "{}\n{} syntax error(s) in {}",
+            diagnostic::render_all(&diagnostics, &source, path),
+            diagnostics.len(),
+            path
+        )),
+    }
+}
@@ -0,0 +1,624 @@
+// src/lang/parser.rs
+// Charcot EMR: Recursive-descent parser producing the typed AST
+//
+// Keywords are contextual: `patient`, `dose`, `unit` and friends are ordinary
+// identifiers everywhere except in the positions where the grammar expects them.
+
+use super::ast::*;
+use super::diagnostic::{Diagnostic, Span};
+use super::lexer::{Token, TokenKind};
+
+// Words that end a quantity instead of being read as its unit (`5 on "..."`)
+const CLAUSE_KEYWORDS: &[&str] = &[
+    "on", "with", "unit", "if", "as", "to", "for", "in", "when", "over", "starting", "every",
+];
+
+type PResult<T> = Result<T, Diagnostic>;
+
+pub struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Parser {
+    // Comments are not part of the syntax tree and are dropped here
+    pub fn new(tokens: Vec<Token>) -> Self {
+        let tokens = tokens.into_iter()
+            .filter(|t| !matches!(t.kind, TokenKind::Comment(_)))
+            .collect();
+        Parser { tokens, pos: 0, diagnostics: Vec::new() }
+    }
+
+    // Parse a whole program, recovering at item and statement boundaries so
+    // that every syntax error in the file is reported in one pass
+    pub fn parse_program(mut self) -> (Program, Vec<Diagnostic>) {
+        let mut items = Vec::new();
+        while !self.at_eof() {
+            match self.parse_item() {
+                Ok(item) => items.push(item),
+                Err(diagnostic) => {
+                    self.diagnostics.push(diagnostic);
+                    self.synchronize_item();
+                }
+            }
+        }
+        (Program { items }, self.diagnostics)
+    }
+
+    // Parse exactly one block statement, as typed at the REPL
+    pub fn parse_single_stmt(mut self) -> (Option<Stmt>, Vec<Diagnostic>) {
+        let stmt = match self.parse_stmt() {
+            Ok(stmt) => stmt,
+            Err(diagnostic) => {
+                self.diagnostics.push(diagnostic);
+                return (None, self.diagnostics);
+            }
+        };
+        if !self.at_eof() {
+            let diagnostic = self.unexpected("end of input after the statement");
+            self.diagnostics.push(diagnostic);
+            return (None, self.diagnostics);
+        }
+        (Some(stmt), self.diagnostics)
+    }
+
+    // ---- token helpers ----
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos.min(self.tokens.len() - 1)]
+    }
+
+    fn peek_at(&self, n: usize) -> &Token {
+        &self.tokens[(self.pos + n).min(self.tokens.len() - 1)]
+    }
+
+    fn at_eof(&self) -> bool {
+        self.peek().kind == TokenKind::Eof
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn previous_span(&self) -> Span {
+        self.tokens[self.pos.saturating_sub(1)].span
+    }
+
+    fn check(&self, kind: &TokenKind) -> bool {
+        &self.peek().kind == kind
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.check(kind) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn check_word(&self, word: &str) -> bool {
+        matches!(&self.peek().kind, TokenKind::Ident(name) if name == word)
+    }
+
+    fn check_word_at(&self, n: usize, word: &str) -> bool {
+        matches!(&self.peek_at(n).kind, TokenKind::Ident(name) if name == word)
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        if self.check_word(word) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> Diagnostic {
+        let token = self.peek();
+        Diagnostic::error(
+            format!("expected {}, found {}", expected, token.kind.describe()),
+            token.span,
+        )
+    }
+
+    fn expect(&mut self, kind: TokenKind, context: &str) -> PResult<Token> {
+        if self.check(&kind) {
+            Ok(self.advance())
+        } else {
+            Err(self.unexpected(&format!("`{}` {}", kind.punct(), context)))
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> PResult<Span> {
+        if self.check_word(word) {
+            Ok(self.advance().span)
+        } else {
+            Err(self.unexpected(&format!("`{}`", word)))
+        }
+    }
+
+    fn expect_ident(&mut self, what: &str) -> PResult<Ident> {
+        match self.peek().kind.clone() {
+            TokenKind::Ident(name) => {
+                let span = self.advance().span;
+                Ok(Ident { name, span })
+            }
+            _ => Err(self.unexpected(what)),
+        }
+    }
+
+    fn expect_string(&mut self, what: &str) -> PResult<StrLit> {
+        match self.peek().kind.clone() {
+            TokenKind::Str(value) => {
+                let span = self.advance().span;
+                Ok(StrLit { value, span })
+            }
+            _ => Err(self.unexpected(what)),
+        }
+    }
+
+    fn expect_patient(&mut self) -> PResult<PatientRef> {
+        self.expect_word("patient")?;
+        match self.peek().kind.clone() {
+            TokenKind::PatientId(id) => {
+                let span = self.advance().span;
+                Ok(PatientRef { id, span })
+            }
+            _ => Err(self.unexpected("a patient reference like `#123`")),
+        }
+    }
+
+    // Skip to the end of the current top-level item: past the next `;` or the
+    // `}` that closes the block we are in
+    fn synchronize_item(&mut self) {
+        let mut depth = 0usize;
+        loop {
+            match self.advance().kind {
+                TokenKind::Eof => return,
+                TokenKind::LBrace => depth += 1,
+                TokenKind::RBrace => {
+                    if depth <= 1 {
+                        return;
+                    }
+                    depth -= 1;
+                }
+                TokenKind::Semi if depth == 0 => return,
+                _ => {}
+            }
+        }
+    }
+
+    // Skip to the end of the current statement inside a block, leaving a
+    // closing `}` for the block parser
+    fn synchronize_stmt(&mut self) {
+        let mut depth = 0usize;
+        loop {
+            match self.peek().kind {
+                TokenKind::Eof => return,
+                TokenKind::RBrace if depth == 0 => return,
+                TokenKind::Semi if depth == 0 => {
+                    self.advance();
+                    return;
+                }
+                TokenKind::LBrace | TokenKind::LParen | TokenKind::LBracket => depth += 1,
+                TokenKind::RBrace | TokenKind::RParen | TokenKind::RBracket => {
+                    depth = depth.saturating_sub(1)
+                }
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    // ---- items ----
+
+    fn parse_item(&mut self) -> PResult<Item> {
+        let start = self.peek().span;
+        let word = match &self.peek().kind {
+            TokenKind::Ident(word) => word.clone(),
+            _ => return Err(self.unexpected("a top-level statement")),
+        };
+
+        let kind = match word.as_str() {
+            "import" => {
+                self.advance();
+                let mut path = vec![self.expect_ident("a module name")?];
+                while self.eat(&TokenKind::Dot) {
+                    path.push(self.expect_ident("a module name")?);
+                }
+                self.expect(TokenKind::Semi, "after import")?;
+                ItemKind::Import(path)
+            }
+            "record" => {
+                self.advance();
+                let patient = self.expect_patient()?;
+                ItemKind::Block(self.parse_block_body(BlockHeader::Record, patient)?)
+            }
+            "track" | "prescribe" | "schedule" | "analyze" | "bill" => {
+                self.advance();
+                let header = match word.as_str() {
+                    "track" => BlockHeader::Track,
+                    "prescribe" => BlockHeader::Prescribe,
+                    "schedule" => BlockHeader::Schedule,
+                    "analyze" => BlockHeader::Analyze,
+                    _ => BlockHeader::Bill,
+                };
+                self.expect_word("for")?;
+                let patient = self.expect_patient()?;
+                ItemKind::Block(self.parse_block_body(header, patient)?)
+            }
+            "connect" => {
+                self.advance();
+                self.expect_word("device")?;
+                let device = self.expect_string("a device name")?;
+                self.expect_word("to")?;
+                let patient = self.expect_patient()?;
+                ItemKind::Block(self.parse_block_body(BlockHeader::ConnectDevice { device }, patient)?)
+            }
+            "generate" => {
+                self.advance();
+                let artifact = self.expect_ident("what to generate")?;
+                self.expect_word("for")?;
+                let patient = self.expect_patient()?;
+                ItemKind::Block(self.parse_block_body(BlockHeader::Generate { artifact }, patient)?)
+            }
+            "share" => {
+                self.advance();
+                let anonymized = self.eat_word("anonymized");
+                let patient = self.expect_patient()?;
+                self.expect_word("with")?;
+                self.expect_word("team")?;
+                let team = self.expect_string("a team name")?;
+                ItemKind::Block(self.parse_block_body(BlockHeader::Share { anonymized, team }, patient)?)
+            }
+            "commit" => {
+                self.advance();
+                self.expect_word("changes")?;
+                self.expect_word("to")?;
+                let patient = self.expect_patient()?;
+                self.expect_word("with")?;
+                self.expect_word("message")?;
+                let message = self.expect_string("a commit message")?;
+                self.expect(TokenKind::Semi, "after commit")?;
+                ItemKind::Commit(Commit { patient, message })
+            }
+            _ => {
+                return Err(Diagnostic::error(
+                    format!("unknown statement `{}`; expected import, record, track, prescribe, \
+                             connect, schedule, generate, analyze, commit, share or bill", word),
+                    start,
+                ))
+            }
+        };
+
+        Ok(Item { kind, span: start.to(self.previous_span()) })
+    }
+
+    fn parse_block_body(&mut self, header: BlockHeader, patient: PatientRef) -> PResult<Block> {
+        self.expect(TokenKind::LBrace, "to open the block")?;
+        let mut body = Vec::new();
+        while !self.check(&TokenKind::RBrace) {
+            if self.at_eof() {
+                return Err(self.unexpected("`}` to close the block"));
+            }
+            match self.parse_stmt() {
+                Ok(stmt) => body.push(stmt),
+                Err(diagnostic) => {
+                    self.diagnostics.push(diagnostic);
+                    self.synchronize_stmt();
+                }
+            }
+        }
+        self.advance();
+        Ok(Block { header, patient, body })
+    }
+
+    // ---- statements ----
+
+    fn parse_stmt(&mut self) -> PResult<Stmt> {
+        let start = self.peek().span;
+        let word = match &self.peek().kind {
+            TokenKind::Ident(word) => word.clone(),
+            _ => return Err(self.unexpected("a statement")),
+        };
+
+        let kind = if let Some(key_len) = self.field_key_len() {
+            let mut key = Vec::with_capacity(key_len);
+            for _ in 0..key_len {
+                key.push(self.expect_ident("a field name")?);
+            }
+            self.advance(); // `:`
+            let value = self.parse_expr()?;
+            let modifiers = self.parse_modifiers()?;
+            StmtKind::Field { key, value, modifiers }
+        } else if self.peek_at(1).kind == TokenKind::Eq {
+            let target = self.expect_ident("a variable name")?;
+            self.advance(); // `=`
+            let value = self.parse_expr()?;
+            let modifiers = self.parse_modifiers()?;
+            StmtKind::Assign { target, value, modifiers }
+        } else if word == "encrypt" && self.check_word_at(1, "with") {
+            self.advance();
+            self.advance();
+            StmtKind::Encrypt { key: self.expect_ident("a key name")? }
+        } else if word == "calculate" {
+            self.advance();
+            let property = self.expect_ident("what to calculate")?;
+            self.expect_word("for")?;
+            let subject = self.expect_ident("a variable name")?;
+            self.expect_word("as")?;
+            let value = self.parse_expr()?;
+            let unit = if self.eat_word("unit") {
+                Some(self.expect_string("a unit")?)
+            } else {
+                None
+            };
+            StmtKind::Calculate { property, subject, value, unit }
+        } else if word == "administer" {
+            self.advance();
+            let medication = self.expect_ident("a medication variable")?;
+            let dose = if self.check_word("dose") {
+                Dose::Calculated(self.advance().span)
+            } else {
+                Dose::Amount(self.parse_unary()?)
+            };
+            let sig = self.parse_sig()?;
+            StmtKind::Administer { medication, dose, sig }
+        } else if self.peek_at(1).kind == TokenKind::LParen {
+            let call = self.parse_expr()?;
+            let condition = if self.eat_word("if") {
+                Some(self.parse_expr()?)
+            } else {
+                None
+            };
+            StmtKind::Call { call, condition }
+        } else {
+            let verb = self.expect_ident("a statement")?;
+            let mut args = Vec::new();
+            while !self.check(&TokenKind::Semi) && !self.check(&TokenKind::RBrace) && !self.at_eof() {
+                args.push(self.parse_expr()?);
+            }
+            StmtKind::Directive { verb, args }
+        };
+
+        self.expect(TokenKind::Semi, "after statement")?;
+        Ok(Stmt { kind, span: start.to(self.previous_span()) })
+    }
+
+    // Number of identifiers before a `:` if the statement is a field (`a b: value`)
+    fn field_key_len(&self) -> Option<usize> {
+        let mut n = 0;
+        while matches!(self.peek_at(n).kind, TokenKind::Ident(_)) {
+            n += 1;
+        }
+        (n > 0 && self.peek_at(n).kind == TokenKind::Colon).then_some(n)
+    }
+
+    fn parse_modifiers(&mut self) -> PResult<Vec<Modifier>> {
+        let mut modifiers = Vec::new();
+        loop {
+            if self.eat_word("unit") {
+                modifiers.push(Modifier::Unit(self.expect_string("a unit string")?));
+            } else if self.eat_word("on") {
+                modifiers.push(Modifier::On(self.expect_string("a date/time string")?));
+            } else if self.eat_word("with") {
+                let system = self.expect_ident("`note` or a code system such as `rxnorm`")?;
+                let value = self.expect_string("a string")?;
+                if system.name == "note" {
+                    modifiers.push(Modifier::Note(value));
+                } else {
+                    modifiers.push(Modifier::Code { system, code: value });
+                }
+            } else {
+                return Ok(modifiers);
+            }
+        }
+    }
+
+    fn parse_sig(&mut self) -> PResult<Vec<SigWord>> {
+        let mut sig = Vec::new();
+        while !self.check(&TokenKind::Semi) {
+            let token = self.peek().clone();
+            let word = match token.kind {
+                TokenKind::Ident(name) => SigWord::Word(Ident { name, span: token.span }),
+                TokenKind::Number(n) => SigWord::Number(n, token.span),
+                TokenKind::Str(value) => SigWord::Str(StrLit { value, span: token.span }),
+                _ => return Err(self.unexpected("administration instructions or `;`")),
+            };
+            self.advance();
+            sig.push(word);
+        }
+        Ok(sig)
+    }
+
+    // ---- expressions ----
+
+    pub fn parse_expr(&mut self) -> PResult<Expr> {
+        self.parse_binary(0)
+    }
+
+    fn peek_binop(&self) -> Option<BinOp> {
+        Some(match self.peek().kind {
+            TokenKind::Plus => BinOp::Add,
+            TokenKind::Minus => BinOp::Sub,
+            TokenKind::Star => BinOp::Mul,
+            TokenKind::Slash => BinOp::Div,
+            TokenKind::EqEq => BinOp::Eq,
+            TokenKind::NotEq => BinOp::NotEq,
+            TokenKind::Lt => BinOp::Lt,
+            TokenKind::Le => BinOp::Le,
+            TokenKind::Gt => BinOp::Gt,
+            TokenKind::Ge => BinOp::Ge,
+            _ => return None,
+        })
+    }
+
+    // Precedence climbing; all binary operators are left-associative
+    fn parse_binary(&mut self, min_precedence: u8) -> PResult<Expr> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(op) = self.peek_binop() {
+            if op.precedence() <= min_precedence {
+                break;
+            }
+            self.advance();
+            let rhs = self.parse_binary(op.precedence())?;
+            let span = lhs.span.to(rhs.span);
+            lhs = Expr {
+                kind: ExprKind::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs) },
+                span,
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> PResult<Expr> {
+        if self.check(&TokenKind::Minus) {
+            let start = self.advance().span;
+            let operand = self.parse_unary()?;
+            let span = start.to(operand.span);
+            return Ok(Expr { kind: ExprKind::Neg(Box::new(operand)), span });
+        }
+        if self.check_word("in") {
+            let start = self.advance().span;
+            let operand = self.parse_unary()?;
+            let span = start.to(operand.span);
+            return Ok(Expr { kind: ExprKind::Offset(Box::new(operand)), span });
+        }
+        self.parse_postfix()
+    }
+
+    fn parse_postfix(&mut self) -> PResult<Expr> {
+        let mut expr = self.parse_primary()?;
+        while self.check(&TokenKind::Dot) {
+            self.advance();
+            let property = self.expect_ident("a property name")?;
+            let span = expr.span.to(property.span);
+            expr = Expr {
+                kind: ExprKind::Member { object: Box::new(expr), property },
+                span,
+            };
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> PResult<Expr> {
+        let token = self.peek().clone();
+        match token.kind {
+            TokenKind::Number(value) => {
+                self.advance();
+                self.parse_number_suffix(value, token.span)
+            }
+            TokenKind::Str(value) => {
+                self.advance();
+                Ok(Expr { kind: ExprKind::Str(value), span: token.span })
+            }
+            TokenKind::Ident(name) if name == "true" || name == "false" => {
+                self.advance();
+                Ok(Expr { kind: ExprKind::Bool(name == "true"), span: token.span })
+            }
+            TokenKind::Ident(name) => {
+                self.advance();
+                if self.check(&TokenKind::LParen) {
+                    self.advance();
+                    let args = self.parse_comma_list(TokenKind::RParen)?;
+                    let callee = Ident { name, span: token.span };
+                    Ok(Expr {
+                        kind: ExprKind::Call { callee, args },
+                        span: token.span.to(self.previous_span()),
+                    })
+                } else {
+                    Ok(Expr { kind: ExprKind::Ident(name), span: token.span })
+                }
+            }
+            TokenKind::LBracket => {
+                self.advance();
+                let elements = self.parse_comma_list(TokenKind::RBracket)?;
+                Ok(Expr {
+                    kind: ExprKind::List(elements),
+                    span: token.span.to(self.previous_span()),
+                })
+            }
+            TokenKind::LParen => {
+                self.advance();
+                let inner = self.parse_expr()?;
+                self.expect(TokenKind::RParen, "to close the parenthesis")?;
+                Ok(Expr { kind: inner.kind, span: token.span.to(self.previous_span()) })
+            }
+            _ => Err(self.unexpected("an expression")),
+        }
+    }
+
+    // Elements up to and including `close`; a trailing comma is allowed
+    fn parse_comma_list(&mut self, close: TokenKind) -> PResult<Vec<Expr>> {
+        let mut elements = Vec::new();
+        while !self.check(&close) {
+            elements.push(self.parse_expr()?);
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(close, "to close the list")?;
+        Ok(elements)
+    }
+
+    // A number may be followed by a range (`70-130`) and/or a unit (`mg/dL`)
+    fn parse_number_suffix(&mut self, value: f64, span: Span) -> PResult<Expr> {
+        // Ranges are written without spaces, which distinguishes them from subtraction
+        if self.check(&TokenKind::Minus)
+            && self.peek().span.start == span.end
+            && matches!(self.peek_at(1).kind, TokenKind::Number(_))
+            && self.peek_at(1).span.start == self.peek().span.end
+        {
+            self.advance();
+            let high_token = self.advance();
+            let high = match high_token.kind {
+                TokenKind::Number(n) => n,
+                _ => unreachable!(),
+            };
+            let unit = self.parse_unit();
+            return Ok(Expr {
+                kind: ExprKind::Range { low: value, high, unit },
+                span: span.to(self.previous_span()),
+            });
+        }
+
+        match self.parse_unit() {
+            Some(unit) => Ok(Expr {
+                kind: ExprKind::Quantity { value, unit },
+                span: span.to(self.previous_span()),
+            }),
+            None => Ok(Expr { kind: ExprKind::Number(value), span }),
+        }
+    }
+
+    // Unit after a number: an identifier or `%`, optionally followed by
+    // directly attached `/ident` parts (`mg/dL`, `mL/min`)
+    fn parse_unit(&mut self) -> Option<String> {
+        let mut unit = match &self.peek().kind {
+            TokenKind::Percent => "%".to_string(),
+            TokenKind::Ident(name) if !CLAUSE_KEYWORDS.contains(&name.as_str()) => name.clone(),
+            _ => return None,
+        };
+        let mut end = self.advance().span.end;
+        while self.check(&TokenKind::Slash) && self.peek().span.start == end {
+            let next = self.peek_at(1).clone();
+            match next.kind {
+                TokenKind::Ident(part) if next.span.start == self.peek().span.end => {
+                    self.advance();
+                    self.advance();
+                    unit.push('/');
+                    unit.push_str(&part);
+                    end = next.span.end;
+                }
+                _ => break,
+            }
+        }
+        Some(unit)
+    }
+}
@@ -0,0 +1,445 @@
+// src/lang/printer.rs
+// Charcot EMR: Pretty-printer emitting Charcot source in a canonical layout
+//
+// Printing a parsed program and parsing the output again yields the same tree
+// (modulo spans), and printing is a fixed point: print(parse(print(p))) == print(p).
+// `format_source` additionally carries over the comments and blank lines of
+// the original text, which the syntax tree does not hold.
+
+use super::ast::*;
+use super::diagnostic::{Diagnostic, Span};
+use super::lexer::{self, TokenKind};
+use super::parser::Parser;
+
+const INDENT: &str = "    ";
+const MAX_WIDTH: usize = 80;
+
+// Precedence of prefix `-` and `in`; binary operators are all lower
+const UNARY: u8 = u8::MAX - 1;
+
+pub fn print_program(program: &Program) -> String {
+    let mut printer = Printer::default();
+    printer.program(program);
+    printer.out
+}
+
+// Reformat Charcot source, keeping its comments. Comments on their own line
+// stay in front of the statement that follows them, comments after code stay
+// at the end of that line, and single blank lines between statements are
+// kept. Running it on its own output changes nothing. A comment inside a
+// statement or block header has nowhere to go, so the source is refused.
+pub fn format_source(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let (tokens, mut diagnostics) = lexer::tokenize(source);
+    let comments: Vec<(Span, String)> = tokens.iter()
+        .filter_map(|t| match &t.kind {
+            TokenKind::Comment(text) => Some((t.span, text.clone())),
+            _ => None,
+        })
+        .collect();
+    let braces: Vec<usize> = tokens.iter()
+        .filter(|t| t.kind == TokenKind::LBrace)
+        .map(|t| t.span.start)
+        .collect();
+    let (program, parse_diagnostics) = Parser::new(tokens).parse_program();
+    diagnostics.extend(parse_diagnostics);
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(diagnostics);
+    }
+
+    let code = code_spans(&program, &braces);
+    let misplaced: Vec<Diagnostic> = comments.iter()
+        .filter(|(comment, _)| code.iter().any(|span| span.start < comment.start && comment.start < span.end))
+        .map(|(comment, _)| Diagnostic::error(
+            "comment inside a statement cannot be formatted; move it to its own line or the end of the statement",
+            *comment,
+        ))
+        .collect();
+    if !misplaced.is_empty() {
+        return Err(misplaced);
+    }
+
+    let mut printer = Printer { source, comments, ..Printer::default() };
+    printer.program(&program);
+    Ok(printer.out)
+}
+
+pub fn print_expr(expr: &Expr) -> String {
+    let mut printer = Printer::default();
+    printer.expr(expr);
+    printer.out
+}
+
+pub fn print_stmt(stmt: &Stmt) -> String {
+    let mut printer = Printer::default();
+    printer.stmt(stmt);
+    printer.out
+}
+
+pub fn format_number(value: f64) -> String {
+    format!("{}", value)
+}
+
+pub fn quote(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// The spans comments may not fall inside: statements, items other than
+// blocks, and block headers up to their `{`
+fn code_spans(program: &Program, braces: &[usize]) -> Vec<Span> {
+    let mut spans = Vec::new();
+    for item in &program.items {
+        let ItemKind::Block(block) = &item.kind else {
+            spans.push(item.span);
+            continue;
+        };
+        let brace = braces.iter().copied().find(|&brace| brace >= item.span.start).unwrap_or(item.span.end);
+        spans.push(Span::new(item.span.start, brace));
+        spans.extend(block.body.iter().map(|stmt| stmt.span));
+    }
+    spans
+}
+
+// Whether the text between two pieces of code contains an empty line
+fn has_blank_line(gap: &str) -> bool {
+    let lines: Vec<&str> = gap.split('\n').collect();
+    lines.len() > 2 && lines[1..lines.len() - 1].iter().any(|line| line.trim().is_empty())
+}
+
+#[derive(Default)]
+struct Printer<'a> {
+    out: String,
+    indent: usize,
+    // Original text and its comments, only set by `format_source`
+    source: &'a str,
+    comments: Vec<(Span, String)>,
+    next_comment: usize,
+}
+
+impl Printer<'_> {
+    fn write(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    fn column(&self) -> usize {
+        let line_start = self.out.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        self.out[line_start..].chars().count()
+    }
+
+    fn program(&mut self, program: &Program) {
+        let mut previous: Option<(&ItemKind, usize)> = None;
+        for item in &program.items {
+            // Consecutive imports stay together; everything else gets a blank line
+            let mut force_blank = previous.is_some_and(|(prev, _)| {
+                !(matches!(prev, ItemKind::Import(_)) && matches!(item.kind, ItemKind::Import(_)))
+            });
+            let mut end = previous.map(|(_, end)| end);
+            self.comments_before(item.span.start, &mut end, &mut force_blank);
+            self.separator(end, item.span.start, force_blank);
+            self.item(item);
+            let mut end = item.span.end;
+            self.trailing_comment(&mut end);
+            previous = Some((&item.kind, end));
+        }
+        let mut end = previous.map(|(_, end)| end);
+        self.comments_before(self.source.len() + 1, &mut end, &mut false);
+        if !self.out.is_empty() {
+            self.write("\n");
+        }
+    }
+
+    // Start a new line for the next statement or comment, keeping a blank
+    // line if the source had one there
+    fn separator(&mut self, previous: Option<usize>, start: usize, force_blank: bool) {
+        if self.out.is_empty() {
+            return;
+        }
+        let blank = previous
+            .and_then(|end| self.source.get(end..start))
+            .is_some_and(has_blank_line);
+        if force_blank || blank {
+            self.out.push('\n');
+        }
+        self.newline();
+    }
+
+    // Print the comments that start before `offset`, each on its own line
+    fn comments_before(&mut self, offset: usize, previous: &mut Option<usize>, force_blank: &mut bool) {
+        while let Some((span, text)) = self.comments.get(self.next_comment).cloned() {
+            if span.start >= offset {
+                break;
+            }
+            self.next_comment += 1;
+            self.separator(*previous, span.start, *force_blank);
+            self.write(&text);
+            *previous = Some(span.end);
+            *force_blank = false;
+        }
+    }
+
+    // Print a comment that follows `end` on the same source line
+    fn trailing_comment(&mut self, end: &mut usize) {
+        let Some((span, text)) = self.comments.get(self.next_comment).cloned() else {
+            return;
+        };
+        let same_line = self.source.get(*end..span.start)
+            .is_some_and(|gap| gap.trim().is_empty() && !gap.contains('\n'));
+        if span.start >= *end && same_line {
+            self.next_comment += 1;
+            self.write(" ");
+            self.write(&text);
+            *end = span.end;
+        }
+    }
+
+    fn has_comments_before(&self, offset: usize) -> bool {
+        self.comments.get(self.next_comment).is_some_and(|(span, _)| span.start < offset)
+    }
+
+    fn item(&mut self, item: &Item) {
+        match &item.kind {
+            ItemKind::Import(path) => {
+                let path: Vec<&str> = path.iter().map(|i| i.name.as_str()).collect();
+                self.write(&format!("import {};", path.join(".")));
+            }
+            ItemKind::Commit(commit) => {
+                self.write(&format!(
+                    "commit changes to patient #{} with message {};",
+                    commit.patient.id,
+                    quote(&commit.message.value)
+                ));
+            }
+            ItemKind::Block(block) => self.block(block, item.span.end),
+        }
+    }
+
+    fn block(&mut self, block: &Block, end: usize) {
+        let patient = format!("patient #{}", block.patient.id);
+        let header = match &block.header {
+            BlockHeader::Record => format!("record {}", patient),
+            BlockHeader::Track => format!("track for {}", patient),
+            BlockHeader::Prescribe => format!("prescribe for {}", patient),
+            BlockHeader::ConnectDevice { device } => {
+                format!("connect device {} to {}", quote(&device.value), patient)
+            }
+            BlockHeader::Schedule => format!("schedule for {}", patient),
+            BlockHeader::Generate { artifact } => format!("generate {} for {}", artifact.name, patient),
+            BlockHeader::Analyze => format!("analyze for {}", patient),
+            BlockHeader::Share { anonymized, team } => format!(
+                "share {}{} with team {}",
+                if *anonymized { "anonymized " } else { "" },
+                patient,
+                quote(&team.value)
+            ),
+            BlockHeader::Bill => format!("bill for {}", patient),
+        };
+        self.write(&header);
+
+        if block.body.is_empty() && !self.has_comments_before(end) {
+            self.write(" {}");
+            return;
+        }
+
+        self.write(" {");
+        // A comment after the `{` stays on its line
+        let header_end = match &block.header {
+            BlockHeader::Share { team, .. } => team.span.end,
+            _ => block.patient.span.end,
+        };
+        let mut brace_end = self.source.get(header_end..)
+            .and_then(|rest| rest.find('{'))
+            .map_or(header_end, |i| header_end + i + 1);
+        self.trailing_comment(&mut brace_end);
+        self.indent += 1;
+        let mut previous = None;
+        for stmt in &block.body {
+            self.comments_before(stmt.span.start, &mut previous, &mut false);
+            self.separator(previous, stmt.span.start, false);
+            self.stmt(stmt);
+            let mut stmt_end = stmt.span.end;
+            self.trailing_comment(&mut stmt_end);
+            previous = Some(stmt_end);
+        }
+        self.comments_before(end, &mut previous, &mut false);
+        self.indent -= 1;
+        self.newline();
+        self.write("}");
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Field { key, value, modifiers } => {
+                let key: Vec<&str> = key.iter().map(|i| i.name.as_str()).collect();
+                self.write(&key.join(" "));
+                self.write(": ");
+                self.expr(value);
+                self.modifiers(modifiers);
+            }
+            StmtKind::Assign { target, value, modifiers } => {
+                self.write(&target.name);
+                self.write(" = ");
+                self.expr(value);
+                self.modifiers(modifiers);
+            }
+            StmtKind::Encrypt { key } => {
+                self.write(&format!("encrypt with {}", key.name));
+            }
+            StmtKind::Calculate { property, subject, value, unit } => {
+                self.write(&format!("calculate {} for {} as ", property.name, subject.name));
+                self.expr(value);
+                if let Some(unit) = unit {
+                    self.write(&format!(" unit {}", quote(&unit.value)));
+                }
+            }
+            StmtKind::Administer { medication, dose, sig } => {
+                self.write(&format!("administer {} ", medication.name));
+                match dose {
+                    Dose::Calculated(_) => self.write("dose"),
+                    Dose::Amount(expr) => self.expr(expr),
+                }
+                for word in sig {
+                    self.write(" ");
+                    match word {
+                        SigWord::Word(ident) => self.write(&ident.name),
+                        SigWord::Number(n, _) => self.write(&format_number(*n)),
+                        SigWord::Str(lit) => self.write(&quote(&lit.value)),
+                    }
+                }
+            }
+            StmtKind::Call { call, condition } => {
+                self.expr(call);
+                if let Some(condition) = condition {
+                    self.write(" if ");
+                    self.expr(condition);
+                }
+            }
+            StmtKind::Directive { verb, args } => {
+                self.write(&verb.name);
+                for arg in args {
+                    self.write(" ");
+                    self.expr(arg);
+                }
+            }
+        }
+        self.write(";");
+    }
+
+    fn modifiers(&mut self, modifiers: &[Modifier]) {
+        for modifier in modifiers {
+            match modifier {
+                Modifier::Unit(unit) => self.write(&format!(" unit {}", quote(&unit.value))),
+                Modifier::On(time) => self.write(&format!(" on {}", quote(&time.value))),
+                Modifier::Note(note) => self.write(&format!(" with note {}", quote(&note.value))),
+                Modifier::Code { system, code } => {
+                    self.write(&format!(" with {} {}", system.name, quote(&code.value)))
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Number(n) => self.write(&format_number(*n)),
+            ExprKind::Str(s) => self.write(&quote(s)),
+            ExprKind::Bool(b) => self.write(if *b { "true" } else { "false" }),
+            ExprKind::Quantity { value, unit } => {
+                self.write(&format!("{} {}", format_number(*value), unit))
+            }
+            ExprKind::Range { low, high, unit } => {
+                self.write(&format!("{}-{}", format_number(*low), format_number(*high)));
+                if let Some(unit) = unit {
+                    self.write(&format!(" {}", unit));
+                }
+            }
+            ExprKind::Ident(name) => self.write(name),
+            ExprKind::Member { object, property } => {
+                self.operand(object, u8::MAX);
+                self.write(".");
+                self.write(&property.name);
+            }
+            ExprKind::Call { callee, args } => {
+                self.write(&callee.name);
+                self.write("(");
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        self.write(", ");
+                    }
+                    self.expr(arg);
+                }
+                self.write(")");
+            }
+            ExprKind::List(elements) => self.list(elements),
+            ExprKind::Neg(operand) => {
+                self.write("-");
+                self.operand(operand, UNARY);
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                self.operand(lhs, op.precedence());
+                self.write(&format!(" {} ", op.symbol()));
+                // Operators are left-associative, so an equal-precedence right
+                // operand needs parentheses to keep its grouping
+                self.operand(rhs, op.precedence() + 1);
+            }
+            ExprKind::Offset(operand) => {
+                self.write("in ");
+                self.operand(operand, UNARY);
+            }
+        }
+    }
+
+    // Print `expr`, parenthesized if it binds more loosely than `min_precedence`
+    fn operand(&mut self, expr: &Expr, min_precedence: u8) {
+        let precedence = match &expr.kind {
+            ExprKind::Binary { op, .. } => op.precedence(),
+            ExprKind::Neg(_) | ExprKind::Offset(_) => UNARY,
+            _ => u8::MAX,
+        };
+        if precedence < min_precedence {
+            self.write("(");
+            self.expr(expr);
+            self.write(")");
+        } else {
+            self.expr(expr);
+        }
+    }
+
+    // Lists stay on one line when they fit, otherwise one element per line
+    fn list(&mut self, elements: &[Expr]) {
+        let inline: Vec<String> = elements.iter().map(print_expr).collect();
+        let inline = format!("[{}]", inline.join(", "));
+        if self.column() + inline.len() < MAX_WIDTH || elements.is_empty() {
+            self.write(&inline);
+            return;
+        }
+        self.write("[");
+        self.indent += 1;
+        for (i, element) in elements.iter().enumerate() {
+            self.newline();
+            self.expr(element);
+            if i + 1 < elements.len() {
+                self.write(",");
+            }
+        }
+        self.indent -= 1;
+        self.newline();
+        self.write("]");
+    }
+}
@@ -0,0 +1,255 @@
+// src/lang/repl.rs
+// Charcot EMR: Interactive session evaluating Charcot statements one at a time
+//
+// A session keeps one patient record open in memory. Statements are applied
+// to it immediately and the resulting FHIR resources are shown, but nothing
+// reaches the `.med` file until the user explicitly runs `commit` and `save`.
+
+use std::path::Path;
+use anyhow::{Result, anyhow};
+
+use super::ast::StmtKind;
+use super::diagnostic::render_all;
+use super::interp::{self, Planner};
+use crate::{EMR, Resource, med_filename};
+
+const HELP: &str = "\
+Commands:
+  open patient #<id> with key <key>   Load a patient record
+  show                                List the resources in the open record
+  commit [message]                    Record a new version of the open record
+  save                                Write the committed record to its .med file
+  close                               Close the open record
+  help                                Show this help
+  quit                                Leave the REPL
+Any other input is a Charcot statement for the open patient, e.g.
+  bp = BloodPressure(130, 85)
+  metformin = Medication(\"metformin\")
+  administer metformin 500 mg twice daily";
+
+pub enum Outcome {
+    Output(String),
+    Quit,
+}
+
+#[derive(Default)]
+pub struct Session {
+    patient: Option<String>,
+    key: String,
+    planner: Planner,
+    uncommitted: bool,
+    unsaved: bool,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session::default()
+    }
+
+    pub fn prompt(&self) -> String {
+        match &self.patient {
+            Some(id) if self.uncommitted || self.unsaved => format!("charcot #{}*> ", id),
+            Some(id) => format!("charcot #{}> ", id),
+            None => "charcot> ".to_string(),
+        }
+    }
+
+    // Evaluate one line of input
+    pub fn eval(&mut self, emr: &mut EMR, line: &str) -> Result<Outcome> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace)
+            .map(|(command, rest)| (command, rest.trim()))
+            .unwrap_or((line, ""));
+
+        let output = match command {
+            "" => String::new(),
+            "help" => HELP.to_string(),
+            "quit" | "exit" => {
+                self.ensure_saved("quit")?;
+                return Ok(Outcome::Quit);
+            }
+            "quit!" | "exit!" => return Ok(Outcome::Quit),
+            "open" => self.open(emr, rest)?,
+            "close" => {
+                self.ensure_saved("close")?;
+                self.close()
+            }
+            "close!" => self.close(),
+            "show" => self.show(emr)?,
+            "commit" => self.commit(emr, rest)?,
+            "save" => self.save(emr)?,
+            _ => self.statement(emr, line)?,
+        };
+        Ok(Outcome::Output(output))
+    }
+
+    fn current(&self) -> Result<String> {
+        self.patient.clone()
+            .ok_or_else(|| anyhow!("No patient is open; use `open patient #<id> with key <key>`"))
+    }
+
+    fn ensure_saved(&self, command: &str) -> Result<()> {
+        if self.uncommitted || self.unsaved {
+            return Err(anyhow!(
+                "Patient #{} has changes that are not saved; `commit` and `save` them, \
+                 or use `{}!` to discard them",
+                self.patient.as_deref().unwrap_or_default(), command
+            ));
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> String {
+        let closed = self.patient.take();
+        self.uncommitted = false;
+        self.unsaved = false;
+        self.key.clear();
+        match closed {
+            Some(id) => format!("Closed patient #{}", id),
+            None => "No patient is open".to_string(),
+        }
+    }
+
+    // open patient #<id> with key <key>
+    fn open(&mut self, emr: &mut EMR, args: &str) -> Result<String> {
+        self.ensure_saved("close")?;
+        let usage = || anyhow!("Usage: open patient #<id> with key <key>");
+        let rest = args.strip_prefix("patient").ok_or_else(usage)?.trim_start();
+        let rest = rest.strip_prefix('#').ok_or_else(usage)?;
+        let (id, rest) = rest.split_once(char::is_whitespace).ok_or_else(usage)?;
+        let key = rest.trim().strip_prefix("with").ok_or_else(usage)?
+            .trim_start().strip_prefix("key").ok_or_else(usage)?
+            .trim();
+        if id.is_empty() || key.is_empty() {
+            return Err(usage());
+        }
+
+        let filename = med_filename(id);
+        if !Path::new(&filename).exists() {
+            return Err(anyhow!("Patient file not found: {}", filename));
+        }
+        let patient_id = emr.load_patient(&filename, key)?;
+
+        self.close();
+        // Picks up the EMR's lab ranges and drug dictionary
+        self.planner = Planner::new(emr);
+        self.patient = Some(patient_id.clone());
+        self.key = key.to_string();
+        let count = emr.bundles.get(&patient_id).map(|b| b.entry.len()).unwrap_or(0);
+        Ok(format!("Opened patient #{} from {} ({} resources)", patient_id, filename, count))
+    }
+
+    fn show(&self, emr: &EMR) -> Result<String> {
+        let patient_id = self.current()?;
+        let bundle = emr.bundles.get(&patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        let lines: Vec<String> = bundle.entry.iter()
+            .enumerate()
+            .map(|(i, entry)| format!("{:>3}. {}", i + 1, summarize(&entry.resource)))
+            .collect();
+        Ok(lines.join("\n"))
+    }
+
+    fn commit(&mut self, emr: &mut EMR, message: &str) -> Result<String> {
+        let patient_id = self.current()?;
+        let message = message.trim_matches('"');
+        let message = if message.is_empty() { "Changes from REPL session" } else { message };
+        emr.commit_changes(&patient_id, message)?;
+        self.uncommitted = false;
+        self.unsaved = true;
+        Ok(format!("Committed patient #{}: {}", patient_id, message))
+    }
+
+    fn save(&mut self, emr: &mut EMR) -> Result<String> {
+        let patient_id = self.current()?;
+        if self.uncommitted {
+            return Err(anyhow!("Patient #{} has uncommitted changes; run `commit` first", patient_id));
+        }
+        emr.save_patient(&patient_id, &self.key)?;
+        self.unsaved = false;
+        Ok(format!("Saved patient #{} to {}", patient_id, med_filename(&patient_id)))
+    }
+
+    fn statement(&mut self, emr: &mut EMR, line: &str) -> Result<String> {
+        let patient_id = self.current()?;
+        let source = if line.ends_with(';') { line.to_string() } else { format!("{};", line) };
+        let stmt = super::parse_statement(&source)
+            .map_err(|diagnostics| anyhow!("{}", render_all(&diagnostics, &source, "<repl>")))?;
+
+        self.planner.statement(&patient_id, &stmt);
+        let errors = std::mem::take(&mut self.planner.errors);
+        let warnings = std::mem::take(&mut self.planner.warnings);
+        let actions = std::mem::take(&mut self.planner.actions);
+        if !errors.is_empty() {
+            return Err(anyhow!("{}", render_all(&errors, &source, "<repl>")));
+        }
+
+        let mut output = vec![render_all(&warnings, &source, "<repl>")];
+        let mut added = 0;
+        for action in &actions {
+            let before = emr.bundles.get(&patient_id).map_or(0, |b| b.entry.len());
+            interp::apply(emr, action, &self.key)?;
+            // Show the resources the statement added; checks such as
+            // `verify_interactions()` add none
+            for entry in emr.bundles.get(&patient_id).map_or(&[][..], |b| &b.entry[before..]) {
+                output.push(serde_json::to_string_pretty(&entry.resource)?);
+                added += 1;
+                self.uncommitted = true;
+            }
+        }
+        if added == 0 && warnings.is_empty() {
+            output.push(match &stmt.kind {
+                StmtKind::Assign { target, .. } => format!("defined `{}`", target.name),
+                _ => "ok".to_string(),
+            });
+        }
+        Ok(output.into_iter().filter(|s| !s.is_empty()).collect::<Vec<_>>().join("\n"))
+    }
+}
+
+// One-line description of a resource for `show`
+pub fn summarize(resource: &Resource) -> String {
+    match resource {
+        Resource::Patient(patient) => {
+            let name = patient.name.first()
+                .map(|n| format!("{} {}", n.given.join(" "), n.family.clone().unwrap_or_default()))
+                .unwrap_or_default();
+            format!("Patient/{} {} ({}, born {})", patient.id, name.trim(), patient.gender, patient.birth_date)
+        }
+        Resource::Observation(obs) => {
+            let value = match (&obs.value_quantity, &obs.component) {
+                (Some(q), _) => format!("{} {}", q.value, q.unit),
+                (None, Some(components)) => components.iter()
+                    .map(|c| c.value_quantity.value.to_string())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+                (None, None) => String::new(),
+            };
+            let flag = obs.interpretation.iter().flatten()
+                .filter(|i| i.code != "N")
+                .map(|i| format!(" [{}]", i.code))
+                .collect::<String>();
+            format!("Observation {}: {}{} at {}", obs.code.display, value, flag, obs.effective_date_time)
+        }
+        Resource::MedicationRequest(med) => {
+            let dosage = med.dosage_instruction.first().map(|d| d.text.clone()).unwrap_or_default();
+            format!("MedicationRequest {} {} ({})", med.medication_codeable_concept.display, dosage, med.status)
+        }
+        Resource::DiagnosticReport(report) => {
+            format!("DiagnosticReport {}: {} result(s) at {}", report.code.display, report.result.len(), report.effective_date_time)
+        }
+        Resource::AllergyIntolerance(allergy) => {
+            format!("AllergyIntolerance {} ({}, criticality {}, {}, {})", allergy.code.display, allergy.allergy_type,
+                    allergy.criticality, allergy.verification_status, allergy.clinical_status)
+        }
+        Resource::MedicationAdministration(administration) => {
+            let outcome = administration.status_reason.as_deref().unwrap_or("given");
+            format!("MedicationAdministration {} {} at {}", administration.medication_codeable_concept.display,
+                    outcome, administration.effective_date_time)
+        }
+        Resource::Condition(condition) => {
+            format!("Condition {} (ICD-10-CM {}, {}, {})", condition.code.display, condition.code.code,
+                    condition.verification_status, condition.clinical_status)
+        }
+    }
+}
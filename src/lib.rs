@@ -1,20 +1,54 @@
 // src/lib.rs
 // Charcot EMR: Library module exposing core EMR functionality
 
-use std::collections::HashMap;
-use std::fs::{self, File, OpenOptions};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
+use std::sync::Arc;
 use serde::{Serialize, Deserialize};
-use chrono::{DateTime, Utc};
-use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
-    Aes256Gcm, Key, Nonce
-};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use sha2::{Sha256, Digest};
-use base64::{Engine as _, engine::general_purpose};
 use uuid::Uuid;
 use anyhow::{Result, anyhow, Context};
 
+pub mod allergies;
+pub mod conditions;
+pub mod dose_calc;
+pub mod dose_limits;
+pub mod history;
+pub mod interactions;
+pub mod lang;
+pub mod labs;
+pub mod mar;
+pub mod med_format;
+pub mod medications;
+pub mod reconcile;
+pub mod rxnorm;
+pub mod sig;
+pub mod signing;
+pub mod terminology;
+pub mod units;
+pub mod vitals;
+
+pub use allergies::{AllergyAction, AllergyAlert, NewAllergy};
+pub use conditions::{CodeTable, ConditionCodes, NewCondition};
+pub use dose_calc::{CalculationInput, DoseBasis, DoseCalculation, DoseOrder, RenalAdjustments, RenalFunction};
+pub use dose_limits::{DoseAlert, DoseLimit, DoseLimits, DoseSeverity};
+pub use history::ResourceChange;
+pub use interactions::{Interaction, InteractionAlert, InteractionSeverity, InteractionTable};
+pub use labs::{Interpretation, LabRanges, LabTest};
+pub use mar::{Adherence, AdministrationOutcome, NewAdministration, ScheduledDose};
+pub use med_format::{KdfParams, MED_FORMAT_VERSION, MedFile};
+pub use medications::MedicationChange;
+pub use reconcile::{ExternalMedication, ReconcileAction, Reconciliation, ReconciliationItem};
+pub use rxnorm::{Drug, DrugDictionary, ResolvedDrug};
+pub use signing::{ClinicianRegistry, CommitSignature, SignatureStatus, Signer};
+
+use terminology::RXNORM_SYSTEM;
+pub use sig::{Sig, parse_sig};
+pub use vitals::{Vital, VitalKind};
+
 // FHIR-aligned data structures
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Patient {
@@ -45,9 +79,25 @@ pub struct Observation {
     pub status: String,
     pub code: Coding,
     pub subject: Reference,
-    pub effective_date_time: String,
+    pub effective_date_time: String,        // When the observation was made
+    pub issued: Option<String>,             // When it was entered into the record
     pub value_quantity: Option<Quantity>,
     pub component: Option<Vec<Component>>,
+    pub reference_range: Option<Vec<ReferenceRange>>,
+    pub interpretation: Option<Vec<Coding>>,
+    pub note: Option<Vec<Annotation>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReferenceRange {
+    pub low: Option<Quantity>,
+    pub high: Option<Quantity>,
+    pub text: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Annotation {
+    pub text: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -62,7 +112,7 @@ pub struct Reference {
     pub reference: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Quantity {
     pub value: f64,
     pub unit: String,
@@ -82,14 +132,93 @@ pub struct MedicationRequest {
     pub status: String,
     pub medication_codeable_concept: Coding,
     pub subject: Reference,
-    pub authored_on: String,
+    pub authored_on: String,                // When the prescription was written
+    pub recorded: Option<String>,           // When it was entered into the record
     pub dosage_instruction: Vec<DosageInstruction>,
+    pub replaces: Option<Reference>,        // Request this one changes or renews
+    #[serde(default)]
+    pub status_history: Vec<StatusChange>,
+    pub dose_calculation: Option<DoseCalculation>,  // Inputs of a weight- or BSA-based dose
+}
+
+// A change to a prescription's status
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatusChange {
+    pub status: String,                     // Status after the change, e.g. stopped, on-hold
+    pub reason: String,
+    pub author: String,
+    pub date: String,
+}
+
+// A dose of a prescription given, or not given, to the patient
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MedicationAdministration {
+    pub id: String,
+    pub status: String,                     // completed | not-done
+    pub status_reason: Option<String>,      // missed | refused, for doses not given
+    pub medication_codeable_concept: Coding,
+    pub subject: Reference,
+    pub request: Reference,                 // The MedicationRequest
+    pub scheduled: Option<String>,          // Scheduled time of the dose; none for as-needed doses
+    pub effective_date_time: String,        // When it was given or recorded as not given
+    pub performer: Option<String>,
+    pub dose: Option<Quantity>,
+    pub note: Option<Vec<Annotation>>,
+}
+
+// An allergy or intolerance to a substance
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AllergyIntolerance {
+    pub id: String,
+    pub clinical_status: String,            // active | inactive | resolved
+    pub verification_status: String,        // unconfirmed | confirmed | refuted | entered-in-error
+    pub allergy_type: String,               // allergy | intolerance
+    pub category: String,                   // medication | food | environment | biologic
+    pub criticality: String,                // low | high | unable-to-assess
+    pub code: Coding,                       // Substance or drug class
+    pub patient: Reference,
+    pub recorded_date: String,
+    pub reaction: Vec<AllergyReaction>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AllergyReaction {
+    pub manifestation: String,              // e.g. "hives"
+    pub severity: Option<String>,           // mild | moderate | severe
+}
+
+// An entry on the patient's problem list
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Condition {
+    pub id: String,
+    pub clinical_status: String,            // active | recurrence | relapse | inactive | remission | resolved
+    pub verification_status: String,        // unconfirmed | provisional | differential | confirmed | refuted | entered-in-error
+    pub code: Coding,                       // ICD-10-CM
+    pub snomed: Option<Coding>,             // SNOMED CT equivalent
+    pub subject: Reference,
+    pub onset_date_time: Option<String>,
+    pub abatement_date_time: Option<String>,
+    pub recorded_date: String,
+    pub note: Option<Vec<Annotation>>,
+}
+
+// Several results reported together, e.g. a lab panel
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DiagnosticReport {
+    pub id: String,
+    pub status: String,
+    pub code: Coding,
+    pub subject: Reference,
+    pub effective_date_time: String,
+    pub issued: Option<String>,
+    pub result: Vec<Reference>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DosageInstruction {
     pub text: String,
     pub timing: Timing,
+    pub as_needed_boolean: Option<bool>,    // PRN
     pub dose_and_rate: Vec<DoseAndRate>,
 }
 
@@ -102,7 +231,10 @@ pub struct Timing {
 pub struct Repeat {
     pub frequency: Option<i32>,
     pub period: Option<f64>,
+    pub period_max: Option<f64>,
     pub period_unit: Option<String>,
+    pub time_of_day: Option<Vec<String>>,   // hh:mm:ss
+    pub when: Option<Vec<String>>,          // EventTiming codes, e.g. C (with meals)
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -118,8 +250,12 @@ pub struct Bundle {
     #[serde(rename = "type")]
     pub type_field: String,
     pub entry: Vec<BundleEntry>,
-    #[serde(skip)]
+    // Missing from files saved before the history was kept
+    #[serde(default)]
     pub version_history: Vec<VersionEntry>,
+    // Every entry any version held, by the SHA-256 of its JSON
+    #[serde(default)]
+    pub objects: BTreeMap<String, BundleEntry>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -134,23 +270,72 @@ pub enum Resource {
     Patient(Patient),
     Observation(Observation),
     MedicationRequest(MedicationRequest),
+    MedicationAdministration(MedicationAdministration),
+    DiagnosticReport(DiagnosticReport),
+    AllergyIntolerance(AllergyIntolerance),
+    Condition(Condition),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct VersionEntry {
     pub timestamp: DateTime<Utc>,
     pub message: String,
-    pub hash: String,
+    pub hash: String,           // SHA-256 over parent, author, timestamp, message and content
+    #[serde(default)]
+    pub author: String,         // Empty for versions committed before authors were kept
+    pub tree: Vec<String>,      // Hashes of the entries in the bundle's objects
+    pub parent: String,         // Hash of the version before, empty for the first
+    pub content: String,        // SHA-256 of the entries as committed
+    #[serde(default)]
+    pub signature: Option<CommitSignature>,  // The author's signature of `hash`
 }
 
-// Encrypted .med file format
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct MedFile {
-    pub iv: String,             // Base64 encoded initialization vector
-    pub data: String,           // Base64 encoded encrypted data
-    pub hash: String,           // SHA-256 hash of the unencrypted data
-    pub created: DateTime<Utc>, // Creation timestamp
-    pub modified: DateTime<Utc>, // Last modified timestamp
+impl fmt::Display for VersionEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} - {}", self.timestamp, self.message)?;
+        if !self.author.is_empty() {
+            write!(f, " (by {})", self.author)?;
+        }
+        Ok(())
+    }
+}
+
+// SHA-256 of a bundle's entries, identifying a version
+pub fn entries_hash(entries: &[BundleEntry]) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_string(entries)?.as_bytes());
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Clinical times may lie at most this far in the future, to allow for clock
+// differences between devices
+pub const FUTURE_TOLERANCE_MINUTES: i64 = 5;
+
+// Parse a clinical date/time. RFC 3339 times carry their own offset; times
+// without one ("2025-04-08T08:30:00", "2025-04-08 08:30", "2025-04-08") are
+// taken as local time.
+pub fn parse_clinical_time(text: &str) -> Result<DateTime<Utc>> {
+    let text = text.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let naive = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok().and_then(|d| d.and_hms_opt(0, 0, 0)))
+        .ok_or_else(|| anyhow!("Invalid date/time: {}. Expected e.g. 2025-04-08T08:30:00", text))?;
+    let local = Local.from_local_datetime(&naive).earliest()
+        .ok_or_else(|| anyhow!("Invalid local time: {} does not exist in this time zone", text))?;
+    Ok(local.with_timezone(&Utc))
+}
+
+// Reject clinical times that are in the future
+pub fn validate_clinical_time(time: DateTime<Utc>) -> Result<()> {
+    let latest = Utc::now() + Duration::minutes(FUTURE_TOLERANCE_MINUTES);
+    if time > latest {
+        return Err(anyhow!("Time {} is in the future", time.to_rfc3339()));
+    }
+    Ok(())
 }
 
 // Special data types with validation
@@ -173,6 +358,11 @@ impl BloodPressure {
     }
 
     pub fn to_observation(&self, patient_id: &str) -> Observation {
+        self.to_observation_at(patient_id, Utc::now())
+    }
+
+    // Observation of a reading taken at `effective`
+    pub fn to_observation_at(&self, patient_id: &str, effective: DateTime<Utc>) -> Observation {
         let now = Utc::now().to_rfc3339();
         
         Observation {
@@ -186,8 +376,12 @@ impl BloodPressure {
             subject: Reference {
                 reference: format!("Patient/{}", patient_id),
             },
-            effective_date_time: now,
+            effective_date_time: effective.to_rfc3339(),
+            issued: Some(now),
             value_quantity: None,
+            reference_range: None,
+            interpretation: None,
+            note: None,
             component: Some(vec![
                 Component {
                     code: Coding {
@@ -220,10 +414,53 @@ impl BloodPressure {
     }
 }
 
+// Name of the encrypted file a patient record is saved to
+pub fn med_filename(patient_id: &str) -> String {
+    format!("patient_{}.med", patient_id)
+}
+
 // Main EMR functionality
 pub struct EMR {
     pub bundles: HashMap<String, Bundle>,
     pub audit_log: File,
+    pub lab_ranges: LabRanges,
+    pub drugs: Arc<DrugDictionary>,
+    pub dose_limits: DoseLimits,
+    pub interactions: InteractionTable,
+    pub condition_codes: ConditionCodes,
+    pub renal_adjustments: RenalAdjustments,
+    pub user: String,           // Who commits are attributed to
+    pub clinicians: ClinicianRegistry,
+    pub signer: Option<Signer>, // Signs commits while a clinician is signed in
+}
+
+// The login name, which commits are attributed to unless told otherwise
+pub fn login_name() -> String {
+    std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_default()
+}
+
+// How a prescription is written
+#[derive(Debug, Clone)]
+pub struct PrescribeOptions {
+    pub authored: DateTime<Utc>,          // When the prescription was written
+    pub unlisted: bool,                   // Allow medications not in the RxNorm dictionary
+    pub override_reason: Option<String>,  // Why dose warnings, severe interactions and allergies are accepted
+}
+
+impl Default for PrescribeOptions {
+    fn default() -> Self {
+        PrescribeOptions { authored: Utc::now(), unlisted: false, override_reason: None }
+    }
+}
+
+// The alerts a prescription was checked against, as accepted: dose warnings,
+// allergies and interactions, with those that needed an override reason
+pub(crate) struct PrescriptionChecks {
+    warnings: Vec<String>,
+    allergies: Vec<AllergyAlert>,
+    allergy_overrides: Vec<String>,
+    interactions: Vec<InteractionAlert>,
+    severe: Vec<String>,
 }
 
 impl EMR {
@@ -238,6 +475,15 @@ impl EMR {
         Ok(EMR {
             bundles: HashMap::new(),
             audit_log,
+            lab_ranges: LabRanges::load_or_default()?,
+            drugs: Arc::new(DrugDictionary::load_or_default()?),
+            dose_limits: DoseLimits::load_or_default()?,
+            interactions: InteractionTable::load_or_default()?,
+            condition_codes: ConditionCodes::load_or_default()?,
+            renal_adjustments: RenalAdjustments::load_or_default()?,
+            user: login_name(),
+            clinicians: ClinicianRegistry::load_or_default()?,
+            signer: None,
         })
     }
 
@@ -271,24 +517,21 @@ impl EMR {
             birth_date: birth_date.to_string(),
         };
 
-        let bundle = Bundle {
+        let entry = vec![
+            BundleEntry {
+                resource_type: "Patient".to_string(),
+                resource: Resource::Patient(patient),
+            }
+        ];
+        let mut bundle = Bundle {
             resource_type: "Bundle".to_string(),
             id: Uuid::new_v4().to_string(),
             type_field: "collection".to_string(),
-            entry: vec![
-                BundleEntry {
-                    resource_type: "Patient".to_string(),
-                    resource: Resource::Patient(patient),
-                }
-            ],
-            version_history: vec![
-                VersionEntry {
-                    timestamp: Utc::now(),
-                    message: "Patient created".to_string(),
-                    hash: "".to_string(), // Will be filled in by save_patient
-                }
-            ],
+            version_history: Vec::new(),
+            objects: BTreeMap::new(),
+            entry,
         };
+        history::add_version(&mut bundle, "Patient created", &self.user, Utc::now(), self.signer.as_ref())?;
 
         self.bundles.insert(id.to_string(), bundle);
         self.log_audit("Patient created", id)?;
@@ -296,12 +539,19 @@ impl EMR {
         Ok(())
     }
 
-    // Add blood pressure reading
+    // Add blood pressure reading taken now
     pub fn add_blood_pressure(&mut self, patient_id: &str, 
                              systolic: i32, diastolic: i32) -> Result<()> {
-        // Validate blood pressure values
+        self.add_blood_pressure_at(patient_id, systolic, diastolic, Utc::now())
+    }
+
+    // Add blood pressure reading taken at `effective`
+    pub fn add_blood_pressure_at(&mut self, patient_id: &str, systolic: i32, diastolic: i32,
+                                 effective: DateTime<Utc>) -> Result<()> {
+        // Validate blood pressure values and time
         let bp = BloodPressure::new(systolic, diastolic)?;
-        let observation = bp.to_observation(patient_id);
+        validate_clinical_time(effective)?;
+        let observation = bp.to_observation_at(patient_id, effective);
 
         // Add observation to patient bundle
         let bundle = self.bundles.get_mut(patient_id)
@@ -312,55 +562,135 @@ impl EMR {
             resource: Resource::Observation(observation),
         });
 
-        self.log_audit(&format!("Added BP: {}/{}", systolic, diastolic), patient_id)?;
+        self.log_audit(&format!("Added BP: {}/{} taken {}", systolic, diastolic,
+                               effective.to_rfc3339()), patient_id)?;
         
         Ok(())
     }
 
-    // Prescribe medication
+    // Prescribe medication now
     pub fn prescribe_medication(&mut self, patient_id: &str, medication: &str, 
                                dose_mg: f64, frequency: &str) -> Result<()> {
+        self.prescribe_medication_with(patient_id, medication, dose_mg, frequency, &PrescribeOptions::default())?;
+        Ok(())
+    }
+
+    // Prescribe medication, recording that the prescription was written at `authored`
+    pub fn prescribe_medication_at(&mut self, patient_id: &str, medication: &str,
+                                   dose_mg: f64, frequency: &str,
+                                   authored: DateTime<Utc>) -> Result<()> {
+        let options = PrescribeOptions { authored, ..PrescribeOptions::default() };
+        self.prescribe_medication_with(patient_id, medication, dose_mg, frequency, &options)?;
+        Ok(())
+    }
+
+    // Dose alerts for a prescription in mg, without writing it
+    pub fn check_dose(&self, medication: &str, dose_mg: f64, frequency: &str) -> Result<Vec<DoseAlert>> {
+        let sig = parse_sig(frequency)?;
+        match self.dose_limit(medication) {
+            Some(limit) if limit.unit != "mg" => Err(anyhow!("{} is dosed in {}, not mg", limit.name, limit.unit)),
+            Some(limit) => Ok(limit.check(dose_mg, &sig)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // The dose limit for `medication`. Medications that are not in the RxNorm
+    // dictionary use a limit recorded under their name, or else that of their
+    // closest match.
+    pub fn dose_limit(&self, medication: &str) -> Option<&DoseLimit> {
+        match self.drugs.resolve(medication) {
+            Ok(drug) => self.dose_limits.limit_for(&drug),
+            Err(_) => self.dose_limits.find_unlisted(medication).or_else(|| {
+                self.drugs.resolve_or_closest(medication).and_then(|drug| self.dose_limits.limit_for(&drug))
+            }),
+        }
+    }
+
+    // Prescribe medication. The medication must be in the RxNorm dictionary
+    // unless `options.unlisted` is set, in which case an unknown name is
+    // recorded without a code. Unlisted medications need an override reason
+    // and a dose limit, and are checked for allergies and interactions as
+    // their closest match. Doses above the maximum are refused; doses
+    // above the usual range, severe interactions with the patient's other
+    // active prescriptions and allergies need an override reason, which is
+    // audited; confirmed high-criticality allergies are refused. Returns the
+    // new MedicationRequest's id.
+    pub fn prescribe_medication_with(&mut self, patient_id: &str, medication: &str,
+                                     dose_mg: f64, frequency: &str,
+                                     options: &PrescribeOptions) -> Result<String> {
+        self.prescribe_dose(patient_id, medication, dose_mg, "mg", frequency, options)
+    }
+
+    // Prescribe a dose in mg or in units (e.g. insulin). The dose must be in
+    // the unit of the medication's dose limit; only medications with a limit
+    // in units can be prescribed in units.
+    pub(crate) fn prescribe_dose(&mut self, patient_id: &str, medication: &str,
+                                 dose: f64, unit: &str, frequency: &str,
+                                 options: &PrescribeOptions) -> Result<String> {
         // Basic validation
-        if dose_mg <= 0.0 {
-            return Err(anyhow!("Invalid dose: {} mg", dose_mg));
+        if medication.trim().is_empty() {
+            return Err(anyhow!("Medication name is required"));
+        }
+        if dose <= 0.0 {
+            return Err(anyhow!("Invalid dose: {} {}", dose, unit));
+        }
+        if !matches!(unit, "mg" | "units") {
+            return Err(anyhow!("Unsupported dose unit: {} (expected mg or units)", unit));
+        }
+        let sig = parse_sig(frequency)?;
+        validate_clinical_time(options.authored)?;
+        let override_reason = options.override_reason.as_deref()
+            .map(str::trim)
+            .filter(|reason| !reason.is_empty());
+
+        let medication = match self.drugs.resolve(medication) {
+            Ok(drug) => Coding { system: RXNORM_SYSTEM.to_string(), code: drug.drug.rxcui, display: drug.drug.name },
+            Err(_) if options.unlisted => {
+                Coding { system: String::new(), code: String::new(), display: medication.trim().to_string() }
+            }
+            Err(e) => return Err(e),
+        };
+        let unlisted = medication.code.is_empty();
+        let limit = self.dose_limit(if unlisted { &medication.display } else { &medication.code });
+        if unlisted && override_reason.is_none() {
+            return Err(anyhow!("{} is not in the RxNorm dictionary. An override reason is required to prescribe it",
+                               medication.display));
+        }
+        if unlisted && limit.is_none() {
+            return Err(anyhow!("No dose limit in {} is known for {}, which is not in the RxNorm dictionary. \
+                                Add one under its name to {} to prescribe it", unit, medication.display,
+                               dose_limits::DOSE_LIMITS_FILE));
         }
+        let checks = self.check_prescription(patient_id, &medication, limit, dose, unit, &sig, override_reason)?;
+        let authored = options.authored;
 
         // Create medication request
         let med_request = MedicationRequest {
             id: Uuid::new_v4().to_string(),
             status: "active".to_string(),
-            medication_codeable_concept: Coding {
-                system: "http://www.nlm.nih.gov/research/umls/rxnorm".to_string(),
-                code: "1234".to_string(), // Placeholder - would use actual RxNorm code
-                display: medication.to_string(),
-            },
+            medication_codeable_concept: medication.clone(),
             subject: Reference {
                 reference: format!("Patient/{}", patient_id),
             },
-            authored_on: Utc::now().to_rfc3339(),
+            authored_on: authored.to_rfc3339(),
+            recorded: Some(Utc::now().to_rfc3339()),
             dosage_instruction: vec![
                 DosageInstruction {
-                    text: format!("{} mg {}", dose_mg, frequency),
-                    timing: Timing {
-                        repeat: Some(Repeat {
-                            frequency: Some(1),
-                            period: Some(1.0),
-                            period_unit: Some("d".to_string()), // daily
-                        }),
-                    },
+                    text: format!("{} {} {}", dose, unit, frequency),
+                    timing: sig.timing(),
+                    as_needed_boolean: sig.as_needed.then_some(true),
                     dose_and_rate: vec![
                         DoseAndRate {
-                            dose_quantity: Some(Quantity {
-                                value: dose_mg,
-                                unit: "mg".to_string(),
-                                system: "http://unitsofmeasure.org".to_string(),
-                                code: "mg".to_string(),
-                            }),
+                            dose_quantity: Some(Quantity::ucum(dose, unit)?),
                         }
                     ],
                 }
             ],
+            replaces: None,
+            status_history: Vec::new(),
+            dose_calculation: None,
         };
+        let id = med_request.id.clone();
 
         // Add medication request to patient bundle
         let bundle = self.bundles.get_mut(patient_id)
@@ -371,115 +701,153 @@ impl EMR {
             resource: Resource::MedicationRequest(med_request),
         });
 
-        self.log_audit(&format!("Prescribed: {} {}mg {}", 
-                               medication, dose_mg, frequency), patient_id)?;
+        let code = if unlisted { "unlisted".to_string() } else { format!("RxNorm {}", medication.code) };
+        let amount = if unit == "mg" { format!("{}mg", dose) } else { format!("{} {}", dose, unit) };
+        if let Some(reason) = override_reason.filter(|_| unlisted) {
+            let checked = match self.drugs.resolve_or_closest(&medication.display) {
+                Some(drug) => format!("checked as {} (RxNorm {})", drug.drug.name, drug.drug.rxcui),
+                None => "with no close RxNorm match to check allergies and interactions against".to_string(),
+            };
+            self.log_audit(&format!("Unlisted medication {} {} {}, {}. Reason: {}",
+                                   medication.display, amount, frequency, checked, reason), patient_id)?;
+        }
+        self.log_prescription_checks(patient_id, &medication.display, &format!("{} {}", amount, frequency),
+                                     &checks, override_reason)?;
+        self.log_audit(&format!("Prescribed: {} ({}) {} {}", 
+                               medication.display, code, amount, frequency), patient_id)?;
         
+        Ok(id)
+    }
+
+    // Check a dose of `medication` against its limit, the patient's allergies
+    // and their other active prescriptions. Hard stops and refusing allergies
+    // are errors; dose warnings, allergies that need an override and severe
+    // interactions are errors unless there is an override reason.
+    pub(crate) fn check_prescription(&self, patient_id: &str, medication: &Coding, limit: Option<&DoseLimit>,
+                                     dose: f64, unit: &str, sig: &Sig,
+                                     override_reason: Option<&str>) -> Result<PrescriptionChecks> {
+        let alerts = match limit {
+            Some(limit) if limit.unit != unit => {
+                return Err(anyhow!("{} is dosed in {}, not {}", medication.display, limit.unit, unit));
+            }
+            Some(limit) => limit.check(dose, sig),
+            None if unit != "mg" => {
+                return Err(anyhow!("No dose limit in {} is known for {}; only medications with one can be prescribed in {}",
+                                   unit, medication.display, unit));
+            }
+            None => Vec::new(),
+        };
+
+        let hard_stops: Vec<String> = alerts.iter()
+            .filter(|alert| alert.severity == DoseSeverity::HardStop)
+            .map(|alert| alert.to_string())
+            .collect();
+        if !hard_stops.is_empty() {
+            return Err(anyhow!("Dose refused: {}", hard_stops.join("; ")));
+        }
+        let warnings: Vec<String> = alerts.iter().map(|alert| alert.to_string()).collect();
+        if !warnings.is_empty() && override_reason.is_none() {
+            return Err(anyhow!("Dose warning: {}. An override reason is required", warnings.join("; ")));
+        }
+        let allergies = self.check_allergies(patient_id, &medication.display)?;
+        let refused: Vec<String> = allergies.iter()
+            .filter(|alert| alert.action == AllergyAction::Refuse)
+            .map(|alert| alert.to_string())
+            .collect();
+        if !refused.is_empty() {
+            return Err(anyhow!("Refused due to allergy: {}", refused.join("; ")));
+        }
+        let allergy_overrides: Vec<String> = allergies.iter()
+            .filter(|alert| alert.action == AllergyAction::RequireOverride)
+            .map(|alert| alert.to_string())
+            .collect();
+        if !allergy_overrides.is_empty() && override_reason.is_none() {
+            return Err(anyhow!("Allergy warning: {}. An override reason is required", allergy_overrides.join("; ")));
+        }
+        let interactions = self.verify_interactions(patient_id, &medication.display)?;
+        let severe: Vec<String> = interactions.iter()
+            .filter(|alert| alert.is_severe())
+            .map(|alert| alert.to_string())
+            .collect();
+        if !severe.is_empty() && override_reason.is_none() {
+            return Err(anyhow!("Severe interaction: {}. An override reason is required", severe.join("; ")));
+        }
+        Ok(PrescriptionChecks { warnings, allergies, allergy_overrides, interactions, severe })
+    }
+
+    // Audit the overrides of a prescription that was written, and the alerts
+    // it was written with. `dosage` is e.g. "500mg twice daily".
+    pub(crate) fn log_prescription_checks(&mut self, patient_id: &str, medication: &str, dosage: &str,
+                                          checks: &PrescriptionChecks, override_reason: Option<&str>) -> Result<()> {
+        let PrescriptionChecks { warnings, allergies, allergy_overrides, interactions, severe } = checks;
+        if let Some(reason) = override_reason.filter(|_| !warnings.is_empty()) {
+            self.log_audit(&format!("Dose override for {} {}: {}. Reason: {}",
+                                   medication, dosage, warnings.join("; "), reason), patient_id)?;
+        }
+        if let Some(reason) = override_reason.filter(|_| !severe.is_empty()) {
+            self.log_audit(&format!("Interaction override for {}: {}. Reason: {}",
+                                   medication, severe.join("; "), reason), patient_id)?;
+        }
+        if let Some(reason) = override_reason.filter(|_| !allergy_overrides.is_empty()) {
+            self.log_audit(&format!("Allergy override for {}: {}. Reason: {}",
+                                   medication, allergy_overrides.join("; "), reason), patient_id)?;
+        }
+        for alert in allergies.iter().filter(|alert| alert.action == AllergyAction::Warn) {
+            self.log_audit(&format!("Allergy noted: {}", alert), patient_id)?;
+        }
+        for alert in interactions.iter().filter(|alert| !alert.is_severe()) {
+            self.log_audit(&format!("Interaction noted: {}", alert), patient_id)?;
+        }
         Ok(())
     }
 
     // Commit changes to patient record with versioning
     pub fn commit_changes(&mut self, patient_id: &str, message: &str) -> Result<()> {
+        let author = self.user.clone();
         let bundle = self.bundles.get_mut(patient_id)
             .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
         
-        // Create a hash of the current state
-        let bundle_json = serde_json::to_string(&bundle.entry)?;
-        let mut hasher = Sha256::new();
-        hasher.update(bundle_json.as_bytes());
-        let hash = format!("{:x}", hasher.finalize());
-        
-        // Add to version history
-        bundle.version_history.push(VersionEntry {
-            timestamp: Utc::now(),
-            message: message.to_string(),
-            hash,
-        });
+        // Add a snapshot of the current state to the version history,
+        // chained to the version before
+        history::add_version(bundle, message, &author, Utc::now(), self.signer.as_ref())?;
         
         self.log_audit(&format!("Committed changes: {}", message), patient_id)?;
         
         Ok(())
     }
 
-    // Save patient data to .med file
+    // Save patient data to its .med file
     pub fn save_patient(&self, patient_id: &str, key: &str) -> Result<()> {
+        self.save_patient_as(patient_id, key, &med_filename(patient_id))
+    }
+
+    // Save patient data to `path`, always in the current .med format
+    pub(crate) fn save_patient_as(&self, patient_id: &str, key: &str, path: &str) -> Result<()> {
         let bundle = self.bundles.get(patient_id)
             .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
         
-        // Serialize the bundle to JSON
+        // Serialize the bundle to JSON and encrypt it
         let bundle_json = serde_json::to_string(bundle)?;
+        let created = bundle.version_history.first().map_or_else(Utc::now, |version| version.timestamp);
+        let med_file = MedFile::seal(bundle_json.as_bytes(), key, created)?;
         
-        // Calculate hash of unencrypted data
-        let mut hasher = Sha256::new();
-        hasher.update(bundle_json.as_bytes());
-        let hash = format!("{:x}", hasher.finalize());
-        
-        // Generate a key from the password
-        let mut key_hasher = Sha256::new();
-        key_hasher.update(key.as_bytes());
-        let key_bytes = key_hasher.finalize();
-        let key = Key::<Aes256Gcm>::from_slice(&key_bytes);
-        
-        // Generate a random 96-bit nonce
-        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
-        
-        // Encrypt the data
-        let cipher = Aes256Gcm::new(key);
-        let encrypted_data = cipher.encrypt(&nonce, bundle_json.as_bytes())
-            .map_err(|e| anyhow!("Encryption failed: {:?}", e))?;
-        
-        // Create the MedFile structure
-        let med_file = MedFile {
-            iv: general_purpose::STANDARD.encode(nonce),
-            data: general_purpose::STANDARD.encode(encrypted_data),
-            hash,
-            created: bundle.version_history[0].timestamp,
-            modified: Utc::now(),
-        };
-        
-        // Serialize and write to file
-        let med_json = serde_json::to_string(&med_file)?;
-        let filename = format!("patient_{}.med", patient_id);
-        fs::write(&filename, med_json)?;
-        
-        Ok(())
+        med_file.write(path)
     }
 
     // Load patient data from .med file
     pub fn load_patient(&mut self, filename: &str, key: &str) -> Result<String> {
-        // Read the .med file
-        let med_json = fs::read_to_string(filename)?;
-        let med_file: MedFile = serde_json::from_str(&med_json)?;
-        
-        // Generate key from password
-        let mut key_hasher = Sha256::new();
-        key_hasher.update(key.as_bytes());
-        let key_bytes = key_hasher.finalize();
-        let key = Key::<Aes256Gcm>::from_slice(&key_bytes);
+        // Read and decrypt the .med file, checking its hash
+        let med_file = MedFile::read(filename)?;
+        let decrypted_data = med_file.open(key)?;
         
-        // Decode IV and encrypted data
-        let iv = general_purpose::STANDARD.decode(&med_file.iv)?;
-        let encrypted_data = general_purpose::STANDARD.decode(&med_file.data)?;
-        
-        // Create nonce from IV
-        let nonce = Nonce::from_slice(&iv);
-        
-        // Decrypt the data
-        let cipher = Aes256Gcm::new(key);
-        let decrypted_data = cipher.decrypt(nonce, encrypted_data.as_ref())
-            .map_err(|e| anyhow!("Decryption failed: {:?}", e))?;
-        
-        // Verify hash
-        let mut hasher = Sha256::new();
-        hasher.update(&decrypted_data);
-        let calculated_hash = format!("{:x}", hasher.finalize());
+        // Deserialize to bundle
+        let mut bundle: Bundle = serde_json::from_slice(&decrypted_data)?;
         
-        if calculated_hash != med_file.hash {
-            return Err(anyhow!("Hash verification failed - file may be corrupted"));
+        // Files saved before the history was kept start it from what they hold
+        if bundle.version_history.is_empty() {
+            history::add_version(&mut bundle, history::EARLIER_HISTORY, "", med_file.created, None)?;
         }
         
-        // Deserialize to bundle
-        let bundle: Bundle = serde_json::from_slice(&decrypted_data)?;
-        
         // Extract patient ID
         let patient_id = match &bundle.entry[0].resource {
             Resource::Patient(patient) => patient.id.clone(),
@@ -489,6 +857,14 @@ impl EMR {
         // Add to EMR
         self.bundles.insert(patient_id.clone(), bundle);
         self.log_audit(&format!("Loaded patient from {}", filename), &patient_id)?;
+        if !med_file.is_current() {
+            self.log_audit(&format!("{} is in .med format {}; it will be saved in format {}",
+                                    filename, med_file.format, MED_FORMAT_VERSION), &patient_id)?;
+        }
+        let warnings = self.signature_warnings(&patient_id)?;
+        if !warnings.is_empty() {
+            self.log_audit(&format!("Signature warning for {}: {}", filename, warnings), &patient_id)?;
+        }
         
         Ok(patient_id)
     }
@@ -1,45 +1,13 @@
 // src/main.rs
 // Charcot EMR: Command-line interface for the EMR system
 
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
 use std::path::Path;
-use anyhow::{Result, anyhowfn load_patient(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
-    let filename = args.get_one::<String>("filename").unwrap();
-    let key = args.get_one::<String>("key").unwrap();
-    
-    let patient_id = emr.load_patient(filename, key)?;
-    println!("Loaded patient {} from {}", patient_id, filename);
-    
-    // Display basic info
-    if let Some(bundle) = emr.bundles.get(&patient_id) {
-        if let Some(BundleEntry { resource: Resource::Patient(patient), .. }) = bundle.entry.first() {
-            if let Some(name) = patient.name.first() {
-                let given = name.given.join(" ");
-                let family = name.family.clone().unwrap_or_default();
-                println!("Name: {} {}", given, family);
-                println!("Gender: {}", patient.gender);
-                println!("Birth date: {}", patient.birth_date);
-            }
-        }
-        
-        println!("Version history:");
-        for (i, version) in bundle.version_history.iter().enumerate() {
-            println!("  {}: {} - {}", i+1, version.timestamp, version.message);
-        }
-    }
-    
-    Ok(())
-}
-
-fn print_usage() {
-    println!("Charcot EMR System");
-    println!("Usage:");
-    println!("  emr_cli create-patient <id> <given_name> <family_name> <gender> <birth_date> <key>");
-    println!("  emr_cli add-vital <patient_id> bp <systolic> <diastolic> <key>");
-    println!("  emr_cli prescribe <patient_id> <medication> <dose_mg> <frequency> <key>");
-    println!("  emr_cli connect-device <patient_id> <device_type> <key>");
-    println!("  emr_cli load <filename> <key>");
-};
-use clap::{Command, Arg, ArgMatches, value_parser};
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Duration, Local, Utc};
+use clap::{Command, Arg, ArgAction, ArgMatches, value_parser};
 use charcot_emr::*;
 
 fn main() -> Result<()> {
@@ -48,6 +16,12 @@ fn main() -> Result<()> {
         .version("0.1.0")
         .author("Charcot Team")
         .about("A medical EMR system for the Charcot language")
+        .arg(Arg::new("user").long("user").global(true)
+            .help("Who committed changes are attributed to; defaults to the login name"))
+        .arg(Arg::new("sign_as").long("sign-as").global(true).conflicts_with("user")
+            .help("Sign committed changes with this clinician's key; they become the author"))
+        .arg(Arg::new("passphrase").long("passphrase").global(true)
+            .help("Passphrase of the --sign-as clinician's key"))
         .subcommand(
             Command::new("create-patient")
                 .about("Create a new patient record")
@@ -62,10 +36,21 @@ fn main() -> Result<()> {
             Command::new("add-vital")
                 .about("Add vital signs to a patient record")
                 .arg(Arg::new("patient_id").required(true).help("Patient ID"))
-                .arg(Arg::new("type").required(true).help("Type of vital (bp for blood pressure)"))
-                .arg(Arg::new("value1").required(true).value_parser(value_parser!(i32)).help("First value (systolic for bp)"))
-                .arg(Arg::new("value2").required(true).value_parser(value_parser!(i32)).help("Second value (diastolic for bp)"))
-                .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+                .arg(Arg::new("type").required(true).help("Type of vital: bp, hr, temp, spo2, rr, weight or height"))
+                .arg(Arg::new("values").required(true).num_args(2..=3)
+                    .help("The value, optionally with a unit such as 189[lb_av] (systolic and diastolic for bp), followed by the encryption key"))
+                .arg(Arg::new("at").long("at").help("When the reading was taken (e.g. 2025-04-08T08:30:00); defaults to now"))
+        )
+        .subcommand(
+            Command::new("add-lab")
+                .about("Add laboratory results to a patient record, flagged against reference ranges")
+                .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                .arg(Arg::new("results").required(true).num_args(2..)
+                    .help("Results as <test>=<value>[unit] (e.g. glucose=180 hba1c=7.2 glucose=10mmol/L) followed by the encryption key"))
+                .arg(Arg::new("at").long("at").help("When the sample was taken; defaults to now"))
+                .arg(Arg::new("note").long("note").help("Note attached to each result (e.g. fasting)"))
+                .arg(Arg::new("report").long("report")
+                    .help("Group the results in a DiagnosticReport with this title"))
         )
         .subcommand(
             Command::new("prescribe")
@@ -73,8 +58,216 @@ fn main() -> Result<()> {
                 .arg(Arg::new("patient_id").required(true).help("Patient ID"))
                 .arg(Arg::new("medication").required(true).help("Medication name"))
                 .arg(Arg::new("dose_mg").required(true).value_parser(value_parser!(f64)).help("Dose in mg"))
-                .arg(Arg::new("frequency").required(true).help("Frequency (e.g., daily, twice daily)"))
+                .arg(Arg::new("frequency").required(true).help("Frequency (e.g. daily, BID, q8h, every 24 hours starting 08:00, with meals, PRN)"))
+                .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+                .arg(Arg::new("at").long("at").help("When the prescription was written; defaults to now"))
+                .arg(Arg::new("unlisted").long("unlisted").action(ArgAction::SetTrue).requires("override_reason")
+                    .help("Allow a medication that is not in the RxNorm dictionary; needs --override-reason"))
+                .arg(Arg::new("override_reason").long("override-reason")
+                    .help("Why an unlisted medication, a dose above the usual range or a severe interaction is accepted; \
+                           recorded in the audit log"))
+        )
+        .subcommand(
+            Command::new("calculate-dose")
+                .about("Calculate a weight- or BSA-based dose from the patient's latest measurements, adjusted for renal function")
+                .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                .arg(Arg::new("medication").required(true).help("Medication name"))
+                .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+                .arg(Arg::new("per_kg").long("per-kg").value_parser(value_parser!(f64))
+                    .conflicts_with("per_m2").required_unless_present("per_m2").help("Dose per kg of body weight"))
+                .arg(Arg::new("per_m2").long("per-m2").value_parser(value_parser!(f64))
+                    .help("Dose per m2 of body surface area"))
+                .arg(Arg::new("unit").long("unit").default_value("mg").value_parser(dose_calc::DOSE_UNITS.to_vec()))
+                .arg(Arg::new("prescribe").long("prescribe")
+                    .help("Prescribe the calculated dose at this frequency (e.g. every 24 hours starting 08:00)"))
+                .arg(Arg::new("override_reason").long("override-reason")
+                    .help("Why a dose above the usual range or a severe interaction is accepted; recorded in the audit log"))
+                .arg(Arg::new("at").long("at").help("When the prescription is written; defaults to now"))
+        )
+        .subcommand(
+            Command::new("medication")
+                .about("List a patient's prescriptions or stop, hold, resume, change or renew one")
+                .subcommand(
+                    Command::new("list")
+                        .about("List a patient's current prescriptions")
+                        .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                        .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+                        .arg(Arg::new("all").long("all").action(ArgAction::SetTrue)
+                            .help("Include stopped, completed and replaced prescriptions with their history"))
+                )
+                .subcommand(change_command("stop", "Discontinue a prescription"))
+                .subcommand(change_command("hold", "Put a prescription on hold"))
+                .subcommand(
+                    change_command("resume", "Take a prescription off hold")
+                        .arg(Arg::new("override_reason").long("override-reason")
+                            .help("Why a dose warning, allergy or severe interaction found on resuming is accepted"))
+                )
+                .subcommand(change_command("renew", "Renew a prescription with the same dosage"))
+                .subcommand(
+                    change_command("change", "Change the dose or frequency of a prescription")
+                        .arg(Arg::new("dose_mg").long("dose").required(true).value_parser(value_parser!(f64))
+                            .help("New dose in mg"))
+                        .arg(Arg::new("frequency").long("frequency").required(true).help("New frequency"))
+                        .arg(Arg::new("override_reason").long("override-reason")
+                            .help("Why a dose above the usual range or a severe interaction is accepted"))
+                )
+        )
+        .subcommand(
+            Command::new("reconcile")
+                .about("Compare an outside medication list with a patient's current prescriptions")
+                .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                .arg(Arg::new("list").required(true)
+                    .help("Medication list: a FHIR Bundle of MedicationStatements (JSON) or a CSV file with a header"))
+                .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+                .arg(Arg::new("context").long("context").default_value("admission")
+                    .value_parser(reconcile::CONTEXTS.to_vec()))
+                .arg(Arg::new("decide").long("decide").action(ArgAction::Append)
+                    .help("Change a proposed decision, as <n>=<continue|modify|stop|new>"))
+                .arg(Arg::new("apply").long("apply").action(ArgAction::SetTrue)
+                    .help("Apply the decisions and commit them as one change"))
+                .arg(Arg::new("by").long("by").help("Who is reconciling the medications; required with --apply"))
+                .arg(Arg::new("override_reason").long("override-reason")
+                    .help("Why dose warnings, severe interactions or allergies are accepted for modified and new medications"))
+                .arg(Arg::new("at").long("at").help("When the medications were reconciled; defaults to now"))
+        )
+        .subcommand(
+            Command::new("administer")
+                .about("Record a dose given, missed or refused on the medication administration record")
+                .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                .arg(Arg::new("medication").required(true).help("Medication name or MedicationRequest ID"))
+                .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+                .arg(Arg::new("by").long("by").required(true).help("Who gave or recorded the dose"))
+                .arg(Arg::new("outcome").long("outcome").default_value("given")
+                    .value_parser(["given", "missed", "refused"]))
+                .arg(Arg::new("scheduled").long("scheduled")
+                    .help("Scheduled time of the dose; defaults to the nearest unrecorded one"))
+                .arg(Arg::new("dose").long("dose")
+                    .help("Dose given, if not the prescribed dose, e.g. 500mg or 10 units; in the prescribed unit if none is given"))
+                .arg(Arg::new("note").long("note").help("Note, e.g. why a dose was refused"))
+                .arg(Arg::new("at").long("at").help("When it was given or recorded; defaults to now"))
+        )
+        .subcommand(
+            Command::new("mar")
+                .about("Show a patient's medication administration record for a day")
+                .arg(Arg::new("patient_id").required(true).help("Patient ID"))
                 .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+                .arg(Arg::new("date").long("date").help("Day to show (YYYY-MM-DD); defaults to today"))
+                .arg(Arg::new("due").long("due").action(ArgAction::SetTrue)
+                    .help("Only show overdue doses and those due within the hour"))
+        )
+        .subcommand(
+            Command::new("adherence")
+                .about("Show the share of scheduled doses given for each prescription")
+                .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+                .arg(Arg::new("days").long("days").default_value("30").value_parser(value_parser!(i64))
+                    .help("Number of days to look back"))
+        )
+        .subcommand(
+            Command::new("add-allergy")
+                .about("Record an allergy or intolerance for a patient")
+                .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                .arg(Arg::new("substance").required(true)
+                    .help("Medication, drug class (e.g. penicillins, sulfa) or other substance"))
+                .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+                .arg(Arg::new("reaction").long("reaction").help("Reaction, e.g. hives"))
+                .arg(Arg::new("severity").long("severity").value_parser(["mild", "moderate", "severe"])
+                    .help("Severity of the reaction"))
+                .arg(Arg::new("criticality").long("criticality").default_value("unable-to-assess")
+                    .value_parser(["low", "high", "unable-to-assess"]))
+                .arg(Arg::new("category").long("category").default_value("medication")
+                    .value_parser(["medication", "food", "environment", "biologic"]))
+                .arg(Arg::new("type").long("type").default_value("allergy")
+                    .value_parser(["allergy", "intolerance"]))
+                .arg(Arg::new("status").long("status").default_value("unconfirmed")
+                    .value_parser(["unconfirmed", "confirmed", "refuted", "entered-in-error"])
+                    .help("Verification status"))
+                .arg(Arg::new("at").long("at").help("When the allergy was recorded; defaults to now"))
+        )
+        .subcommand(
+            Command::new("condition")
+                .about("Manage a patient's problem list of ICD-10-CM coded conditions")
+                .subcommand(
+                    Command::new("add")
+                        .about("Add a condition to a patient's problem list")
+                        .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                        .arg(Arg::new("icd10").required(true).help("ICD-10-CM code (e.g. E11.9)"))
+                        .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+                        .arg(Arg::new("snomed").long("snomed").help("Equivalent SNOMED CT concept"))
+                        .arg(Arg::new("onset").long("onset").help("When the condition began"))
+                        .arg(Arg::new("status").long("status").default_value("active")
+                            .value_parser(["active", "recurrence", "relapse", "inactive", "remission", "resolved"])
+                            .help("Clinical status"))
+                        .arg(Arg::new("verification").long("verification").default_value("confirmed")
+                            .value_parser(["unconfirmed", "provisional", "differential", "confirmed", "refuted", "entered-in-error"])
+                            .help("Verification status"))
+                        .arg(Arg::new("note").long("note").help("Note attached to the condition"))
+                        .arg(Arg::new("at").long("at").help("When the condition was recorded; defaults to now"))
+                )
+                .subcommand(
+                    Command::new("resolve")
+                        .about("Mark a current problem as resolved")
+                        .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                        .arg(Arg::new("condition").required(true).help("ICD-10-CM code or Condition ID"))
+                        .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+                        .arg(Arg::new("at").long("at").help("When the condition resolved; defaults to now"))
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List a patient's problems")
+                        .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                        .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+                        .arg(Arg::new("all").long("all").action(ArgAction::SetTrue)
+                            .help("Include resolved and inactive problems"))
+                )
+                .subcommand(
+                    Command::new("search")
+                        .about("Search the ICD-10-CM code table by code or description")
+                        .arg(Arg::new("text").required(true).help("Code prefix or words from the description"))
+                        .arg(Arg::new("snomed").long("snomed").action(ArgAction::SetTrue)
+                            .help("Search the SNOMED CT table instead"))
+                )
+                .subcommand(
+                    Command::new("import")
+                        .about("Build icd10cm.json from the CMS ICD-10-CM code file")
+                        .arg(Arg::new("path").required(true).help("icd10cm_codes_<year>.txt"))
+                )
+        )
+        .subcommand(
+            Command::new("check-interactions")
+                .about("Check a patient's active prescriptions for drug-drug interactions")
+                .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+                .arg(Arg::new("medication").long("medication")
+                    .help("Check this medication against the active prescriptions instead"))
+        )
+        .subcommand(
+            Command::new("rxnorm")
+                .about("Manage the offline RxNorm medication dictionary")
+                .subcommand(
+                    Command::new("import")
+                        .about("Build rxnorm.json from RXNCONSO.RRF of an RxNorm release")
+                        .arg(Arg::new("path").required(true).help("RXNCONSO.RRF or the directory holding it"))
+                )
+                .subcommand(
+                    Command::new("search")
+                        .about("Look up a medication and suggest close matches")
+                        .arg(Arg::new("name").required(true).help("Medication name or RXCUI"))
+                )
+        )
+        .subcommand(
+            Command::new("key")
+                .about("Manage clinician signing keys")
+                .subcommand(
+                    Command::new("generate")
+                        .about("Generate an Ed25519 signing key for a clinician, encrypted with their passphrase")
+                        .arg(Arg::new("clinician").required(true).help("Clinician name, e.g. dr.smith"))
+                        .arg(Arg::new("passphrase").required(true).help("Passphrase protecting the key"))
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List the clinicians whose signatures are trusted")
+                )
         )
         .subcommand(
             Command::new("connect-device")
@@ -89,16 +282,111 @@ fn main() -> Result<()> {
                 .arg(Arg::new("filename").required(true).help("Path to the .med file"))
                 .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
         )
+        .subcommand(
+            Command::new("migrate")
+                .about("Re-encrypt a .med file in the current format, with its key derived by Argon2id")
+                .arg(Arg::new("filename").required(true).help("Path to the .med file"))
+                .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Check the hash chain of a .med file's version history and report the first broken link")
+                .arg(Arg::new("filename").required(true).help("Path to the .med file"))
+                .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+        )
+        .subcommand(
+            Command::new("log")
+                .about("List the versions of a patient record")
+                .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+        )
+        .subcommand(
+            Command::new("show")
+                .about("Show a version of a patient record and what it changed")
+                .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                .arg(Arg::new("version").required(true).help("Version number from the log, as 3 or #3, or a prefix of its hash"))
+                .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Show the resources added, removed and modified between two versions of a patient record")
+                .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                .arg(Arg::new("from").required(true).help("Earlier version number or hash prefix"))
+                .arg(Arg::new("to").required(true).help("Later version number or hash prefix"))
+                .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+        )
+        .subcommand(
+            Command::new("revert")
+                .about("Restore an earlier version of a patient record as a new version")
+                .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+                .arg(Arg::new("version").required(true).help("Version number from the log, as 3 or #3, or a prefix of its hash"))
+                .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+                .arg(Arg::new("reason").long("reason").required(true).help("Why the record is reverted"))
+        )
+        .subcommand(
+            Command::new("run")
+                .about("Run a Charcot script against the patient records")
+                .arg(Arg::new("script").required(true).help("Path to the .charcot script"))
+                .arg(Arg::new("key").required(true).help("Encryption key for the patient files"))
+                .arg(Arg::new("dry_run").long("dry-run").action(ArgAction::SetTrue)
+                    .help("Print the planned changes without writing any .med files"))
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Check a Charcot script for errors without running it")
+                .arg(Arg::new("script").required(true).help("Path to the .charcot script"))
+        )
+        .subcommand(
+            Command::new("fmt")
+                .about("Reformat Charcot scripts to the canonical layout")
+                .arg(Arg::new("scripts").required(true).num_args(1..).help("Paths to .charcot scripts"))
+                .arg(Arg::new("check").long("check").action(ArgAction::SetTrue)
+                    .help("Only report scripts that are not formatted; exit non-zero if any"))
+        )
+        .subcommand(
+            Command::new("repl")
+                .about("Start an interactive Charcot session")
+        )
         .get_matches();
 
     let mut emr = EMR::new()?;
+    if let Some(user) = matches.get_one::<String>("user") {
+        emr.user = user.clone();
+    }
+    if let Some(clinician) = matches.get_one::<String>("sign_as") {
+        let passphrase = matches.get_one::<String>("passphrase")
+            .ok_or_else(|| anyhow!("--sign-as needs the key's --passphrase"))?;
+        emr.sign_in(Signer::unlock(clinician, passphrase)?);
+    }
     
     match matches.subcommand() {
         Some(("create-patient", args)) => create_patient(&mut emr, args),
         Some(("add-vital", args)) => add_vital(&mut emr, args),
+        Some(("add-lab", args)) => add_lab(&mut emr, args),
         Some(("prescribe", args)) => prescribe_medication(&mut emr, args),
+        Some(("calculate-dose", args)) => calculate_dose(&mut emr, args),
+        Some(("medication", args)) => medication(&mut emr, args),
+        Some(("reconcile", args)) => reconcile_medications(&mut emr, args),
+        Some(("administer", args)) => administer(&mut emr, args),
+        Some(("mar", args)) => mar(&mut emr, args),
+        Some(("adherence", args)) => adherence(&mut emr, args),
+        Some(("add-allergy", args)) => add_allergy(&mut emr, args),
+        Some(("condition", args)) => condition(&mut emr, args),
+        Some(("check-interactions", args)) => check_interactions(&mut emr, args),
+        Some(("rxnorm", args)) => rxnorm(&emr, args),
+        Some(("key", args)) => signing_key(&mut emr, args),
         Some(("connect-device", args)) => connect_device(&mut emr, args),
         Some(("load", args)) => load_patient(&mut emr, args),
+        Some(("migrate", args)) => migrate_patient(&mut emr, args),
+        Some(("verify", args)) => verify_history(&mut emr, args),
+        Some(("log", args)) => history_log(&mut emr, args),
+        Some(("show", args)) => show_version(&mut emr, args),
+        Some(("diff", args)) => diff_versions(&mut emr, args),
+        Some(("revert", args)) => revert_version(&mut emr, args),
+        Some(("run", args)) => run_script(&mut emr, args),
+        Some(("check", args)) => check_script(args),
+        Some(("fmt", args)) => format_scripts(args),
+        Some(("repl", _)) => repl(&mut emr),
         _ => {
             print_usage();
             Ok(())
@@ -125,63 +413,670 @@ fn create_patient(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
 fn add_vital(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
     let patient_id = args.get_one::<String>("patient_id").unwrap();
     let vital_type = args.get_one::<String>("type").unwrap();
-    let value1 = args.get_one::<i32>("value1").unwrap();
-    let value2 = args.get_one::<i32>("value2").unwrap();
+    let values: Vec<&String> = args.get_many::<String>("values").unwrap().collect();
+    let (key, values) = values.split_last().unwrap();
+    let effective = clinical_time(args)?;
+
+    // Check the arguments before touching the patient file
+    let kind = if vital_type == "bp" {
+        None
+    } else {
+        Some(VitalKind::from_name(vital_type).ok_or_else(|| anyhow!(
+            "Unknown vital type: {}. Expected bp, hr, temp, spo2, rr, weight or height", vital_type
+        ))?)
+    };
+    let expected = if kind.is_none() { 2 } else { 1 };
+    if values.len() != expected {
+        return Err(anyhow!("{} takes {} value(s) followed by the key", vital_type, expected));
+    }
+
+    load_existing(emr, patient_id, key)?;
+
+    match kind {
+        None => {
+            let systolic: i32 = values[0].parse().map_err(|_| anyhow!("Invalid systolic value: {}", values[0]))?;
+            let diastolic: i32 = values[1].parse().map_err(|_| anyhow!("Invalid diastolic value: {}", values[1]))?;
+            emr.add_blood_pressure_at(patient_id, systolic, diastolic, effective)?;
+            emr.commit_changes(patient_id, &format!("Added BP: {}/{}", systolic, diastolic))?;
+            emr.save_patient(patient_id, key)?;
+
+            println!("Added blood pressure {}/{} taken {} to patient {}",
+                     systolic, diastolic, effective.to_rfc3339(), patient_id);
+        }
+        Some(kind) => {
+            // A value with a unit is converted to the vital's unit
+            let value = match units::split_quantity(values[0])? {
+                (value, Some(unit)) => Vital::from_quantity(kind, value, unit)?.value,
+                (value, None) => value,
+            };
+            emr.add_vital_at(patient_id, kind, value, effective)?;
+            emr.commit_changes(patient_id, &format!("Added {}: {} {}", kind, value, kind.unit().0))?;
+            emr.save_patient(patient_id, key)?;
+
+            println!("Added {} {} {} taken {} to patient {}",
+                     kind.display().to_lowercase(), value, kind.unit().0, effective.to_rfc3339(), patient_id);
+        }
+    }
+
+    Ok(())
+}
+
+fn add_lab(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let patient_id = args.get_one::<String>("patient_id").unwrap();
+    let results: Vec<&String> = args.get_many::<String>("results").unwrap().collect();
+    let (key, results) = results.split_last().unwrap();
+    let effective = clinical_time(args)?;
+    let note = args.get_one::<String>("note").map(String::as_str);
+
+    // Check every result before touching the patient file
+    let mut parsed = Vec::new();
+    for result in results {
+        let (name, value) = result.split_once('=')
+            .ok_or_else(|| anyhow!("Invalid result: {}. Expected <test>=<value>, e.g. glucose=180", result))?;
+        let test = emr.lab_ranges.find(name)
+            .ok_or_else(|| anyhow!("Unknown lab test: {}", name))?
+            .clone();
+        let value = match units::split_quantity(value)? {
+            (value, Some(unit)) => {
+                let converted = units::convert_analyte(value, unit, &test.unit, &test.loinc)
+                    .map_err(|e| anyhow!("Invalid unit for {}: {}", test.name, e))?;
+                println!("Converted {} {} {} to {:.2} {}", test.name, value, unit, converted, test.unit);
+                converted
+            }
+            (value, None) => value,
+        };
+        parsed.push((test, value));
+    }
+
+    load_existing(emr, patient_id, key)?;
+
+    let mut ids = Vec::new();
+    for (test, value) in &parsed {
+        let (id, interpretation) = emr.add_lab_result_at(patient_id, &test.name, *value, &test.unit, effective, note)?;
+        println!("Added {} {} {} ({}, reference {}-{})",
+                 test.name, value, test.unit, interpretation, test.low, test.high);
+        ids.push(id);
+    }
+    if let Some(title) = args.get_one::<String>("report") {
+        emr.add_diagnostic_report(patient_id, title, &ids)?;
+        println!("Grouped {} result(s) in report {}", ids.len(), title);
+    }
+
+    let names: Vec<&str> = parsed.iter().map(|(test, _)| test.name.as_str()).collect();
+    emr.commit_changes(patient_id, &format!("Added labs: {}", names.join(", ")))?;
+    emr.save_patient(patient_id, key)?;
+    Ok(())
+}
+
+fn add_allergy(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let patient_id = args.get_one::<String>("patient_id").unwrap();
     let key = args.get_one::<String>("key").unwrap();
-    
-    if vital_type == "bp" {
-        // Load patient first
-        let filename = format!("patient_{}.med", patient_id);
-        if Path::new(&filename).exists() {
-            emr.load_patient(&filename, key)?;
-        } else {
-            return Err(anyhow!("Patient file not found: {}", filename));
+    let allergy = NewAllergy {
+        substance: args.get_one::<String>("substance").unwrap().clone(),
+        allergy_type: args.get_one::<String>("type").unwrap().clone(),
+        category: args.get_one::<String>("category").unwrap().clone(),
+        criticality: args.get_one::<String>("criticality").unwrap().clone(),
+        verification_status: args.get_one::<String>("status").unwrap().clone(),
+        reaction: args.get_one::<String>("reaction").cloned(),
+        reaction_severity: args.get_one::<String>("severity").cloned(),
+        recorded: clinical_time(args)?,
+    };
+
+    load_existing(emr, patient_id, key)?;
+
+    emr.add_allergy(patient_id, &allergy)?;
+    emr.commit_changes(patient_id, &format!("Added {}: {}", allergy.allergy_type, allergy.substance))?;
+    emr.save_patient(patient_id, key)?;
+
+    println!("Added {} to {} for patient {}", allergy.allergy_type, allergy.substance, patient_id);
+    Ok(())
+}
+
+// A subcommand changing one of a patient's prescriptions
+fn change_command(name: &'static str, about: &'static str) -> Command {
+    Command::new(name)
+        .about(about)
+        .arg(Arg::new("patient_id").required(true).help("Patient ID"))
+        .arg(Arg::new("medication").required(true).help("Medication name or MedicationRequest ID"))
+        .arg(Arg::new("key").required(true).help("Encryption key for the patient file"))
+        .arg(Arg::new("reason").long("reason").required(true).help("Why the prescription is changed"))
+        .arg(Arg::new("by").long("by")
+            .help("Who is changing it; defaults to --user or --sign-as, else the login name"))
+        .arg(Arg::new("at").long("at").help("When it was changed; defaults to now"))
+}
+
+fn medication(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let (action, args) = args.subcommand()
+        .ok_or_else(|| anyhow!("Usage: emr_cli medication <list|stop|hold|resume|change|renew> ..."))?;
+    let patient_id = args.get_one::<String>("patient_id").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+    load_existing(emr, patient_id, key)?;
+
+    if action == "list" {
+        let all = args.get_flag("all");
+        let requests: Vec<_> = emr.medication_requests(patient_id)?.into_iter()
+            .filter(|request| all || medications::is_current(request))
+            .collect();
+        if requests.is_empty() {
+            println!("No {} for patient {}", if all { "prescriptions" } else { "current prescriptions" }, patient_id);
         }
-        
-        // Add blood pressure
-        emr.add_blood_pressure(patient_id, *value1, *value2)?;
-        emr.commit_changes(patient_id, &format!("Added BP: {}/{}", value1, value2))?;
+        for request in requests {
+            let dosage = request.dosage_instruction.first().map(|d| d.text.as_str()).unwrap_or_default();
+            println!("  {} {} ({}) written {}", request.medication_codeable_concept.display, dosage,
+                     request.status, request.authored_on);
+            if all {
+                println!("    ID: {}", request.id);
+                if let Some(replaces) = &request.replaces {
+                    println!("    Replaces {}", replaces.reference);
+                }
+                for change in &request.status_history {
+                    println!("    {} {} by {}: {}", change.date, change.status, change.author, change.reason);
+                }
+                if let Some(calculation) = &request.dose_calculation {
+                    print_dose_calculation(calculation);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let medication = args.get_one::<String>("medication").unwrap();
+    let change = MedicationChange {
+        reason: args.get_one::<String>("reason").unwrap().clone(),
+        author: args.get_one::<String>("by").cloned().unwrap_or_else(|| emr.user.clone()),
+        at: clinical_time(args)?,
+    };
+    match action {
+        "stop" => emr.discontinue_medication(patient_id, medication, &change)?,
+        "hold" => emr.hold_medication(patient_id, medication, &change)?,
+        "resume" => {
+            let override_reason = args.get_one::<String>("override_reason").map(String::as_str);
+            emr.resume_medication(patient_id, medication, &change, override_reason)?;
+        }
+        "renew" => {
+            emr.renew_medication(patient_id, medication, &change)?;
+        }
+        "change" => {
+            let dose_mg = args.get_one::<f64>("dose_mg").unwrap();
+            let frequency = args.get_one::<String>("frequency").unwrap();
+            let override_reason = args.get_one::<String>("override_reason").map(String::as_str);
+            emr.modify_medication(patient_id, medication, *dose_mg, frequency, &change, override_reason)?;
+        }
+        _ => return Err(anyhow!("Unknown medication action: {}", action)),
+    }
+    emr.save_patient(patient_id, key)?;
+
+    if let Some(version) = emr.bundles.get(patient_id).and_then(|bundle| bundle.version_history.last()) {
+        println!("{}", version.message);
+    }
+    Ok(())
+}
+
+fn reconcile_medications(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let patient_id = args.get_one::<String>("patient_id").unwrap();
+    let path = args.get_one::<String>("list").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+    let list = fs::read_to_string(path).map_err(|e| anyhow!("Failed to read {}: {}", path, e))?;
+    let list = reconcile::parse_medication_list(&list)?;
+    let reconciliation = Reconciliation {
+        context: args.get_one::<String>("context").unwrap().clone(),
+        author: args.get_one::<String>("by").cloned().unwrap_or_default(),
+        at: clinical_time(args)?,
+    };
+    if args.get_flag("apply") && reconciliation.author.trim().is_empty() {
+        return Err(anyhow!("--by is required with --apply"));
+    }
+    load_existing(emr, patient_id, key)?;
+
+    let mut items = emr.reconcile_medications(patient_id, &list)?;
+    for decision in args.get_many::<String>("decide").unwrap_or_default() {
+        let (number, action) = decision.split_once('=')
+            .ok_or_else(|| anyhow!("Expected <n>=<decision>, got {}", decision))?;
+        let item = number.trim().parse::<usize>().ok()
+            .and_then(|n| n.checked_sub(1))
+            .and_then(|i| items.get_mut(i))
+            .ok_or_else(|| anyhow!("No medication numbered {}", number))?;
+        item.decision = ReconcileAction::parse(action)?;
+    }
+    let override_reason = args.get_one::<String>("override_reason");
+    for item in &mut items {
+        item.override_reason = override_reason.cloned();
+    }
+
+    if items.is_empty() {
+        println!("No medications to reconcile for patient {}", patient_id);
+    }
+    for (i, item) in items.iter().enumerate() {
+        let changed = if item.decision == item.proposed { String::new() } else { format!(" (proposed {})", item.proposed) };
+        println!("{:>3}. {}{}", i + 1, item.decision, changed);
+        if let Some(external) = &item.external {
+            println!("       Outside list: {}", external);
+        }
+        if let Some(current) = &item.current {
+            println!("       Current:      {}", current);
+        }
+        println!("       {}", item.note);
+    }
+
+    if args.get_flag("apply") {
+        let message = emr.apply_reconciliation(patient_id, &items, &reconciliation)?;
         emr.save_patient(patient_id, key)?;
-        
-        println!("Added blood pressure {}/{} to patient {}", value1, value2, patient_id);
+        println!("{}", message);
+    } else if !items.is_empty() {
+        println!("Review the decisions, change any with --decide <n>=<decision>, then run again with --apply --by <name>");
+    }
+    Ok(())
+}
+
+fn administer(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let patient_id = args.get_one::<String>("patient_id").unwrap();
+    let medication = args.get_one::<String>("medication").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+    let (dose, dose_unit) = match args.get_one::<String>("dose") {
+        Some(text) => {
+            let (value, unit) = units::split_quantity(text)?;
+            (Some(value), unit.map(str::to_string))
+        }
+        None => (None, None),
+    };
+    let administration = NewAdministration {
+        outcome: AdministrationOutcome::parse(args.get_one::<String>("outcome").unwrap())?,
+        scheduled: args.get_one::<String>("scheduled").map(|text| parse_clinical_time(text)).transpose()?,
+        at: clinical_time(args)?,
+        dose,
+        dose_unit,
+        performer: args.get_one::<String>("by").unwrap().clone(),
+        note: args.get_one::<String>("note").cloned(),
+    };
+    load_existing(emr, patient_id, key)?;
+
+    let id = emr.record_administration(patient_id, medication, &administration)?;
+    let recorded = emr.administrations(patient_id)?.into_iter()
+        .find(|a| a.id == id)
+        .cloned()
+        .ok_or_else(|| anyhow!("Administration {} was not recorded", id))?;
+    let scheduled = recorded.scheduled.as_deref()
+        .map(|time| format!(" (scheduled {})", local_clock(time)))
+        .unwrap_or_default();
+    let message = format!("{} dose of {}{}", administration.outcome, recorded.medication_codeable_concept.display, scheduled);
+    emr.commit_changes(patient_id, &format!("Recorded {}", message))?;
+    emr.save_patient(patient_id, key)?;
+
+    println!("Recorded {} for patient {}", message, patient_id);
+    Ok(())
+}
+
+fn mar(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let patient_id = args.get_one::<String>("patient_id").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+    load_existing(emr, patient_id, key)?;
+
+    let now = Utc::now();
+    let doses = if args.get_flag("due") {
+        emr.due_doses(patient_id, now)?
     } else {
-        println!("Only blood pressure (bp) vital is supported at this time");
+        let day = match args.get_one::<String>("date") {
+            Some(text) => parse_clinical_time(text)?,
+            None => now,
+        };
+        let (from, to) = local_day(day)?;
+        emr.scheduled_doses(patient_id, from, to)?
+    };
+    if doses.is_empty() {
+        println!("No doses scheduled");
+    }
+    for dose in doses {
+        let amount = dose.dose.as_ref().map(|q| format!(" {} {}", q.value, q.unit)).unwrap_or_default();
+        let state = match (&dose.administration, dose.outcome()) {
+            (Some(administration), Some(outcome)) => format!("{} {} by {}", outcome,
+                local_clock(&administration.effective_date_time),
+                administration.performer.as_deref().unwrap_or("unknown")),
+            _ if dose.scheduled < now => "overdue".to_string(),
+            _ => "due".to_string(),
+        };
+        println!("  {}  {}{}  {}", dose.scheduled.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                 dose.medication, amount, state);
+    }
+    Ok(())
+}
+
+fn adherence(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let patient_id = args.get_one::<String>("patient_id").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+    let days = args.get_one::<i64>("days").unwrap();
+    load_existing(emr, patient_id, key)?;
+
+    let now = Utc::now();
+    let adherence = emr.adherence(patient_id, now - Duration::days(*days), now)?;
+    if adherence.is_empty() {
+        println!("No doses were scheduled in the last {} days", days);
+    }
+    for entry in adherence {
+        println!("  {}", entry);
     }
-    
     Ok(())
 }
 
+// Start and end of the local day containing `time`
+fn local_day(time: DateTime<Utc>) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let day = time.with_timezone(&Local).date_naive();
+    let start = parse_clinical_time(&day.format("%Y-%m-%d").to_string())?;
+    Ok((start, start + Duration::days(1) - Duration::seconds(1)))
+}
+
+// "2025-04-08T21:00:00+00:00" as local "HH:MM"
+fn local_clock(time: &str) -> String {
+    match DateTime::parse_from_rfc3339(time) {
+        Ok(time) => time.with_timezone(&Local).format("%H:%M").to_string(),
+        Err(_) => time.to_string(),
+    }
+}
+
+fn condition(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    match args.subcommand() {
+        Some(("add", args)) => {
+            let patient_id = args.get_one::<String>("patient_id").unwrap();
+            let key = args.get_one::<String>("key").unwrap();
+            let condition = NewCondition {
+                icd10: args.get_one::<String>("icd10").unwrap().clone(),
+                snomed: args.get_one::<String>("snomed").cloned(),
+                clinical_status: args.get_one::<String>("status").unwrap().clone(),
+                verification_status: args.get_one::<String>("verification").unwrap().clone(),
+                onset: args.get_one::<String>("onset").map(|text| parse_clinical_time(text)).transpose()?,
+                recorded: clinical_time(args)?,
+                note: args.get_one::<String>("note").cloned(),
+            };
+            load_existing(emr, patient_id, key)?;
+
+            let id = emr.add_condition(patient_id, &condition)?;
+            let code = emr.conditions(patient_id)?.into_iter()
+                .find(|c| c.id == id)
+                .map(|c| c.code.clone())
+                .ok_or_else(|| anyhow!("Condition {} was not added", id))?;
+            emr.commit_changes(patient_id, &format!("Added condition: {} ({})", code.display, code.code))?;
+            emr.save_patient(patient_id, key)?;
+
+            println!("Added {} ({}) to the problem list of patient {}", code.display, code.code, patient_id);
+        }
+        Some(("resolve", args)) => {
+            let patient_id = args.get_one::<String>("patient_id").unwrap();
+            let condition = args.get_one::<String>("condition").unwrap();
+            let key = args.get_one::<String>("key").unwrap();
+            let abated = clinical_time(args)?;
+            load_existing(emr, patient_id, key)?;
+
+            emr.resolve_condition(patient_id, condition, abated)?;
+            emr.commit_changes(patient_id, &format!("Resolved condition: {}", condition))?;
+            emr.save_patient(patient_id, key)?;
+
+            println!("Resolved {} for patient {}", condition, patient_id);
+        }
+        Some(("list", args)) => {
+            let patient_id = args.get_one::<String>("patient_id").unwrap();
+            let key = args.get_one::<String>("key").unwrap();
+            load_existing(emr, patient_id, key)?;
+
+            let all = args.get_flag("all");
+            let conditions: Vec<_> = emr.conditions(patient_id)?.into_iter()
+                .filter(|c| all || conditions::is_current(c))
+                .collect();
+            if conditions.is_empty() {
+                println!("No {} for patient {}", if all { "problems" } else { "current problems" }, patient_id);
+            }
+            for condition in conditions {
+                print_condition(condition);
+            }
+        }
+        Some(("search", args)) => {
+            let text = args.get_one::<String>("text").unwrap();
+            let (table, system) = if args.get_flag("snomed") {
+                (&emr.condition_codes.snomed, "SNOMED CT")
+            } else {
+                (&emr.condition_codes.icd10, "ICD-10-CM")
+            };
+            let codes = table.search(text, 20);
+            if codes.is_empty() {
+                return Err(anyhow!("No {} code matches {}", system, text));
+            }
+            for code in codes {
+                println!("{:<10} {}", code.code, code.display);
+            }
+        }
+        Some(("import", args)) => {
+            let path = args.get_one::<String>("path").unwrap();
+            let table = CodeTable::import_icd10(path)?;
+            table.save(conditions::ICD10_FILE)?;
+            println!("Imported {} ICD-10-CM codes to {}", table.len(), conditions::ICD10_FILE);
+        }
+        _ => return Err(anyhow!("Usage: emr_cli condition <add|resolve|list|search|import> ...")),
+    }
+    Ok(())
+}
+
+fn print_condition(condition: &Condition) {
+    let mut line = format!("  {} {} ({}, {})", condition.code.code, condition.code.display,
+                           condition.clinical_status, condition.verification_status);
+    if let Some(onset) = &condition.onset_date_time {
+        line.push_str(&format!(", onset {}", onset));
+    }
+    if let Some(abatement) = &condition.abatement_date_time {
+        line.push_str(&format!(", resolved {}", abatement));
+    }
+    if let Some(snomed) = &condition.snomed {
+        line.push_str(&format!(" [SNOMED CT {}]", snomed.code));
+    }
+    println!("{}", line);
+    for note in condition.note.iter().flatten() {
+        println!("    Note: {}", note.text);
+    }
+}
+
+// Load a patient's .med file, which must exist
+fn load_existing(emr: &mut EMR, patient_id: &str, key: &str) -> Result<()> {
+    let filename = med_filename(patient_id);
+    if Path::new(&filename).exists() {
+        emr.load_patient(&filename, key)?;
+        Ok(())
+    } else {
+        Err(anyhow!("Patient file not found: {}", filename))
+    }
+}
+
 fn prescribe_medication(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
     let patient_id = args.get_one::<String>("patient_id").unwrap();
     let medication = args.get_one::<String>("medication").unwrap();
     let dose_mg = args.get_one::<f64>("dose_mg").unwrap();
     let frequency = args.get_one::<String>("frequency").unwrap();
     let key = args.get_one::<String>("key").unwrap();
+    let authored = clinical_time(args)?;
+
+    // Check the medication before touching the patient file
+    let drug = match emr.drugs.resolve(medication) {
+        Ok(resolved) => Some(resolved.drug),
+        Err(_) if args.get_flag("unlisted") => None,
+        Err(e) => return Err(anyhow!("{}\nUse --unlisted with --override-reason to prescribe it anyway", e)),
+    };
+    let alerts = emr.check_dose(medication, *dose_mg, frequency)?;
     
     // Load patient first
-    let filename = format!("patient_{}.med", patient_id);
+    let filename = med_filename(patient_id);
     if Path::new(&filename).exists() {
         emr.load_patient(&filename, key)?;
     } else {
         return Err(anyhow!("Patient file not found: {}", filename));
     }
     
+    let interactions = emr.verify_interactions(patient_id, drug.as_ref().map_or(medication, |drug| &drug.name))?;
+    let allergies = emr.check_allergies(patient_id, drug.as_ref().map_or(medication, |drug| &drug.name))?;
+    
     // Prescribe medication
-    emr.prescribe_medication(patient_id, medication, *dose_mg, frequency)?;
-    emr.commit_changes(patient_id, &format!("Prescribed {} {}mg {}", medication, dose_mg, frequency))?;
+    let options = PrescribeOptions {
+        authored,
+        unlisted: drug.is_none(),
+        override_reason: args.get_one::<String>("override_reason").cloned(),
+    };
+    let described = match &drug {
+        Some(drug) => {
+            emr.prescribe_medication_with(patient_id, &drug.name, *dose_mg, frequency, &options)?;
+            format!("{} (RxNorm {})", drug.name, drug.rxcui)
+        }
+        None => {
+            emr.prescribe_medication_with(patient_id, medication, *dose_mg, frequency, &options)?;
+            format!("{} (not in RxNorm)", medication)
+        }
+    };
+    emr.commit_changes(patient_id, &format!("Prescribed {} {}mg {}", described, dose_mg, frequency))?;
     emr.save_patient(patient_id, key)?;
     
-    println!("Prescribed {} {}mg {} to patient {}", medication, dose_mg, frequency, patient_id);
+    for alert in &alerts {
+        println!("Overridden: {}", alert);
+    }
+    for alert in &allergies {
+        if alert.action == AllergyAction::RequireOverride {
+            println!("Overridden: {}", alert);
+        } else {
+            println!("Warning: {}", alert);
+        }
+    }
+    for alert in &interactions {
+        if alert.is_severe() {
+            println!("Overridden: {}", alert);
+        } else {
+            println!("Warning: {}", alert);
+        }
+    }
+    println!("Prescribed {} {}mg {} to patient {}", described, dose_mg, frequency, patient_id);
+    Ok(())
+}
+
+fn calculate_dose(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let patient_id = args.get_one::<String>("patient_id").unwrap();
+    let medication = args.get_one::<String>("medication").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+    let (amount, basis) = match (args.get_one::<f64>("per_kg"), args.get_one::<f64>("per_m2")) {
+        (Some(amount), _) => (*amount, DoseBasis::Weight),
+        (_, Some(amount)) => (*amount, DoseBasis::Bsa),
+        _ => return Err(anyhow!("Give the dose with --per-kg or --per-m2")),
+    };
+    let order = DoseOrder { amount, unit: args.get_one::<String>("unit").unwrap().clone(), basis };
+    let at = clinical_time(args)?;
+    load_existing(emr, patient_id, key)?;
+
+    let calculation = match args.get_one::<String>("prescribe") {
+        Some(frequency) => {
+            let options = PrescribeOptions {
+                authored: at,
+                unlisted: false,
+                override_reason: args.get_one::<String>("override_reason").cloned(),
+            };
+            let (_, calculation) = emr.prescribe_calculated_dose(patient_id, medication, &order, frequency, &options)?;
+            emr.commit_changes(patient_id, &format!("Prescribed {} {} {} {} ({})", medication, calculation.dose,
+                                                    calculation.unit, frequency, order))?;
+            emr.save_patient(patient_id, key)?;
+            calculation
+        }
+        None => emr.calculate_dose(patient_id, medication, &order, at)?,
+    };
+    print_dose_calculation(&calculation);
+    match args.get_one::<String>("prescribe") {
+        Some(frequency) => println!("Prescribed {} {} {} {} to patient {}", medication, calculation.dose,
+                                    calculation.unit, frequency, patient_id),
+        None => println!("Dose: {} {} of {}", calculation.dose, calculation.unit, medication),
+    }
+    Ok(())
+}
+
+fn print_dose_calculation(calculation: &DoseCalculation) {
+    println!("  Calculated {} from:", calculation.order);
+    for input in &calculation.inputs {
+        println!("    {}", input);
+    }
+    for step in &calculation.steps {
+        println!("    {}", step);
+    }
+}
+
+fn check_interactions(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let patient_id = args.get_one::<String>("patient_id").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+
+    load_existing(emr, patient_id, key)?;
+
+    let alerts = match args.get_one::<String>("medication") {
+        Some(medication) => {
+            let drug = emr.drugs.resolve(medication)?.drug;
+            emr.verify_interactions(patient_id, &drug.name)?
+        }
+        None => emr.check_interactions(patient_id)?,
+    };
+    if alerts.is_empty() {
+        println!("No interactions found for patient {}", patient_id);
+    }
+    for alert in &alerts {
+        println!("{}", alert);
+    }
     Ok(())
 }
 
+fn rxnorm(emr: &EMR, args: &ArgMatches) -> Result<()> {
+    match args.subcommand() {
+        Some(("import", args)) => {
+            let path = args.get_one::<String>("path").unwrap();
+            let dictionary = DrugDictionary::import_rrf(path)?;
+            dictionary.save(rxnorm::RXNORM_FILE)?;
+            println!("Imported {} RxNorm concepts to {}", dictionary.drugs.len(), rxnorm::RXNORM_FILE);
+        }
+        Some(("search", args)) => {
+            let name = args.get_one::<String>("name").unwrap();
+            match emr.drugs.resolve(name) {
+                Ok(resolved) => {
+                    println!("{} (RxNorm {}, {})", resolved.drug.name, resolved.drug.rxcui, resolved.drug.tty);
+                    for ingredient in resolved.ingredients.iter().filter(|i| i.rxcui != resolved.drug.rxcui) {
+                        println!("  Ingredient: {} (RxNorm {})", ingredient.name, ingredient.rxcui);
+                    }
+                    if let Some(strength) = resolved.strength {
+                        println!("  Strength: {}", strength);
+                    }
+                    if let Some(form) = resolved.form {
+                        println!("  Dose form: {}", form);
+                    }
+                }
+                Err(_) => {
+                    let suggestions = emr.drugs.suggest(name, 10);
+                    if suggestions.is_empty() {
+                        return Err(anyhow!("No medication matches {}", name));
+                    }
+                    println!("{} is not in the dictionary. Close matches:", name);
+                    for drug in suggestions {
+                        println!("  {} (RxNorm {}, {})", drug.name, drug.rxcui, drug.tty);
+                    }
+                }
+            }
+        }
+        _ => return Err(anyhow!("Usage: emr_cli rxnorm <import|search> ...")),
+    }
+    Ok(())
+}
+
+// The `--at` time, or now if it was not given
+fn clinical_time(args: &ArgMatches) -> Result<DateTime<Utc>> {
+    match args.get_one::<String>("at") {
+        Some(text) => {
+            let time = parse_clinical_time(text)?;
+            validate_clinical_time(time)?;
+            Ok(time)
+        }
+        None => Ok(Utc::now()),
+    }
+}
+
 fn connect_device(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
     let patient_id = args.get_one::<String>("patient_id").unwrap();
     let device_type = args.get_one::<String>("device_type").unwrap();
     let key = args.get_one::<String>("key").unwrap();
     
     // Load patient first
-    let filename = format!("patient_{}.med", patient_id);
+    let filename = med_filename(patient_id);
     if Path::new(&filename).exists() {
         emr.load_patient(&filename, key)?;
     } else {
@@ -194,4 +1089,407 @@ fn connect_device(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
     emr.save_patient(patient_id, key)?;
     
     println!("Connected device {} to patient {}", device_type, patient_id);
-    Ok(())
\ No newline at end of file
+    Ok(())
+}
+
+fn load_patient(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let filename = args.get_one::<String>("filename").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+    
+    let med_file = MedFile::read(filename)?;
+    let patient_id = emr.load_patient(filename, key)?;
+    println!("Loaded patient {} from {}", patient_id, filename);
+    if !med_file.is_current() {
+        println!("Note: {} is in .med format {}; it will be saved in format {}, or run emr_cli migrate to convert it now",
+                 filename, med_file.format, MED_FORMAT_VERSION);
+    }
+    
+    // Display basic info
+    if let Some(bundle) = emr.bundles.get(&patient_id) {
+        if let Some(BundleEntry { resource: Resource::Patient(patient), .. }) = bundle.entry.first() {
+            if let Some(name) = patient.name.first() {
+                let given = name.given.join(" ");
+                let family = name.family.clone().unwrap_or_default();
+                println!("Name: {} {}", given, family);
+                println!("Gender: {}", patient.gender);
+                println!("Birth date: {}", patient.birth_date);
+            }
+        }
+
+        let allergies = emr.allergies(&patient_id)?;
+        if allergies.is_empty() {
+            println!("Allergies: none recorded");
+        } else {
+            println!("Allergies:");
+            for allergy in allergies {
+                let reactions: Vec<&str> = allergy.reaction.iter().map(|r| r.manifestation.as_str()).collect();
+                println!("  {} ({}, criticality {}, {}){}", allergy.code.display, allergy.allergy_type,
+                         allergy.criticality, allergy.verification_status,
+                         if reactions.is_empty() { String::new() } else { format!(": {}", reactions.join(", ")) });
+            }
+        }
+
+        let problems: Vec<_> = emr.conditions(&patient_id)?.into_iter().filter(|c| conditions::is_current(c)).collect();
+        if problems.is_empty() {
+            println!("Problems: none recorded");
+        } else {
+            println!("Problems:");
+            for condition in problems {
+                print_condition(condition);
+            }
+        }
+        
+        println!("Version history:");
+        for (i, version) in bundle.version_history.iter().enumerate() {
+            println!("  {}: {}", i+1, version);
+        }
+        println!("{}", history::verify_chain(bundle));
+        let warnings = emr.signature_warnings(&patient_id)?;
+        if !warnings.is_empty() {
+            println!("Warning: {}", warnings);
+        }
+    }
+    
+    Ok(())
+}
+
+fn print_version(number: usize, version: &VersionEntry, signature: &SignatureStatus) {
+    println!("{:>4}  {}  {}  {}{}", number, history::short_hash(&version.hash),
+             version.timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"), version.message,
+             if version.author.is_empty() { String::new() } else { format!(" (by {})", version.author) });
+    if signature.is_warning() {
+        println!("      Warning: {}", signature);
+    } else {
+        println!("      {}", signature);
+    }
+}
+
+fn signing_key(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    match args.subcommand() {
+        Some(("generate", args)) => {
+            let clinician = args.get_one::<String>("clinician").unwrap();
+            let passphrase = args.get_one::<String>("passphrase").unwrap();
+            let signer = Signer::generate(clinician, passphrase, &mut emr.clinicians)?;
+            println!("Generated a signing key for {} in {}", clinician, signing::key_path(clinician).display());
+            println!("Public key: {}", signer.public_key());
+        }
+        Some(("list", _)) => {
+            if emr.clinicians.clinicians.is_empty() {
+                println!("No clinician keys registered");
+            }
+            for (clinician, public_key) in &emr.clinicians.clinicians {
+                println!("{}  {}", clinician, public_key);
+            }
+        }
+        _ => return Err(anyhow!("Usage: emr_cli key <generate|list> ...")),
+    }
+    Ok(())
+}
+
+fn migrate_patient(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let filename = args.get_one::<String>("filename").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+
+    let format = emr.migrate_patient(filename, key)?;
+    if format == MED_FORMAT_VERSION {
+        println!("{} is already in .med format {}", filename, MED_FORMAT_VERSION);
+    } else {
+        println!("Migrated {} from .med format {} to {}", filename, format, MED_FORMAT_VERSION);
+    }
+    Ok(())
+}
+
+fn verify_history(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let filename = args.get_one::<String>("filename").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+
+    let patient_id = emr.load_patient(filename, key)?;
+    let report = emr.verify_history(&patient_id)?;
+    emr.log_audit(&format!("Verified {}: {}", filename, report), &patient_id)?;
+    let signatures = emr.signatures(&patient_id)?;
+    let warnings = emr.signature_warnings(&patient_id)?;
+    if let Some((index, _)) = report.broken {
+        let history = emr.history(&patient_id)?;
+        if index > 0 {
+            println!("Last good version:");
+            print_version(index, &history[index - 1], &signatures[index - 1]);
+        }
+        println!("First broken version:");
+        print_version(index + 1, &history[index], &signatures[index]);
+        if !warnings.is_empty() {
+            println!("Warning: {}", warnings);
+        }
+        return Err(anyhow!("{}", report));
+    }
+    println!("{}", report);
+    if warnings.is_empty() {
+        println!("Every version is signed by its clinician");
+        return Ok(());
+    }
+    println!("Warning: {}", warnings);
+    for (i, (version, signature)) in emr.history(&patient_id)?.iter().zip(&signatures).enumerate() {
+        if signature.is_warning() {
+            print_version(i + 1, version, signature);
+        }
+    }
+    // Unsigned versions are expected in older records; bad signatures are not
+    if signatures.iter().any(SignatureStatus::is_untrusted) {
+        return Err(anyhow!("{} has versions whose signatures cannot be trusted", filename));
+    }
+    Ok(())
+}
+
+fn history_log(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let patient_id = args.get_one::<String>("patient_id").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+    load_existing(emr, patient_id, key)?;
+
+    let signatures = emr.signatures(patient_id)?;
+    for (i, version) in emr.history(patient_id)?.iter().enumerate().rev() {
+        print_version(i + 1, version, &signatures[i]);
+    }
+    let warnings = emr.signature_warnings(patient_id)?;
+    if !warnings.is_empty() {
+        println!("Warning: {}", warnings);
+    }
+    Ok(())
+}
+
+fn show_version(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let patient_id = args.get_one::<String>("patient_id").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+    load_existing(emr, patient_id, key)?;
+
+    let index = emr.find_version(patient_id, args.get_one::<String>("version").unwrap())?;
+    let version = &emr.history(patient_id)?[index];
+    println!("Version {}", index + 1);
+    println!("Hash:    {}", version.hash);
+    println!("Date:    {}", version.timestamp.to_rfc3339());
+    if !version.author.is_empty() {
+        println!("Author:  {}", version.author);
+    }
+    println!("Signature: {}", emr.clinicians.verify(version));
+    println!("Message: {}", version.message);
+    let changes = emr.version_changes(patient_id, index)?;
+    if changes.is_empty() {
+        println!("No changes to the record");
+    }
+    for change in &changes {
+        println!("  {}", change);
+    }
+    Ok(())
+}
+
+fn diff_versions(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let patient_id = args.get_one::<String>("patient_id").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+    load_existing(emr, patient_id, key)?;
+
+    let from = emr.find_version(patient_id, args.get_one::<String>("from").unwrap())?;
+    let to = emr.find_version(patient_id, args.get_one::<String>("to").unwrap())?;
+    let changes = emr.diff_versions(patient_id, from, to)?;
+    println!("Changes from version {} to version {}:", from + 1, to + 1);
+    if changes.is_empty() {
+        println!("  none");
+    }
+    for change in &changes {
+        println!("  {}", change);
+    }
+    Ok(())
+}
+
+fn revert_version(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let patient_id = args.get_one::<String>("patient_id").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+    let reason = args.get_one::<String>("reason").unwrap();
+    load_existing(emr, patient_id, key)?;
+
+    let index = emr.find_version(patient_id, args.get_one::<String>("version").unwrap())?;
+    let head = emr.history(patient_id)?.len() - 1;
+    let changes = emr.diff_versions(patient_id, head, index)?;
+    let message = emr.revert_to_version(patient_id, index, reason)?;
+    emr.save_patient(patient_id, key)?;
+    println!("{}", message);
+    for change in &changes {
+        println!("  {}", change);
+    }
+    Ok(())
+}
+
+fn run_script(emr: &mut EMR, args: &ArgMatches) -> Result<()> {
+    let path = args.get_one::<String>("script").unwrap();
+    let key = args.get_one::<String>("key").unwrap();
+    let dry_run = args.get_flag("dry_run");
+
+    let (source, program) = lang::parse_file(path)?;
+    let error_count = report_diagnostics(&source, &program, path);
+    if error_count > 0 {
+        return Err(anyhow!("Script {} has {} error(s); no records were changed", path, error_count));
+    }
+
+    let plan = match lang::interp::plan(&program, emr) {
+        Ok(plan) => plan,
+        Err(diagnostics) => {
+            return Err(anyhow!("{}\nScript {} cannot be run",
+                               lang::diagnostic::render_all(&diagnostics, &source, path), path));
+        }
+    };
+
+    for warning in &plan.warnings {
+        eprintln!("{}", warning.render(&source, path));
+    }
+
+    if dry_run {
+        println!("Dry run of {}: {} planned change(s), nothing will be written", path, plan.actions.len());
+        for action in &plan.actions {
+            println!("  {}", action);
+        }
+        return Ok(());
+    }
+
+    lang::interp::execute(emr, &plan, key)?;
+    println!("Ran {}: applied {} change(s)", path, plan.actions.len());
+    Ok(())
+}
+
+fn check_script(args: &ArgMatches) -> Result<()> {
+    let path = args.get_one::<String>("script").unwrap();
+    let (source, program) = lang::parse_file(path)?;
+    let error_count = report_diagnostics(&source, &program, path);
+    if error_count > 0 {
+        return Err(anyhow!("Script {} has {} error(s)", path, error_count));
+    }
+    println!("No errors in {}", path);
+    Ok(())
+}
+
+// Print the checker's diagnostics for a parsed script and count the errors
+fn report_diagnostics(source: &str, program: &lang::Program, path: &str) -> usize {
+    // Patients that already have a record on disk may be used without a `record` block
+    let known_patients: HashSet<String> = patient_refs(program).into_iter()
+        .filter(|id| Path::new(&med_filename(id)).exists())
+        .collect();
+    let diagnostics = lang::check::check(program, &known_patients);
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic.render(source, path));
+    }
+    diagnostics.iter().filter(|d| d.is_error()).count()
+}
+
+fn format_scripts(args: &ArgMatches) -> Result<()> {
+    let check = args.get_flag("check");
+    let mut unformatted = 0;
+
+    for path in args.get_many::<String>("scripts").unwrap() {
+        let source = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read script {}: {}", path, e))?;
+        let formatted = lang::printer::format_source(&source).map_err(|diagnostics| {
+            anyhow!("{}\nCannot format {}", lang::diagnostic::render_all(&diagnostics, &source, path), path)
+        })?;
+        if formatted == source {
+            continue;
+        }
+        unformatted += 1;
+        if check {
+            println!("Not formatted: {}", path);
+        } else {
+            fs::write(path, formatted)?;
+            println!("Formatted {}", path);
+        }
+    }
+
+    if check && unformatted > 0 {
+        return Err(anyhow!("{} script(s) are not formatted; run `emr_cli fmt` to fix them", unformatted));
+    }
+    Ok(())
+}
+
+fn repl(emr: &mut EMR) -> Result<()> {
+    let mut session = lang::repl::Session::new();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    println!("Charcot REPL - type `help` for commands");
+
+    loop {
+        print!("{}", session.prompt());
+        io::stdout().flush()?;
+        let Some(line) = lines.next() else {
+            // End of input: don't lose work silently
+            println!();
+            if let Err(e) = session.eval(emr, "quit") {
+                eprintln!("Warning: {}", e);
+            }
+            return Ok(());
+        };
+        match session.eval(emr, &line?) {
+            Ok(lang::repl::Outcome::Output(output)) if !output.is_empty() => println!("{}", output),
+            Ok(lang::repl::Outcome::Output(_)) => {}
+            Ok(lang::repl::Outcome::Quit) => return Ok(()),
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    }
+}
+
+// Every patient id a script refers to
+fn patient_refs(program: &lang::Program) -> Vec<String> {
+    program.items.iter()
+        .filter_map(|item| match &item.kind {
+            lang::ast::ItemKind::Block(block) => Some(block.patient.id.clone()),
+            lang::ast::ItemKind::Commit(commit) => Some(commit.patient.id.clone()),
+            lang::ast::ItemKind::Import(_) => None,
+        })
+        .collect()
+}
+
+fn print_usage() {
+    println!("Charcot EMR System");
+    println!("Usage:");
+    println!("  emr_cli create-patient <id> <given_name> <family_name> <gender> <birth_date> <key>");
+    println!("  emr_cli add-vital <patient_id> bp <systolic> <diastolic> <key> [--at <datetime>]");
+    println!("  emr_cli add-vital <patient_id> <hr|temp|spo2|rr|weight|height> <value>[unit] <key> [--at <datetime>]");
+    println!("  emr_cli add-lab <patient_id> <test>=<value>[unit]... <key> [--at <datetime>] [--note <text>] [--report <title>]");
+    println!("  emr_cli prescribe <patient_id> <medication> <dose_mg> <frequency> <key> [--at <datetime>] [--unlisted] [--override-reason <text>]");
+    println!("  emr_cli calculate-dose <patient_id> <medication> <key> (--per-kg <amount> | --per-m2 <amount>) [--unit <mg|units>]");
+    println!("          [--prescribe <frequency>] [--override-reason <text>] [--at <datetime>]");
+    println!("  emr_cli medication list <patient_id> <key> [--all]");
+    println!("  emr_cli medication <stop|hold|renew> <patient_id> <medication|request_id> <key> --reason <text> [--by <name>] [--at <datetime>]");
+    println!("  emr_cli medication resume <patient_id> <medication|request_id> <key> --reason <text> [--by <name>] [--at <datetime>]");
+    println!("          [--override-reason <text>]");
+    println!("  emr_cli medication change <patient_id> <medication|request_id> <key> --dose <mg> --frequency <text> --reason <text> [--by <name>]");
+    println!("          [--at <datetime>] [--override-reason <text>]");
+    println!("  emr_cli reconcile <patient_id> <medications.json|medications.csv> <key> [--context <admission|transfer>]");
+    println!("          [--decide <n>=<continue|modify|stop|new>]... [--apply --by <name>] [--override-reason <text>] [--at <datetime>]");
+    println!("  emr_cli administer <patient_id> <medication|request_id> <key> --by <name> [--outcome <given|missed|refused>]");
+    println!("          [--scheduled <datetime>] [--dose <amount>[unit]] [--note <text>] [--at <datetime>]");
+    println!("  emr_cli mar <patient_id> <key> [--date <YYYY-MM-DD>] [--due]");
+    println!("  emr_cli adherence <patient_id> <key> [--days <n>]");
+    println!("  emr_cli add-allergy <patient_id> <substance> <key> [--reaction <text>] [--severity <mild|moderate|severe>]");
+    println!("          [--criticality <low|high|unable-to-assess>] [--category <medication|food|environment|biologic>]");
+    println!("          [--type <allergy|intolerance>] [--status <unconfirmed|confirmed|refuted|entered-in-error>] [--at <datetime>]");
+    println!("  emr_cli condition add <patient_id> <icd10> <key> [--snomed <code>] [--onset <datetime>] [--note <text>] [--at <datetime>]");
+    println!("          [--status <active|recurrence|relapse|inactive|remission|resolved>]");
+    println!("          [--verification <unconfirmed|provisional|differential|confirmed|refuted|entered-in-error>]");
+    println!("  emr_cli condition resolve <patient_id> <icd10|condition_id> <key> [--at <datetime>]");
+    println!("  emr_cli condition list <patient_id> <key> [--all]");
+    println!("  emr_cli condition search <text> [--snomed]");
+    println!("  emr_cli condition import <icd10cm_codes.txt>");
+    println!("  emr_cli check-interactions <patient_id> <key> [--medication <name>]");
+    println!("  emr_cli rxnorm import <RXNCONSO.RRF|dir>");
+    println!("  emr_cli rxnorm search <name>");
+    println!("  emr_cli key generate <clinician> <passphrase>");
+    println!("  emr_cli key list");
+    println!("  emr_cli connect-device <patient_id> <device_type> <key>");
+    println!("  emr_cli load <filename> <key>");
+    println!("  emr_cli migrate <filename> <key>");
+    println!("  emr_cli verify <filename> <key>");
+    println!("  emr_cli log <patient_id> <key>");
+    println!("  emr_cli show <patient_id> <version|hash> <key>");
+    println!("  emr_cli diff <patient_id> <version|hash> <version|hash> <key>");
+    println!("  emr_cli revert <patient_id> <version|hash> <key> --reason <text>");
+    println!("  emr_cli run <script.charcot> <key> [--dry-run]");
+    println!("  emr_cli check <script.charcot>");
+    println!("  emr_cli fmt <script.charcot>... [--check]");
+    println!("  emr_cli repl");
+    println!("Any command takes --user <name> to attribute its commits to someone other than the login name,");
+    println!("or --sign-as <clinician> --passphrase <text> to sign them with the clinician's key");
+}
@@ -0,0 +1,614 @@
+// src/mar.rs
+// Charcot EMR: Medication administration record (MAR)
+//
+// Scheduled doses are not stored. They are generated from the structured
+// timing of each prescription, from when it was written until it was stopped,
+// skipping any time it was on hold. Timings that name no clock times use the
+// ward's standard administration times (e.g. twice daily at 09:00 and 21:00).
+// Each dose given, missed or refused is recorded as a MedicationAdministration
+// against its scheduled time; as-needed doses have no schedule and are
+// recorded when given.
+
+use std::fmt;
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use uuid::Uuid;
+use anyhow::{Result, anyhow};
+
+use crate::{
+    Annotation, BundleEntry, EMR, MedicationAdministration, MedicationRequest, Quantity, Reference, Repeat,
+    Resource, units, validate_clinical_time,
+};
+
+// A scheduled dose may be given this long before or after its time
+pub const ADMINISTRATION_WINDOW_MINUTES: i64 = 60;
+// Unrecorded doses stay due for this long after their time
+pub const OVERDUE_HOURS: i64 = 24;
+// Upper bound on the doses generated for one prescription in one query
+const MAX_DOSES: usize = 10_000;
+
+// Clock times for FHIR EventTiming codes
+const EVENT_TIMES: &[(&str, &[&str])] = &[
+    ("WAKE", &["07:00"]),
+    ("MORN", &["08:00"]),
+    ("AFT", &["14:00"]),
+    ("EVE", &["18:00"]),
+    ("NIGHT", &["22:00"]),
+    ("HS", &["22:00"]),
+    ("AC", &["07:30", "11:30", "17:30"]),
+    ("C", &["08:00", "12:00", "18:00"]),
+    ("PC", &["08:30", "12:30", "18:30"]),
+    ("ACM", &["07:30"]),
+    ("CM", &["08:00"]),
+    ("PCM", &["08:30"]),
+    ("ACD", &["11:30"]),
+    ("CD", &["12:00"]),
+    ("PCD", &["12:30"]),
+    ("ACV", &["17:30"]),
+    ("CV", &["18:00"]),
+    ("PCV", &["18:30"]),
+];
+
+// Standard times for one to four doses a day
+const STANDARD_TIMES: &[&[&str]] = &[
+    &["09:00"],
+    &["09:00", "21:00"],
+    &["08:00", "14:00", "22:00"],
+    &["08:00", "12:00", "16:00", "20:00"],
+];
+
+// Round-the-clock intervals ("every 6 hours") start from this time of day
+const INTERVAL_START: &str = "06:00";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdministrationOutcome {
+    Given,
+    Missed,
+    Refused,
+}
+
+impl AdministrationOutcome {
+    pub fn parse(text: &str) -> Result<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "given" => Ok(AdministrationOutcome::Given),
+            "missed" => Ok(AdministrationOutcome::Missed),
+            "refused" => Ok(AdministrationOutcome::Refused),
+            other => Err(anyhow!("Invalid administration outcome: {}. Expected given, missed or refused", other)),
+        }
+    }
+
+    // Outcome of a recorded administration
+    pub fn of(administration: &MedicationAdministration) -> Option<Self> {
+        match (administration.status.as_str(), administration.status_reason.as_deref()) {
+            ("completed", _) => Some(AdministrationOutcome::Given),
+            ("not-done", Some("refused")) => Some(AdministrationOutcome::Refused),
+            ("not-done", _) => Some(AdministrationOutcome::Missed),
+            _ => None,
+        }
+    }
+
+    fn status(self) -> &'static str {
+        match self {
+            AdministrationOutcome::Given => "completed",
+            AdministrationOutcome::Missed | AdministrationOutcome::Refused => "not-done",
+        }
+    }
+
+    fn reason(self) -> Option<&'static str> {
+        match self {
+            AdministrationOutcome::Given => None,
+            AdministrationOutcome::Missed => Some("missed"),
+            AdministrationOutcome::Refused => Some("refused"),
+        }
+    }
+}
+
+impl fmt::Display for AdministrationOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            AdministrationOutcome::Given => "given",
+            AdministrationOutcome::Missed => "missed",
+            AdministrationOutcome::Refused => "refused",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+// When a prescription's doses fall
+#[derive(Debug, Clone, PartialEq)]
+enum Schedule {
+    // At these local times on every `every_days`th day from the first
+    Daily { times: Vec<NaiveTime>, every_days: i64 },
+    // Every so often from when the prescription was written
+    Interval(Duration),
+}
+
+fn clock(text: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(text, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(text, "%H:%M"))
+        .ok()
+}
+
+fn period_minutes(period: f64, unit: &str) -> Option<f64> {
+    let minutes = match unit {
+        "min" => 1.0,
+        "h" => 60.0,
+        "d" => 1440.0,
+        "wk" => 10080.0,
+        "mo" => 43200.0,
+        _ => return None,
+    };
+    Some(period * minutes)
+}
+
+// The dose schedule of a prescription, or None for as-needed and
+// unscheduled ones
+fn schedule(request: &MedicationRequest) -> Option<Schedule> {
+    let dosage = request.dosage_instruction.first()?;
+    if dosage.as_needed_boolean == Some(true) {
+        return None;
+    }
+    let repeat: &Repeat = dosage.timing.repeat.as_ref()?;
+    let fixed: Vec<NaiveTime> = repeat.time_of_day.iter().flatten().filter_map(|t| clock(t)).collect();
+    let events: Vec<NaiveTime> = repeat.when.iter().flatten()
+        .filter_map(|code| EVENT_TIMES.iter().find(|(event, _)| event == code))
+        .flat_map(|(_, times)| times.iter().filter_map(|t| clock(t)))
+        .collect();
+
+    // Minutes between doses; "every 4-6 hours" is scheduled at the shortest
+    let interval = match (repeat.period, repeat.period_unit.as_deref()) {
+        (Some(period), Some(unit)) => period_minutes(period, unit)? / repeat.frequency.unwrap_or(1).max(1) as f64,
+        _ => 1440.0 / fixed.len().max(events.len()).max(1) as f64,
+    };
+    // Doses under a minute apart cannot be scheduled, and are not valid sigs
+    if interval.is_nan() || interval < 1.0 {
+        return None;
+    }
+    let per_day = 1440.0 / interval;
+
+    if interval >= 1440.0 {
+        // Once every day or more, at the first time given
+        let every_days = (interval / 1440.0).round().max(1.0) as i64;
+        let time = fixed.first().or(events.first()).copied().or_else(|| clock(STANDARD_TIMES[0][0]))?;
+        return Some(Schedule::Daily { times: vec![time], every_days });
+    }
+    if !fixed.is_empty() {
+        return Some(Schedule::Daily { times: fixed, every_days: 1 });
+    }
+    if per_day.fract() != 0.0 {
+        return Some(Schedule::Interval(Duration::minutes(interval.round() as i64)));
+    }
+    let per_day = per_day as usize;
+    if !events.is_empty() {
+        // "twice daily with meals" is given with breakfast and dinner
+        let mut times = events;
+        times.sort();
+        times.dedup();
+        let times = match (per_day, times.len()) {
+            (1, n) if n > 1 => vec![times[0]],
+            (2, n) if n > 2 => vec![times[0], times[n - 1]],
+            _ => times,
+        };
+        return Some(Schedule::Daily { times, every_days: 1 });
+    }
+    let daily = repeat.period_unit.as_deref() == Some("d") || repeat.period_unit.as_deref() == Some("wk");
+    if daily && per_day <= STANDARD_TIMES.len() {
+        let times = STANDARD_TIMES[per_day - 1].iter().filter_map(|t| clock(t)).collect();
+        return Some(Schedule::Daily { times, every_days: 1 });
+    }
+    let start = clock(INTERVAL_START)?;
+    let mut times: Vec<NaiveTime> = (0..per_day as i64)
+        .map(|i| start + Duration::minutes(i * interval as i64))
+        .collect();
+    times.sort();
+    Some(Schedule::Daily { times, every_days: 1 })
+}
+
+// Times a prescription was active: from when it was written, through holds
+// and resumptions, until it was stopped, completed or replaced
+fn active_periods(request: &MedicationRequest) -> Vec<(DateTime<Utc>, Option<DateTime<Utc>>)> {
+    let Ok(written) = DateTime::parse_from_rfc3339(&request.authored_on) else {
+        return Vec::new();
+    };
+    let mut periods = Vec::new();
+    let mut active_since = Some(written.with_timezone(&Utc));
+    for change in &request.status_history {
+        let Ok(date) = DateTime::parse_from_rfc3339(&change.date) else {
+            continue;
+        };
+        let date = date.with_timezone(&Utc);
+        match (change.status == "active", active_since) {
+            (true, None) => active_since = Some(date),
+            (false, Some(since)) => {
+                periods.push((since, Some(date)));
+                active_since = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(since) = active_since {
+        if request.status == "active" {
+            periods.push((since, None));
+        }
+    }
+    periods
+}
+
+fn active_at(periods: &[(DateTime<Utc>, Option<DateTime<Utc>>)], time: DateTime<Utc>) -> bool {
+    periods.iter().any(|(since, until)| time >= *since && until.is_none_or(|until| time < until))
+}
+
+// Scheduled times of a prescription's doses between `from` and `to`
+fn dose_times(request: &MedicationRequest, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+    let Some(schedule) = schedule(request) else {
+        return Vec::new();
+    };
+    let periods = active_periods(request);
+    let Some(start) = periods.first().map(|(since, _)| *since) else {
+        return Vec::new();
+    };
+    let active = |time: DateTime<Utc>| active_at(&periods, time);
+    let from = from.max(start);
+
+    let mut times = Vec::new();
+    match schedule {
+        Schedule::Daily { times: clock_times, every_days } => {
+            let first_day = start.with_timezone(&Local).date_naive();
+            let mut day = from.with_timezone(&Local).date_naive();
+            let last_day = to.with_timezone(&Local).date_naive();
+            while day <= last_day && times.len() < MAX_DOSES {
+                if (day - first_day).num_days() % every_days == 0 {
+                    for clock_time in &clock_times {
+                        if let Some(time) = local_time(day, *clock_time) {
+                            if time >= from && time <= to && active(time) {
+                                times.push(time);
+                            }
+                        }
+                    }
+                }
+                day = match day.succ_opt() {
+                    Some(next) => next,
+                    None => break,
+                };
+            }
+        }
+        Schedule::Interval(interval) if interval <= Duration::zero() => {}
+        Schedule::Interval(interval) => {
+            let skipped = (from - start).num_minutes().max(0) / interval.num_minutes().max(1);
+            let mut time = start + interval * skipped as i32;
+            while time <= to && times.len() < MAX_DOSES {
+                if time >= from && active(time) {
+                    times.push(time);
+                }
+                time += interval;
+            }
+        }
+    }
+    times
+}
+
+fn local_time(day: NaiveDate, time: NaiveTime) -> Option<DateTime<Utc>> {
+    Local.from_local_datetime(&day.and_time(time)).earliest().map(|t| t.with_timezone(&Utc))
+}
+
+// A dose due at a scheduled time, with what happened to it if recorded
+#[derive(Debug, Clone)]
+pub struct ScheduledDose {
+    pub request_id: String,
+    pub medication: String,
+    pub dose: Option<Quantity>,
+    pub scheduled: DateTime<Utc>,
+    pub administration: Option<MedicationAdministration>,
+}
+
+impl ScheduledDose {
+    pub fn outcome(&self) -> Option<AdministrationOutcome> {
+        self.administration.as_ref().and_then(AdministrationOutcome::of)
+    }
+}
+
+// Doses given against the doses scheduled for one prescription
+#[derive(Debug, Clone, PartialEq)]
+pub struct Adherence {
+    pub request_id: String,
+    pub medication: String,
+    pub scheduled: usize,
+    pub given: usize,
+    pub missed: usize,
+    pub refused: usize,
+}
+
+impl Adherence {
+    pub fn unrecorded(&self) -> usize {
+        self.scheduled - self.given - self.missed - self.refused
+    }
+
+    // Percentage of scheduled doses given; None if none were scheduled
+    pub fn percent(&self) -> Option<f64> {
+        (self.scheduled > 0).then(|| self.given as f64 * 100.0 / self.scheduled as f64)
+    }
+}
+
+impl fmt::Display for Adherence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.percent() {
+            Some(percent) => write!(f, "{}: {:.0}% ({} of {} doses given", self.medication, percent, self.given, self.scheduled)?,
+            None => return write!(f, "{}: no doses scheduled", self.medication),
+        }
+        if self.missed > 0 {
+            write!(f, ", {} missed", self.missed)?;
+        }
+        if self.refused > 0 {
+            write!(f, ", {} refused", self.refused)?;
+        }
+        if self.unrecorded() > 0 {
+            write!(f, ", {} not recorded", self.unrecorded())?;
+        }
+        write!(f, ")")
+    }
+}
+
+// A dose to record on the MAR
+#[derive(Debug, Clone)]
+pub struct NewAdministration {
+    pub outcome: AdministrationOutcome,
+    // The scheduled dose this is for; the nearest one to `at` if not given
+    pub scheduled: Option<DateTime<Utc>>,
+    pub at: DateTime<Utc>,
+    // The prescribed dose if not given, in `dose_unit` if that is given and
+    // otherwise in the unit of the prescribed dose, which it must match
+    pub dose: Option<f64>,
+    pub dose_unit: Option<String>,
+    pub performer: String,
+    pub note: Option<String>,
+}
+
+fn ordered_dose(request: &MedicationRequest) -> Option<Quantity> {
+    request.dosage_instruction.first()
+        .and_then(|dosage| dosage.dose_and_rate.first())
+        .and_then(|dose| dose.dose_quantity.clone())
+}
+
+fn same_time(recorded: Option<&str>, time: DateTime<Utc>) -> bool {
+    recorded.and_then(|text| DateTime::parse_from_rfc3339(text).ok()).is_some_and(|t| t == time)
+}
+
+impl EMR {
+    // The patient's recorded administrations
+    pub fn administrations(&self, patient_id: &str) -> Result<Vec<&MedicationAdministration>> {
+        let bundle = self.bundles.get(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        Ok(bundle.entry.iter()
+            .filter_map(|entry| match &entry.resource {
+                Resource::MedicationAdministration(administration) => Some(administration),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // Every scheduled dose between `from` and `to`, in time order, with its
+    // recorded administration if any
+    pub fn scheduled_doses(&self, patient_id: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<ScheduledDose>> {
+        let administrations = self.administrations(patient_id)?;
+        let mut doses = Vec::new();
+        for request in self.medication_requests(patient_id)? {
+            let reference = format!("MedicationRequest/{}", request.id);
+            for scheduled in dose_times(request, from, to) {
+                let administration = administrations.iter()
+                    .find(|a| a.request.reference == reference && same_time(a.scheduled.as_deref(), scheduled))
+                    .map(|a| (*a).clone());
+                doses.push(ScheduledDose {
+                    request_id: request.id.clone(),
+                    medication: request.medication_codeable_concept.display.clone(),
+                    dose: ordered_dose(request),
+                    scheduled,
+                    administration,
+                });
+            }
+        }
+        doses.sort_by_key(|dose| dose.scheduled);
+        Ok(doses)
+    }
+
+    // Doses not yet recorded that are due by `at`: overdue ones from the last
+    // day and those coming up within the administration window
+    pub fn due_doses(&self, patient_id: &str, at: DateTime<Utc>) -> Result<Vec<ScheduledDose>> {
+        let from = at - Duration::hours(OVERDUE_HOURS);
+        let to = at + Duration::minutes(ADMINISTRATION_WINDOW_MINUTES);
+        Ok(self.scheduled_doses(patient_id, from, to)?.into_iter()
+            .filter(|dose| dose.administration.is_none())
+            .collect())
+    }
+
+    // Record a dose given, missed or refused against a prescription, by
+    // MedicationRequest id or medication name. Returns the new
+    // MedicationAdministration's id.
+    pub fn record_administration(&mut self, patient_id: &str, id_or_name: &str,
+                                 administration: &NewAdministration) -> Result<String> {
+        if administration.performer.trim().is_empty() {
+            return Err(anyhow!("Who gave or recorded the dose is required"));
+        }
+        if administration.dose.is_some_and(|dose| dose <= 0.0 || !dose.is_finite()) {
+            return Err(anyhow!("Invalid dose: {}", administration.dose.unwrap_or_default()));
+        }
+        validate_clinical_time(administration.at)?;
+        let request = self.find_medication_request(patient_id, id_or_name)?.clone();
+        let outcome = administration.outcome;
+        let description = format!("{} {}", request.medication_codeable_concept.display,
+                                  request.dosage_instruction.first().map(|d| d.text.as_str()).unwrap_or_default());
+
+        let scheduled = if schedule(&request).is_none() {
+            if outcome != AdministrationOutcome::Given || administration.scheduled.is_some() {
+                return Err(anyhow!("{} has no dose schedule; only doses given can be recorded", description));
+            }
+            if !active_at(&active_periods(&request), administration.at) {
+                return Err(anyhow!("{} was not active at {}", description, administration.at.to_rfc3339()));
+            }
+            None
+        } else {
+            let window = Duration::minutes(ADMINISTRATION_WINDOW_MINUTES);
+            let (from, to) = match administration.scheduled {
+                Some(time) => (time, time),
+                None => (administration.at - window, administration.at + window),
+            };
+            let doses = self.scheduled_doses(patient_id, from, to)?;
+            let dose = doses.iter()
+                .filter(|dose| dose.request_id == request.id && dose.administration.is_none())
+                .min_by_key(|dose| (dose.scheduled - administration.at).num_seconds().abs())
+                .ok_or_else(|| match administration.scheduled {
+                    Some(time) if doses.iter().any(|d| d.request_id == request.id) =>
+                        anyhow!("The {} dose of {} is already recorded", time.to_rfc3339(), description),
+                    Some(time) => anyhow!("No dose of {} is scheduled at {}", description, time.to_rfc3339()),
+                    None => anyhow!("No unrecorded dose of {} is scheduled within {} minutes of {}",
+                                    description, ADMINISTRATION_WINDOW_MINUTES, administration.at.to_rfc3339()),
+                })?;
+            if outcome == AdministrationOutcome::Given && administration.at < dose.scheduled - window {
+                return Err(anyhow!("The {} dose of {} cannot be given more than {} minutes early",
+                                   dose.scheduled.to_rfc3339(), description, ADMINISTRATION_WINDOW_MINUTES));
+            }
+            Some(dose.scheduled)
+        };
+
+        let dose = match (outcome, administration.dose) {
+            (AdministrationOutcome::Given, Some(amount)) => {
+                let ordered = ordered_dose(&request)
+                    .ok_or_else(|| anyhow!("{} has no prescribed dose to give a different one of", description))?;
+                if let Some(unit) = administration.dose_unit.as_deref() {
+                    if units::ucum_code(unit) != ordered.code {
+                        return Err(anyhow!("{} is prescribed in {}; a dose in {} cannot be recorded against it",
+                                           description, ordered.unit, unit));
+                    }
+                }
+                Some(Quantity::ucum(amount, &ordered.unit)?)
+            }
+            (AdministrationOutcome::Given, None) => ordered_dose(&request),
+            _ => None,
+        };
+        let record = MedicationAdministration {
+            id: Uuid::new_v4().to_string(),
+            status: outcome.status().to_string(),
+            status_reason: outcome.reason().map(str::to_string),
+            medication_codeable_concept: request.medication_codeable_concept.clone(),
+            subject: Reference {
+                reference: format!("Patient/{}", patient_id),
+            },
+            request: Reference {
+                reference: format!("MedicationRequest/{}", request.id),
+            },
+            scheduled: scheduled.map(|time| time.to_rfc3339()),
+            effective_date_time: administration.at.to_rfc3339(),
+            performer: Some(administration.performer.trim().to_string()),
+            dose: dose.clone(),
+            note: administration.note.as_ref().map(|text| vec![Annotation { text: text.clone() }]),
+        };
+        let id = record.id.clone();
+
+        let bundle = self.bundles.get_mut(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        bundle.entry.push(BundleEntry {
+            resource_type: "MedicationAdministration".to_string(),
+            resource: Resource::MedicationAdministration(record),
+        });
+
+        let mut event = match (outcome, &dose) {
+            (AdministrationOutcome::Given, Some(dose)) => format!("Administered: {} {} {}",
+                                                                  request.medication_codeable_concept.display, dose.value, dose.unit),
+            (AdministrationOutcome::Given, None) => format!("Administered: {}", request.medication_codeable_concept.display),
+            _ => format!("Dose {}: {}", outcome, description),
+        };
+        if let Some(time) = scheduled {
+            event.push_str(&format!(" (scheduled {})", time.to_rfc3339()));
+        }
+        self.log_audit(&format!("{} at {} by {}", event, administration.at.to_rfc3339(), administration.performer.trim()),
+                       patient_id)?;
+
+        Ok(id)
+    }
+
+    // Adherence to each prescription with doses scheduled between `from` and
+    // `to`. Doses scheduled after now are not counted.
+    pub fn adherence(&self, patient_id: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Adherence>> {
+        let mut adherence: Vec<Adherence> = Vec::new();
+        for dose in self.scheduled_doses(patient_id, from, to.min(Utc::now()))? {
+            let index = match adherence.iter().position(|a| a.request_id == dose.request_id) {
+                Some(index) => index,
+                None => {
+                    adherence.push(Adherence {
+                        request_id: dose.request_id.clone(),
+                        medication: dose.medication.clone(),
+                        scheduled: 0,
+                        given: 0,
+                        missed: 0,
+                        refused: 0,
+                    });
+                    adherence.len() - 1
+                }
+            };
+            let entry = &mut adherence[index];
+            entry.scheduled += 1;
+            match dose.outcome() {
+                Some(AdministrationOutcome::Given) => entry.given += 1,
+                Some(AdministrationOutcome::Missed) => entry.missed += 1,
+                Some(AdministrationOutcome::Refused) => entry.refused += 1,
+                None => {}
+            }
+        }
+        Ok(adherence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Coding, DosageInstruction, Timing};
+
+    fn request(frequency: i32, period: f64, period_unit: &str) -> MedicationRequest {
+        MedicationRequest {
+            id: "request".to_string(),
+            status: "active".to_string(),
+            medication_codeable_concept: Coding { system: String::new(), code: String::new(), display: "test".to_string() },
+            subject: Reference { reference: "Patient/1".to_string() },
+            authored_on: "2025-01-01T00:00:00Z".to_string(),
+            recorded: None,
+            dosage_instruction: vec![DosageInstruction {
+                text: String::new(),
+                timing: Timing {
+                    repeat: Some(Repeat {
+                        frequency: Some(frequency),
+                        period: Some(period),
+                        period_max: None,
+                        period_unit: Some(period_unit.to_string()),
+                        time_of_day: None,
+                        when: None,
+                    }),
+                },
+                as_needed_boolean: None,
+                dose_and_rate: Vec::new(),
+            }],
+            replaces: None,
+            status_history: Vec::new(),
+            dose_calculation: None,
+        }
+    }
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn interval_doses() {
+        let times = dose_times(&request(1, 7.0, "h"), utc("2025-01-02T00:00:00Z"), utc("2025-01-02T23:59:00Z"));
+        assert_eq!(times.len(), 3);
+        assert!(times.windows(2).all(|pair| pair[1] - pair[0] == Duration::hours(7)));
+    }
+
+    #[test]
+    fn sub_minute_intervals_are_not_scheduled() {
+        // Written before such sigs were rejected; these used to hang or
+        // repeat one time thousands of times
+        for (frequency, period, unit) in [(1, 0.35, "min"), (1, 0.5, "min"), (3, 1.0, "min"), (1, 0.0, "h")] {
+            let request = request(frequency, period, unit);
+            assert!(schedule(&request).is_none());
+            assert!(dose_times(&request, utc("2025-01-02T00:00:00Z"), utc("2025-01-03T00:00:00Z")).is_empty());
+        }
+    }
+}
@@ -0,0 +1,307 @@
+// src/med_format.rs
+// Charcot EMR: The encrypted .med file format
+//
+// A format 2 file carries a header in the clear: the format version, how the
+// key is derived from the password (Argon2id, with its salt and costs) and
+// when it was created and last saved. The record is encrypted with
+// AES-256-GCM with the header as associated data, so changing any of it makes
+// the file fail to open. Format 1 files have no header, used a single
+// unsalted SHA-256 of the password as the key and stored the SHA-256 of the
+// record in the clear. They are still read, and are written in the current
+// format the next time they are saved or by `emr_cli migrate`.
+
+use std::fs;
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{Engine as _, engine::general_purpose};
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use anyhow::{Result, anyhow, Context};
+
+use crate::EMR;
+
+pub const MED_FORMAT_VERSION: u32 = 2;
+pub const KDF_ARGON2ID: &str = "argon2id";
+
+const SALT_LEN: usize = 16;
+
+// Costs above these are refused rather than attempted, so a crafted file
+// cannot make opening it take much more memory or time than the defaults
+const MAX_MEMORY_KIB: u32 = 4 * Params::DEFAULT_M_COST;
+const MAX_ITERATIONS: u32 = 4 * Params::DEFAULT_T_COST;
+const MAX_PARALLELISM: u32 = 4 * Params::DEFAULT_P_COST;
+
+fn legacy_format() -> u32 {
+    1
+}
+
+// How the file key is derived from the password
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KdfParams {
+    pub algorithm: String,      // "argon2id"
+    pub salt: String,           // Base64 encoded salt
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    // Argon2id with a fresh salt and the recommended costs
+    pub fn argon2id() -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        KdfParams {
+            algorithm: KDF_ARGON2ID.to_string(),
+            salt: general_purpose::STANDARD.encode(salt),
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+
+    pub fn derive_key(&self, password: &str) -> Result<[u8; 32]> {
+        if self.algorithm != KDF_ARGON2ID {
+            return Err(anyhow!("Unsupported key derivation: {}", self.algorithm));
+        }
+        if self.memory_kib > MAX_MEMORY_KIB || self.iterations > MAX_ITERATIONS || self.parallelism > MAX_PARALLELISM {
+            return Err(anyhow!("Key derivation costs (memory {} KiB, {} iterations, parallelism {}) exceed the limits \
+                                of {} KiB, {} and {}", self.memory_kib, self.iterations, self.parallelism,
+                               MAX_MEMORY_KIB, MAX_ITERATIONS, MAX_PARALLELISM));
+        }
+        let salt = general_purpose::STANDARD.decode(&self.salt)?;
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map_err(|e| anyhow!("Invalid key derivation parameters: {}", e))?;
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), &salt, &mut key)
+            .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+        Ok(key)
+    }
+}
+
+// Encrypted .med file format
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MedFile {
+    #[serde(default = "legacy_format")]
+    pub format: u32,            // Format version; absent, meaning 1, in older files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kdf: Option<KdfParams>, // None in format 1, whose key is the SHA-256 of the password
+    pub iv: String,             // Base64 encoded initialization vector
+    pub data: String,           // Base64 encoded encrypted data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,   // SHA-256 hash of the unencrypted data, in format 1 only
+    pub created: DateTime<Utc>, // Creation timestamp
+    pub modified: DateTime<Utc>, // Last modified timestamp
+}
+
+// The parts of a format 2 file authenticated along with the record
+#[derive(Serialize)]
+struct Header<'a> {
+    format: u32,
+    kdf: &'a Option<KdfParams>,
+    created: &'a DateTime<Utc>,
+    modified: &'a DateTime<Utc>,
+}
+
+impl MedFile {
+    pub fn read(path: &str) -> Result<MedFile> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path))?;
+        let med_file: MedFile = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid .med file {}", path))?;
+        if med_file.format > MED_FORMAT_VERSION {
+            return Err(anyhow!("{} is in .med format {}, newer than this version supports ({})",
+                               path, med_file.format, MED_FORMAT_VERSION));
+        }
+        Ok(med_file)
+    }
+
+    // Write via a temporary file so an interrupted save leaves the old file intact
+    pub fn write(&self, path: &str) -> Result<()> {
+        let temp = format!("{}.tmp", path);
+        fs::write(&temp, serde_json::to_string(self)?)
+            .with_context(|| format!("Failed to write {}", temp))?;
+        fs::rename(&temp, path)
+            .with_context(|| format!("Failed to replace {}", path))
+    }
+
+    pub fn is_current(&self) -> bool {
+        self.format == MED_FORMAT_VERSION
+    }
+
+    fn header(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&Header {
+            format: self.format,
+            kdf: &self.kdf,
+            created: &self.created,
+            modified: &self.modified,
+        })?)
+    }
+
+    // Encrypt `plaintext` with `password` in the current format
+    pub fn seal(plaintext: &[u8], password: &str, created: DateTime<Utc>) -> Result<MedFile> {
+        let kdf = KdfParams::argon2id();
+        let key = kdf.derive_key(password)?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+        let mut med_file = MedFile {
+            format: MED_FORMAT_VERSION,
+            kdf: Some(kdf),
+            iv: general_purpose::STANDARD.encode(nonce),
+            data: String::new(),
+            hash: None,
+            created,
+            modified: Utc::now(),
+        };
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        let encrypted = cipher.encrypt(&nonce, Payload { msg: plaintext, aad: &med_file.header()? })
+            .map_err(|e| anyhow!("Encryption failed: {:?}", e))?;
+        med_file.data = general_purpose::STANDARD.encode(encrypted);
+        Ok(med_file)
+    }
+
+    // Decrypt the record with `password`. Format 1 records are also checked
+    // against their hash.
+    pub fn open(&self, password: &str) -> Result<Vec<u8>> {
+        let iv = general_purpose::STANDARD.decode(&self.iv)?;
+        let encrypted = general_purpose::STANDARD.decode(&self.data)?;
+        if iv.len() != 12 {
+            return Err(anyhow!("Invalid initialization vector"));
+        }
+        let nonce = Nonce::from_slice(&iv);
+
+        let decrypted = match self.format {
+            1 => {
+                let mut key_hasher = Sha256::new();
+                key_hasher.update(password.as_bytes());
+                let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_hasher.finalize()));
+                let decrypted = cipher.decrypt(nonce, encrypted.as_ref())
+                    .map_err(|_| anyhow!("Decryption failed - wrong key or the file has been altered"))?;
+                let mut hasher = Sha256::new();
+                hasher.update(&decrypted);
+                if self.hash.as_deref() != Some(format!("{:x}", hasher.finalize()).as_str()) {
+                    return Err(anyhow!("Hash verification failed - file may be corrupted"));
+                }
+                decrypted
+            }
+            MED_FORMAT_VERSION => {
+                let kdf = self.kdf.as_ref()
+                    .ok_or_else(|| anyhow!("The file header has no key derivation parameters"))?;
+                let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&kdf.derive_key(password)?));
+                cipher.decrypt(nonce, Payload { msg: &encrypted, aad: &self.header()? })
+                    .map_err(|_| anyhow!("Decryption failed - wrong key or the file or its header has been altered"))?
+            }
+            format => return Err(anyhow!("Unsupported .med format {}", format)),
+        };
+        Ok(decrypted)
+    }
+}
+
+impl EMR {
+    // Re-encrypt a .med file in place in the current format. Returns the
+    // format it was in; files already current are left untouched.
+    pub fn migrate_patient(&mut self, filename: &str, key: &str) -> Result<u32> {
+        let format = MedFile::read(filename)?.format;
+        let patient_id = self.load_patient(filename, key)?;
+        if format != MED_FORMAT_VERSION {
+            self.save_patient_as(&patient_id, key, filename)?;
+            self.log_audit(&format!("Migrated {} from .med format {} to {}", filename, format, MED_FORMAT_VERSION),
+                           &patient_id)?;
+        }
+        Ok(format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORD: &[u8] = br#"{"resource_type":"Bundle"}"#;
+
+    // A file as format 1 wrote it
+    fn seal_format_1(plaintext: &[u8], password: &str) -> MedFile {
+        let mut key_hasher = Sha256::new();
+        key_hasher.update(password.as_bytes());
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_hasher.finalize()));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let mut hasher = Sha256::new();
+        hasher.update(plaintext);
+        MedFile {
+            format: 1,
+            kdf: None,
+            iv: general_purpose::STANDARD.encode(nonce),
+            data: general_purpose::STANDARD.encode(cipher.encrypt(&nonce, plaintext).unwrap()),
+            hash: Some(format!("{:x}", hasher.finalize())),
+            created: Utc::now(),
+            modified: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn format_1_round_trips_to_format_2() {
+        let old = seal_format_1(RECORD, "secret");
+        assert!(!old.is_current());
+        let plaintext = old.open("secret").unwrap();
+        assert_eq!(plaintext, RECORD);
+        assert!(old.open("wrong").is_err());
+
+        let new = MedFile::seal(&plaintext, "secret", old.created).unwrap();
+        assert!(new.is_current());
+        assert_eq!(new.created, old.created);
+        assert_eq!(new.open("secret").unwrap(), RECORD);
+        assert!(new.open("wrong").is_err());
+
+        // Format 2 keeps no hash of the record in the clear
+        assert_eq!(new.hash, None);
+        let json = serde_json::to_string(&new).unwrap();
+        assert!(!json.contains("hash"), "{}", json);
+        let read: MedFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.open("secret").unwrap(), RECORD);
+    }
+
+    #[test]
+    fn format_1_record_must_match_its_hash() {
+        let mut old = seal_format_1(RECORD, "secret");
+        old.hash = Some("0".repeat(64));
+        assert!(old.open("secret").unwrap_err().to_string().contains("Hash verification failed"));
+        old.hash = None;
+        assert!(old.open("secret").is_err());
+    }
+
+    #[test]
+    fn modified_header_is_rejected() {
+        let sealed = MedFile::seal(RECORD, "secret", Utc::now()).unwrap();
+        let kdf = |change: fn(&mut KdfParams)| {
+            let mut file = sealed.clone();
+            change(file.kdf.as_mut().unwrap());
+            file
+        };
+        let tampered = [
+            MedFile { created: sealed.created - chrono::Duration::days(1), ..sealed.clone() },
+            MedFile { modified: sealed.modified + chrono::Duration::seconds(1), ..sealed.clone() },
+            kdf(|kdf| kdf.iterations += 1),
+            kdf(|kdf| kdf.salt = general_purpose::STANDARD.encode([0u8; SALT_LEN])),
+        ];
+        for file in &tampered {
+            assert!(file.open("secret").is_err(), "{:?}", file);
+        }
+        // Downgraded to format 1, it is no longer decrypted with the derived key
+        assert!(MedFile { format: 1, ..sealed.clone() }.open("secret").is_err());
+        assert_eq!(sealed.open("secret").unwrap(), RECORD);
+    }
+
+    #[test]
+    fn costs_far_above_the_defaults_are_refused() {
+        let mut kdf = KdfParams::argon2id();
+        kdf.memory_kib = MAX_MEMORY_KIB + 1;
+        assert!(kdf.derive_key("secret").unwrap_err().to_string().contains("exceed the limits"));
+        let mut kdf = KdfParams::argon2id();
+        kdf.iterations = 1000;
+        assert!(kdf.derive_key("secret").is_err());
+    }
+}
@@ -0,0 +1,303 @@
+// src/medications.rs
+// Charcot EMR: Prescription lifecycle - discontinue, hold, resume, modify and renew
+//
+// A MedicationRequest is never edited in place beyond its status. Stopping,
+// holding and resuming change the status and append to its status history;
+// changing the dose or renewing writes a new request that `replaces` the old
+// one. Every change carries a reason, an author and a time, is audited and is
+// committed to the record's version history.
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use anyhow::{Result, anyhow};
+
+use crate::{AllergyAction, BundleEntry, EMR, MedicationRequest, PrescribeOptions, Quantity, Reference, Resource, Signer,
+            StatusChange, parse_sig, validate_clinical_time};
+
+// Statuses of prescriptions the patient is still on
+pub const CURRENT_STATUSES: &[&str] = &["active", "on-hold"];
+
+// Who changed a prescription, when and why
+#[derive(Debug, Clone)]
+pub struct MedicationChange {
+    pub reason: String,
+    pub author: String,
+    pub at: DateTime<Utc>,
+}
+
+impl MedicationChange {
+    pub fn new(reason: &str, author: &str) -> Self {
+        MedicationChange { reason: reason.to_string(), author: author.to_string(), at: Utc::now() }
+    }
+
+    // `signer` signs the commit, so the change must be theirs
+    fn validate(&self, request: &MedicationRequest, signer: Option<&Signer>) -> Result<()> {
+        if self.reason.trim().is_empty() {
+            return Err(anyhow!("A reason is required to change a prescription"));
+        }
+        if self.author.trim().is_empty() {
+            return Err(anyhow!("The author of the change is required"));
+        }
+        if let Some(signer) = signer {
+            if self.author.trim() != signer.clinician {
+                return Err(anyhow!("Signed in as {}; a change by {} cannot be signed with their key",
+                                   signer.clinician, self.author.trim()));
+            }
+        }
+        validate_clinical_time(self.at)?;
+        if DateTime::parse_from_rfc3339(&request.authored_on).is_ok_and(|authored| self.at < authored) {
+            return Err(anyhow!("Change at {} is before the prescription was written ({})",
+                               self.at.to_rfc3339(), request.authored_on));
+        }
+        Ok(())
+    }
+}
+
+pub fn is_current(request: &MedicationRequest) -> bool {
+    CURRENT_STATUSES.contains(&request.status.as_str())
+}
+
+// The prescribed dose, in mg or units
+pub fn dose(request: &MedicationRequest) -> Option<&Quantity> {
+    request.dosage_instruction.first()
+        .and_then(|dosage| dosage.dose_and_rate.first())
+        .and_then(|dose| dose.dose_quantity.as_ref())
+}
+
+// The frequency from dosage text "<dose> <unit> <frequency>"
+pub fn frequency(request: &MedicationRequest) -> Option<&str> {
+    let unit = dose(request).map_or("mg", |quantity| quantity.unit.as_str());
+    request.dosage_instruction.first()
+        .and_then(|dosage| dosage.text.split_once(&format!(" {} ", unit)))
+        .map(|(_, frequency)| frequency)
+}
+
+// "metformin 1000 mg twice daily"
+fn describe(request: &MedicationRequest) -> String {
+    match request.dosage_instruction.first() {
+        Some(dosage) => format!("{} {}", request.medication_codeable_concept.display, dosage.text),
+        None => request.medication_codeable_concept.display.clone(),
+    }
+}
+
+impl EMR {
+    // All of the patient's prescriptions, in the order they were written
+    pub fn medication_requests(&self, patient_id: &str) -> Result<Vec<&MedicationRequest>> {
+        let bundle = self.bundles.get(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        Ok(bundle.entry.iter()
+            .filter_map(|entry| match &entry.resource {
+                Resource::MedicationRequest(request) => Some(request),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // A current prescription by MedicationRequest id or medication name
+    pub fn find_medication_request(&self, patient_id: &str, id_or_name: &str) -> Result<&MedicationRequest> {
+        let requests = self.medication_requests(patient_id)?;
+        if let Some(request) = requests.iter().find(|request| request.id == id_or_name) {
+            return Ok(request);
+        }
+        let named: Vec<&MedicationRequest> = requests.into_iter()
+            .filter(|request| is_current(request)
+                && request.medication_codeable_concept.display.eq_ignore_ascii_case(id_or_name.trim()))
+            .collect();
+        match named.as_slice() {
+            [request] => Ok(request),
+            [] => Err(anyhow!("No current prescription of {} for patient {}", id_or_name, patient_id)),
+            _ => Err(anyhow!("Patient {} has {} current prescriptions of {}; give the MedicationRequest id instead",
+                             patient_id, named.len(), id_or_name)),
+        }
+    }
+
+    pub(crate) fn request_mut(&mut self, patient_id: &str, id: &str) -> Result<&mut MedicationRequest> {
+        let bundle = self.bundles.get_mut(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        bundle.entry.iter_mut()
+            .find_map(|entry| match &mut entry.resource {
+                Resource::MedicationRequest(request) if request.id == id => Some(request),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("MedicationRequest not found: {}", id))
+    }
+
+    // Move a prescription from one of `from` to `to`. Returns its description.
+    fn change_status(&mut self, patient_id: &str, id_or_name: &str, from: &[&str], to: &str,
+                     change: &MedicationChange) -> Result<String> {
+        let request = self.find_medication_request(patient_id, id_or_name)?;
+        if !from.contains(&request.status.as_str()) {
+            return Err(anyhow!("{} is {}; it must be {} to become {}",
+                               describe(request), request.status, from.join(" or "), to));
+        }
+        change.validate(request, self.signer.as_ref())?;
+        let id = request.id.clone();
+
+        let request = self.request_mut(patient_id, &id)?;
+        request.status = to.to_string();
+        request.status_history.push(StatusChange {
+            status: to.to_string(),
+            reason: change.reason.trim().to_string(),
+            author: change.author.trim().to_string(),
+            date: change.at.to_rfc3339(),
+        });
+        Ok(describe(request))
+    }
+
+    // Audit a change to a prescription. Returns the message to commit it with.
+    fn log_change(&mut self, patient_id: &str, action: &str, description: &str,
+                  change: &MedicationChange) -> Result<String> {
+        let message = format!("{} {}. Reason: {}. By: {}", action, description, change.reason.trim(), change.author.trim());
+        self.log_audit(&format!("{} at {}", message, change.at.to_rfc3339()), patient_id)?;
+        Ok(message)
+    }
+
+    // Audit and commit a change to a prescription
+    fn record_change(&mut self, patient_id: &str, action: &str, description: &str,
+                     change: &MedicationChange) -> Result<()> {
+        let message = self.log_change(patient_id, action, description, change)?;
+        self.commit_changes(patient_id, &message)
+    }
+
+    // Stop a current prescription
+    pub fn discontinue_medication(&mut self, patient_id: &str, id_or_name: &str, change: &MedicationChange) -> Result<()> {
+        let message = self.stop_medication(patient_id, id_or_name, change)?;
+        self.commit_changes(patient_id, &message)
+    }
+
+    // Stop a current prescription without committing, for changes made
+    // together with others. Returns the audit message.
+    pub(crate) fn stop_medication(&mut self, patient_id: &str, id_or_name: &str, change: &MedicationChange) -> Result<String> {
+        let description = self.change_status(patient_id, id_or_name, CURRENT_STATUSES, "stopped", change)?;
+        self.log_change(patient_id, "Discontinued", &description, change)
+    }
+
+    // Put an active prescription on hold
+    pub fn hold_medication(&mut self, patient_id: &str, id_or_name: &str, change: &MedicationChange) -> Result<()> {
+        let description = self.change_status(patient_id, id_or_name, &["active"], "on-hold", change)?;
+        self.record_change(patient_id, "Held", &description, change)
+    }
+
+    // Take a prescription off hold. It is checked again like a new
+    // prescription, since allergies and other prescriptions may have been
+    // recorded while it was held, with `override_reason` accepting warnings.
+    pub fn resume_medication(&mut self, patient_id: &str, id_or_name: &str, change: &MedicationChange,
+                             override_reason: Option<&str>) -> Result<()> {
+        let override_reason = override_reason.map(str::trim).filter(|reason| !reason.is_empty());
+        let request = self.find_medication_request(patient_id, id_or_name)?;
+        let mut checked = None;
+        if request.status == "on-hold" {
+            let coding = request.medication_codeable_concept.clone();
+            let quantity = dose(request).cloned()
+                .ok_or_else(|| anyhow!("{} has no dose to check", describe(request)))?;
+            let frequency = frequency(request).unwrap_or_default().to_string();
+            let sig = parse_sig(&frequency)?;
+            let limit = self.dose_limit(if coding.code.is_empty() { &coding.display } else { &coding.code });
+            let checks = self.check_prescription(patient_id, &coding, limit, quantity.value, &quantity.unit, &sig,
+                                                 override_reason)?;
+            checked = Some((coding.display, format!("{} {} {}", quantity.value, quantity.unit, frequency), checks));
+        }
+        let description = self.change_status(patient_id, id_or_name, &["on-hold"], "active", change)?;
+        if let Some((medication, dosage, checks)) = checked {
+            self.log_prescription_checks(patient_id, &medication, &dosage, &checks, override_reason)?;
+        }
+        self.record_change(patient_id, "Resumed", &description, change)
+    }
+
+    // Change the dose or frequency of a current prescription, keeping its
+    // unit (mg, or units for e.g. insulin). The new dose is checked like any
+    // prescription, with `override_reason` accepting warnings; the old
+    // request is stopped and the new one replaces it, still on hold if the
+    // old one was.
+    // Returns the new MedicationRequest's id.
+    pub fn modify_medication(&mut self, patient_id: &str, id_or_name: &str, dose_mg: f64, frequency: &str,
+                             change: &MedicationChange, override_reason: Option<&str>) -> Result<String> {
+        let (new_id, message) = self.change_dose(patient_id, id_or_name, dose_mg, frequency, change, override_reason)?;
+        self.commit_changes(patient_id, &message)?;
+        Ok(new_id)
+    }
+
+    // Change the dose or frequency without committing. Returns the new
+    // MedicationRequest's id and the audit message.
+    pub(crate) fn change_dose(&mut self, patient_id: &str, id_or_name: &str, dose_mg: f64, frequency: &str,
+                              change: &MedicationChange, override_reason: Option<&str>) -> Result<(String, String)> {
+        let request = self.find_medication_request(patient_id, id_or_name)?;
+        if !is_current(request) {
+            return Err(anyhow!("{} is {} and cannot be changed", describe(request), request.status));
+        }
+        change.validate(request, self.signer.as_ref())?;
+        let old_id = request.id.clone();
+        let coding = request.medication_codeable_concept.clone();
+        let unit = dose(request).map_or("mg".to_string(), |quantity| quantity.unit.clone());
+        let old = describe(request);
+        let held = request.status == "on-hold";
+
+        let options = PrescribeOptions {
+            authored: change.at,
+            unlisted: coding.code.is_empty(),
+            override_reason: override_reason.map(str::to_string),
+        };
+        let medication = if coding.code.is_empty() { &coding.display } else { &coding.code };
+        let new_id = self.prescribe_dose(patient_id, medication, dose_mg, &unit, frequency, &options)?;
+        let new = self.request_mut(patient_id, &new_id)?;
+        new.replaces = Some(Reference {
+            reference: format!("MedicationRequest/{}", old_id),
+        });
+        if held {
+            new.status = "on-hold".to_string();
+        }
+        self.change_status(patient_id, &old_id, CURRENT_STATUSES, "stopped", change)?;
+
+        let description = format!("{} to {} {} {}", old, dose_mg, unit, frequency);
+        let message = self.log_change(patient_id, "Changed", &description, change)?;
+        Ok((new_id, message))
+    }
+
+    // Renew a current prescription with the same dosage. The old request is
+    // completed and a new one with the same status replaces it. Allergies recorded since the
+    // prescription was written are checked again. Returns the new
+    // MedicationRequest's id.
+    pub fn renew_medication(&mut self, patient_id: &str, id_or_name: &str, change: &MedicationChange) -> Result<String> {
+        let request = self.find_medication_request(patient_id, id_or_name)?;
+        if !is_current(request) {
+            return Err(anyhow!("{} is {} and cannot be renewed", describe(request), request.status));
+        }
+        change.validate(request, self.signer.as_ref())?;
+        let old = request.clone();
+
+        let allergies = self.check_allergies(patient_id, &old.medication_codeable_concept.display)?;
+        let blocking: Vec<String> = allergies.iter()
+            .filter(|alert| alert.action != AllergyAction::Warn)
+            .map(|alert| alert.to_string())
+            .collect();
+        if !blocking.is_empty() {
+            return Err(anyhow!("Cannot renew due to allergy: {}. Prescribe it again instead", blocking.join("; ")));
+        }
+
+        let renewed = MedicationRequest {
+            id: Uuid::new_v4().to_string(),
+            authored_on: change.at.to_rfc3339(),
+            recorded: Some(Utc::now().to_rfc3339()),
+            status_history: Vec::new(),
+            replaces: Some(Reference {
+                reference: format!("MedicationRequest/{}", old.id),
+            }),
+            ..old.clone()
+        };
+        let new_id = renewed.id.clone();
+        self.change_status(patient_id, &old.id, CURRENT_STATUSES, "completed", change)?;
+
+        let bundle = self.bundles.get_mut(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        bundle.entry.push(BundleEntry {
+            resource_type: "MedicationRequest".to_string(),
+            resource: Resource::MedicationRequest(renewed),
+        });
+
+        for alert in &allergies {
+            self.log_audit(&format!("Allergy noted: {}", alert), patient_id)?;
+        }
+        self.record_change(patient_id, "Renewed", &describe(&old), change)?;
+        Ok(new_id)
+    }
+}
@@ -0,0 +1,598 @@
+// src/reconcile.rs
+// Charcot EMR: Medication reconciliation on admission and transfer
+//
+// An outside medication list - a FHIR Bundle of MedicationStatements or a CSV
+// file - is compared with the patient's current prescriptions. Medications
+// are matched by RxNorm ingredient, so "metformin hydrochloride 500 MG Oral
+// Tablet" on the list matches a prescription of metformin. Each medication is
+// proposed to be continued, modified to the listed dose, stopped or newly
+// prescribed; the clinician can change any decision before the decisions are
+// applied together as a single committed version of the record. If any
+// decision fails, none are kept.
+
+use std::fmt;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use anyhow::{Result, anyhow, Context};
+
+use crate::dose_calc::DOSE_UNITS;
+use crate::medications::{self, is_current};
+use crate::terminology::{RXNORM_SYSTEM, UCUM_SYSTEM};
+use crate::{EMR, MedicationChange, MedicationRequest, PrescribeOptions, Quantity, parse_sig, units, validate_clinical_time};
+
+pub const CONTEXTS: &[&str] = &["admission", "transfer"];
+
+// MedicationStatement statuses that mean the patient is taking the medication
+const TAKING_STATUSES: &[&str] = &["active", "intended", "unknown", "recorded"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReconcileAction {
+    Continue,
+    Modify,
+    Stop,
+    New,
+}
+
+impl ReconcileAction {
+    pub const ALL: [ReconcileAction; 4] =
+        [ReconcileAction::Continue, ReconcileAction::Modify, ReconcileAction::Stop, ReconcileAction::New];
+
+    pub fn parse(text: &str) -> Result<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "continue" => Ok(ReconcileAction::Continue),
+            "modify" | "change" => Ok(ReconcileAction::Modify),
+            "stop" | "discontinue" => Ok(ReconcileAction::Stop),
+            "new" | "start" => Ok(ReconcileAction::New),
+            _ => Err(anyhow!("Unknown reconciliation decision: {} (expected continue, modify, stop or new)", text)),
+        }
+    }
+}
+
+impl fmt::Display for ReconcileAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReconcileAction::Continue => write!(f, "continue"),
+            ReconcileAction::Modify => write!(f, "modify"),
+            ReconcileAction::Stop => write!(f, "stop"),
+            ReconcileAction::New => write!(f, "new"),
+        }
+    }
+}
+
+// A medication on an outside list
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalMedication {
+    pub name: String,
+    pub rxcui: Option<String>,
+    pub dose: Option<Quantity>,         // In the unit the list gives, which may not be one this EMR prescribes in
+    pub frequency: Option<String>,
+    pub taking: bool,                   // False for medications the list says were stopped
+}
+
+impl fmt::Display for ExternalMedication {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(dose) = &self.dose {
+            write!(f, " {} {}", dose.value, dose.unit)?;
+        }
+        if let Some(frequency) = &self.frequency {
+            write!(f, " {}", frequency)?;
+        }
+        if !self.taking {
+            write!(f, " (not taking)")?;
+        }
+        Ok(())
+    }
+}
+
+// An outside medication paired with the current prescription it matches, and
+// what to do about it
+#[derive(Debug, Clone)]
+pub struct ReconciliationItem {
+    pub external: Option<ExternalMedication>,
+    pub request_id: Option<String>,
+    pub current: Option<String>,            // e.g. "metformin 500 mg twice daily (active)"
+    pub proposed: ReconcileAction,
+    pub note: String,                       // Why the decision was proposed
+    pub decision: ReconcileAction,
+    pub dose: Option<f64>,                  // Dose and frequency to prescribe, for modify and new
+    pub dose_unit: String,                  // The current prescription's, or mg or units for a new one
+    pub frequency: Option<String>,
+    pub override_reason: Option<String>,    // For dose warnings, severe interactions and allergies
+}
+
+impl ReconciliationItem {
+    fn new(external: Option<ExternalMedication>, request: Option<&MedicationRequest>,
+           proposed: ReconcileAction, note: String) -> Self {
+        let prescribed = request.and_then(medications::dose);
+        let unit = prescribed.map(|quantity| quantity.unit.as_str());
+        let (dose, dose_unit, frequency) = match (&external, prescribed) {
+            (Some(external), _) if external.dose.is_some() || external.frequency.is_some() => {
+                let (dose, dose_unit) = match &external.dose {
+                    Some(listed) => dose_in(listed, unit),
+                    None => (None, unit.unwrap_or("mg").to_string()),
+                };
+                (dose, dose_unit, external.frequency.clone())
+            }
+            (_, Some(prescribed)) => (
+                Some(prescribed.value),
+                prescribed.unit.clone(),
+                request.and_then(medications::frequency).map(str::to_string),
+            ),
+            _ => (None, "mg".to_string(), None),
+        };
+        ReconciliationItem {
+            external,
+            request_id: request.map(|request| request.id.clone()),
+            current: request.map(|request| format!("{} ({})", describe(request), request.status)),
+            proposed,
+            note,
+            decision: proposed,
+            dose,
+            dose_unit,
+            frequency,
+            override_reason: None,
+        }
+    }
+
+    // Name of the medication, from the outside list if it is on it
+    pub fn medication(&self) -> String {
+        match (&self.external, &self.current) {
+            (Some(external), _) => external.name.clone(),
+            (None, Some(current)) => current.clone(),
+            (None, None) => String::new(),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        let medication = self.medication();
+        match (self.decision, &self.request_id) {
+            (ReconcileAction::Continue, None) => {
+                Err(anyhow!("{} is not currently prescribed; choose new to prescribe it", medication))
+            }
+            (ReconcileAction::Modify, None) => {
+                Err(anyhow!("{} is not currently prescribed and cannot be modified", medication))
+            }
+            (ReconcileAction::New, Some(_)) => {
+                Err(anyhow!("{} is already prescribed; choose continue or modify", medication))
+            }
+            (ReconcileAction::Modify | ReconcileAction::New, _) => {
+                let (Some(dose), Some(frequency)) = (self.dose, &self.frequency) else {
+                    return Err(anyhow!("A dose and frequency are needed to {} {}", self.decision, medication));
+                };
+                if dose <= 0.0 {
+                    return Err(anyhow!("Invalid dose for {}: {} {}", medication, dose, self.dose_unit));
+                }
+                if !DOSE_UNITS.contains(&self.dose_unit.as_str()) {
+                    return Err(anyhow!("The dose of {} must be in {}, not {}",
+                                       medication, DOSE_UNITS.join(" or "), self.dose_unit));
+                }
+                parse_sig(frequency).with_context(|| format!("Invalid frequency for {}", medication))?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Who reconciled the medications, when, and on which occasion
+#[derive(Debug, Clone)]
+pub struct Reconciliation {
+    pub context: String,                    // admission | transfer
+    pub author: String,
+    pub at: DateTime<Utc>,
+}
+
+impl Reconciliation {
+    pub fn new(context: &str, author: &str) -> Self {
+        Reconciliation { context: context.to_string(), author: author.to_string(), at: Utc::now() }
+    }
+
+    fn change(&self) -> MedicationChange {
+        MedicationChange {
+            reason: format!("Medication reconciliation on {}", self.context),
+            author: self.author.clone(),
+            at: self.at,
+        }
+    }
+}
+
+// "metformin 500 mg twice daily"
+fn describe(request: &MedicationRequest) -> String {
+    match request.dosage_instruction.first() {
+        Some(dosage) => format!("{} {}", request.medication_codeable_concept.display, dosage.text),
+        None => request.medication_codeable_concept.display.clone(),
+    }
+}
+
+// A listed dose in the unit it would be prescribed in: the current
+// prescription's if there is one, otherwise mg or units. The dose is None if
+// it cannot be converted, e.g. a dose in mL of a medication prescribed in mg.
+fn dose_in(listed: &Quantity, prescribed_unit: Option<&str>) -> (Option<f64>, String) {
+    let candidates = match prescribed_unit {
+        Some(unit) => vec![unit],
+        None => DOSE_UNITS.to_vec(),
+    };
+    candidates.iter()
+        .find_map(|unit| listed.convert_to(unit).ok())
+        .map(|dose| (Some(dose.value), dose.unit))
+        .unwrap_or_else(|| (None, candidates[0].to_string()))
+}
+
+// Whether two frequencies mean the same schedule ("BID" and "twice daily")
+fn same_frequency(a: &str, b: &str) -> bool {
+    match (parse_sig(a), parse_sig(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a.trim().eq_ignore_ascii_case(b.trim()),
+    }
+}
+
+// "500 mg", "0.5 g", "500mg" or "10 units". A bare number is in mg.
+fn parse_dose(text: &str) -> Result<Quantity> {
+    let (value, unit) = units::split_quantity(text)?;
+    Quantity::ucum(value, unit.unwrap_or("mg"))
+}
+
+// Split "500 mg twice daily" into the dose and the frequency
+fn split_dosage_text(text: &str) -> (Option<Quantity>, Option<String>) {
+    let text = text.trim();
+    let words: Vec<&str> = text.split_whitespace().collect();
+    for count in [2, 1] {
+        if words.len() > count {
+            if let Ok(dose) = parse_dose(&words[..count].join(" ")) {
+                return (Some(dose), Some(words[count..].join(" ")));
+            }
+        }
+    }
+    (None, Some(text.to_string()).filter(|text| !text.is_empty()))
+}
+
+// Parse an outside medication list: a FHIR Bundle (or array) of
+// MedicationStatements if it is JSON, otherwise CSV
+pub fn parse_medication_list(text: &str) -> Result<Vec<ExternalMedication>> {
+    if text.trim_start().starts_with(['{', '[']) {
+        parse_medication_statements(text)
+    } else {
+        parse_medication_csv(text)
+    }
+}
+
+// First of several keys present in a JSON object. FHIR uses camelCase; this
+// EMR's own files use snake_case.
+fn field<'a>(value: &'a Value, keys: &[&str]) -> Option<&'a Value> {
+    keys.iter().find_map(|key| value.get(*key))
+}
+
+fn text_field(value: &Value, keys: &[&str]) -> Option<String> {
+    field(value, keys)
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+}
+
+// Parse a FHIR Bundle, array or single MedicationStatement. Other resources
+// are skipped, as are statements entered in error.
+pub fn parse_medication_statements(json: &str) -> Result<Vec<ExternalMedication>> {
+    let value: Value = serde_json::from_str(json).context("Invalid medication list JSON")?;
+    let resources: Vec<&Value> = match &value {
+        Value::Array(items) => items.iter().collect(),
+        Value::Object(_) if value.get("entry").is_some() => {
+            value["entry"].as_array()
+                .ok_or_else(|| anyhow!("Bundle entry must be an array"))?
+                .iter()
+                .map(|entry| entry.get("resource").unwrap_or(entry))
+                .collect()
+        }
+        Value::Object(_) => vec![&value],
+        _ => return Err(anyhow!("Expected a Bundle of MedicationStatements")),
+    };
+
+    let mut medications = Vec::new();
+    for (i, resource) in resources.into_iter().enumerate() {
+        if text_field(resource, &["resourceType"]).as_deref() != Some("MedicationStatement") {
+            continue;
+        }
+        let status = text_field(resource, &["status"]).unwrap_or_else(|| "active".to_string());
+        if status == "entered-in-error" {
+            continue;
+        }
+        // R4 medicationCodeableConcept, R5 medication.concept, or this EMR's flat Coding
+        let concept = field(resource, &["medicationCodeableConcept", "medication_codeable_concept"])
+            .or_else(|| resource.get("medication").and_then(|medication| medication.get("concept")))
+            .ok_or_else(|| anyhow!("MedicationStatement {} has no medication", i + 1))?;
+        let codings: Vec<&Value> = match concept.get("coding").and_then(Value::as_array) {
+            Some(codings) => codings.iter().collect(),
+            None => vec![concept],
+        };
+        let rxnorm = codings.iter()
+            .find(|coding| text_field(coding, &["system"]).as_deref() == Some(RXNORM_SYSTEM));
+        let rxcui = rxnorm.and_then(|coding| text_field(coding, &["code"]));
+        let name = text_field(concept, &["text"])
+            .or_else(|| rxnorm.and_then(|coding| text_field(coding, &["display"])))
+            .or_else(|| codings.iter().find_map(|coding| text_field(coding, &["display"])))
+            .or_else(|| rxcui.clone())
+            .ok_or_else(|| anyhow!("MedicationStatement {} has no medication name or code", i + 1))?;
+
+        let dosage = field(resource, &["dosage", "dosage_instruction"])
+            .and_then(Value::as_array)
+            .and_then(|dosages| dosages.first());
+        let (mut dose, mut frequency) = dosage
+            .and_then(|dosage| text_field(dosage, &["text"]))
+            .map(|text| split_dosage_text(&text))
+            .unwrap_or((None, None));
+        let quantity = dosage
+            .and_then(|dosage| field(dosage, &["doseAndRate", "dose_and_rate"]))
+            .and_then(Value::as_array)
+            .and_then(|rates| rates.first())
+            .and_then(|rate| field(rate, &["doseQuantity", "dose_quantity"]));
+        // Kept in the list's unit, even one that is not UCUM such as "tablet",
+        // and converted when it is compared or prescribed
+        if let Some(quantity) = quantity {
+            let value = quantity.get("value").and_then(Value::as_f64)
+                .ok_or_else(|| anyhow!("Dose of {} has no value", name))?;
+            let unit = text_field(quantity, &["unit", "code"]).unwrap_or_else(|| "mg".to_string());
+            dose = Some(Quantity {
+                value,
+                code: text_field(quantity, &["code"]).unwrap_or_else(|| units::ucum_code(&unit)),
+                system: text_field(quantity, &["system"]).unwrap_or_else(|| UCUM_SYSTEM.to_string()),
+                unit,
+            });
+        }
+        if let Some(code) = dosage
+            .and_then(|dosage| dosage.get("timing"))
+            .and_then(|timing| timing.get("code"))
+            .and_then(|code| text_field(code, &["text"])) {
+            frequency = Some(code);
+        }
+
+        medications.push(ExternalMedication {
+            name,
+            rxcui,
+            dose,
+            frequency,
+            taking: TAKING_STATUSES.contains(&status.as_str()),
+        });
+    }
+    Ok(medications)
+}
+
+// Parse a CSV medication list. The header names the columns: medication (or
+// name or drug), and optionally rxnorm, dose, frequency and status, e.g.
+//   medication,dose,frequency
+//   metformin,500 mg,twice daily
+pub fn parse_medication_csv(text: &str) -> Result<Vec<ExternalMedication>> {
+    let mut lines = text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+    let (_, header) = lines.next().ok_or_else(|| anyhow!("The medication list is empty"))?;
+    let columns: Vec<String> = split_csv(header).into_iter().map(|column| column.to_lowercase()).collect();
+    let column = |names: &[&str]| columns.iter().position(|column| names.contains(&column.as_str()));
+    let name_column = column(&["medication", "name", "drug"])
+        .ok_or_else(|| anyhow!("The medication list needs a medication column, e.g. medication,dose,frequency"))?;
+    let rxnorm_column = column(&["rxnorm", "rxcui", "code"]);
+    let dose_column = column(&["dose", "dose_mg"]);
+    let frequency_column = column(&["frequency", "sig"]);
+    let status_column = column(&["status"]);
+
+    let mut medications = Vec::new();
+    for (number, line) in lines {
+        let fields = split_csv(line);
+        let get = |column: Option<usize>| column
+            .and_then(|i| fields.get(i))
+            .map(String::as_str)
+            .filter(|field| !field.is_empty());
+        let name = get(Some(name_column))
+            .ok_or_else(|| anyhow!("Line {}: medication is missing", number + 1))?;
+        let dose = get(dose_column)
+            .map(parse_dose)
+            .transpose()
+            .with_context(|| format!("Line {}: invalid dose", number + 1))?;
+        let status = get(status_column).unwrap_or("active").to_lowercase();
+        if status == "entered-in-error" {
+            continue;
+        }
+        medications.push(ExternalMedication {
+            name: name.to_string(),
+            rxcui: get(rxnorm_column).map(str::to_string),
+            dose,
+            frequency: get(frequency_column).map(str::to_string),
+            taking: TAKING_STATUSES.contains(&status.as_str()),
+        });
+    }
+    Ok(medications)
+}
+
+// Split a CSV line, allowing double-quoted fields with commas in them
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+impl EMR {
+    // What identifies a medication for matching: its RxNorm ingredients, or
+    // the name of a medication that is not in the dictionary
+    fn medication_key(&self, rxcui: Option<&str>, name: &str) -> String {
+        let drug = rxcui.and_then(|code| self.drugs.resolve(code).ok())
+            .or_else(|| self.drugs.resolve(name).ok());
+        match drug {
+            Some(drug) if !drug.ingredients.is_empty() => {
+                let mut codes: Vec<&str> = drug.ingredients.iter().map(|ingredient| ingredient.rxcui.as_str()).collect();
+                codes.sort();
+                codes.dedup();
+                codes.join("+")
+            }
+            Some(drug) => drug.drug.rxcui,
+            None => format!("name:{}", name.trim().to_lowercase()),
+        }
+    }
+
+    // Compare an outside medication list with the patient's current
+    // prescriptions and propose a decision for each medication
+    pub fn reconcile_medications(&self, patient_id: &str, list: &[ExternalMedication]) -> Result<Vec<ReconciliationItem>> {
+        let current: Vec<&MedicationRequest> = self.medication_requests(patient_id)?.into_iter()
+            .filter(|request| is_current(request))
+            .collect();
+        let keys: Vec<String> = current.iter()
+            .map(|request| {
+                let code = &request.medication_codeable_concept.code;
+                self.medication_key(Some(code.as_str()).filter(|code| !code.is_empty()),
+                                    &request.medication_codeable_concept.display)
+            })
+            .collect();
+        let mut matched = vec![false; current.len()];
+
+        let mut items = Vec::new();
+        for external in list {
+            let key = self.medication_key(external.rxcui.as_deref(), &external.name);
+            let found = (0..current.len()).find(|&i| !matched[i] && keys[i] == key);
+            let Some(i) = found else {
+                if external.taking && keys.contains(&key) {
+                    items.push(ReconciliationItem::new(Some(external.clone()), None, ReconcileAction::Stop,
+                                                       "Listed again; the current prescription is matched above".to_string()));
+                } else if external.taking {
+                    items.push(ReconciliationItem::new(Some(external.clone()), None, ReconcileAction::New,
+                                                       "Not currently prescribed".to_string()));
+                }
+                continue;
+            };
+            matched[i] = true;
+            let request = current[i];
+
+            // Compared in the prescription's unit; a dose that cannot be
+            // converted to it is left for the clinician to enter
+            let listed_dose = external.dose.as_ref().zip(medications::dose(request))
+                .map(|(listed, prescribed)| (listed.convert_to(&prescribed.unit), prescribed));
+            let dose_differs = listed_dose.as_ref().is_some_and(|(listed, prescribed)| {
+                !listed.as_ref().is_ok_and(|listed| (listed.value - prescribed.value).abs() <= 1e-9)
+            });
+            let frequency_differs = external.frequency.as_deref().zip(medications::frequency(request))
+                .is_some_and(|(listed, prescribed)| !same_frequency(listed, prescribed));
+            let (proposed, note) = if !external.taking {
+                (ReconcileAction::Stop, "Not being taken according to the outside list".to_string())
+            } else if dose_differs || frequency_differs {
+                let note = match &listed_dose {
+                    Some((Err(_), prescribed)) => format!("Listed as {}, which cannot be compared with the prescribed {}",
+                                                          external, prescribed.unit),
+                    _ => format!("Listed as {}", external),
+                };
+                (ReconcileAction::Modify, note)
+            } else {
+                (ReconcileAction::Continue, "Matches the outside list".to_string())
+            };
+            items.push(ReconciliationItem::new(Some(external.clone()), Some(request), proposed, note));
+        }
+        for (request, _) in current.iter().zip(&matched).filter(|(_, matched)| !**matched) {
+            items.push(ReconciliationItem::new(None, Some(request), ReconcileAction::Stop,
+                                               "Not on the outside list".to_string()));
+        }
+        Ok(items)
+    }
+
+    // Apply reconciliation decisions and commit them as one version of the
+    // record. On any error the record is left as it was. Returns the commit
+    // message.
+    pub fn apply_reconciliation(&mut self, patient_id: &str, items: &[ReconciliationItem],
+                                reconciliation: &Reconciliation) -> Result<String> {
+        if !CONTEXTS.contains(&reconciliation.context.as_str()) {
+            return Err(anyhow!("Unknown reconciliation context: {} (expected {})",
+                               reconciliation.context, CONTEXTS.join(" or ")));
+        }
+        if reconciliation.author.trim().is_empty() {
+            return Err(anyhow!("The clinician reconciling the medications is required"));
+        }
+        validate_clinical_time(reconciliation.at)?;
+        for item in items {
+            item.validate()?;
+        }
+
+        let saved = self.bundles.get(patient_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+        match self.apply_decisions(patient_id, items, reconciliation) {
+            Ok(summary) => {
+                let message = format!("Medication reconciliation on {} by {}: {}",
+                                      reconciliation.context, reconciliation.author.trim(), summary);
+                self.commit_changes(patient_id, &message)?;
+                Ok(message)
+            }
+            Err(e) => {
+                self.bundles.insert(patient_id.to_string(), saved);
+                self.log_audit(&format!("Medication reconciliation abandoned, no changes kept: {:#}", e), patient_id)?;
+                Err(e)
+            }
+        }
+    }
+
+    // Apply each decision. Returns a summary such as "2 continued, 1 stopped".
+    fn apply_decisions(&mut self, patient_id: &str, items: &[ReconciliationItem],
+                       reconciliation: &Reconciliation) -> Result<String> {
+        let change = reconciliation.change();
+        let mut counts = [0; 4];
+        for item in items {
+            let medication = item.medication();
+            match (item.decision, &item.request_id) {
+                (ReconcileAction::Continue, Some(_)) => {
+                    self.log_audit(&format!("Reconciled: continue {}", item.current.as_deref().unwrap_or(&medication)),
+                                   patient_id)?;
+                    counts[0] += 1;
+                }
+                (ReconcileAction::Modify, Some(id)) => {
+                    let dose = item.dose.unwrap_or_default();
+                    let frequency = item.frequency.as_deref().unwrap_or_default();
+                    self.change_dose(patient_id, id, dose, frequency, &change, item.override_reason.as_deref())
+                        .with_context(|| format!("Cannot modify {}", medication))?;
+                    counts[1] += 1;
+                }
+                (ReconcileAction::Stop, Some(id)) => {
+                    self.stop_medication(patient_id, id, &change)?;
+                    counts[2] += 1;
+                }
+                (ReconcileAction::Stop, None) => {
+                    self.log_audit(&format!("Reconciled: not started {} from the outside list", medication), patient_id)?;
+                    counts[2] += 1;
+                }
+                (ReconcileAction::New, None) => {
+                    let external = item.external.as_ref()
+                        .ok_or_else(|| anyhow!("Nothing to prescribe for {}", medication))?;
+                    let options = PrescribeOptions {
+                        authored: reconciliation.at,
+                        unlisted: true,
+                        override_reason: item.override_reason.clone(),
+                    };
+                    let name = external.rxcui.as_deref()
+                        .filter(|code| self.drugs.lookup(code).is_some())
+                        .unwrap_or(&external.name);
+                    let dose = item.dose.unwrap_or_default();
+                    let frequency = item.frequency.as_deref().unwrap_or_default();
+                    self.prescribe_dose(patient_id, name, dose, &item.dose_unit, frequency, &options)
+                        .with_context(|| format!("Cannot prescribe {}", medication))?;
+                    counts[3] += 1;
+                }
+                _ => return Err(anyhow!("Cannot {} {}", item.decision, medication)),
+            }
+        }
+
+        let summary: Vec<String> = ["continued", "modified", "stopped", "started"].iter()
+            .zip(counts)
+            .filter(|(_, count)| *count > 0)
+            .map(|(label, count)| format!("{} {}", count, label))
+            .collect();
+        Ok(if summary.is_empty() { "no medications".to_string() } else { summary.join(", ") })
+    }
+}
@@ -0,0 +1,289 @@
+// src/rxnorm.rs
+// Charcot EMR: Offline RxNorm medication dictionary
+//
+// The EMR ships with the ingredients of commonly prescribed drugs. A larger
+// dictionary can be built from the RxNorm release files (`RXNCONSO.RRF`) with
+// `emr_cli rxnorm import`, which writes `rxnorm.json` to the working
+// directory; that file then replaces the built-in list.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, anyhow, Context};
+
+pub const RXNORM_FILE: &str = "rxnorm.json";
+
+// Term types kept from RXNCONSO, most general first. A name that appears
+// under several term types resolves to the first one.
+const TERM_TYPES: &[&str] = &["IN", "PIN", "MIN", "SCD", "SBD", "BN"];
+
+// (RXCUI, ingredient) for commonly prescribed drugs
+const BUILTIN: &[(&str, &str)] = &[
+    ("161", "acetaminophen"),
+    ("1191", "aspirin"),
+    ("5640", "ibuprofen"),
+    ("7258", "naproxen"),
+    ("6809", "metformin"),
+    ("4821", "glipizide"),
+    ("4815", "glyburide"),
+    ("33738", "pioglitazone"),
+    ("593411", "sitagliptin"),
+    ("1545653", "empagliflozin"),
+    ("1488564", "dapagliflozin"),
+    ("475968", "liraglutide"),
+    ("274783", "insulin glargine"),
+    ("86009", "insulin lispro"),
+    ("51428", "insulin aspart"),
+    ("29046", "lisinopril"),
+    ("3827", "enalapril"),
+    ("35296", "ramipril"),
+    ("52175", "losartan"),
+    ("69749", "valsartan"),
+    ("17767", "amlodipine"),
+    ("6918", "metoprolol"),
+    ("1202", "atenolol"),
+    ("20352", "carvedilol"),
+    ("8787", "propranolol"),
+    ("5487", "hydrochlorothiazide"),
+    ("4603", "furosemide"),
+    ("9997", "spironolactone"),
+    ("3443", "diltiazem"),
+    ("11170", "verapamil"),
+    ("703", "amiodarone"),
+    ("3407", "digoxin"),
+    ("83367", "atorvastatin"),
+    ("36567", "simvastatin"),
+    ("301542", "rosuvastatin"),
+    ("11289", "warfarin"),
+    ("1364430", "apixaban"),
+    ("1114195", "rivaroxaban"),
+    ("32968", "clopidogrel"),
+    ("5224", "heparin"),
+    ("67108", "enoxaparin"),
+    ("704", "amitriptyline"),
+    ("36437", "sertraline"),
+    ("4493", "fluoxetine"),
+    ("2556", "citalopram"),
+    ("321988", "escitalopram"),
+    ("72625", "duloxetine"),
+    ("42347", "bupropion"),
+    ("10737", "trazodone"),
+    ("6470", "lorazepam"),
+    ("596", "alprazolam"),
+    ("3322", "diazepam"),
+    ("39993", "zolpidem"),
+    ("51272", "quetiapine"),
+    ("5093", "haloperidol"),
+    ("6448", "lithium"),
+    ("25480", "gabapentin"),
+    ("10689", "tramadol"),
+    ("7052", "morphine"),
+    ("7804", "oxycodone"),
+    ("7646", "omeprazole"),
+    ("40790", "pantoprazole"),
+    ("26225", "ondansetron"),
+    ("10582", "levothyroxine"),
+    ("8640", "prednisone"),
+    ("723", "amoxicillin"),
+    ("18631", "azithromycin"),
+    ("2551", "ciprofloxacin"),
+    ("3640", "doxycycline"),
+    ("2231", "cephalexin"),
+    ("21212", "clarithromycin"),
+    ("4450", "fluconazole"),
+    ("519", "allopurinol"),
+    ("2683", "colchicine"),
+    ("6851", "methotrexate"),
+    ("8591", "potassium chloride"),
+    ("435", "albuterol"),
+    ("88249", "montelukast"),
+    ("20610", "cetirizine"),
+];
+
+// Name of a built-in ingredient, by RXCUI
+pub fn builtin_name(rxcui: &str) -> Option<&'static str> {
+    BUILTIN.iter().find(|(code, _)| *code == rxcui).map(|(_, name)| *name)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Drug {
+    pub rxcui: String,
+    pub name: String,
+    pub tty: String,                // RxNorm term type: IN ingredient, SCD clinical drug, ...
+}
+
+// A drug with its ingredient, strength and dose form worked out. Clinical
+// drug names follow "<ingredient> <strength> <dose form>", e.g.
+// "metformin hydrochloride 500 MG Oral Tablet".
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedDrug {
+    pub drug: Drug,
+    pub ingredients: Vec<Drug>,
+    pub strength: Option<String>,
+    pub form: Option<String>,
+}
+
+impl ResolvedDrug {
+    // The drug's own RXCUI and those of its ingredients
+    pub fn codes(&self) -> Vec<&str> {
+        std::iter::once(self.drug.rxcui.as_str())
+            .chain(self.ingredients.iter().map(|ingredient| ingredient.rxcui.as_str()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrugDictionary {
+    pub drugs: Vec<Drug>,
+    // Lower-case name -> index of its most general entry
+    index: HashMap<String, usize>,
+}
+
+impl Default for DrugDictionary {
+    fn default() -> Self {
+        DrugDictionary::new(BUILTIN.iter()
+            .map(|(rxcui, name)| Drug { rxcui: rxcui.to_string(), name: name.to_string(), tty: "IN".to_string() })
+            .collect())
+    }
+}
+
+impl DrugDictionary {
+    pub fn new(mut drugs: Vec<Drug>) -> Self {
+        drugs.sort_by_key(|drug| TERM_TYPES.iter().position(|tty| *tty == drug.tty).unwrap_or(TERM_TYPES.len()));
+        let mut index = HashMap::new();
+        for (i, drug) in drugs.iter().enumerate() {
+            index.entry(drug.name.to_lowercase()).or_insert(i);
+        }
+        DrugDictionary { drugs, index }
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read RxNorm dictionary from {}", path))?;
+        let drugs: Vec<Drug> = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid RxNorm dictionary in {}", path))?;
+        Ok(DrugDictionary::new(drugs))
+    }
+
+    // `rxnorm.json` if present, otherwise the built-in ingredients
+    pub fn load_or_default() -> Result<Self> {
+        if Path::new(RXNORM_FILE).exists() {
+            DrugDictionary::load(RXNORM_FILE)
+        } else {
+            Ok(DrugDictionary::default())
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, serde_json::to_string(&self.drugs)?)
+            .with_context(|| format!("Failed to write RxNorm dictionary to {}", path))
+    }
+
+    // Build a dictionary from an RxNorm release. `path` is `RXNCONSO.RRF` or
+    // the directory holding it (e.g. `rrf/` of the unpacked download).
+    pub fn import_rrf(path: &str) -> Result<Self> {
+        let path = Path::new(path);
+        let file = if path.is_dir() { path.join("RXNCONSO.RRF") } else { path.to_path_buf() };
+        let reader = BufReader::new(File::open(&file)
+            .with_context(|| format!("Failed to open {}", file.display()))?);
+
+        let mut drugs = Vec::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            // RXCUI|LAT|TS|LUI|STT|SUI|ISPREF|RXAUI|SAUI|SCUI|SDUI|SAB|TTY|CODE|STR|SRL|SUPPRESS|CVF
+            let fields: Vec<&str> = line.split('|').collect();
+            if fields.len() < 17 {
+                return Err(anyhow!("{}:{}: not an RXNCONSO line", file.display(), number + 1));
+            }
+            let (rxcui, language, source, tty, name, suppress) =
+                (fields[0], fields[1], fields[11], fields[12], fields[14], fields[16]);
+            if language == "ENG" && source == "RXNORM" && suppress == "N" && TERM_TYPES.contains(&tty) {
+                drugs.push(Drug { rxcui: rxcui.to_string(), name: name.to_string(), tty: tty.to_string() });
+            }
+        }
+        if drugs.is_empty() {
+            return Err(anyhow!("No RxNorm drugs found in {}", file.display()));
+        }
+        Ok(DrugDictionary::new(drugs))
+    }
+
+    // Exact (case-insensitive) lookup by name or RXCUI
+    pub fn lookup(&self, name: &str) -> Option<&Drug> {
+        let name = name.trim();
+        self.index.get(&name.to_lowercase())
+            .map(|&i| &self.drugs[i])
+            .or_else(|| self.drugs.iter().find(|drug| drug.rxcui == name))
+    }
+
+    // Drugs whose names are close to `name`, best match first
+    pub fn suggest(&self, name: &str, limit: usize) -> Vec<&Drug> {
+        let name = name.trim().to_lowercase();
+        if name.is_empty() {
+            return Vec::new();
+        }
+        let mut scored: Vec<(f64, &Drug)> = self.index.iter()
+            .map(|(candidate, &i)| (strsim::jaro_winkler(&name, candidate), &self.drugs[i]))
+            .filter(|(score, _)| *score >= 0.85)
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.name.cmp(&b.1.name)));
+        scored.into_iter().take(limit).map(|(_, drug)| drug).collect()
+    }
+
+    // Look up a drug, failing with the closest matches if it is unknown
+    pub fn resolve(&self, name: &str) -> Result<ResolvedDrug> {
+        let drug = self.lookup(name).ok_or_else(|| {
+            let suggestions: Vec<String> = self.suggest(name, 3).iter()
+                .map(|drug| format!("{} (RxNorm {})", drug.name, drug.rxcui))
+                .collect();
+            if suggestions.is_empty() {
+                anyhow!("Unknown medication: {}", name)
+            } else {
+                anyhow!("Unknown medication: {}. Did you mean {}?", name, suggestions.join(", "))
+            }
+        })?;
+        Ok(self.details(drug))
+    }
+
+    // The drug to check a medication as: the drug itself, or for a name
+    // not in the dictionary its closest match, e.g. a misspelling
+    pub fn resolve_or_closest(&self, name: &str) -> Option<ResolvedDrug> {
+        match self.lookup(name) {
+            Some(drug) => Some(self.details(drug)),
+            None => self.suggest(name, 1).first().map(|drug| self.details(drug)),
+        }
+    }
+
+    fn details(&self, drug: &Drug) -> ResolvedDrug {
+        // "metformin hydrochloride 500 MG Oral Tablet [Glucophage]"
+        let name = drug.name.split(" [").next().unwrap_or(&drug.name);
+        let mut ingredients = Vec::new();
+        let mut strengths = Vec::new();
+        let mut form = None;
+        // Combinations list each ingredient with its strength:
+        // "amlodipine 5 MG / benazepril hydrochloride 10 MG Oral Capsule"
+        for part in name.split(" / ") {
+            // The ingredient is the longest ingredient name the part starts with
+            let lower = part.to_lowercase();
+            let ingredient = self.drugs.iter()
+                .filter(|candidate| candidate.tty == "IN")
+                .filter(|candidate| {
+                    let candidate = candidate.name.to_lowercase();
+                    lower == candidate || lower.starts_with(&format!("{} ", candidate))
+                })
+                .max_by_key(|candidate| candidate.name.len());
+            ingredients.extend(ingredient.cloned());
+
+            let words: Vec<&str> = part.split_whitespace().collect();
+            if let Some(start) = words.iter().position(|word| word.starts_with(|c: char| c.is_ascii_digit())) {
+                // The number and its unit, e.g. "500 MG" or "100 UNT/ML"
+                let end = (start + 2).min(words.len());
+                strengths.push(words[start..end].join(" "));
+                form = Some(words[end..].join(" ")).filter(|form| !form.is_empty());
+            }
+        }
+        let strength = (!strengths.is_empty()).then(|| strengths.join(" / "));
+        ResolvedDrug { drug: drug.clone(), ingredients, strength, form }
+    }
+}
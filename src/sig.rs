@@ -0,0 +1,474 @@
+// src/sig.rs
+// Charcot EMR: Parser for dosing frequencies ("sigs") such as "twice daily with meals"
+//
+// A sig is turned into the structured FHIR `Timing.repeat` of a dosage
+// instruction. Free text ("every 8 hours"), Latin abbreviations (BID, q6h,
+// PRN) and times of day ("starting 08:00") are understood; anything else is
+// rejected rather than guessed at.
+
+use chrono::{Duration, NaiveTime, Timelike};
+use anyhow::{Result, anyhow};
+
+use crate::{Repeat, Timing};
+
+// Structured form of a sig
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sig {
+    pub frequency: Option<i32>,             // Doses per period
+    pub period: Option<f64>,
+    pub period_max: Option<f64>,            // Upper bound for "every 4-6 hours"
+    pub period_unit: Option<String>,        // UCUM: min, h, d, wk, mo
+    pub time_of_day: Vec<String>,           // hh:mm:ss
+    pub when: Vec<String>,                  // FHIR EventTiming codes, e.g. C (with meals)
+    pub as_needed: bool,
+}
+
+impl Sig {
+    pub fn timing(&self) -> Timing {
+        let scheduled = self.frequency.is_some() || !self.time_of_day.is_empty() || !self.when.is_empty();
+        Timing {
+            repeat: scheduled.then(|| Repeat {
+                frequency: self.frequency,
+                period: self.period,
+                period_max: self.period_max,
+                period_unit: self.period_unit.clone(),
+                time_of_day: (!self.time_of_day.is_empty()).then(|| self.time_of_day.clone()),
+                when: (!self.when.is_empty()).then(|| self.when.clone()),
+            }),
+        }
+    }
+
+    // Scheduled doses per day, if the sig fixes one
+    pub fn doses_per_day(&self) -> Option<f64> {
+        let hours = period_hours(self.period?, self.period_unit.as_deref()?)?;
+        Some(self.frequency? as f64 * 24.0 / hours)
+    }
+}
+
+// Doses closer together than this (96 a day) are rejected as mistakes
+pub const MIN_INTERVAL_MINUTES: f64 = 15.0;
+
+// Abbreviations: (word, frequency, period, period unit, event)
+const ABBREVIATIONS: &[(&str, i32, f64, &str, Option<&str>)] = &[
+    ("qd", 1, 1.0, "d", None),
+    ("od", 1, 1.0, "d", None),
+    ("daily", 1, 1.0, "d", None),
+    ("bid", 2, 1.0, "d", None),
+    ("tid", 3, 1.0, "d", None),
+    ("qid", 4, 1.0, "d", None),
+    ("qod", 1, 2.0, "d", None),
+    ("hourly", 1, 1.0, "h", None),
+    ("weekly", 1, 1.0, "wk", None),
+    ("monthly", 1, 1.0, "mo", None),
+    ("qam", 1, 1.0, "d", Some("MORN")),
+    ("qpm", 1, 1.0, "d", Some("EVE")),
+    ("qhs", 1, 1.0, "d", Some("HS")),
+    ("nightly", 1, 1.0, "d", Some("NIGHT")),
+];
+
+// Events without a frequency of their own: (phrase, event)
+const EVENTS: &[(&[&str], &str)] = &[
+    (&["ac"], "AC"),
+    (&["pc"], "PC"),
+    (&["hs"], "HS"),
+    (&["in", "the", "morning"], "MORN"),
+    (&["in", "the", "afternoon"], "AFT"),
+    (&["in", "the", "evening"], "EVE"),
+    (&["at", "night"], "NIGHT"),
+    (&["at", "bedtime"], "HS"),
+    (&["on", "waking"], "WAKE"),
+    (&["upon", "waking"], "WAKE"),
+];
+
+// Meals for "before/with/after <meal>": (meal, before, with, after)
+const MEALS: &[(&str, &str, &str, &str)] = &[
+    ("meals", "AC", "C", "PC"),
+    ("food", "AC", "C", "PC"),
+    ("breakfast", "ACM", "CM", "PCM"),
+    ("lunch", "ACD", "CD", "PCD"),
+    ("dinner", "ACV", "CV", "PCV"),
+    ("supper", "ACV", "CV", "PCV"),
+];
+
+// Parse a free-text dosing frequency
+pub fn parse_sig(text: &str) -> Result<Sig> {
+    let normalized = text.to_lowercase().replace([',', ';'], " ");
+    let words: Vec<String> = normalized.split_whitespace()
+        .map(strip_points)
+        .filter(|word| !word.is_empty())
+        .collect();
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    if words.is_empty() {
+        return Err(anyhow!("Missing dosing frequency, e.g. \"twice daily\""));
+    }
+
+    let mut sig = Sig {
+        frequency: None,
+        period: None,
+        period_max: None,
+        period_unit: None,
+        time_of_day: Vec::new(),
+        when: Vec::new(),
+        as_needed: false,
+    };
+    let mut times = Vec::new();
+    let mut rest = words.as_slice();
+
+    while let Some(&word) = rest.first() {
+        // bid, q.h.s., daily
+        if let Some(&(_, frequency, period, unit, event)) = ABBREVIATIONS.iter().find(|(w, ..)| *w == word) {
+            set_frequency(&mut sig, frequency, period, None, unit, text)?;
+            sig.when.extend(event.map(str::to_string));
+            rest = &rest[1..];
+            continue;
+        }
+        if let Some((phrase, event)) = EVENTS.iter().find(|(phrase, _)| rest.starts_with(phrase)) {
+            sig.when.push(event.to_string());
+            rest = &rest[phrase.len()..];
+            continue;
+        }
+
+        // once daily, 3 times a day, twice weekly
+        if let Some((frequency, used)) = times_word(word, &rest[1..]) {
+            let tail = &rest[1 + used..];
+            let (period, unit, len) = per_period(tail)
+                .ok_or_else(|| anyhow!("Expected a period after `{}` in \"{}\", e.g. `daily`", word, text))?;
+            set_frequency(&mut sig, frequency, period, None, unit, text)?;
+            rest = &tail[len..];
+            continue;
+        }
+        // q6h, q4-6h, q2d
+        if let Some((period, period_max, unit)) = compact_interval(word) {
+            set_frequency(&mut sig, 1, period, period_max, unit, text)?;
+            rest = &rest[1..];
+            continue;
+        }
+        // starting 08:00, at 8am and 8pm
+        let time = match rest {
+            ["starting" | "from" | "at", tail @ ..] => time_of_day(tail).map(|(time, len)| (time, len + 1)),
+            _ if !times.is_empty() => time_of_day(rest),
+            _ => None,
+        };
+        if let Some((time, len)) = time {
+            times.push(time);
+            rest = &rest[len..];
+            continue;
+        }
+
+        rest = match rest {
+            ["and" | "then", tail @ ..] => tail,
+            ["prn", tail @ ..] | ["as", "needed" | "required", tail @ ..] | ["when", "required", tail @ ..] => {
+                sig.as_needed = true;
+                tail
+            }
+            // with meals, before breakfast
+            [relation @ ("before" | "with" | "after"), meal, tail @ ..] => {
+                let &(_, before, with, after) = MEALS.iter().find(|(m, ..)| m == meal)
+                    .ok_or_else(|| anyhow!("Unrecognized meal `{}` in \"{}\"", meal, text))?;
+                sig.when.push(match *relation {
+                    "before" => before,
+                    "with" => with,
+                    _ => after,
+                }.to_string());
+                tail
+            }
+            // every morning
+            ["every" | "each", moment @ ("morning" | "afternoon" | "evening" | "night"), tail @ ..] => {
+                set_frequency(&mut sig, 1, 1.0, None, "d", text)?;
+                sig.when.push(match *moment {
+                    "morning" => "MORN",
+                    "afternoon" => "AFT",
+                    "evening" => "EVE",
+                    _ => "NIGHT",
+                }.to_string());
+                tail
+            }
+            // every 6 hours, every 4-6 hours, every other day, every day
+            ["every" | "each" | "q", tail @ ..] => {
+                let (period, period_max, unit, len) = interval(tail)
+                    .ok_or_else(|| anyhow!("Expected an interval after `{}` in \"{}\", e.g. `every 8 hours`", word, text))?;
+                set_frequency(&mut sig, 1, period, period_max, unit, text)?;
+                &tail[len..]
+            }
+            _ => return Err(anyhow!("Unrecognized dosing frequency `{}` in \"{}\"", word, text)),
+        };
+    }
+
+    schedule_times(&mut sig, times, text)?;
+
+    // "with meals" alone is three times a day, "before breakfast" once
+    if sig.frequency.is_none() && !sig.when.is_empty() {
+        let per_day: i32 = sig.when.iter()
+            .map(|event| if matches!(event.as_str(), "C" | "AC" | "PC") { 3 } else { 1 })
+            .sum();
+        sig.frequency = Some(per_day);
+        sig.period = Some(1.0);
+        sig.period_unit = Some("d".to_string());
+    }
+    if sig.frequency.is_none() && !sig.as_needed {
+        return Err(anyhow!("No dosing frequency in \"{}\", e.g. \"twice daily\" or \"every 8 hours\"", text));
+    }
+    Ok(sig)
+}
+
+// "b.i.d." -> "bid", but keep the decimal point in "0.5" and "q0.5h"
+fn strip_points(word: &str) -> String {
+    let chars: Vec<char> = word.chars().collect();
+    chars.iter().enumerate()
+        .filter(|&(i, &c)| c != '.' || (i > 0 && chars[i - 1].is_ascii_digit()
+                                         && chars.get(i + 1).is_some_and(char::is_ascii_digit)))
+        .map(|(_, &c)| c)
+        .collect()
+}
+
+fn set_frequency(sig: &mut Sig, frequency: i32, period: f64, period_max: Option<f64>, unit: &str,
+                 text: &str) -> Result<()> {
+    if sig.frequency.is_some() {
+        return Err(anyhow!("More than one dosing frequency in \"{}\"", text));
+    }
+    if frequency <= 0 || !period.is_finite() || period <= 0.0
+        || period_max.is_some_and(|max| !max.is_finite() || max <= period) {
+        return Err(anyhow!("Invalid dosing frequency in \"{}\"", text));
+    }
+    let minutes = period_hours(period, unit).map(|hours| hours * 60.0 / frequency as f64);
+    if minutes.is_none_or(|minutes| minutes < MIN_INTERVAL_MINUTES) {
+        return Err(anyhow!("Doses in \"{}\" are less than {} minutes apart", text, MIN_INTERVAL_MINUTES));
+    }
+    sig.frequency = Some(frequency);
+    sig.period = Some(period);
+    sig.period_max = period_max;
+    sig.period_unit = Some(unit.to_string());
+    Ok(())
+}
+
+// Times of day: one start time is repeated through the day at the dosing
+// interval, several times must match the number of daily doses
+fn schedule_times(sig: &mut Sig, times: Vec<NaiveTime>, text: &str) -> Result<()> {
+    if times.is_empty() {
+        return Ok(());
+    }
+    if sig.frequency.is_none() {
+        // "at 08:00 and 20:00" is twice daily
+        sig.frequency = Some(times.len() as i32);
+        sig.period = Some(1.0);
+        sig.period_unit = Some("d".to_string());
+    }
+
+    let mut times = match (times.as_slice(), sig.doses_per_day()) {
+        ([start], Some(per_day)) if per_day > 1.0 => {
+            let minutes = 1440.0 / per_day;
+            if per_day.fract() != 0.0 || minutes.fract() != 0.0 {
+                return Err(anyhow!("Cannot schedule {} doses a day from a start time in \"{}\"", per_day, text));
+            }
+            (0..per_day as i64)
+                .map(|i| *start + Duration::minutes(i * minutes as i64))
+                .collect()
+        }
+        (times, Some(per_day)) if times.len() > 1 && times.len() as f64 != per_day => {
+            return Err(anyhow!("{} times of day given for {} dose(s) a day in \"{}\"", times.len(), per_day, text));
+        }
+        (times, _) => times.to_vec(),
+    };
+    times.sort();
+    sig.time_of_day = times.iter()
+        .map(|t| format!("{:02}:{:02}:00", t.hour(), t.minute()))
+        .collect();
+    Ok(())
+}
+
+// Length of a period in hours
+fn period_hours(period: f64, unit: &str) -> Option<f64> {
+    let hours = match unit {
+        "min" => 1.0 / 60.0,
+        "h" => 1.0,
+        "d" => 24.0,
+        "wk" => 168.0,
+        "mo" => 720.0,
+        _ => return None,
+    };
+    Some(period * hours)
+}
+
+fn period_unit(word: &str) -> Option<&'static str> {
+    Some(match word {
+        "min" | "mins" | "minute" | "minutes" => "min",
+        "h" | "hr" | "hrs" | "hour" | "hours" => "h",
+        "d" | "day" | "days" => "d",
+        "w" | "wk" | "wks" | "week" | "weeks" => "wk",
+        "mo" | "month" | "months" => "mo",
+        _ => return None,
+    })
+}
+
+// "once", "twice", "3 times", "3x": (frequency, words used)
+fn times_word(count: &str, tail: &[&str]) -> Option<(i32, usize)> {
+    match (count, tail) {
+        ("once", _) => Some((1, 0)),
+        ("twice", _) => Some((2, 0)),
+        ("thrice", _) => Some((3, 0)),
+        (n, ["times" | "x", ..]) => number(n).filter(|n| n.fract() == 0.0).map(|n| (n as i32, 1)),
+        (n, _) => n.strip_suffix('x').and_then(number).filter(|n| n.fract() == 0.0).map(|n| (n as i32, 0)),
+    }
+}
+
+// "3", "0.5" or "three"
+fn number(word: &str) -> Option<f64> {
+    const WORDS: [&str; 12] = ["one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+                               "ten", "eleven", "twelve"];
+    match WORDS.iter().position(|w| *w == word) {
+        Some(i) => Some(i as f64 + 1.0),
+        None => word.parse().ok().filter(|n: &f64| n.is_finite()),
+    }
+}
+
+// "daily", "a day", "per week": (period, unit, words used)
+fn per_period(words: &[&str]) -> Option<(f64, &'static str, usize)> {
+    match words {
+        ["daily" | "nightly", ..] => Some((1.0, "d", 1)),
+        ["hourly", ..] => Some((1.0, "h", 1)),
+        ["weekly", ..] => Some((1.0, "wk", 1)),
+        ["monthly", ..] => Some((1.0, "mo", 1)),
+        ["a" | "per" | "each" | "every", unit, ..] => period_unit(unit).map(|unit| (1.0, unit, 2)),
+        _ => None,
+    }
+}
+
+// After "every": "6 hours", "4-6 hours", "4 to 6 hours", "other day", "day",
+// "morning": (period, period max, unit, words used)
+fn interval(words: &[&str]) -> Option<(f64, Option<f64>, &'static str, usize)> {
+    match words {
+        ["other", unit, ..] => period_unit(unit).map(|unit| (2.0, None, unit, 2)),
+        [low, "to" | "-", high, unit, ..] => {
+            Some((number(low)?, Some(number(high)?), period_unit(unit)?, 4))
+        }
+        [range, unit, ..] if range.contains('-') => {
+            let (low, high) = range.split_once('-')?;
+            Some((number(low)?, Some(number(high)?), period_unit(unit)?, 2))
+        }
+        [n, unit, ..] if number(n).is_some() => {
+            Some((number(n)?, None, period_unit(unit)?, 2))
+        }
+        [unit, ..] => period_unit(unit).map(|unit| (1.0, None, unit, 1)),
+        [] => None,
+    }
+}
+
+// "q6h", "q4-6h", "q2d"
+fn compact_interval(word: &str) -> Option<(f64, Option<f64>, &'static str)> {
+    let rest = word.strip_prefix('q')?;
+    let split = rest.find(|c: char| c.is_ascii_alphabetic())?;
+    let (number, unit) = rest.split_at(split);
+    let unit = period_unit(unit)?;
+    match number.split_once('-') {
+        Some((low, high)) => Some((low.parse().ok()?, Some(high.parse().ok()?), unit)),
+        None => Some((number.parse().ok()?, None, unit)),
+    }
+}
+
+// "08:00", "8am", "8:30 pm", "20:00": (time, words used)
+fn time_of_day(words: &[&str]) -> Option<(NaiveTime, usize)> {
+    let (text, meridiem, used) = match words {
+        [time, meridiem @ ("am" | "pm"), ..] => (*time, Some(*meridiem), 2),
+        [time, ..] => match time.strip_suffix("am").or_else(|| time.strip_suffix("pm")) {
+            Some(stripped) => (stripped, Some(&time[stripped.len()..]), 1),
+            None => (*time, None, 1),
+        },
+        [] => return None,
+    };
+    let (hour, minute) = match text.split_once(':') {
+        Some((hour, minute)) => (hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?),
+        // A bare number is only a time with am/pm
+        None if meridiem.is_some() => (text.parse::<u32>().ok()?, 0),
+        None => return None,
+    };
+    let hour = match meridiem {
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some("am") => hour % 12,
+        Some(_) => hour % 12 + 12,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0).map(|time| (time, used))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn times(sig: &Sig) -> Vec<&str> {
+        sig.time_of_day.iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn abbreviations() {
+        let bid = parse_sig("BID").unwrap();
+        assert_eq!((bid.frequency, bid.period, bid.period_unit.as_deref()), (Some(2), Some(1.0), Some("d")));
+        assert_eq!(parse_sig("b.i.d.").unwrap(), bid);
+        assert_eq!(bid.doses_per_day(), Some(2.0));
+
+        let q6h = parse_sig("q6h").unwrap();
+        assert_eq!((q6h.frequency, q6h.period, q6h.period_unit.as_deref()), (Some(1), Some(6.0), Some("h")));
+        assert_eq!(q6h.doses_per_day(), Some(4.0));
+        assert_eq!(parse_sig("every 6 hours").unwrap(), q6h);
+    }
+
+    #[test]
+    fn interval_range() {
+        let sig = parse_sig("q4-6h").unwrap();
+        assert_eq!((sig.period, sig.period_max, sig.period_unit.as_deref()), (Some(4.0), Some(6.0), Some("h")));
+        assert_eq!(parse_sig("every 4 to 6 hours").unwrap(), sig);
+        assert!(parse_sig("q6-4h").is_err());
+    }
+
+    #[test]
+    fn as_needed() {
+        let sig = parse_sig("PRN").unwrap();
+        assert!(sig.as_needed);
+        assert_eq!(sig.frequency, None);
+        assert!(sig.timing().repeat.is_none());
+
+        let sig = parse_sig("q4h prn").unwrap();
+        assert!(sig.as_needed);
+        assert_eq!(sig.frequency, Some(1));
+    }
+
+    #[test]
+    fn meals() {
+        let sig = parse_sig("with meals").unwrap();
+        assert_eq!(sig.when, vec!["C"]);
+        assert_eq!((sig.frequency, sig.period_unit.as_deref()), (Some(3), Some("d")));
+
+        let sig = parse_sig("twice daily before breakfast and before dinner").unwrap();
+        assert_eq!(sig.frequency, Some(2));
+        assert_eq!(sig.when, vec!["ACM", "ACV"]);
+    }
+
+    #[test]
+    fn times_of_day() {
+        let sig = parse_sig("every 24 hours starting 08:00").unwrap();
+        assert_eq!((sig.frequency, sig.period, sig.period_unit.as_deref()), (Some(1), Some(24.0), Some("h")));
+        assert_eq!(times(&sig), vec!["08:00:00"]);
+
+        let sig = parse_sig("every 8 hours starting 6am").unwrap();
+        assert_eq!(times(&sig), vec!["06:00:00", "14:00:00", "22:00:00"]);
+
+        let sig = parse_sig("at 8am and 8pm").unwrap();
+        assert_eq!(sig.frequency, Some(2));
+        assert_eq!(times(&sig), vec!["08:00:00", "20:00:00"]);
+    }
+
+    #[test]
+    fn rejects_nonsense() {
+        for text in ["", "sometimes", "bid tid", "with snacks", "every", "3 times", "q0h", "every 0 hours",
+                     "at 08:00 and 12:00 and 20:00 bid", "every 7 hours starting 08:00"] {
+            assert!(parse_sig(text).is_err(), "accepted {:?}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_doses_too_close_together() {
+        for text in ["every 0.3 minutes", "q0.35min", "every 10 minutes", "100 times daily", "q1-2min"] {
+            assert!(parse_sig(text).is_err(), "accepted {:?}", text);
+        }
+        assert_eq!(parse_sig("every 15 minutes").unwrap().doses_per_day(), Some(96.0));
+    }
+}
@@ -0,0 +1,283 @@
+// src/signing.rs
+// Charcot EMR: Clinician signing keys and signed commits
+//
+// Each clinician has an Ed25519 key pair. The secret key is kept in
+// `clinician_keys/<clinician>.key`, encrypted with AES-256-GCM under a key
+// derived from their passphrase with Argon2id; the public key is registered in
+// `clinicians.json`. While a clinician is signed in every version committed is
+// signed over its chained hash, so the signature also covers its author, time,
+// message, content and everything before it.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce
+};
+use argon2::Argon2;
+use base64::{Engine as _, engine::general_purpose};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, Signer as _, SigningKey, Verifier as _, VerifyingKey};
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, anyhow, Context};
+
+use crate::{EMR, VersionEntry};
+
+pub const KEYS_DIR: &str = "clinician_keys";
+pub const CLINICIANS_FILE: &str = "clinicians.json";
+
+const SALT_LEN: usize = 16;
+
+// A clinician's signature on a version
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommitSignature {
+    pub signer: String,
+    pub public_key: String,     // Base64 Ed25519 public key
+    pub signature: String,      // Base64 Ed25519 signature of the version hash
+}
+
+// A clinician's secret key as stored on disk
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyFile {
+    pub clinician: String,
+    pub public_key: String,     // Base64 Ed25519 public key
+    pub salt: String,           // Base64 Argon2id salt
+    pub iv: String,             // Base64 AES-GCM nonce
+    pub data: String,           // Base64 encrypted secret key
+    pub created: DateTime<Utc>,
+}
+
+pub fn key_path(clinician: &str) -> PathBuf {
+    Path::new(KEYS_DIR).join(format!("{}.key", clinician))
+}
+
+// Clinician names become file names, so keep them to a safe set of characters
+pub fn validate_clinician(clinician: &str) -> Result<()> {
+    if clinician.is_empty()
+        || !clinician.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        || clinician.starts_with('.') {
+        return Err(anyhow!("Clinician names may only use letters, digits, '.', '-' and '_': {}", clinician));
+    }
+    Ok(())
+}
+
+fn passphrase_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+// An unlocked signing key
+#[derive(Clone)]
+pub struct Signer {
+    pub clinician: String,
+    key: SigningKey,
+}
+
+impl fmt::Debug for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Signer({})", self.clinician)
+    }
+}
+
+impl Signer {
+    // Generate a key pair for `clinician`, save the secret key encrypted with
+    // `passphrase` and register the public key. Existing keys are never
+    // replaced, as versions signed with them could no longer be attributed.
+    pub fn generate(clinician: &str, passphrase: &str, registry: &mut ClinicianRegistry) -> Result<Signer> {
+        validate_clinician(clinician)?;
+        if passphrase.is_empty() {
+            return Err(anyhow!("A passphrase is required to protect the signing key"));
+        }
+        let path = key_path(clinician);
+        if path.exists() || registry.clinicians.contains_key(clinician) {
+            return Err(anyhow!("{} already has a signing key", clinician));
+        }
+
+        let key = SigningKey::generate(&mut OsRng);
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&passphrase_key(passphrase, &salt)?));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let encrypted = cipher.encrypt(&nonce, key.to_bytes().as_ref())
+            .map_err(|e| anyhow!("Encryption failed: {:?}", e))?;
+        let public_key = general_purpose::STANDARD.encode(key.verifying_key().to_bytes());
+        let file = KeyFile {
+            clinician: clinician.to_string(),
+            public_key: public_key.clone(),
+            salt: general_purpose::STANDARD.encode(salt),
+            iv: general_purpose::STANDARD.encode(nonce),
+            data: general_purpose::STANDARD.encode(encrypted),
+            created: Utc::now(),
+        };
+
+        fs::create_dir_all(KEYS_DIR).with_context(|| format!("Failed to create {}", KEYS_DIR))?;
+        fs::write(&path, serde_json::to_string(&file)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        registry.clinicians.insert(clinician.to_string(), public_key);
+        registry.save(CLINICIANS_FILE)?;
+        Ok(Signer { clinician: clinician.to_string(), key })
+    }
+
+    // Decrypt `clinician`'s key with their passphrase
+    pub fn unlock(clinician: &str, passphrase: &str) -> Result<Signer> {
+        validate_clinician(clinician)?;
+        let path = key_path(clinician);
+        let json = fs::read_to_string(&path)
+            .with_context(|| format!("No signing key for {} ({})", clinician, path.display()))?;
+        let file: KeyFile = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid key file {}", path.display()))?;
+
+        let salt = general_purpose::STANDARD.decode(&file.salt)?;
+        let iv = general_purpose::STANDARD.decode(&file.iv)?;
+        let data = general_purpose::STANDARD.decode(&file.data)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&passphrase_key(passphrase, &salt)?));
+        let secret = cipher.decrypt(Nonce::from_slice(&iv), data.as_ref())
+            .map_err(|_| anyhow!("Wrong passphrase for {}'s signing key", clinician))?;
+        let secret: [u8; 32] = secret.as_slice().try_into()
+            .map_err(|_| anyhow!("Invalid signing key in {}", path.display()))?;
+        let key = SigningKey::from_bytes(&secret);
+        if general_purpose::STANDARD.encode(key.verifying_key().to_bytes()) != file.public_key {
+            return Err(anyhow!("Signing key in {} does not match its public key", path.display()));
+        }
+        Ok(Signer { clinician: file.clinician, key })
+    }
+
+    pub fn public_key(&self) -> String {
+        general_purpose::STANDARD.encode(self.key.verifying_key().to_bytes())
+    }
+
+    // Sign a version's hash
+    pub fn sign(&self, hash: &str) -> CommitSignature {
+        CommitSignature {
+            signer: self.clinician.clone(),
+            public_key: self.public_key(),
+            signature: general_purpose::STANDARD.encode(self.key.sign(hash.as_bytes()).to_bytes()),
+        }
+    }
+}
+
+// Whether a version's signature can be trusted
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignatureStatus {
+    Unsigned,
+    Valid(String),
+    UnknownKey(String),         // Valid, but not with the key registered to the signer
+    NotAuthor { signer: String, author: String },  // Valid, but signed by someone other than the author
+    Invalid(String),
+}
+
+impl SignatureStatus {
+    pub fn is_warning(&self) -> bool {
+        !matches!(self, SignatureStatus::Valid(_))
+    }
+
+    // Signed, but the signature does not show who wrote the version.
+    // Unsigned versions are only a warning, as older ones were never signed.
+    pub fn is_untrusted(&self) -> bool {
+        matches!(self, SignatureStatus::UnknownKey(_) | SignatureStatus::NotAuthor { .. } | SignatureStatus::Invalid(_))
+    }
+}
+
+impl fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureStatus::Unsigned => write!(f, "unsigned"),
+            SignatureStatus::Valid(signer) => write!(f, "signed by {}", signer),
+            SignatureStatus::UnknownKey(signer) => write!(f, "signed as {} with a key not registered to them", signer),
+            SignatureStatus::NotAuthor { signer, author } => write!(f, "signed by {} but authored by {}", signer, author),
+            SignatureStatus::Invalid(signer) => write!(f, "invalid signature claiming {}", signer),
+        }
+    }
+}
+
+// Public keys of the clinicians whose signatures are trusted
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ClinicianRegistry {
+    pub clinicians: BTreeMap<String, String>,   // Clinician -> base64 public key
+}
+
+impl ClinicianRegistry {
+    pub fn load(path: &str) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read clinician keys from {}", path))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Invalid clinician keys in {}", path))
+    }
+
+    // `clinicians.json` if present, otherwise no one
+    pub fn load_or_default() -> Result<Self> {
+        if Path::new(CLINICIANS_FILE).exists() {
+            ClinicianRegistry::load(CLINICIANS_FILE)
+        } else {
+            Ok(ClinicianRegistry::default())
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write clinician keys to {}", path))
+    }
+
+    pub fn verify(&self, version: &VersionEntry) -> SignatureStatus {
+        let Some(signature) = &version.signature else {
+            return SignatureStatus::Unsigned;
+        };
+        let signer = signature.signer.clone();
+        let public_key = general_purpose::STANDARD.decode(&signature.public_key).ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok());
+        let bytes = general_purpose::STANDARD.decode(&signature.signature).ok()
+            .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok());
+        let (Some(public_key), Some(bytes)) = (public_key, bytes) else {
+            return SignatureStatus::Invalid(signer);
+        };
+        if public_key.verify(version.hash.as_bytes(), &Signature::from_bytes(&bytes)).is_err() {
+            return SignatureStatus::Invalid(signer);
+        }
+        match self.clinicians.get(&signer) {
+            Some(registered) if *registered != signature.public_key => SignatureStatus::UnknownKey(signer),
+            None => SignatureStatus::UnknownKey(signer),
+            Some(_) if signer != version.author => {
+                SignatureStatus::NotAuthor { signer, author: version.author.clone() }
+            }
+            Some(_) => SignatureStatus::Valid(signer),
+        }
+    }
+}
+
+impl EMR {
+    // Sign every version committed from now on as `signer`, who also
+    // becomes the author
+    pub fn sign_in(&mut self, signer: Signer) {
+        self.user = signer.clinician.clone();
+        self.signer = Some(signer);
+    }
+
+    // The signature status of each of the patient's versions
+    pub fn signatures(&self, patient_id: &str) -> Result<Vec<SignatureStatus>> {
+        Ok(self.history(patient_id)?.iter().map(|version| self.clinicians.verify(version)).collect())
+    }
+
+    // e.g. "2 unsigned, 1 invalid signature"; empty if every version is
+    // signed by its registered clinician
+    pub fn signature_warnings(&self, patient_id: &str) -> Result<String> {
+        let statuses = self.signatures(patient_id)?;
+        let count = |wanted: fn(&SignatureStatus) -> bool| statuses.iter().filter(|status| wanted(status)).count();
+        let counts = [
+            (count(|status| matches!(status, SignatureStatus::Unsigned)), "unsigned"),
+            (count(|status| matches!(status, SignatureStatus::UnknownKey(_))), "signed with an unregistered key"),
+            (count(|status| matches!(status, SignatureStatus::NotAuthor { .. })), "signed by someone other than the author"),
+            (count(|status| matches!(status, SignatureStatus::Invalid(_))), "with an invalid signature"),
+        ];
+        let parts: Vec<String> = counts.iter()
+            .filter(|(n, _)| *n > 0)
+            .map(|(n, what)| format!("{} {} {}", n, if *n == 1 { "version" } else { "versions" }, what))
+            .collect();
+        Ok(parts.join(", "))
+    }
+}
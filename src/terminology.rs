@@ -0,0 +1,57 @@
+// src/terminology.rs
+// Charcot EMR: Built-in table of the clinical codes used by the EMR and its scripts
+
+// Coding system URIs used in FHIR resources
+pub const LOINC_SYSTEM: &str = "http://loinc.org";
+pub const RXNORM_SYSTEM: &str = "http://www.nlm.nih.gov/research/umls/rxnorm";
+pub const ICD10_SYSTEM: &str = "http://hl7.org/fhir/sid/icd-10-cm";
+pub const SNOMED_SYSTEM: &str = "http://snomed.info/sct";
+pub const ATC_SYSTEM: &str = "http://www.whocc.no/atc";
+pub const UCUM_SYSTEM: &str = "http://unitsofmeasure.org";
+pub const INTERPRETATION_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/v3-ObservationInterpretation";
+
+// (code, display) pairs for the LOINC observations the EMR records
+const LOINC: &[(&str, &str)] = &[
+    ("85354-9", "Blood pressure panel with all children optional"),
+    ("8480-6", "Systolic blood pressure"),
+    ("8462-4", "Diastolic blood pressure"),
+    ("2345-7", "Glucose [Mass/volume] in Serum or Plasma"),
+    ("4548-4", "Hemoglobin A1c/Hemoglobin.total in Blood"),
+    ("11502-2", "Laboratory report"),
+    ("2160-0", "Creatinine [Mass/volume] in Serum or Plasma"),
+    ("2823-3", "Potassium [Moles/volume] in Serum or Plasma"),
+    ("8867-4", "Heart rate"),
+    ("8310-5", "Body temperature"),
+    ("59408-5", "Oxygen saturation in Arterial blood by Pulse oximetry"),
+    ("9279-1", "Respiratory rate"),
+    ("29463-7", "Body weight"),
+    ("8302-2", "Body height"),
+];
+
+pub fn loinc_display(code: &str) -> Option<&'static str> {
+    LOINC.iter().find(|(c, _)| *c == code).map(|(_, display)| *display)
+}
+
+pub fn rxnorm_display(code: &str) -> Option<&'static str> {
+    crate::rxnorm::builtin_name(code)
+}
+
+pub fn icd10_display(code: &str) -> Option<&'static str> {
+    crate::conditions::builtin_icd10(code)
+}
+
+pub fn snomed_display(code: &str) -> Option<&'static str> {
+    crate::conditions::builtin_snomed(code)
+}
+
+// Human-readable description of `code` in the named Charcot code system
+// (`loinc`, `rxnorm`, `icd10`, `snomed`), e.g. for editor hovers
+pub fn describe(system: &str, code: &str) -> Option<String> {
+    match system {
+        "loinc" => loinc_display(code).map(|d| format!("LOINC {}: {}", code, d)),
+        "rxnorm" => rxnorm_display(code).map(|d| format!("RxNorm {}: {}", code, d)),
+        "icd10" => icd10_display(code).map(|d| format!("ICD-10-CM {}: {}", code, d)),
+        "snomed" => snomed_display(code).map(|d| format!("SNOMED CT {}: {}", code, d)),
+        _ => None,
+    }
+}
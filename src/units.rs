@@ -0,0 +1,442 @@
+// src/units.rs
+// Charcot EMR: UCUM unit parsing and conversion
+//
+// A unit code such as "mg/dL", "mm[Hg]" or "[lb_av]" is parsed into a scale
+// factor over the base units m, g, s, mol and K (plus UCUM's arbitrary units
+// like [iU]). Two units are commensurable when they have the same dimension;
+// mass and molar concentrations of one analyte convert through its molar mass.
+// Common spellings that are not UCUM ("mmHg", "lb", "°F", "units") are
+// accepted as aliases.
+
+use std::cmp::Ordering;
+use std::fmt;
+use anyhow::{Result, anyhow};
+
+use crate::Quantity;
+use crate::terminology::UCUM_SYSTEM;
+
+// Exponents of length, mass, time, amount of substance, temperature and
+// arbitrary units
+pub type Dims = [i8; 6];
+
+const MASS: usize = 1;
+const AMOUNT: usize = 3;
+
+const fn dims(length: i8, mass: i8, time: i8, amount: i8, temperature: i8, arbitrary: i8) -> Dims {
+    [length, mass, time, amount, temperature, arbitrary]
+}
+
+// (code, factor to base units, dimension, takes metric prefixes)
+const ATOMS: &[(&str, f64, Dims, bool)] = &[
+    ("m", 1.0, dims(1, 0, 0, 0, 0, 0), true),
+    ("g", 1.0, dims(0, 1, 0, 0, 0, 0), true),
+    ("s", 1.0, dims(0, 0, 1, 0, 0, 0), true),
+    ("mol", 1.0, dims(0, 0, 0, 1, 0, 0), true),
+    ("eq", 1.0, dims(0, 0, 0, 1, 0, 0), true),  // monovalent ions only
+    ("K", 1.0, dims(0, 0, 0, 0, 1, 0), false),
+    ("L", 1e-3, dims(3, 0, 0, 0, 0, 0), true),
+    ("l", 1e-3, dims(3, 0, 0, 0, 0, 0), true),
+    ("Pa", 1e3, dims(-1, 1, -2, 0, 0, 0), true),
+    ("m[Hg]", 133_322_387.415, dims(-1, 1, -2, 0, 0, 0), true),
+    ("min", 60.0, dims(0, 0, 1, 0, 0, 0), false),
+    ("h", 3600.0, dims(0, 0, 1, 0, 0, 0), false),
+    ("d", 86_400.0, dims(0, 0, 1, 0, 0, 0), false),
+    ("wk", 604_800.0, dims(0, 0, 1, 0, 0, 0), false),
+    ("mo", 2_629_800.0, dims(0, 0, 1, 0, 0, 0), false),
+    ("a", 31_557_600.0, dims(0, 0, 1, 0, 0, 0), false),
+    ("[lb_av]", 453.592_37, dims(0, 1, 0, 0, 0, 0), false),
+    ("[oz_av]", 28.349_523_125, dims(0, 1, 0, 0, 0, 0), false),
+    ("[in_i]", 0.0254, dims(1, 0, 0, 0, 0, 0), false),
+    ("[ft_i]", 0.3048, dims(1, 0, 0, 0, 0, 0), false),
+    ("%", 0.01, dims(0, 0, 0, 0, 0, 0), false),
+    ("[iU]", 1.0, dims(0, 0, 0, 0, 0, 1), true),
+    ("[IU]", 1.0, dims(0, 0, 0, 0, 0, 1), true),
+];
+
+// Units measured from their own zero: (code, factor, offset) with
+// kelvin = value * factor + offset
+const OFFSET_UNITS: &[(&str, f64, f64)] = &[
+    ("Cel", 1.0, 273.15),
+    ("[degF]", 5.0 / 9.0, 459.67 * 5.0 / 9.0),
+];
+
+const PREFIXES: &[(&str, f64)] = &[
+    ("da", 1e1), ("k", 1e3), ("M", 1e6), ("G", 1e9), ("h", 1e2),
+    ("d", 1e-1), ("c", 1e-2), ("m", 1e-3), ("u", 1e-6), ("μ", 1e-6), ("n", 1e-9), ("p", 1e-12),
+];
+
+// Everyday spellings and their UCUM codes
+const ALIASES: &[(&str, &str)] = &[
+    ("mmHg", "mm[Hg]"),
+    ("lb", "[lb_av]"), ("lbs", "[lb_av]"), ("oz", "[oz_av]"),
+    ("in", "[in_i]"), ("ft", "[ft_i]"),
+    ("°C", "Cel"), ("C", "Cel"), ("degC", "Cel"),
+    ("°F", "[degF]"), ("F", "[degF]"), ("degF", "[degF]"),
+    ("units", "[iU]"), ("unit", "[iU]"), ("IU", "[iU]"), ("U", "[iU]"),
+    ("bpm", "/min"), ("beats/min", "/min"), ("breaths/min", "/min"),
+    ("mcg", "ug"), ("cc", "mL"), ("sec", "s"), ("mEq", "meq"),
+    ("minute", "min"), ("minutes", "min"),
+    ("hr", "h"), ("hour", "h"), ("hours", "h"),
+    ("day", "d"), ("days", "d"), ("week", "wk"), ("weeks", "wk"),
+    ("month", "mo"), ("months", "mo"), ("year", "a"), ("years", "a"),
+];
+
+// Molar masses (g/mol) of analytes reported in both mass and molar units,
+// by name and LOINC code
+const MOLAR_MASSES: &[(&str, &str, f64)] = &[
+    ("glucose", "2345-7", 180.156),
+    ("cholesterol", "2093-3", 386.654),
+    ("ldl", "13457-7", 386.654),
+    ("hdl", "2085-9", 386.654),
+    ("triglycerides", "2571-8", 885.7),
+    ("creatinine", "2160-0", 113.12),
+];
+
+// Physical dimension of a unit, used in messages like "cannot compare mass and length"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dimension {
+    Mass,
+    MassConcentration,
+    MolarConcentration,
+    Amount,
+    Pressure,
+    Time,
+    Length,
+    Volume,
+    Temperature,
+    Fraction,
+    Frequency,
+    Units,
+    Other(Dims),
+}
+
+impl Dimension {
+    fn from_dims(d: Dims) -> Dimension {
+        match d {
+            [0, 1, 0, 0, 0, 0] => Dimension::Mass,
+            [-3, 1, 0, 0, 0, 0] => Dimension::MassConcentration,
+            [-3, 0, 0, 1, 0, 0] => Dimension::MolarConcentration,
+            [0, 0, 0, 1, 0, 0] => Dimension::Amount,
+            [-1, 1, -2, 0, 0, 0] => Dimension::Pressure,
+            [0, 0, 1, 0, 0, 0] => Dimension::Time,
+            [1, 0, 0, 0, 0, 0] => Dimension::Length,
+            [3, 0, 0, 0, 0, 0] => Dimension::Volume,
+            [0, 0, 0, 0, 1, 0] => Dimension::Temperature,
+            [0, 0, 0, 0, 0, 0] => Dimension::Fraction,
+            [0, 0, -1, 0, 0, 0] => Dimension::Frequency,
+            [0, 0, 0, 0, 0, 1] => Dimension::Units,
+            other => Dimension::Other(other),
+        }
+    }
+}
+
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Dimension::Mass => "mass",
+            Dimension::MassConcentration => "mass concentration",
+            Dimension::MolarConcentration => "molar concentration",
+            Dimension::Amount => "amount of substance",
+            Dimension::Pressure => "pressure",
+            Dimension::Time => "time",
+            Dimension::Length => "length",
+            Dimension::Volume => "volume",
+            Dimension::Temperature => "temperature",
+            Dimension::Fraction => "fraction",
+            Dimension::Frequency => "frequency",
+            Dimension::Units => "units",
+            Dimension::Other(d) => {
+                let base = ["m", "g", "s", "mol", "K", "[iU]"];
+                let parts: Vec<String> = base.iter().zip(d)
+                    .filter(|(_, &e)| e != 0)
+                    .map(|(b, e)| if *e == 1 { b.to_string() } else { format!("{}{}", b, e) })
+                    .collect();
+                return write!(f, "quantity in {}", parts.join("."));
+            }
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// A parsed unit: base value = value * factor + offset
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Unit {
+    pub factor: f64,
+    pub offset: f64,
+    pub dims: Dims,
+}
+
+impl Unit {
+    pub fn dimension(&self) -> Dimension {
+        Dimension::from_dims(self.dims)
+    }
+
+    pub fn is_commensurable(&self, other: &Unit) -> bool {
+        self.dims == other.dims
+    }
+
+    // `self` times `other` to the power `exponent`; None if an exponent
+    // or the factor goes out of range
+    fn times(self, other: Unit, exponent: i8) -> Option<Unit> {
+        let mut dims = self.dims;
+        for (d, o) in dims.iter_mut().zip(other.dims) {
+            *d = o.checked_mul(exponent).and_then(|e| d.checked_add(e))?;
+        }
+        let factor = self.factor * other.factor.powi(exponent.into());
+        (factor.is_finite() && factor != 0.0).then_some(Unit { factor, offset: 0.0, dims })
+    }
+}
+
+const ONE: Unit = Unit { factor: 1.0, offset: 0.0, dims: [0; 6] };
+
+// UCUM code for `unit`, resolving aliases ("lb" -> "[lb_av]")
+pub fn ucum_code(unit: &str) -> String {
+    let unit = unit.trim();
+    ALIASES.iter()
+        .find(|(alias, _)| *alias == unit)
+        .map(|(_, code)| code.to_string())
+        .unwrap_or_else(|| unit.to_string())
+}
+
+pub fn parse(unit: &str) -> Result<Unit> {
+    let code = ucum_code(unit);
+    if code.is_empty() || code == "1" {
+        return Ok(ONE);
+    }
+    if let Some((_, factor, offset)) = OFFSET_UNITS.iter().find(|(c, _, _)| *c == code) {
+        return Ok(Unit { factor: *factor, offset: *offset, dims: dims(0, 0, 0, 0, 1, 0) });
+    }
+
+    let mut result = ONE;
+    let mut exponent: i8 = 1;
+    let mut start = 0;
+    let mut depth = 0;
+    // Split on `.` and `/` outside brackets and braces
+    for (i, c) in code.char_indices().chain([(code.len(), '.')]) {
+        match c {
+            '[' | '{' => depth += 1,
+            ']' | '}' => depth -= 1,
+            '.' | '/' if depth == 0 => {
+                let component = &code[start..i];
+                if !component.is_empty() {
+                    result = result.times(parse_component(component, unit)?, exponent)
+                        .ok_or_else(|| anyhow!("Unit `{}` is out of range", unit))?;
+                } else if i > 0 {
+                    return Err(anyhow!("Invalid unit `{}`", unit));
+                }
+                if c == '/' {
+                    exponent = -1;
+                }
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    Ok(result)
+}
+
+// One factor of a unit term: an atom with optional prefix and exponent
+// ("cm2", "s-1"), a power of ten ("10*3") or an annotation ("{beats}")
+fn parse_component(component: &str, unit: &str) -> Result<Unit> {
+    let invalid = || anyhow!("Unknown unit `{}`", unit);
+    // Annotations have no effect on the value
+    let stripped: String = {
+        let mut out = String::new();
+        let mut depth = 0;
+        for c in component.chars() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ if depth == 0 => out.push(c),
+                _ => {}
+            }
+        }
+        out
+    };
+    if stripped.is_empty() {
+        return Ok(ONE);
+    }
+    if let Some(power) = stripped.strip_prefix("10*").or_else(|| stripped.strip_prefix("10^")) {
+        let power: i32 = power.parse().map_err(|_| invalid())?;
+        return ONE.times(Unit { factor: 10f64.powi(power), offset: 0.0, dims: [0; 6] }, 1)
+            .ok_or_else(|| anyhow!("Unit `{}` is out of range", unit));
+    }
+    if let Ok(number) = stripped.parse::<f64>() {
+        return ONE.times(Unit { factor: number, offset: 0.0, dims: [0; 6] }, 1)
+            .ok_or_else(|| anyhow!("Unit `{}` is out of range", unit));
+    }
+
+    // Trailing exponent: "cm2", "s-1"
+    let base_end = stripped.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let (atom, exponent) = if base_end > 0 && base_end < stripped.len() {
+        let base = &stripped[..base_end];
+        let base = base.strip_suffix(['-', '+']).unwrap_or(base);
+        let exponent: i8 = stripped[base.len()..].parse()
+            .map_err(|_| anyhow!("Exponent out of range in unit `{}`", unit))?;
+        (base, exponent)
+    } else {
+        (stripped.as_str(), 1)
+    };
+    let atom = ucum_code(atom);
+    ONE.times(atom_unit(&atom).ok_or_else(invalid)?, exponent)
+        .ok_or_else(|| anyhow!("Unit `{}` is out of range", unit))
+}
+
+fn atom_unit(atom: &str) -> Option<Unit> {
+    let unit = |factor: f64, dims: Dims| Unit { factor, offset: 0.0, dims };
+    if let Some((_, factor, d, _)) = ATOMS.iter().find(|(code, ..)| *code == atom) {
+        return Some(unit(*factor, *d));
+    }
+    PREFIXES.iter().find_map(|(prefix, scale)| {
+        let rest = atom.strip_prefix(prefix)?;
+        ATOMS.iter()
+            .find(|(code, _, _, metric)| *metric && *code == rest)
+            .map(|(_, factor, d, _)| unit(scale * factor, *d))
+    })
+}
+
+// Convert `value` from one unit to another
+pub fn convert(value: f64, from: &str, to: &str) -> Result<f64> {
+    let (source, target) = (parse(from)?, parse(to)?);
+    if !source.is_commensurable(&target) {
+        return Err(anyhow!("Cannot convert {} ({}) to {} ({})",
+                           from, source.dimension(), to, target.dimension()));
+    }
+    Ok(round((value * source.factor + source.offset - target.offset) / target.factor))
+}
+
+// Drop the floating point noise conversion factors leave behind
+// (37.2 Cel -> K -> Cel gives 37.19999999999999)
+fn round(value: f64) -> f64 {
+    if value == 0.0 || !value.is_finite() {
+        return value;
+    }
+    let scale = 10f64.powi(12 - value.abs().log10().ceil() as i32);
+    (value * scale).round() / scale
+}
+
+// Molar mass of an analyte, by name or LOINC code
+pub fn molar_mass(analyte: &str) -> Option<f64> {
+    MOLAR_MASSES.iter()
+        .find(|(name, loinc, _)| name.eq_ignore_ascii_case(analyte) || *loinc == analyte)
+        .map(|(_, _, mass)| *mass)
+}
+
+// Convert a result for `analyte`, going between mass and molar units through
+// its molar mass when needed (glucose 10 mmol/L = 180 mg/dL)
+pub fn convert_analyte(value: f64, from: &str, to: &str, analyte: &str) -> Result<f64> {
+    let (source, target) = (parse(from)?, parse(to)?);
+    if source.is_commensurable(&target) {
+        return convert(value, from, to);
+    }
+
+    let mut per_mole = source.dims;
+    per_mole[MASS] -= 1;
+    per_mole[AMOUNT] += 1;
+    let mass_to_molar = per_mole == target.dims;
+    let mut per_gram = source.dims;
+    per_gram[MASS] += 1;
+    per_gram[AMOUNT] -= 1;
+    let molar_to_mass = per_gram == target.dims;
+    if !mass_to_molar && !molar_to_mass {
+        return Err(anyhow!("Cannot convert {} ({}) to {} ({})",
+                           from, source.dimension(), to, target.dimension()));
+    }
+    let mass = molar_mass(analyte)
+        .ok_or_else(|| anyhow!("Cannot convert {} to {} without a known molar mass", from, to))?;
+    let base = value * source.factor;
+    let base = if mass_to_molar { base / mass } else { base * mass };
+    Ok(round(base / target.factor))
+}
+
+impl Quantity {
+    // A UCUM-coded quantity, resolving unit aliases
+    pub fn ucum(value: f64, unit: &str) -> Result<Quantity> {
+        parse(unit)?;
+        Ok(Quantity {
+            value,
+            unit: unit.to_string(),
+            system: UCUM_SYSTEM.to_string(),
+            code: ucum_code(unit),
+        })
+    }
+
+    pub fn convert_to(&self, unit: &str) -> Result<Quantity> {
+        let value = convert(self.value, &self.code, unit)?;
+        Quantity::ucum(value, unit)
+    }
+
+    // Compare two quantities in commensurable units
+    pub fn compare(&self, other: &Quantity) -> Result<Ordering> {
+        let other = convert(other.value, &other.code, &self.code)?;
+        self.value.partial_cmp(&other)
+            .ok_or_else(|| anyhow!("Cannot compare {} and {}", self.value, other))
+    }
+}
+
+// Split "85.5kg", "10 mmol/L" or "72" into value and optional unit
+pub fn split_quantity(text: &str) -> Result<(f64, Option<&str>)> {
+    let text = text.trim();
+    let end = text.char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || (i == 0 && (c == '-' || c == '+'))))
+        .map(|(i, _)| i)
+        .unwrap_or(text.len());
+    let value: f64 = text[..end].parse().map_err(|_| anyhow!("Invalid quantity: {}", text))?;
+    let unit = text[end..].trim();
+    if unit.is_empty() {
+        return Ok((value, None));
+    }
+    parse(unit)?;
+    Ok((value, Some(unit)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glucose_mass_and_molar() {
+        assert_eq!(convert_analyte(180.156, "mg/dL", "mmol/L", "glucose").unwrap(), 10.0);
+        assert_eq!(convert_analyte(5.5, "mmol/L", "mg/dL", "2345-7").unwrap(), 99.0858);
+        assert!(convert_analyte(100.0, "mg/dL", "mmol/L", "sodium").is_err());
+    }
+
+    #[test]
+    fn weight() {
+        assert_eq!(convert(70.0, "kg", "[lb_av]").unwrap(), 154.323583529);
+        assert_eq!(convert(1.0, "lb", "kg").unwrap(), 0.45359237);
+        assert_eq!(convert(1.0, "kg", "g").unwrap(), 1000.0);
+    }
+
+    #[test]
+    fn temperature() {
+        assert_eq!(convert(37.0, "Cel", "[degF]").unwrap(), 98.6);
+        assert_eq!(convert(98.6, "°F", "°C").unwrap(), 37.0);
+        assert_eq!(convert(37.2, "Cel", "K").unwrap(), 310.35);
+    }
+
+    #[test]
+    fn incompatible_units() {
+        let error = convert(70.0, "kg", "cm").unwrap_err();
+        assert_eq!(error.to_string(), "Cannot convert kg (mass) to cm (length)");
+        assert!(convert(5.0, "mg/dL", "mmol/L").is_err());
+        assert!(convert(1.0, "Cel", "mmHg").is_err());
+    }
+
+    #[test]
+    fn dimensions() {
+        assert_eq!(parse("mg/dL").unwrap().dimension(), Dimension::MassConcentration);
+        assert_eq!(parse("/min").unwrap().dimension(), Dimension::Frequency);
+        assert_eq!(parse("kg/m2").unwrap().dimension().to_string(), "quantity in m-2.g");
+        assert_eq!(parse("units").unwrap().dimension(), Dimension::Units);
+    }
+
+    #[test]
+    fn out_of_range_units_are_errors() {
+        for unit in ["m100.m100", "kg256", "m127.m127", "s-128.s-1", "10*400", "10*-400", "1e308.1e308", "xyz"] {
+            assert!(parse(unit).is_err(), "accepted {:?}", unit);
+        }
+        assert!(split_quantity("70m100.m100").is_err());
+        assert_eq!(parse("m127").unwrap().dims[0], 127);
+    }
+}
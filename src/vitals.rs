@@ -0,0 +1,188 @@
+// src/vitals.rs
+// Charcot EMR: Single-value vital signs (heart rate, temperature, SpO2, ...)
+//
+// Blood pressure has two components and keeps its own type in lib.rs; every
+// other vital is one LOINC-coded quantity in a fixed UCUM unit.
+
+use std::fmt;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use anyhow::{Result, anyhow, Context};
+
+use crate::units;
+use crate::terminology::{LOINC_SYSTEM, UCUM_SYSTEM};
+use crate::{BundleEntry, Coding, EMR, Observation, Quantity, Reference, Resource, validate_clinical_time};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VitalKind {
+    HeartRate,
+    Temperature,
+    OxygenSaturation,
+    RespiratoryRate,
+    Weight,
+    Height,
+}
+
+impl VitalKind {
+    pub const ALL: [VitalKind; 6] = [
+        VitalKind::HeartRate,
+        VitalKind::Temperature,
+        VitalKind::OxygenSaturation,
+        VitalKind::RespiratoryRate,
+        VitalKind::Weight,
+        VitalKind::Height,
+    ];
+
+    // Short name used on the command line (`emr_cli add-vital 123 hr 72 <key>`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            VitalKind::HeartRate => "hr",
+            VitalKind::Temperature => "temp",
+            VitalKind::OxygenSaturation => "spo2",
+            VitalKind::RespiratoryRate => "rr",
+            VitalKind::Weight => "weight",
+            VitalKind::Height => "height",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<VitalKind> {
+        VitalKind::ALL.iter().copied().find(|kind| kind.name() == name.to_lowercase())
+    }
+
+    pub fn loinc_code(&self) -> &'static str {
+        match self {
+            VitalKind::HeartRate => "8867-4",
+            VitalKind::Temperature => "8310-5",
+            VitalKind::OxygenSaturation => "59408-5",
+            VitalKind::RespiratoryRate => "9279-1",
+            VitalKind::Weight => "29463-7",
+            VitalKind::Height => "8302-2",
+        }
+    }
+
+    pub fn from_loinc(code: &str) -> Option<VitalKind> {
+        VitalKind::ALL.iter().copied().find(|kind| kind.loinc_code() == code)
+    }
+
+    pub fn display(&self) -> &'static str {
+        match self {
+            VitalKind::HeartRate => "Heart rate",
+            VitalKind::Temperature => "Body temperature",
+            VitalKind::OxygenSaturation => "Oxygen saturation",
+            VitalKind::RespiratoryRate => "Respiratory rate",
+            VitalKind::Weight => "Body weight",
+            VitalKind::Height => "Body height",
+        }
+    }
+
+    // (human-readable unit, UCUM code)
+    pub fn unit(&self) -> (&'static str, &'static str) {
+        match self {
+            VitalKind::HeartRate => ("beats/min", "/min"),
+            VitalKind::Temperature => ("°C", "Cel"),
+            VitalKind::OxygenSaturation => ("%", "%"),
+            VitalKind::RespiratoryRate => ("breaths/min", "/min"),
+            VitalKind::Weight => ("kg", "kg"),
+            VitalKind::Height => ("cm", "cm"),
+        }
+    }
+
+    // Physiologically plausible values, in the unit above
+    pub fn range(&self) -> (f64, f64) {
+        match self {
+            VitalKind::HeartRate => (20.0, 300.0),
+            VitalKind::Temperature => (25.0, 45.0),
+            VitalKind::OxygenSaturation => (50.0, 100.0),
+            VitalKind::RespiratoryRate => (4.0, 80.0),
+            VitalKind::Weight => (0.2, 650.0),
+            VitalKind::Height => (20.0, 275.0),
+        }
+    }
+}
+
+impl fmt::Display for VitalKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.display())
+    }
+}
+
+// A validated single-value vital sign
+pub struct Vital {
+    pub kind: VitalKind,
+    pub value: f64,
+}
+
+impl Vital {
+    pub fn new(kind: VitalKind, value: f64) -> Result<Self> {
+        let (low, high) = kind.range();
+        if !value.is_finite() || value < low || value > high {
+            return Err(anyhow!("Invalid {} value: {}. Expected range {}-{} {}",
+                               kind.display().to_lowercase(), value, low, high, kind.unit().0));
+        }
+        Ok(Vital { kind, value })
+    }
+
+    // A vital given in any unit of the right dimension, e.g. weight in `[lb_av]`
+    // or temperature in `[degF]`, converted to the kind's unit
+    pub fn from_quantity(kind: VitalKind, value: f64, unit: &str) -> Result<Self> {
+        let value = units::convert(value, unit, kind.unit().1)
+            .with_context(|| format!("Invalid unit for {}", kind.display().to_lowercase()))?;
+        Vital::new(kind, value)
+    }
+
+    pub fn to_observation_at(&self, patient_id: &str, effective: DateTime<Utc>) -> Observation {
+        let (unit, code) = self.kind.unit();
+        Observation {
+            id: Uuid::new_v4().to_string(),
+            status: "final".to_string(),
+            code: Coding {
+                system: LOINC_SYSTEM.to_string(),
+                code: self.kind.loinc_code().to_string(),
+                display: self.kind.display().to_string(),
+            },
+            subject: Reference {
+                reference: format!("Patient/{}", patient_id),
+            },
+            effective_date_time: effective.to_rfc3339(),
+            issued: Some(Utc::now().to_rfc3339()),
+            value_quantity: Some(Quantity {
+                value: self.value,
+                unit: unit.to_string(),
+                system: UCUM_SYSTEM.to_string(),
+                code: code.to_string(),
+            }),
+            component: None,
+            reference_range: None,
+            interpretation: None,
+            note: None,
+        }
+    }
+}
+
+impl EMR {
+    // Add a vital sign taken now
+    pub fn add_vital(&mut self, patient_id: &str, kind: VitalKind, value: f64) -> Result<()> {
+        self.add_vital_at(patient_id, kind, value, Utc::now())
+    }
+
+    // Add a vital sign taken at `effective`
+    pub fn add_vital_at(&mut self, patient_id: &str, kind: VitalKind, value: f64,
+                        effective: DateTime<Utc>) -> Result<()> {
+        let vital = Vital::new(kind, value)?;
+        validate_clinical_time(effective)?;
+        let observation = vital.to_observation_at(patient_id, effective);
+
+        let bundle = self.bundles.get_mut(patient_id)
+            .ok_or_else(|| anyhow!("Patient not found: {}", patient_id))?;
+
+        bundle.entry.push(BundleEntry {
+            resource_type: "Observation".to_string(),
+            resource: Resource::Observation(observation),
+        });
+
+        self.log_audit(&format!("Added {}: {} {} taken {}", kind.display(), value, kind.unit().0,
+                               effective.to_rfc3339()), patient_id)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+// tests/allergies.rs
+// Charcot EMR: Recording allergies and checking prescriptions against them
+
+mod common;
+
+use charcot_emr::{AllergyAction, MedicationChange, NewAllergy, PrescribeOptions, Resource};
+
+fn allergy(substance: &str) -> NewAllergy {
+    NewAllergy { substance: substance.to_string(), ..NewAllergy::default() }
+}
+
+fn with_reason(reason: &str) -> PrescribeOptions {
+    PrescribeOptions { override_reason: Some(reason.to_string()), ..PrescribeOptions::default() }
+}
+
+#[test]
+fn confirmed_high_criticality_allergy_is_refused() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.add_allergy("1", &NewAllergy {
+        criticality: "high".to_string(),
+        verification_status: "confirmed".to_string(),
+        reaction: Some("anaphylaxis".to_string()),
+        ..allergy("amoxicillin")
+    }).unwrap();
+
+    let error = emr.prescribe_medication_with("1", "amoxicillin", 500.0, "three times daily", &with_reason("no alternative"))
+        .unwrap_err();
+    assert!(error.to_string().contains("Refused due to allergy: amoxicillin: allergy to amoxicillin (reaction: anaphylaxis)"),
+            "{}", error);
+    assert!(emr.medication_requests("1").unwrap().is_empty());
+}
+
+#[test]
+fn other_allergies_need_an_audited_reason() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.add_allergy("1", &allergy("penicillins")).unwrap();
+
+    let error = emr.prescribe_medication("1", "amoxicillin", 500.0, "three times daily").unwrap_err();
+    assert!(error.to_string().contains("Allergy warning: amoxicillin: allergy to penicillins. An override reason is required"),
+            "{}", error);
+    emr.prescribe_medication_with("1", "amoxicillin", 500.0, "three times daily", &with_reason("tolerated before")).unwrap();
+    let audit = workspace.audit_log();
+    assert!(audit.contains("Allergy override for amoxicillin: amoxicillin: allergy to penicillins. Reason: tolerated before"),
+            "{}", audit);
+}
+
+#[test]
+fn cross_reactive_classes_only_warn() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.add_allergy("1", &allergy("penicillins")).unwrap();
+
+    let alerts = emr.check_allergies("1", "cephalexin").unwrap();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].action, AllergyAction::Warn);
+    emr.prescribe_medication("1", "cephalexin", 500.0, "four times daily").unwrap();
+    let audit = workspace.audit_log();
+    assert!(audit.contains("Allergy noted: cephalexin: allergy to penicillins, which may cross-react with"), "{}", audit);
+}
+
+#[test]
+fn intolerances_only_warn() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.add_allergy("1", &NewAllergy {
+        allergy_type: "intolerance".to_string(),
+        criticality: "low".to_string(),
+        verification_status: "confirmed".to_string(),
+        reaction: Some("nausea".to_string()),
+        ..allergy("metformin")
+    }).unwrap();
+
+    emr.prescribe_medication("1", "metformin", 500.0, "twice daily").unwrap();
+    let audit = workspace.audit_log();
+    assert!(audit.contains("Allergy noted: metformin: intolerance to metformin (reaction: nausea)"), "{}", audit);
+}
+
+#[test]
+fn renewal_is_refused_after_a_new_allergy() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.prescribe_medication("1", "amoxicillin", 500.0, "three times daily").unwrap();
+    emr.add_allergy("1", &allergy("amoxicillin")).unwrap();
+
+    let error = emr.renew_medication("1", "amoxicillin", &MedicationChange::new("Course extended", "alice")).unwrap_err();
+    assert!(error.to_string().contains("Cannot renew due to allergy: amoxicillin: allergy to amoxicillin"), "{}", error);
+    assert_eq!(emr.medication_requests("1").unwrap().len(), 1);
+}
+
+#[test]
+fn allergies_to_a_product_are_recorded_against_its_ingredients() {
+    let workspace = common::workspace();
+    workspace.write("rxnorm.json", r#"[
+        {"rxcui": "723", "name": "amoxicillin", "tty": "IN"},
+        {"rxcui": "48203", "name": "clavulanate", "tty": "IN"},
+        {"rxcui": "308191", "name": "amoxicillin 500 MG Oral Capsule", "tty": "SCD"},
+        {"rxcui": "562508", "name": "amoxicillin 875 MG / clavulanate 125 MG Oral Tablet", "tty": "SCD"}
+    ]"#);
+    let mut emr = workspace.emr();
+
+    assert_eq!(emr.add_allergy("1", &allergy("amoxicillin 500 MG Oral Capsule")).unwrap().len(), 1);
+    assert_eq!(emr.allergies("1").unwrap()[0].code.code, "723");
+    let alerts = emr.check_allergies("1", "amoxicillin").unwrap();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!((alerts[0].action, alerts[0].allergen.as_str()), (AllergyAction::RequireOverride, "amoxicillin"));
+
+    // A combination is recorded as an allergy to each of its ingredients
+    let ids = emr.add_allergy("1", &allergy("amoxicillin 875 MG / clavulanate 125 MG Oral Tablet")).unwrap();
+    assert_eq!(ids.len(), 2);
+    let codes: Vec<&str> = emr.bundles["1"].entry.iter()
+        .filter_map(|entry| match &entry.resource {
+            Resource::AllergyIntolerance(allergy) if ids.contains(&allergy.id) => Some(allergy.code.code.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(codes, ["723", "48203"]);
+    assert!(!emr.check_allergies("1", "clavulanate").unwrap().is_empty());
+}
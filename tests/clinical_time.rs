@@ -0,0 +1,71 @@
+// tests/clinical_time.rs
+// Charcot EMR: When observations were taken, as opposed to when they were entered
+
+mod common;
+
+use chrono::{DateTime, Duration, Utc};
+use charcot_emr::{EMR, FUTURE_TOLERANCE_MINUTES, Observation, Resource, VitalKind, parse_clinical_time,
+                  validate_clinical_time};
+
+fn observations(emr: &EMR) -> Vec<&Observation> {
+    emr.bundles["1"].entry.iter()
+        .filter_map(|entry| match &entry.resource {
+            Resource::Observation(observation) => Some(observation),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse(time: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+}
+
+#[test]
+fn times_slightly_ahead_are_tolerated() {
+    let now = Utc::now();
+    assert!(validate_clinical_time(now - Duration::days(365)).is_ok());
+    assert!(validate_clinical_time(now + Duration::minutes(FUTURE_TOLERANCE_MINUTES - 1)).is_ok());
+    let error = validate_clinical_time(now + Duration::minutes(FUTURE_TOLERANCE_MINUTES + 1)).unwrap_err();
+    assert!(error.to_string().ends_with("is in the future"), "{}", error);
+}
+
+#[test]
+fn when_taken_is_kept_apart_from_when_entered() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    let taken = Utc::now() - Duration::hours(3);
+    emr.add_vital_at("1", VitalKind::HeartRate, 72.0, taken).unwrap();
+    emr.add_lab_result_at("1", "glucose", 90.0, "mg/dL", taken, None).unwrap();
+    assert!(emr.add_vital_at("1", VitalKind::HeartRate, 72.0, Utc::now() + Duration::hours(1)).is_err());
+
+    for observation in observations(&emr) {
+        assert_eq!(observation.effective_date_time, taken.to_rfc3339());
+        let issued = parse(observation.issued.as_deref().unwrap());
+        assert!((Utc::now() - issued).num_seconds().abs() < 60, "{:?}", observation.issued);
+    }
+}
+
+#[test]
+fn add_vital_takes_the_time_it_was_taken() {
+    let workspace = common::workspace();
+    let emr = workspace.emr();
+    emr.save_patient("1", "secret").unwrap();
+
+    let output = workspace.emr_cli(&["add-vital", "1", "hr", "72", "secret", "--at", "2025-04-08 08:30"]);
+    assert!(output.status.success(), "{:?}", output);
+    let output = workspace.emr_cli(&["add-vital", "1", "temp", "98.6[degF]", "secret", "--at", "2025-04-08T09:00:00+02:00"]);
+    assert!(output.status.success(), "{:?}", output);
+
+    let tomorrow = (Utc::now() + Duration::days(1)).format("%Y-%m-%d").to_string();
+    for at in [tomorrow.as_str(), "08:30", "2025-13-01"] {
+        let output = workspace.emr_cli(&["add-vital", "1", "hr", "72", "secret", "--at", at]);
+        assert!(!output.status.success(), "{}: {:?}", at, output);
+    }
+
+    let mut emr = EMR::new().unwrap();
+    emr.load_patient("patient_1.med", "secret").unwrap();
+    let taken: Vec<DateTime<Utc>> = observations(&emr).iter()
+        .map(|observation| parse(&observation.effective_date_time))
+        .collect();
+    assert_eq!(taken, [parse_clinical_time("2025-04-08 08:30").unwrap(), parse("2025-04-08T07:00:00Z")]);
+}
@@ -0,0 +1,69 @@
+// tests/common/mod.rs
+// Charcot EMR: Shared setup for the integration tests
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::sync::{Mutex, MutexGuard};
+use charcot_emr::EMR;
+
+// The EMR keeps its files (audit.log, .med files, tables) in the working
+// directory, which the tests of one binary share
+static WORKING_DIR: Mutex<()> = Mutex::new(());
+
+// A fresh working directory, held for the length of a test
+pub struct Workspace {
+    pub dir: PathBuf,
+    _lock: MutexGuard<'static, ()>,
+}
+
+pub fn workspace() -> Workspace {
+    let lock = WORKING_DIR.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let dir = std::env::temp_dir().join(format!("charcot-test-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    std::env::set_current_dir(&dir).unwrap();
+    Workspace { dir, _lock: lock }
+}
+
+impl Workspace {
+    pub fn write(&self, name: &str, contents: &str) {
+        fs::write(self.dir.join(name), contents).unwrap();
+    }
+
+    pub fn audit_log(&self) -> String {
+        fs::read_to_string(self.dir.join("audit.log")).unwrap_or_default()
+    }
+
+    // Run emr_cli in the workspace
+    pub fn emr_cli(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_emr_cli"))
+            .args(args)
+            .current_dir(&self.dir)
+            .output()
+            .unwrap()
+    }
+
+    // An EMR with one patient, "1"
+    pub fn emr(&self) -> EMR {
+        let mut emr = EMR::new().unwrap();
+        emr.create_patient("1", "Ada", "Lovelace", "female", "1960-12-10").unwrap();
+        emr
+    }
+
+    // Patient "1" on metformin, with the prescription committed as a second
+    // version after the record's creation
+    pub fn patient_on_metformin(&self) -> EMR {
+        let mut emr = self.emr();
+        emr.prescribe_medication("1", "metformin", 500.0, "twice daily").unwrap();
+        emr.commit_changes("1", "Started metformin").unwrap();
+        emr
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,90 @@
+// tests/conditions.rs
+// Charcot EMR: Coding, resolving and listing problems
+
+mod common;
+
+use chrono::{Duration, Utc};
+use charcot_emr::NewCondition;
+
+fn condition(icd10: &str) -> NewCondition {
+    NewCondition { icd10: icd10.to_string(), ..NewCondition::default() }
+}
+
+#[test]
+fn icd10_codes_are_validated() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+
+    let error = emr.add_condition("1", &condition("11.9")).unwrap_err();
+    assert_eq!(error.to_string(), "Invalid ICD-10-CM code: 11.9");
+    let error = emr.add_condition("1", &condition("E11.999")).unwrap_err();
+    assert_eq!(error.to_string(), "Unknown ICD-10-CM code: E11.999");
+    assert!(emr.conditions("1").unwrap().is_empty());
+
+    // Codes are normalized before they are looked up
+    let id = emr.add_condition("1", &condition("e119")).unwrap();
+    let added = emr.conditions("1").unwrap()[0].clone();
+    assert_eq!(added.id, id);
+    assert_eq!((added.code.code.as_str(), added.code.display.as_str()),
+               ("E11.9", "Type 2 diabetes mellitus without complications"));
+    let error = emr.add_condition("1", &condition("E11.9")).unwrap_err();
+    assert!(error.to_string().contains("is already on the problem list"), "{}", error);
+}
+
+#[test]
+fn snomed_codes_are_checked_and_kept_alongside() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+
+    let error = emr.add_condition("1", &NewCondition { snomed: Some("12345".to_string()), ..condition("E11.9") })
+        .unwrap_err();
+    assert_eq!(error.to_string(), "Unknown SNOMED CT code: 12345");
+    assert!(emr.conditions("1").unwrap().is_empty());
+
+    emr.add_condition("1", &NewCondition { snomed: Some(" 44054006 ".to_string()), ..condition("E11.9") }).unwrap();
+    let snomed = emr.conditions("1").unwrap()[0].snomed.clone().unwrap();
+    assert_eq!((snomed.code.as_str(), snomed.display.as_str()), ("44054006", "Diabetes mellitus type 2"));
+}
+
+#[test]
+fn resolving_a_problem_records_its_abatement() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    let onset = Utc::now() - Duration::days(10);
+    emr.add_condition("1", &NewCondition { onset: Some(onset), ..condition("I10") }).unwrap();
+
+    let error = emr.resolve_condition("1", "I10", onset - Duration::days(1)).unwrap_err();
+    assert!(error.to_string().starts_with("Resolution"), "{}", error);
+    let abated = Utc::now() - Duration::days(1);
+    emr.resolve_condition("1", "i10", abated).unwrap();
+
+    let resolved = emr.conditions("1").unwrap()[0].clone();
+    assert_eq!(resolved.clinical_status, "resolved");
+    assert_eq!(resolved.abatement_date_time, Some(abated.to_rfc3339()));
+    assert!(workspace.audit_log().contains("Resolved condition: Essential (primary) hypertension (I10)"));
+    let error = emr.resolve_condition("1", "I10", Utc::now()).unwrap_err();
+    assert_eq!(error.to_string(), "No current problem I10 for patient 1");
+
+    // Once resolved, the problem can be added again
+    emr.add_condition("1", &condition("I10")).unwrap();
+}
+
+#[test]
+fn resolved_problems_are_listed_only_with_all() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.add_condition("1", &condition("E11.9")).unwrap();
+    emr.add_condition("1", &condition("I10")).unwrap();
+    emr.resolve_condition("1", "I10", Utc::now()).unwrap();
+    emr.save_patient("1", "secret").unwrap();
+
+    let current = workspace.emr_cli(&["condition", "list", "1", "secret"]);
+    assert!(current.status.success(), "{:?}", current);
+    let current = String::from_utf8(current.stdout).unwrap();
+    assert!(current.contains("E11.9 Type 2 diabetes mellitus without complications (active, confirmed)"), "{}", current);
+    assert!(!current.contains("I10"), "{}", current);
+
+    let all = String::from_utf8(workspace.emr_cli(&["condition", "list", "1", "secret", "--all"]).stdout).unwrap();
+    assert!(all.contains("E11.9"), "{}", all);
+    assert!(all.contains("I10 Essential (primary) hypertension (resolved, confirmed)"), "{}", all);
+}
@@ -0,0 +1,79 @@
+// tests/example.rs
+// Charcot EMR: Running the example script end to end
+
+mod common;
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use chrono::{DateTime, Duration, Utc};
+use charcot_emr::{lang, EMR, Resource};
+
+const EXAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/diabetes_management.charcot");
+
+fn run(source: &str, emr: &mut EMR) -> anyhow::Result<()> {
+    let program = lang::parse(source).unwrap();
+    let diagnostics = lang::check::check(&program, &HashSet::new());
+    assert!(diagnostics.iter().all(|d| !d.is_error()), "{:?}", diagnostics);
+    let plan = lang::interp::plan(&program, emr).unwrap();
+    lang::interp::execute(emr, &plan, "patient_key")
+}
+
+#[test]
+fn diabetes_management_example_runs_with_recent_readings() {
+    let _workspace = common::workspace();
+    let mut emr = EMR::new().unwrap();
+
+    // The example's readings, taken yesterday
+    let yesterday = (Utc::now() - Duration::days(1)).format("%Y-%m-%d").to_string();
+    let source = fs::read_to_string(EXAMPLE).unwrap().replace("2025-04-08", &yesterday);
+    run(&source, &mut emr).unwrap();
+    assert!(Path::new("patient_123.med").exists());
+
+    let requests: Vec<_> = emr.bundles["123"].entry.iter()
+        .filter_map(|entry| match &entry.resource {
+            Resource::MedicationRequest(request) => Some(request),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(requests.len(), 2);
+
+    // 0.5 units/kg of the 85.5 kg weight, rounded, written now
+    let insulin = requests.iter()
+        .find(|request| request.medication_codeable_concept.display == "insulin glargine")
+        .unwrap();
+    let authored = DateTime::parse_from_rfc3339(&insulin.authored_on).unwrap().with_timezone(&Utc);
+    assert!(Utc::now() - authored < Duration::minutes(1), "{}", insulin.authored_on);
+    let dose = insulin.dosage_instruction[0].dose_and_rate[0].dose_quantity.as_ref().unwrap();
+    assert_eq!((dose.value, dose.unit.as_str(), dose.code.as_str()), (43.0, "units", "[iU]"));
+}
+
+#[test]
+fn diabetes_management_example_refuses_to_dose_from_an_old_weight() {
+    let _workspace = common::workspace();
+    let mut emr = EMR::new().unwrap();
+
+    let error = run(&fs::read_to_string(EXAMPLE).unwrap(), &mut emr).unwrap_err();
+    assert!(error.to_string().contains("The latest weight for patient 123"), "{}", error);
+    assert!(error.to_string().contains("dose calculations need one from the last 30 days"), "{}", error);
+}
+
+#[test]
+fn dry_run_prints_the_plan_and_writes_nothing() {
+    let workspace = common::workspace();
+    let yesterday = (Utc::now() - Duration::days(1)).format("%Y-%m-%d").to_string();
+    workspace.write("diabetes_management.charcot", &fs::read_to_string(EXAMPLE).unwrap().replace("2025-04-08", &yesterday));
+
+    let output = workspace.emr_cli(&["run", "diabetes_management.charcot", "patient_key", "--dry-run"]);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Dry run of diabetes_management.charcot: "), "{}", stdout);
+    assert!(stdout.contains("planned change(s), nothing will be written"), "{}", stdout);
+    assert!(stdout.contains("insulin glargine"), "{}", stdout);
+
+    let med_files: Vec<_> = fs::read_dir(&workspace.dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "med"))
+        .collect();
+    assert!(med_files.is_empty(), "{:?}", med_files);
+}
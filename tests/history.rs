@@ -0,0 +1,213 @@
+// tests/history.rs
+// Charcot EMR: Version history: the hash chain, saving, diffs and reverts
+
+mod common;
+
+use chrono::{Duration, Utc};
+use charcot_emr::{EMR, MedFile, MedicationChange, ResourceChange, VersionEntry, VitalKind, entries_hash, history};
+
+// Unlink a version from the chain, rehashing it so only its link is wrong
+fn unchain(emr: &mut EMR, index: usize) {
+    let version = &mut emr.bundles.get_mut("1").unwrap().version_history[index];
+    version.parent = String::new();
+    version.hash = history::version_hash("", &version.author, version.timestamp, &version.message, &version.content).unwrap();
+}
+
+#[test]
+fn chained_history_is_intact() {
+    let workspace = common::workspace();
+    let emr = workspace.patient_on_metformin();
+    let report = emr.verify_history("1").unwrap();
+    assert!(report.is_intact(), "{}", report);
+}
+
+#[test]
+fn unchained_versions_are_broken() {
+    let workspace = common::workspace();
+    let mut emr = workspace.patient_on_metformin();
+    unchain(&mut emr, 1);
+    let report = emr.verify_history("1").unwrap();
+    assert_eq!(report.broken.as_ref().map(|(index, _)| *index), Some(1), "{}", report);
+}
+
+#[test]
+fn history_replaced_by_a_record_of_earlier_history_is_broken() {
+    let workspace = common::workspace();
+    let mut emr = workspace.patient_on_metformin();
+    let bundle = emr.bundles.get_mut("1").unwrap();
+    bundle.version_history = vec![VersionEntry {
+        timestamp: Utc::now(),
+        message: history::EARLIER_HISTORY.to_string(),
+        hash: entries_hash(&bundle.entry).unwrap(),
+        author: String::new(),
+        tree: Vec::new(),
+        parent: String::new(),
+        content: String::new(),
+        signature: None,
+    }];
+    let report = emr.verify_history("1").unwrap();
+    assert_eq!(report.broken.as_ref().map(|(index, _)| *index), Some(0), "{}", report);
+}
+
+#[test]
+fn versions_without_a_snapshot_are_broken() {
+    let workspace = common::workspace();
+    let mut emr = workspace.patient_on_metformin();
+    emr.bundles.get_mut("1").unwrap().version_history[0].tree.clear();
+    let report = emr.verify_history("1").unwrap();
+    assert_eq!(report.broken, Some((0, "it has no snapshot".to_string())), "{}", report);
+}
+
+#[test]
+fn versions_are_found_by_number_or_hash() {
+    let workspace = common::workspace();
+    let emr = workspace.patient_on_metformin();
+    let hashes: Vec<String> = emr.history("1").unwrap().iter().map(|version| version.hash.clone()).collect();
+
+    assert_eq!(emr.find_version("1", "2").unwrap(), 1);
+    assert_eq!(emr.find_version("1", "#1").unwrap(), 0);
+    assert_eq!(emr.find_version("1", &hashes[1][..8]).unwrap(), 1);
+    assert_eq!(emr.find_version("1", &hashes[0].to_uppercase()).unwrap(), 0);
+    assert!(emr.find_version("1", "3").is_err());
+    assert!(emr.find_version("1", "#3").is_err());
+    assert!(emr.find_version("1", "#abcd").is_err());
+}
+
+#[test]
+fn numbers_that_start_a_hash_are_ambiguous() {
+    let workspace = common::workspace();
+    let mut emr = workspace.patient_on_metformin();
+    let first = &mut emr.bundles.get_mut("1").unwrap().version_history[0];
+    first.hash = format!("0002{}", &first.hash[4..]);
+
+    let error = emr.find_version("1", "0002").unwrap_err();
+    assert!(error.to_string().contains("write #2 for the version"), "{}", error);
+    assert_eq!(emr.find_version("1", "#0002").unwrap(), 1);
+    let prefix = emr.history("1").unwrap()[0].hash[..6].to_string();
+    assert_eq!(emr.find_version("1", &prefix).unwrap(), 0);
+    // A number no hash starts with is still a version number
+    assert_eq!(emr.find_version("1", "0001").unwrap(), 0);
+}
+
+#[test]
+fn history_survives_saving_and_loading() {
+    let workspace = common::workspace();
+    let mut emr = workspace.patient_on_metformin();
+    emr.user = "grace".to_string();
+    emr.add_vital("1", VitalKind::Weight, 80.0).unwrap();
+    emr.commit_changes("1", "Weighed").unwrap();
+    emr.save_patient("1", "secret").unwrap();
+
+    let mut loaded = EMR::new().unwrap();
+    loaded.load_patient("patient_1.med", "secret").unwrap();
+    let summary = |emr: &EMR| -> Vec<(String, String, String, String)> {
+        emr.history("1").unwrap().iter()
+            .map(|v| (v.timestamp.to_rfc3339(), v.message.clone(), v.author.clone(), v.hash.clone()))
+            .collect()
+    };
+    assert_eq!(summary(&loaded), summary(&emr));
+    let messages: Vec<&str> = loaded.history("1").unwrap().iter().map(|v| v.message.as_str()).collect();
+    assert_eq!(messages, ["Patient created", "Started metformin", "Weighed"]);
+    assert_eq!(loaded.history("1").unwrap()[2].author, "grace");
+    assert!(loaded.verify_history("1").unwrap().is_intact());
+}
+
+#[test]
+fn files_saved_without_history_load_as_one_earlier_version() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.add_vital("1", VitalKind::Weight, 80.0).unwrap();
+    // The record as files were written before the history was kept
+    let mut bundle = serde_json::to_value(&emr.bundles["1"]).unwrap();
+    let fields = bundle.as_object_mut().unwrap();
+    fields.remove("version_history");
+    fields.remove("objects");
+    let created = Utc::now() - Duration::days(30);
+    MedFile::seal(&serde_json::to_vec(&bundle).unwrap(), "secret", created).unwrap()
+        .write("patient_1.med").unwrap();
+
+    let mut emr = EMR::new().unwrap();
+    emr.load_patient("patient_1.med", "secret").unwrap();
+    let history = emr.history("1").unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].message, history::EARLIER_HISTORY);
+    assert_eq!((history[0].author.as_str(), history[0].timestamp), ("", created));
+    assert_eq!(emr.snapshot("1", 0).unwrap().len(), 2);
+    assert!(emr.verify_history("1").unwrap().is_intact());
+}
+
+// "+", "-" or "~" and the kind of resource for each change
+fn kinds(changes: &[ResourceChange]) -> Vec<String> {
+    changes.iter()
+        .map(|change| {
+            let text = change.to_string();
+            text[..text.find('/').unwrap()].to_string()
+        })
+        .collect()
+}
+
+#[test]
+fn diffs_list_added_removed_and_modified_resources() {
+    let workspace = common::workspace();
+    let mut emr = workspace.patient_on_metformin();
+    emr.hold_medication("1", "metformin", &MedicationChange::new("Contrast scan", "alice")).unwrap();
+    emr.revert_to_version("1", 0, "Prescribed for the wrong patient").unwrap();
+
+    assert_eq!(kinds(&emr.diff_versions("1", 0, 1).unwrap()), ["+ MedicationRequest"]);
+    let held = emr.diff_versions("1", 1, 2).unwrap();
+    assert_eq!(kinds(&held), ["~ MedicationRequest"]);
+    assert!(held[0].fields().contains(&"status: \"active\" -> \"on-hold\"".to_string()), "{:?}", held[0].fields());
+    assert_eq!(kinds(&emr.diff_versions("1", 2, 3).unwrap()), ["- MedicationRequest"]);
+    assert!(emr.diff_versions("1", 0, 3).unwrap().is_empty());
+    assert_eq!(kinds(&emr.version_changes("1", 0).unwrap()), ["+ Patient"]);
+}
+
+#[test]
+fn show_prints_a_version_and_its_changes() {
+    let workspace = common::workspace();
+    let emr = workspace.patient_on_metformin();
+    emr.save_patient("1", "secret").unwrap();
+    let hash = emr.history("1").unwrap()[1].hash.clone();
+
+    let output = workspace.emr_cli(&["show", "1", &hash[..8], "secret"]);
+    assert!(output.status.success(), "{:?}", output);
+    let shown = String::from_utf8(output.stdout).unwrap();
+    assert!(shown.starts_with(&format!("Version 2\nHash:    {}\n", hash)), "{}", shown);
+    assert!(shown.contains("Message: Started metformin"), "{}", shown);
+    assert!(shown.contains("  + MedicationRequest/"), "{}", shown);
+    assert!(!shown.contains("Patient/"), "{}", shown);
+}
+
+#[test]
+fn reverting_adds_a_version_restoring_an_earlier_one() {
+    let workspace = common::workspace();
+    let mut emr = workspace.patient_on_metformin();
+    let before = emr.history("1").unwrap().to_vec();
+
+    assert!(emr.revert_to_version("1", 0, " ").is_err());
+    let message = emr.revert_to_version("1", 0, "Prescribed for the wrong patient").unwrap();
+    assert!(message.starts_with("Reverted to version 1 ("), "{}", message);
+    assert!(message.ends_with("\"Patient created\". Reason: Prescribed for the wrong patient"), "{}", message);
+
+    let history = emr.history("1").unwrap();
+    assert_eq!(history.len(), 3);
+    assert_eq!(history[2].message, message);
+    let hashes = |versions: &[VersionEntry]| versions.iter().map(|v| v.hash.clone()).collect::<Vec<_>>();
+    assert_eq!(hashes(&history[..2]), hashes(&before));
+    assert_eq!(history[2].tree, history[0].tree);
+    assert!(emr.medication_requests("1").unwrap().is_empty());
+    assert!(emr.verify_history("1").unwrap().is_intact());
+    assert!(emr.revert_to_version("1", 0, "Again").unwrap_err().to_string().contains("already matches version 1"));
+}
+
+#[test]
+fn reverting_is_refused_with_uncommitted_changes() {
+    let workspace = common::workspace();
+    let mut emr = workspace.patient_on_metformin();
+    emr.add_vital("1", VitalKind::Weight, 80.0).unwrap();
+
+    let error = emr.revert_to_version("1", 0, "Prescribed for the wrong patient").unwrap_err();
+    assert_eq!(error.to_string(), "Patient 1 has uncommitted changes; commit them before reverting");
+    assert_eq!(emr.history("1").unwrap().len(), 2);
+    assert_eq!(emr.bundles["1"].entry.len(), 3);
+}
@@ -0,0 +1,61 @@
+// tests/interactions.rs
+// Charcot EMR: Drug-drug interactions when prescribing and across a patient's medications
+
+mod common;
+
+use charcot_emr::{InteractionSeverity, PrescribeOptions};
+
+fn with_reason(reason: &str) -> PrescribeOptions {
+    PrescribeOptions { override_reason: Some(reason.to_string()), ..PrescribeOptions::default() }
+}
+
+#[test]
+fn major_interaction_needs_an_audited_reason() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.prescribe_medication("1", "warfarin", 5.0, "daily").unwrap();
+
+    let error = emr.prescribe_medication("1", "aspirin", 81.0, "daily").unwrap_err();
+    assert!(error.to_string().starts_with("Severe interaction: major interaction between aspirin and warfarin: \
+                                           increased bleeding risk"), "{}", error);
+    assert_eq!(emr.medication_requests("1").unwrap().len(), 1);
+
+    emr.prescribe_medication_with("1", "aspirin", 81.0, "daily", &with_reason("recent stent")).unwrap();
+    let audit = workspace.audit_log();
+    assert!(audit.contains("Interaction override for aspirin: major interaction between aspirin and warfarin"), "{}", audit);
+    assert!(audit.contains("Reason: recent stent"), "{}", audit);
+}
+
+#[test]
+fn minor_and_moderate_interactions_only_warn() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.prescribe_medication("1", "warfarin", 5.0, "daily").unwrap();
+    emr.prescribe_medication("1", "acetaminophen", 500.0, "every 6 hours").unwrap();
+    emr.prescribe_medication("1", "metoprolol", 50.0, "twice daily").unwrap();
+    emr.prescribe_medication("1", "diltiazem", 60.0, "three times daily").unwrap();
+
+    let audit = workspace.audit_log();
+    assert!(audit.contains("Interaction noted: minor interaction between acetaminophen and warfarin"), "{}", audit);
+    assert!(audit.contains("Interaction noted: moderate interaction between diltiazem and metoprolol"), "{}", audit);
+    assert!(!audit.contains("Interaction override"), "{}", audit);
+}
+
+#[test]
+fn each_pair_of_active_prescriptions_is_checked_once() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.prescribe_medication("1", "warfarin", 5.0, "daily").unwrap();
+    emr.prescribe_medication_with("1", "aspirin", 81.0, "daily", &with_reason("recent stent")).unwrap();
+    emr.prescribe_medication("1", "acetaminophen", 500.0, "every 6 hours").unwrap();
+    emr.prescribe_medication("1", "metformin", 500.0, "twice daily").unwrap();
+
+    let alerts = emr.check_interactions("1").unwrap();
+    let pairs: Vec<(&str, &str, InteractionSeverity)> = alerts.iter()
+        .map(|alert| (alert.medication.as_str(), alert.other.as_str(), alert.interaction.severity))
+        .collect();
+    assert_eq!(pairs, [
+        ("warfarin", "aspirin", InteractionSeverity::Major),
+        ("warfarin", "acetaminophen", InteractionSeverity::Minor),
+    ]);
+}
@@ -0,0 +1,90 @@
+// tests/labs.rs
+// Charcot EMR: Lab results, their flags and reports
+
+mod common;
+
+use chrono::{Duration, Utc};
+use charcot_emr::{EMR, Interpretation, LabRanges, Observation, Resource};
+
+fn observation<'a>(emr: &'a EMR, id: &str) -> &'a Observation {
+    emr.bundles["1"].entry.iter()
+        .find_map(|entry| match &entry.resource {
+            Resource::Observation(observation) if observation.id == id => Some(observation),
+            _ => None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn results_are_flagged_against_each_tier() {
+    let ranges = LabRanges::default();
+    let glucose = ranges.find("blood sugar").unwrap();
+    let flags: Vec<&str> = [30.0, 40.0, 60.0, 70.0, 85.0, 99.0, 150.0, 400.0, 500.0].into_iter()
+        .map(|value| glucose.interpret(value).code())
+        .collect();
+    assert_eq!(flags, ["LL", "L", "L", "N", "N", "N", "H", "H", "HH"]);
+
+    // HbA1c has no critical low
+    assert_eq!(ranges.find("a1c").unwrap().interpret(1.0), Interpretation::Low);
+
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    let (id, interpretation) = emr.add_lab_result_at("1", "potassium", 6.8, "mmol/L", Utc::now(), None).unwrap();
+    assert_eq!(interpretation, Interpretation::CriticalHigh);
+    let flag = &observation(&emr, &id).interpretation.as_ref().unwrap()[0];
+    assert_eq!((flag.code.as_str(), flag.display.as_str()), ("HH", "Critical high"));
+    assert!(workspace.audit_log().contains("Added lab potassium: 6.8 mmol/L (HH)"));
+}
+
+#[test]
+fn results_in_molar_units_are_converted() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    let (id, interpretation) = emr.add_lab_result_at("1", "glucose", 10.0, "mmol/L", Utc::now(), None).unwrap();
+    let value = observation(&emr, &id).value_quantity.clone().unwrap();
+    assert!((value.value - 180.0).abs() < 0.5, "{:?}", value);
+    assert_eq!(value.unit, "mg/dL");
+    assert_eq!(interpretation, Interpretation::High);
+}
+
+#[test]
+fn invalid_results_are_refused() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    let now = Utc::now();
+    let error = emr.add_lab_result_at("1", "glucose", -5.0, "mg/dL", now, None).unwrap_err();
+    assert_eq!(error.to_string(), "Invalid glucose value: -5");
+    let error = emr.add_lab_result_at("1", "glucose", f64::NAN, "mg/dL", now, None).unwrap_err();
+    assert_eq!(error.to_string(), "Invalid glucose value: NaN");
+    let error = emr.add_lab_result_at("1", "glucose", 90.0, "mg", now, None).unwrap_err();
+    assert_eq!(error.to_string(), "Invalid unit for glucose");
+    let error = emr.add_lab_result_at("1", "troponin", 0.01, "ng/mL", now, None).unwrap_err();
+    assert_eq!(error.to_string(), "Unknown lab test: troponin");
+    assert!(emr.add_lab_result_at("1", "glucose", 90.0, "mg/dL", now + Duration::days(1), None).is_err());
+    assert_eq!(emr.bundles["1"].entry.len(), 1);
+}
+
+#[test]
+fn reports_need_results_that_exist() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    let taken = Utc::now() - Duration::hours(2);
+    let (glucose, _) = emr.add_lab_result_at("1", "glucose", 90.0, "mg/dL", taken, None).unwrap();
+    let (hba1c, _) = emr.add_lab_result_at("1", "hba1c", 6.1, "%", taken - Duration::hours(1), None).unwrap();
+
+    let missing = [glucose.clone(), "no-such-observation".to_string()];
+    let error = emr.add_diagnostic_report("1", "Diabetes panel", &missing).unwrap_err();
+    assert_eq!(error.to_string(), "Observation not found: no-such-observation");
+    assert!(emr.add_diagnostic_report("1", "Diabetes panel", &[]).is_err());
+    assert_eq!(emr.bundles["1"].entry.len(), 3);
+
+    let id = emr.add_diagnostic_report("1", "Diabetes panel", &[glucose, hba1c]).unwrap();
+    let report = emr.bundles["1"].entry.iter()
+        .find_map(|entry| match &entry.resource {
+            Resource::DiagnosticReport(report) if report.id == id => Some(report),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(report.result.len(), 2);
+    assert_eq!(report.effective_date_time, taken.to_rfc3339());
+}
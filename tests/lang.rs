@@ -0,0 +1,135 @@
+// tests/lang.rs
+// Charcot EMR: Parsing, error recovery and printing of Charcot scripts
+
+use std::collections::HashSet;
+use std::fs;
+use charcot_emr::lang::{self, check, ast::{ItemKind, StmtKind}, diagnostic::line_col, printer};
+
+const EXAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/diabetes_management.charcot");
+
+#[test]
+fn example_round_trips_through_the_printer() {
+    let source = fs::read_to_string(EXAMPLE).unwrap();
+    let program = lang::parse(&source).unwrap();
+    assert_eq!(program.items.len(), 14);
+
+    let printed = printer::print_program(&program);
+    let reparsed = lang::parse(&printed).unwrap_or_else(|e| panic!("{:?}\n{}", e, printed));
+    assert_eq!(printer::print_program(&reparsed), printed);
+    assert_eq!(reparsed.items.len(), program.items.len());
+
+    // Formatting keeps the comments, and formatting again changes nothing
+    let formatted = printer::format_source(&source).unwrap();
+    assert!(formatted.contains("// Insulin with dose calculation based on weight"), "{}", formatted);
+    assert_eq!(printer::format_source(&formatted).unwrap(), formatted);
+    assert_eq!(printer::print_program(&lang::parse(&formatted).unwrap()), printed);
+}
+
+#[test]
+fn every_syntax_error_is_reported() {
+    let source = "\
+track for patient #1 {
+    weight = 80 kg
+    bp = BloodPressure(120, 80);
+    glucose = ;
+}
+
+prescribe patient #1 {
+    metformin = Medication(\"metformin\");
+}
+
+commit changes to patient #1 with message \"done\";
+";
+    let diagnostics = lang::parse(source).unwrap_err();
+    let lines: Vec<usize> = diagnostics.iter().map(|d| line_col(source, d.span.start).0).collect();
+    assert_eq!(lines, [3, 4, 7], "{:?}", diagnostics);
+    assert!(diagnostics.iter().all(|d| d.is_error()));
+}
+
+#[test]
+fn spans_cover_their_source() {
+    let source = "track for patient #1 {\n    weight = 80 kg on \"2025-04-08\";\n}\n";
+    let program = lang::parse(source).unwrap();
+    let item = &program.items[0];
+    assert_eq!(&source[item.span.start..item.span.end], source.trim_end());
+    let ItemKind::Block(block) = &item.kind else {
+        panic!("expected a block, got {:?}", item.kind);
+    };
+    assert_eq!(&source[block.patient.span.start..block.patient.span.end], "#1");
+
+    let stmt = &block.body[0];
+    assert_eq!(&source[stmt.span.start..stmt.span.end], "weight = 80 kg on \"2025-04-08\";");
+    let StmtKind::Assign { target, value, .. } = &stmt.kind else {
+        panic!("expected an assignment, got {:?}", stmt.kind);
+    };
+    assert_eq!(&source[target.span.start..target.span.end], "weight");
+    assert_eq!(&source[value.span.start..value.span.end], "80 kg");
+    assert_eq!(line_col(source, value.span.start), (2, 14));
+}
+
+#[test]
+fn checker_reports_every_problem_at_once() {
+    let source = "\
+record patient #1 {
+    name: \"Ada Lovelace\";
+}
+
+track for patient #1 {
+    glucose = 180 mg/dL;
+    bp = BloodPressure(400, 80);
+    alert when glucose > 250 kg;
+    alert when 100 / glucose > 2 kg;
+    alert when glucose / 2 > 2 kg;
+}
+
+prescribe for patient #1 {
+    administer insulin 10 units before meals;
+}
+
+track for patient #2 {
+    weight = 80 kg;
+}
+";
+    let program = lang::parse(source).unwrap();
+    let diagnostics = check::check(&program, &HashSet::new());
+    let lines: Vec<usize> = diagnostics.iter().map(|d| line_col(source, d.span.start).0).collect();
+    // Dividing by a quantity leaves its unit unknown, so line 9 is not a mismatch
+    assert_eq!(lines, [7, 8, 10, 14, 17], "{:?}", diagnostics);
+    assert!(diagnostics.iter().all(|d| d.is_error()));
+}
+
+#[test]
+fn formatting_keeps_inline_comments_on_their_line() {
+    let source = "\
+track for patient #1 { // vitals
+  weight = 80 kg;   // on admission
+  // before breakfast
+  glucose = 180 mg/dL; // fasting
+}
+";
+    let formatted = printer::format_source(source).unwrap();
+    assert_eq!(formatted, "\
+track for patient #1 { // vitals
+    weight = 80 kg; // on admission
+    // before breakfast
+    glucose = 180 mg/dL; // fasting
+}
+");
+    assert_eq!(printer::format_source(&formatted).unwrap(), formatted);
+}
+
+#[test]
+fn comments_inside_a_statement_are_refused() {
+    let source = "\
+track for patient #1 {
+    weight = 80 // before dialysis
+        kg;
+}
+";
+    let diagnostics = printer::format_source(source).unwrap_err();
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    assert_eq!(line_col(source, diagnostics[0].span.start), (2, 17));
+
+    let header = "track for // vitals\n    patient #1 {\n    weight = 80 kg;\n}\n";
+    assert!(printer::format_source(header).is_err());
+}
@@ -0,0 +1,165 @@
+// tests/lsp.rs
+// Charcot EMR: A scripted editor session with emr_lsp
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use serde_json::{Value, json};
+
+const URI: &str = "file:///tmp/plan.charcot";
+
+// A script with one error: patient #2 has no record
+const SCRIPT: &str = "\
+record patient #1 {
+    name: \"Ada Lovelace\";
+}
+
+prescribe for patient #1 {
+    metformin = Medication(\"metformin\") with rxnorm \"6809\";
+    administer metformin 500 mg twice daily;
+}
+
+track for patient #2 {
+    weight = 80 kg;
+}
+";
+
+// An LSP client speaking to emr_lsp over its stdin and stdout
+struct Client {
+    server: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+    next_id: i64,
+}
+
+impl Client {
+    fn start(workspace: &common::Workspace) -> Client {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_emr_lsp"))
+            .current_dir(&workspace.dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let input = server.stdin.take().unwrap();
+        let output = BufReader::new(server.stdout.take().unwrap());
+        let mut client = Client { server, input, output, next_id: 1 };
+        client.request("initialize", json!({ "capabilities": {} }));
+        client.notify("initialized", json!({}));
+        client
+    }
+
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        write!(self.input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.input.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            self.output.read_line(&mut header).unwrap();
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        self.output.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    // Send a request and return its result, skipping notifications
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        loop {
+            let message = self.receive();
+            if message["id"] == id {
+                assert!(message.get("error").is_none(), "{}", message);
+                return message["result"].clone();
+            }
+        }
+    }
+
+    // The next diagnostics published
+    fn diagnostics(&mut self) -> Vec<Value> {
+        loop {
+            let message = self.receive();
+            if message["method"] == "textDocument/publishDiagnostics" {
+                assert_eq!(message["params"]["uri"], URI);
+                return message["params"]["diagnostics"].as_array().unwrap().clone();
+            }
+        }
+    }
+
+    fn at(&mut self, method: &str, line: u32, character: u32) -> Value {
+        self.request(method, json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+        }))
+    }
+
+    fn stop(mut self) {
+        self.request("shutdown", Value::Null);
+        self.notify("exit", Value::Null);
+        assert!(self.server.wait().unwrap().success());
+    }
+}
+
+#[test]
+fn editor_session() {
+    let workspace = common::workspace();
+    let mut client = Client::start(&workspace);
+
+    client.notify("textDocument/didOpen", json!({
+        "textDocument": { "uri": URI, "languageId": "charcot", "version": 1, "text": SCRIPT },
+    }));
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 9);
+    assert!(diagnostics[0]["message"].as_str().unwrap().contains("#2"), "{}", diagnostics[0]);
+
+    // `metformin` in the administer statement
+    let hover = client.at("textDocument/hover", 6, 16);
+    let text = hover["contents"]["value"].as_str().unwrap();
+    assert!(text.contains("metformin = Medication(\"metformin\")"), "{}", text);
+    let code = client.at("textDocument/hover", 5, 54);
+    assert!(code["contents"]["value"].as_str().unwrap().to_lowercase().contains("metformin"), "{}", code);
+
+    let definition = client.at("textDocument/definition", 6, 16);
+    assert_eq!(definition["uri"], URI);
+    assert_eq!(definition["range"]["start"], json!({ "line": 5, "character": 4 }));
+    assert_eq!(definition["range"]["end"], json!({ "line": 5, "character": 13 }));
+
+    // Inside a block: statements, constructors and the patient's variables
+    let completions = client.at("textDocument/completion", 6, 4);
+    let labels: Vec<&str> = completions.as_array().unwrap().iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    for label in ["administer", "Medication", "metformin"] {
+        assert!(labels.contains(&label), "{} not in {:?}", label, labels);
+    }
+    // Between blocks: block keywords
+    let completions = client.at("textDocument/completion", 8, 0);
+    assert!(completions.as_array().unwrap().iter().any(|item| item["label"] == "track"), "{}", completions);
+
+    // Fixing the error clears it
+    client.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": URI, "version": 2 },
+        "contentChanges": [{ "text": SCRIPT.replace("#2", "#1") }],
+    }));
+    assert_eq!(client.diagnostics(), Vec::<Value>::new());
+
+    client.stop();
+}
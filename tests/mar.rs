@@ -0,0 +1,38 @@
+// tests/mar.rs
+// Charcot EMR: Recording doses given against their prescriptions
+
+mod common;
+
+use chrono::Utc;
+use charcot_emr::{AdministrationOutcome, DoseBasis, DoseOrder, NewAdministration, PrescribeOptions, VitalKind};
+
+fn given(dose: f64, dose_unit: Option<&str>) -> NewAdministration {
+    NewAdministration {
+        outcome: AdministrationOutcome::Given,
+        scheduled: None,
+        at: Utc::now(),
+        dose: Some(dose),
+        dose_unit: dose_unit.map(str::to_string),
+        performer: "alice".to_string(),
+        note: None,
+    }
+}
+
+#[test]
+fn doses_given_are_recorded_in_the_prescribed_unit() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.add_vital("1", VitalKind::Weight, 80.0).unwrap();
+    let order = DoseOrder { amount: 0.1, unit: "units".to_string(), basis: DoseBasis::Weight };
+    emr.prescribe_calculated_dose("1", "insulin lispro", &order, "q4h prn", &PrescribeOptions::default()).unwrap();
+
+    let id = emr.record_administration("1", "insulin lispro", &given(6.0, None)).unwrap();
+    let administration = emr.administrations("1").unwrap().into_iter().find(|a| a.id == id).unwrap();
+    let dose = administration.dose.as_ref().unwrap();
+    assert_eq!((dose.value, dose.unit.as_str(), dose.code.as_str(), dose.system.as_str()),
+               (6.0, "units", "[iU]", "http://unitsofmeasure.org"));
+
+    emr.record_administration("1", "insulin lispro", &given(6.0, Some("units"))).unwrap();
+    let error = emr.record_administration("1", "insulin lispro", &given(6.0, Some("mg"))).unwrap_err();
+    assert!(error.to_string().contains("is prescribed in units; a dose in mg cannot be recorded"), "{}", error);
+}
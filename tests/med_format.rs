@@ -0,0 +1,60 @@
+// tests/med_format.rs
+// Charcot EMR: Migrating format 1 .med files to the current format
+
+mod common;
+
+use std::fs;
+use charcot_emr::{Bundle, EMR, MED_FORMAT_VERSION, MedFile, VersionEntry};
+
+// Patient 1 on metformin, saved by format 1 with the password "secret"
+const FORMAT_1: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/patient_1_format_1.med");
+
+fn hashes(versions: &[VersionEntry]) -> Vec<String> {
+    versions.iter().map(|version| version.hash.clone()).collect()
+}
+
+fn saved_history() -> Vec<String> {
+    let plaintext = MedFile::read(FORMAT_1).unwrap().open("secret").unwrap();
+    let bundle: Bundle = serde_json::from_slice(&plaintext).unwrap();
+    hashes(&bundle.version_history)
+}
+
+#[test]
+fn format_1_files_are_saved_in_the_current_format() {
+    let workspace = common::workspace();
+    fs::copy(FORMAT_1, "patient_1.med").unwrap();
+
+    let mut emr = EMR::new().unwrap();
+    assert_eq!(emr.load_patient("patient_1.med", "secret").unwrap(), "1");
+    assert!(workspace.audit_log().contains("patient_1.med is in .med format 1; it will be saved in format 2"));
+    assert_eq!(MedFile::read("patient_1.med").unwrap().format, 1);
+    emr.save_patient("1", "secret").unwrap();
+
+    let saved = MedFile::read("patient_1.med").unwrap();
+    assert_eq!(saved.format, MED_FORMAT_VERSION);
+    assert!(!fs::read_to_string("patient_1.med").unwrap().contains("hash"));
+    let mut emr = EMR::new().unwrap();
+    emr.load_patient("patient_1.med", "secret").unwrap();
+    assert_eq!(hashes(&emr.bundles["1"].version_history), saved_history());
+}
+
+#[test]
+fn format_1_files_are_migrated() {
+    let workspace = common::workspace();
+    fs::copy(FORMAT_1, "patient_1.med").unwrap();
+
+    let mut emr = EMR::new().unwrap();
+    assert!(emr.migrate_patient("patient_1.med", "wrong").is_err());
+    assert_eq!(emr.migrate_patient("patient_1.med", "secret").unwrap(), 1);
+    let migrated = MedFile::read("patient_1.med").unwrap();
+    assert_eq!(migrated.format, MED_FORMAT_VERSION);
+    assert!(migrated.kdf.is_some());
+    assert!(!fs::read_to_string("patient_1.med").unwrap().contains("hash"));
+    assert!(workspace.audit_log().contains("Migrated patient_1.med from .med format 1 to 2"));
+
+    let mut emr = EMR::new().unwrap();
+    assert_eq!(emr.load_patient("patient_1.med", "secret").unwrap(), "1");
+    assert_eq!(hashes(&emr.bundles["1"].version_history), saved_history());
+    assert!(emr.load_patient("patient_1.med", "wrong").is_err());
+    assert_eq!(emr.migrate_patient("patient_1.med", "secret").unwrap(), MED_FORMAT_VERSION);
+}
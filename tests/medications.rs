@@ -0,0 +1,42 @@
+// tests/medications.rs
+// Charcot EMR: Holding, resuming and changing prescriptions
+
+mod common;
+
+use charcot_emr::{MedicationChange, medications};
+
+fn change(reason: &str) -> MedicationChange {
+    MedicationChange::new(reason, "alice")
+}
+
+#[test]
+fn resuming_checks_prescriptions_written_while_held() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.prescribe_medication("1", "warfarin", 5.0, "daily").unwrap();
+    emr.hold_medication("1", "warfarin", &change("Dental extraction")).unwrap();
+    emr.prescribe_medication("1", "aspirin", 81.0, "daily").unwrap();
+
+    let error = emr.resume_medication("1", "warfarin", &change("Extraction healed"), None).unwrap_err();
+    assert!(error.to_string().contains("Severe interaction"), "{}", error);
+    assert_eq!(emr.find_medication_request("1", "warfarin").unwrap().status, "on-hold");
+
+    emr.resume_medication("1", "warfarin", &change("Extraction healed"), Some("INR monitored weekly")).unwrap();
+    assert_eq!(emr.find_medication_request("1", "warfarin").unwrap().status, "active");
+    let audit = workspace.audit_log();
+    assert!(audit.contains("Interaction override for warfarin"), "{}", audit);
+    assert!(audit.contains("Reason: INR monitored weekly"), "{}", audit);
+}
+
+#[test]
+fn changing_the_dose_of_a_held_prescription_keeps_it_on_hold() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.prescribe_medication("1", "lisinopril", 10.0, "daily").unwrap();
+    emr.hold_medication("1", "lisinopril", &change("Low blood pressure")).unwrap();
+
+    let new_id = emr.modify_medication("1", "lisinopril", 5.0, "daily", &change("Restart lower"), None).unwrap();
+    let request = emr.find_medication_request("1", &new_id).unwrap();
+    assert_eq!(request.status, "on-hold");
+    assert_eq!(medications::dose(request).unwrap().value, 5.0);
+}
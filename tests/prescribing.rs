@@ -0,0 +1,120 @@
+// tests/prescribing.rs
+// Charcot EMR: Dose limits, overrides and unlisted medications
+
+mod common;
+
+use charcot_emr::{DoseBasis, DoseOrder, PrescribeOptions, VitalKind};
+
+fn with_reason(reason: &str) -> PrescribeOptions {
+    PrescribeOptions { override_reason: Some(reason.to_string()), ..PrescribeOptions::default() }
+}
+
+fn unlisted(reason: Option<&str>) -> PrescribeOptions {
+    PrescribeOptions { unlisted: true, override_reason: reason.map(str::to_string), ..PrescribeOptions::default() }
+}
+
+#[test]
+fn usual_dose_needs_no_override() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.prescribe_medication("1", "metformin", 500.0, "twice daily").unwrap();
+    assert!(!workspace.audit_log().contains("override"));
+}
+
+#[test]
+fn dose_above_usual_needs_an_audited_reason() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    let error = emr.prescribe_medication("1", "metformin", 1500.0, "daily").unwrap_err();
+    assert!(error.to_string().contains("An override reason is required"), "{}", error);
+    assert!(emr.prescribe_medication_with("1", "metformin", 1500.0, "daily", &with_reason("   ")).is_err());
+
+    emr.prescribe_medication_with("1", "metformin", 1500.0, "daily", &with_reason("titrating up")).unwrap();
+    let audit = workspace.audit_log();
+    assert!(audit.contains("Dose override for metformin 1500mg daily: metformin single dose of 1500 mg is above \
+                            the usual 1000 mg. Reason: titrating up"), "{}", audit);
+}
+
+#[test]
+fn dose_above_maximum_is_refused_even_with_a_reason() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    let error = emr.prescribe_medication_with("1", "metformin", 1000.0, "three times daily", &with_reason("sure"))
+        .unwrap_err();
+    assert!(error.to_string().starts_with("Dose refused: metformin daily dose of 3000 mg exceeds the maximum"), "{}", error);
+    assert!(emr.medication_requests("1").unwrap().is_empty());
+    assert!(!workspace.audit_log().contains("Prescribed"));
+}
+
+#[test]
+fn unlisted_medication_needs_a_reason() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    let error = emr.prescribe_medication_with("1", "amitriptiline", 25.0, "nightly", &unlisted(None)).unwrap_err();
+    assert!(error.to_string().contains("not in the RxNorm dictionary"), "{}", error);
+
+    emr.prescribe_medication_with("1", "amitriptiline", 25.0, "nightly", &unlisted(Some("imported brand"))).unwrap();
+    let audit = workspace.audit_log();
+    assert!(audit.contains("Unlisted medication amitriptiline 25mg nightly, checked as amitriptyline (RxNorm 704). \
+                            Reason: imported brand"), "{}", audit);
+}
+
+#[test]
+fn unlisted_medication_is_limited_as_its_closest_match() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    let error = emr.prescribe_medication_with("1", "amitriptiline", 20000.0, "daily", &unlisted(Some("typo")))
+        .unwrap_err();
+    assert!(error.to_string().starts_with("Dose refused: amitriptyline single dose"), "{}", error);
+}
+
+#[test]
+fn unlisted_medication_without_a_limit_is_refused() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    let error = emr.prescribe_medication_with("1", "zorblatide", 5.0, "daily", &unlisted(Some("trial drug")))
+        .unwrap_err();
+    assert!(error.to_string().contains("No dose limit in mg is known for zorblatide"), "{}", error);
+}
+
+#[test]
+fn unlisted_medication_uses_a_limit_recorded_under_its_name() {
+    let workspace = common::workspace();
+    workspace.write("dose_limits.json", r#"[{"rxcui": "", "name": "zorblatide", "usual_single_mg": 5,
+        "max_single_mg": 10, "usual_daily_mg": 5, "max_daily_mg": 10}]"#);
+    let mut emr = workspace.emr();
+    emr.prescribe_medication_with("1", "zorblatide", 5.0, "daily", &unlisted(Some("trial drug"))).unwrap();
+    let error = emr.prescribe_medication_with("1", "zorblatide", 20.0, "daily", &unlisted(Some("trial drug")))
+        .unwrap_err();
+    assert!(error.to_string().starts_with("Dose refused: zorblatide single dose of 20 mg"), "{}", error);
+}
+
+#[test]
+fn interactions_are_checked_for_unlisted_medications() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.prescribe_medication("1", "warfarin", 5.0, "daily").unwrap();
+    let alerts = emr.verify_interactions("1", "asprin").unwrap();
+    assert!(alerts.iter().any(|alert| alert.other == "warfarin"), "{:?}", alerts);
+}
+
+#[test]
+fn only_medications_dosed_in_units_are_prescribed_in_units() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.add_vital("1", VitalKind::Weight, 80.0).unwrap();
+    let per_kg = |amount: f64, unit: &str| DoseOrder { amount, unit: unit.to_string(), basis: DoseBasis::Weight };
+    let options = PrescribeOptions::default();
+
+    let error = emr.prescribe_calculated_dose("1", "lisinopril", &per_kg(0.1, "units"), "daily", &options).unwrap_err();
+    assert!(error.to_string().contains("lisinopril is dosed in mg, not units"), "{}", error);
+    let error = emr.prescribe_calculated_dose("1", "insulin glargine", &per_kg(0.5, "mg"), "daily", &options).unwrap_err();
+    assert!(error.to_string().contains("insulin glargine is dosed in units, not mg"), "{}", error);
+    assert!(emr.prescribe_medication("1", "insulin glargine", 10.0, "daily").is_err());
+
+    // 0.5 units/kg is 40 units; 2 units/kg is over the 100 unit maximum
+    emr.prescribe_calculated_dose("1", "insulin glargine", &per_kg(0.5, "units"), "daily", &options).unwrap();
+    let error = emr.prescribe_calculated_dose("1", "insulin glargine", &per_kg(2.0, "units"), "daily", &with_reason("DKA"))
+        .unwrap_err();
+    assert!(error.to_string().contains("exceeds the maximum of 100 units"), "{}", error);
+}
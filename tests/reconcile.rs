@@ -0,0 +1,95 @@
+// tests/reconcile.rs
+// Charcot EMR: Reconciling outside medication lists with doses in other units
+
+mod common;
+
+use charcot_emr::reconcile::parse_medication_list;
+use charcot_emr::{medications, EMR, ReconcileAction, Reconciliation, Resource};
+
+fn statement(name: &str, value: f64, unit: &str) -> String {
+    format!(r#"{{"resourceType": "MedicationStatement", "status": "active",
+                 "medicationCodeableConcept": {{"text": "{}"}},
+                 "dosage": [{{"timing": {{"code": {{"text": "daily"}}}},
+                              "doseAndRate": [{{"doseQuantity": {{"value": {}, "unit": "{}"}}}}]}}]}}"#,
+            name, value, unit)
+}
+
+fn bundle(statements: &[String]) -> String {
+    let entries: Vec<String> = statements.iter().map(|s| format!(r#"{{"resource": {}}}"#, s)).collect();
+    format!(r#"{{"resourceType": "Bundle", "entry": [{}]}}"#, entries.join(","))
+}
+
+// The dose and unit of the patient's one current prescription of `name`
+fn prescribed(emr: &EMR, name: &str) -> (f64, String, String) {
+    emr.bundles["1"].entry.iter()
+        .find_map(|entry| match &entry.resource {
+            Resource::MedicationRequest(request)
+                if request.status == "active" && request.medication_codeable_concept.display == name => {
+                medications::dose(request).map(|dose| (dose.value, dose.unit.clone(), dose.code.clone()))
+            }
+            _ => None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn doses_in_other_units_do_not_fail_the_import() {
+    let list = parse_medication_list(&bundle(&[
+        statement("insulin glargine", 20.0, "units"),
+        statement("lactulose", 15.0, "mL"),
+        statement("metformin", 1.0, "tablet"),
+    ])).unwrap();
+    let doses: Vec<(f64, &str)> = list.iter()
+        .map(|medication| medication.dose.as_ref().map(|dose| (dose.value, dose.unit.as_str())).unwrap())
+        .collect();
+    assert_eq!(doses, [(20.0, "units"), (15.0, "mL"), (1.0, "tablet")]);
+}
+
+#[test]
+fn new_medications_are_prescribed_in_the_listed_units() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    let list = parse_medication_list(&bundle(&[
+        statement("insulin glargine", 20.0, "units"),
+        statement("lactulose", 15.0, "mL"),
+    ])).unwrap();
+
+    let mut items = emr.reconcile_medications("1", &list).unwrap();
+    assert_eq!((items[0].proposed, items[0].dose, items[0].dose_unit.as_str()), (ReconcileAction::New, Some(20.0), "units"));
+    // mL is neither mg nor units, so the dose has to be entered
+    assert_eq!((items[1].dose, items[1].dose_unit.as_str()), (None, "mg"));
+    items[1].decision = ReconcileAction::Stop;
+
+    emr.apply_reconciliation("1", &items, &Reconciliation::new("admission", "Dr. Osler")).unwrap();
+    assert_eq!(prescribed(&emr, "insulin glargine"), (20.0, "units".to_string(), "[iU]".to_string()));
+}
+
+#[test]
+fn listed_doses_are_compared_in_the_prescribed_unit() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    emr.prescribe_medication("1", "metformin", 500.0, "daily").unwrap();
+    let reconciliation = Reconciliation::new("admission", "Dr. Osler");
+    let list = parse_medication_list(&bundle(&[statement("insulin glargine", 20.0, "units")])).unwrap();
+    let mut items = emr.reconcile_medications("1", &list).unwrap();
+    items.retain(|item| item.external.is_some());
+    emr.apply_reconciliation("1", &items, &reconciliation).unwrap();
+
+    let list = parse_medication_list("medication,dose,frequency\n\
+                                      metformin,0.5 g,daily\n\
+                                      insulin glargine,24 IU,daily\n").unwrap();
+    let items = emr.reconcile_medications("1", &list).unwrap();
+    assert_eq!(items[0].proposed, ReconcileAction::Continue);
+    assert_eq!((items[1].proposed, items[1].dose, items[1].dose_unit.as_str()),
+               (ReconcileAction::Modify, Some(24.0), "units"));
+
+    emr.apply_reconciliation("1", &items, &reconciliation).unwrap();
+    assert_eq!(prescribed(&emr, "insulin glargine").0, 24.0);
+
+    let list = parse_medication_list(&bundle(&[statement("insulin glargine", 0.24, "mL")])).unwrap();
+    let items = emr.reconcile_medications("1", &list).unwrap();
+    let insulin = items.iter().find(|item| item.external.is_some()).unwrap();
+    assert_eq!((insulin.proposed, insulin.dose, insulin.dose_unit.as_str()), (ReconcileAction::Modify, None, "units"));
+    assert!(insulin.note.contains("cannot be compared with the prescribed units"), "{}", insulin.note);
+    assert!(emr.apply_reconciliation("1", &items, &reconciliation).is_err());
+}
@@ -0,0 +1,105 @@
+// tests/repl.rs
+// Charcot EMR: Evaluating statements in a REPL session
+
+mod common;
+
+use charcot_emr::EMR;
+use charcot_emr::lang::repl::{Outcome, Session};
+
+fn output(session: &mut Session, emr: &mut EMR, line: &str) -> String {
+    match session.eval(emr, line) {
+        Ok(Outcome::Output(output)) => output,
+        Ok(Outcome::Quit) => panic!("{} quit the session", line),
+        Err(e) => panic!("{}: {}", line, e),
+    }
+}
+
+fn error(session: &mut Session, emr: &mut EMR, line: &str) -> String {
+    match session.eval(emr, line) {
+        Err(e) => e.to_string(),
+        Ok(_) => panic!("{} succeeded", line),
+    }
+}
+
+#[test]
+fn statements_need_an_open_patient() {
+    let workspace = common::workspace();
+    workspace.emr().save_patient("1", "secret").unwrap();
+    let mut emr = EMR::new().unwrap();
+    let mut session = Session::new();
+
+    assert!(error(&mut session, &mut emr, "bp = BloodPressure(130, 85)").starts_with("No patient is open"));
+    assert!(error(&mut session, &mut emr, "commit").starts_with("No patient is open"));
+    assert!(error(&mut session, &mut emr, "open patient 1").starts_with("Usage: open patient"));
+    assert_eq!(error(&mut session, &mut emr, "open patient #2 with key secret"), "Patient file not found: patient_2.med");
+    assert!(session.eval(&mut emr, "open patient #1 with key wrong").is_err());
+
+    let opened = output(&mut session, &mut emr, "open patient #1 with key secret");
+    assert_eq!(opened, "Opened patient #1 from patient_1.med (1 resources)");
+    assert_eq!(session.prompt(), "charcot #1> ");
+}
+
+#[test]
+fn statements_show_the_resources_they_add() {
+    let workspace = common::workspace();
+    workspace.emr().save_patient("1", "secret").unwrap();
+    let mut emr = EMR::new().unwrap();
+    let mut session = Session::new();
+    output(&mut session, &mut emr, "open patient #1 with key secret");
+
+    assert_eq!(output(&mut session, &mut emr, "metformin = Medication(\"metformin\")"), "defined `metformin`");
+    assert_eq!(session.prompt(), "charcot #1> ");
+    let prescribed = output(&mut session, &mut emr, "administer metformin 500 mg twice daily");
+    assert!(prescribed.contains("\"MedicationRequest\"") && prescribed.contains("metformin"), "{}", prescribed);
+    let bp = output(&mut session, &mut emr, "bp = BloodPressure(130, 85);");
+    assert!(bp.contains("85354-9") && !bp.contains("MedicationRequest"), "{}", bp);
+    assert_eq!(session.prompt(), "charcot #1*> ");
+
+    // A check adds nothing to the record, so shows nothing of it
+    assert_eq!(output(&mut session, &mut emr, "verify_interactions()"), "ok");
+    assert!(workspace.audit_log().contains("Interaction check: none found"));
+
+    let error = error(&mut session, &mut emr, "bp = BloodPressure(400, 85)");
+    assert!(error.contains("Invalid systolic value: 400"), "{}", error);
+    assert_eq!(emr.bundles["1"].entry.len(), 3);
+}
+
+#[test]
+fn changes_must_be_committed_and_saved_before_quitting() {
+    let workspace = common::workspace();
+    workspace.emr().save_patient("1", "secret").unwrap();
+    let mut emr = EMR::new().unwrap();
+    let mut session = Session::new();
+    output(&mut session, &mut emr, "open patient #1 with key secret");
+    output(&mut session, &mut emr, "bp = BloodPressure(130, 85)");
+
+    assert!(error(&mut session, &mut emr, "quit").contains("has changes that are not saved"));
+    assert!(error(&mut session, &mut emr, "open patient #1 with key secret").contains("use `close!`"));
+    assert_eq!(error(&mut session, &mut emr, "save"), "Patient #1 has uncommitted changes; run `commit` first");
+
+    assert_eq!(output(&mut session, &mut emr, "commit \"Clinic reading\""), "Committed patient #1: Clinic reading");
+    assert!(error(&mut session, &mut emr, "quit").contains("has changes that are not saved"));
+    assert_eq!(output(&mut session, &mut emr, "save"), "Saved patient #1 to patient_1.med");
+    assert_eq!(session.prompt(), "charcot #1> ");
+    assert!(matches!(session.eval(&mut emr, "quit"), Ok(Outcome::Quit)));
+
+    let mut emr = EMR::new().unwrap();
+    emr.load_patient("patient_1.med", "secret").unwrap();
+    assert_eq!(emr.bundles["1"].entry.len(), 2);
+    assert_eq!(emr.bundles["1"].version_history.last().unwrap().message, "Clinic reading");
+}
+
+#[test]
+fn quitting_with_a_bang_discards_changes() {
+    let workspace = common::workspace();
+    workspace.emr().save_patient("1", "secret").unwrap();
+    let mut emr = EMR::new().unwrap();
+    let mut session = Session::new();
+    output(&mut session, &mut emr, "open patient #1 with key secret");
+    output(&mut session, &mut emr, "bp = BloodPressure(130, 85)");
+
+    assert!(matches!(session.eval(&mut emr, "quit!"), Ok(Outcome::Quit)));
+    let mut emr = EMR::new().unwrap();
+    emr.load_patient("patient_1.med", "secret").unwrap();
+    assert_eq!(emr.bundles["1"].entry.len(), 1);
+}
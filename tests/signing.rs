@@ -0,0 +1,93 @@
+// tests/signing.rs
+// Charcot EMR: Signed commits, tampering and keys that do not belong to the author
+
+mod common;
+
+use charcot_emr::{EMR, MedicationChange, SignatureStatus, Signer};
+
+// Patient 1 with a version committed and signed by alice
+fn signed_by_alice(workspace: &common::Workspace) -> EMR {
+    let mut emr = workspace.emr();
+    let alice = Signer::generate("alice", "alice passphrase", &mut emr.clinicians).unwrap();
+    emr.sign_in(alice);
+    emr.prescribe_medication("1", "metformin", 500.0, "twice daily").unwrap();
+    emr.commit_changes("1", "Started metformin").unwrap();
+    emr
+}
+
+fn latest(emr: &EMR) -> SignatureStatus {
+    emr.signatures("1").unwrap().pop().unwrap()
+}
+
+#[test]
+fn signed_versions_verify() {
+    let workspace = common::workspace();
+    let emr = signed_by_alice(&workspace);
+    assert_eq!(latest(&emr), SignatureStatus::Valid("alice".to_string()));
+
+    // A signer unlocked again from the key file signs the same way
+    let mut emr = emr;
+    emr.sign_in(Signer::unlock("alice", "alice passphrase").unwrap());
+    emr.commit_changes("1", "Reviewed").unwrap();
+    assert_eq!(latest(&emr), SignatureStatus::Valid("alice".to_string()));
+    assert!(Signer::unlock("alice", "wrong passphrase").is_err());
+}
+
+#[test]
+fn tampered_versions_are_invalid() {
+    let workspace = common::workspace();
+    let mut emr = signed_by_alice(&workspace);
+    let version = emr.bundles.get_mut("1").unwrap().version_history.last_mut().unwrap();
+    version.hash = "0".repeat(64);
+
+    let status = latest(&emr);
+    assert_eq!(status, SignatureStatus::Invalid("alice".to_string()));
+    assert!(status.is_untrusted());
+    assert!(emr.signature_warnings("1").unwrap().contains("1 version with an invalid signature"));
+}
+
+#[test]
+fn signatures_by_someone_other_than_the_author_are_flagged() {
+    let workspace = common::workspace();
+    let mut emr = signed_by_alice(&workspace);
+    let version = emr.bundles.get_mut("1").unwrap().version_history.last_mut().unwrap();
+    version.author = "bob".to_string();
+
+    let status = latest(&emr);
+    assert_eq!(status, SignatureStatus::NotAuthor { signer: "alice".to_string(), author: "bob".to_string() });
+    assert!(status.is_untrusted());
+}
+
+#[test]
+fn signatures_with_a_key_not_registered_to_the_signer_are_flagged() {
+    let workspace = common::workspace();
+    let mut emr = signed_by_alice(&workspace);
+    let bob = Signer::generate("bob", "bob passphrase", &mut emr.clinicians).unwrap();
+
+    // Signed with bob's key, claiming to be alice
+    let version = emr.bundles.get_mut("1").unwrap().version_history.last_mut().unwrap();
+    let mut signature = bob.sign(&version.hash);
+    signature.signer = "alice".to_string();
+    version.signature = Some(signature);
+    assert_eq!(latest(&emr), SignatureStatus::UnknownKey("alice".to_string()));
+    assert!(emr.signature_warnings("1").unwrap().contains("1 version signed with an unregistered key"));
+
+    // Signed with alice's key after bob's was registered to her
+    emr.commit_changes("1", "Reviewed").unwrap();
+    emr.clinicians.clinicians.insert("alice".to_string(), bob.public_key());
+    assert_eq!(latest(&emr), SignatureStatus::UnknownKey("alice".to_string()));
+}
+
+#[test]
+fn signed_in_clinicians_change_prescriptions_only_as_themselves() {
+    let workspace = common::workspace();
+    let mut emr = signed_by_alice(&workspace);
+
+    let error = emr.hold_medication("1", "metformin", &MedicationChange::new("Nausea", "bob")).unwrap_err();
+    assert!(error.to_string().contains("Signed in as alice"), "{}", error);
+    assert_eq!(emr.find_medication_request("1", "metformin").unwrap().status, "active");
+
+    emr.hold_medication("1", "metformin", &MedicationChange::new("Nausea", "alice")).unwrap();
+    assert_eq!(emr.find_medication_request("1", "metformin").unwrap().status, "on-hold");
+    assert_eq!(latest(&emr), SignatureStatus::Valid("alice".to_string()));
+}
@@ -0,0 +1,67 @@
+// tests/vitals.rs
+// Charcot EMR: Validating vital signs and recording them as Observations
+
+mod common;
+
+use charcot_emr::{Resource, Vital, VitalKind};
+
+#[test]
+fn values_outside_each_kinds_range_are_refused() {
+    for kind in VitalKind::ALL {
+        let (low, high) = kind.range();
+        assert!(Vital::new(kind, low).is_ok(), "{}", kind);
+        assert!(Vital::new(kind, high).is_ok(), "{}", kind);
+        assert!(Vital::new(kind, low - 0.1).is_err(), "{}", kind);
+        assert!(Vital::new(kind, high + 0.1).is_err(), "{}", kind);
+        assert!(Vital::new(kind, f64::NAN).is_err(), "{}", kind);
+    }
+    let error = Vital::new(VitalKind::OxygenSaturation, 101.0).err().unwrap();
+    assert_eq!(error.to_string(), "Invalid oxygen saturation value: 101. Expected range 50-100 %");
+}
+
+#[test]
+fn values_in_other_units_are_converted() {
+    let weight = Vital::from_quantity(VitalKind::Weight, 176.0, "[lb_av]").unwrap();
+    assert!((weight.value - 79.83).abs() < 0.01, "{}", weight.value);
+    let temperature = Vital::from_quantity(VitalKind::Temperature, 98.6, "[degF]").unwrap();
+    assert!((temperature.value - 37.0).abs() < 1e-9, "{}", temperature.value);
+    let height = Vital::from_quantity(VitalKind::Height, 1.8, "m").unwrap();
+    assert!((height.value - 180.0).abs() < 1e-9, "{}", height.value);
+
+    let error = Vital::from_quantity(VitalKind::Weight, 80.0, "cm").err().unwrap();
+    assert_eq!(error.to_string(), "Invalid unit for body weight");
+    // The range applies after conversion
+    assert!(Vital::from_quantity(VitalKind::Temperature, 120.0, "[degF]").is_err());
+}
+
+#[test]
+fn vitals_are_recorded_with_their_loinc_code_and_ucum_unit() {
+    let workspace = common::workspace();
+    let mut emr = workspace.emr();
+    for (kind, value) in [(VitalKind::HeartRate, 72.0), (VitalKind::Temperature, 37.2),
+                          (VitalKind::OxygenSaturation, 97.0), (VitalKind::RespiratoryRate, 16.0),
+                          (VitalKind::Weight, 80.0), (VitalKind::Height, 175.0)] {
+        emr.add_vital("1", kind, value).unwrap();
+    }
+    assert!(emr.add_vital("1", VitalKind::HeartRate, 400.0).is_err());
+
+    let recorded: Vec<(&str, &str, f64, &str, &str)> = emr.bundles["1"].entry.iter()
+        .filter_map(|entry| match &entry.resource {
+            Resource::Observation(observation) => {
+                let quantity = observation.value_quantity.as_ref().unwrap();
+                Some((observation.code.system.as_str(), observation.code.code.as_str(),
+                      quantity.value, quantity.system.as_str(), quantity.code.as_str()))
+            }
+            _ => None,
+        })
+        .collect();
+    let (loinc, ucum) = ("http://loinc.org", "http://unitsofmeasure.org");
+    assert_eq!(recorded, [
+        (loinc, "8867-4", 72.0, ucum, "/min"),
+        (loinc, "8310-5", 37.2, ucum, "Cel"),
+        (loinc, "59408-5", 97.0, ucum, "%"),
+        (loinc, "9279-1", 16.0, ucum, "/min"),
+        (loinc, "29463-7", 80.0, ucum, "kg"),
+        (loinc, "8302-2", 175.0, ucum, "cm"),
+    ]);
+}